@@ -48,6 +48,59 @@ impl GsbMessage {
     pub fn pong() -> GsbMessage {
         packet::Packet::Pong(Pong {})
     }
+
+    /// Size of encoded message on the wire, including length header.
+    pub fn frame_len(&self) -> usize {
+        4 + self.encoded_len()
+    }
+
+    /// Name of the message type, e.g. `"CallRequest"`.
+    pub fn kind(&self) -> &'static str {
+        use packet::Packet::*;
+        match self {
+            Hello(_) => "Hello",
+            RegisterRequest(_) => "RegisterRequest",
+            RegisterReply(_) => "RegisterReply",
+            UnregisterRequest(_) => "UnregisterRequest",
+            UnregisterReply(_) => "UnregisterReply",
+            CallRequest(_) => "CallRequest",
+            CallReply(_) => "CallReply",
+            SubscribeRequest(_) => "SubscribeRequest",
+            SubscribeReply(_) => "SubscribeReply",
+            UnsubscribeRequest(_) => "UnsubscribeRequest",
+            UnsubscribeReply(_) => "UnsubscribeReply",
+            BroadcastRequest(_) => "BroadcastRequest",
+            BroadcastReply(_) => "BroadcastReply",
+            Ping(_) => "Ping",
+            Pong(_) => "Pong",
+            HelloReject(_) => "HelloReject",
+            StreamCredit(_) => "StreamCredit",
+            CallCancel(_) => "CallCancel",
+        }
+    }
+
+    /// Id of the call the message belongs to, for call related messages.
+    pub fn request_id(&self) -> Option<&str> {
+        use packet::Packet::*;
+        match self {
+            CallRequest(r) => Some(&r.request_id),
+            CallReply(r) => Some(&r.request_id),
+            StreamCredit(r) => Some(&r.request_id),
+            CallCancel(r) => Some(&r.request_id),
+            _ => None,
+        }
+    }
+
+    /// Payload carried by the message, for calls, replies and broadcasts.
+    pub fn data(&self) -> Option<&[u8]> {
+        use packet::Packet::*;
+        match self {
+            CallRequest(r) => Some(&r.data),
+            CallReply(r) => Some(&r.data),
+            BroadcastRequest(r) => Some(&r.data),
+            _ => None,
+        }
+    }
 }
 
 macro_rules! into_packet {
@@ -77,7 +130,10 @@ into_packet! {
     BroadcastRequest,
     BroadcastReply,
     Ping,
-    Pong
+    Pong,
+    HelloReject,
+    StreamCredit,
+    CallCancel
 }
 
 fn decode_header(src: &mut bytes::BytesMut) -> Result<Option<u32>, ProtocolError> {
@@ -116,14 +172,69 @@ fn encode_message(dst: &mut bytes::BytesMut, msg: GsbMessage) -> Result<(), Prot
     Ok(())
 }
 
-#[derive(Default)]
+/// Buffer sizing for framed GSB transports.
+#[derive(Clone, Copy, Debug)]
+pub struct CodecConfig {
+    /// Initial capacity of the read buffer.
+    pub initial_capacity: usize,
+    /// Maximum number of bytes reserved at once for incomplete message.
+    pub max_reserve: usize,
+    /// Frames announcing larger length fail with `ProtocolError::MsgTooBig`.
+    pub max_frame_size: usize,
+    /// Skips frames of message types unknown to this version, e.g. sent by
+    /// newer peer, instead of failing with `UnrecognizedMessageType`. Off by
+    /// default.
+    pub skip_unknown: bool,
+}
+
+impl Default for CodecConfig {
+    fn default() -> Self {
+        CodecConfig {
+            initial_capacity: 8 * 1024,
+            max_reserve: usize::MAX,
+            max_frame_size: usize::MAX,
+            skip_unknown: false,
+        }
+    }
+}
+
 pub struct GsbMessageDecoder {
     msg_header: Option<u32>,
+    max_reserve: usize,
+    max_frame_size: usize,
+    skip_unknown: bool,
+}
+
+impl Default for GsbMessageDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GsbMessageDecoder {
     pub fn new() -> Self {
-        GsbMessageDecoder { msg_header: None }
+        GsbMessageDecoder {
+            msg_header: None,
+            max_reserve: usize::MAX,
+            max_frame_size: usize::MAX,
+            skip_unknown: false,
+        }
+    }
+
+    pub fn with_max_reserve(max_reserve: usize) -> Self {
+        GsbMessageDecoder {
+            max_reserve,
+            ..Self::new()
+        }
+    }
+
+    pub fn with_config(config: &CodecConfig) -> Self {
+        GsbMessageDecoder {
+            msg_header: None,
+            max_reserve: config.max_reserve,
+            max_frame_size: config.max_frame_size,
+            skip_unknown: config.skip_unknown,
+        }
     }
 }
 
@@ -132,21 +243,33 @@ impl Decoder for GsbMessageDecoder {
     type Error = ProtocolError;
 
     fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if self.msg_header.is_none() {
-            self.msg_header = decode_header(src)?;
-        }
-        match self.msg_header {
-            None => Ok(None),
-            Some(msg_length) => match decode_message(src, msg_length)? {
-                None => {
-                    src.reserve(msg_length as usize);
-                    Ok(None)
-                }
-                Some(msg) => {
-                    self.msg_header = None;
-                    Ok(Some(msg))
+        loop {
+            if self.msg_header.is_none() {
+                self.msg_header = decode_header(src)?;
+                if let Some(msg_length) = self.msg_header {
+                    if msg_length as usize > self.max_frame_size {
+                        return Err(ProtocolError::MsgTooBig);
+                    }
                 }
-            },
+            }
+            match self.msg_header {
+                None => return Ok(None),
+                Some(msg_length) => match decode_message(src, msg_length) {
+                    Ok(None) => {
+                        src.reserve((msg_length as usize).min(self.max_reserve));
+                        return Ok(None);
+                    }
+                    Ok(Some(msg)) => {
+                        self.msg_header = None;
+                        return Ok(Some(msg));
+                    }
+                    // frame is already consumed, go on with the next one
+                    Err(ProtocolError::UnrecognizedMessageType) if self.skip_unknown => {
+                        self.msg_header = None;
+                    }
+                    Err(e) => return Err(e),
+                },
+            }
         }
     }
 }
@@ -168,6 +291,15 @@ pub struct GsbMessageCodec {
     decoder: GsbMessageDecoder,
 }
 
+impl GsbMessageCodec {
+    pub fn new(config: &CodecConfig) -> Self {
+        GsbMessageCodec {
+            encoder: GsbMessageEncoder,
+            decoder: GsbMessageDecoder::with_config(config),
+        }
+    }
+}
+
 impl Encoder<GsbMessage> for GsbMessageCodec {
     type Error = ProtocolError;
 
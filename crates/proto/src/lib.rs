@@ -40,6 +40,54 @@ impl TryFrom<i32> for CallReplyType {
     }
 }
 
+/// `Hello` capability: call body may be split across `CallRequest` frames
+/// marked with `has_more`.
+pub const CAP_CHUNKED_BODY: &str = "chunked-body";
+
+/// `Hello` capability: streaming calls may carry `window` and be paced with
+/// `StreamCredit` packets.
+pub const CAP_STREAM_CREDIT: &str = "stream-credit";
+
+/// `Hello` capability: pending calls may be abandoned with `CallCancel`.
+pub const CAP_CALL_CANCEL: &str = "call-cancel";
+
+/// `CallRequest` header: id of the instance the call should be routed to.
+pub const TARGET_INSTANCE_HEADER: &str = "gsb-target-instance";
+
+/// `CallRequest` header set by the router when the instance requested with
+/// `TARGET_INSTANCE_HEADER` was gone or did not serve the address, and the
+/// call was routed as usual. Also set in trailers of the last reply.
+pub const INSTANCE_FALLBACK_HEADER: &str = "gsb-instance-fallback";
+
+/// `CallRequest` header marking liveness probe, answered by the connection
+/// owning the address once its handler confirms the address is served,
+/// without calling it.
+pub const PING_HEADER: &str = "gsb-ping";
+
+/// Body of `CallReplyBadRequest` answering call to address nobody serves.
+pub const ENDPOINT_NOT_FOUND: &str = "endpoint address not found";
+
+/// Version of the wire protocol spoken by this crate, sent in
+/// `Hello.proto_version`.
+pub const PROTO_VERSION: u32 = 1;
+
+/// Oldest peer protocol version this crate can talk to. Peers predating
+/// version announcement report `0`.
+pub const MIN_PROTO_VERSION: u32 = 0;
+
+impl Hello {
+    /// Whether the peer announcing this `Hello` can talk to us.
+    pub fn is_proto_compatible(&self) -> bool {
+        (MIN_PROTO_VERSION..).contains(&self.proto_version)
+            && self.min_proto_version <= PROTO_VERSION
+    }
+
+    /// Protocol version used with the peer announcing this `Hello`.
+    pub fn negotiated_proto_version(&self) -> u32 {
+        self.proto_version.min(PROTO_VERSION)
+    }
+}
+
 pub const GSB_URL_ENV_VAR: &str = "GSB_URL";
 #[cfg(unix)]
 pub const DEFAULT_GSB_URL: &str = "unix:/tmp/yagna.sock";
@@ -47,7 +95,7 @@ pub const DEFAULT_GSB_URL: &str = "unix:/tmp/yagna.sock";
 pub const DEFAULT_GSB_URL: &str = "tcp://127.0.0.1:7464";
 pub const DEFAULT_GSB_PORT: u16 = 7464;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum GsbAddr {
     Tcp(String),
     Unix(PathBuf),
@@ -137,6 +185,28 @@ mod tests {
         assert_eq!(addr, "127.0.0.1:7464")
     }
 
+    #[test]
+    pub fn check_proto_compatibility() {
+        let legacy = Hello::default();
+        assert!(legacy.is_proto_compatible());
+        assert_eq!(legacy.negotiated_proto_version(), 0);
+
+        let newer = Hello {
+            proto_version: PROTO_VERSION + 1,
+            min_proto_version: PROTO_VERSION,
+            ..Default::default()
+        };
+        assert!(newer.is_proto_compatible());
+        assert_eq!(newer.negotiated_proto_version(), PROTO_VERSION);
+
+        let incompatible = Hello {
+            proto_version: PROTO_VERSION + 2,
+            min_proto_version: PROTO_VERSION + 1,
+            ..Default::default()
+        };
+        assert!(!incompatible.is_proto_compatible());
+    }
+
     #[test]
     #[serial_test::serial]
     pub fn check_env_var() {
@@ -203,6 +273,34 @@ mod tests {
         GsbAddr::from_url(Some("http://10.9.8.7".parse().unwrap()));
     }
 
+    #[cfg(feature = "with-codec")]
+    #[test]
+    pub fn check_unknown_message_skipped() {
+        use tokio_util::codec::Decoder;
+
+        // field 100 is not a message known to this version
+        let unknown = [0u8, 0, 0, 3, 0xa0, 0x06, 0x01];
+        let mut src = bytes::BytesMut::from(&unknown[..]);
+        let mut strict = codec::GsbMessageDecoder::new();
+        assert!(matches!(
+            strict.decode(&mut src),
+            Err(codec::ProtocolError::UnrecognizedMessageType)
+        ));
+
+        let mut src = bytes::BytesMut::from(&unknown[..]);
+        let mut encoder = codec::GsbMessageEncoder;
+        tokio_util::codec::Encoder::encode(&mut encoder, codec::GsbMessage::pong(), &mut src)
+            .unwrap();
+        let mut lenient = codec::GsbMessageDecoder::with_config(&codec::CodecConfig {
+            skip_unknown: true,
+            ..Default::default()
+        });
+        assert!(matches!(
+            lenient.decode(&mut src),
+            Ok(Some(packet::Packet::Pong(_)))
+        ));
+    }
+
     #[test]
     #[should_panic(expected = "need host for GSB URL")]
     pub fn panic_no_host_gsb_url() {
@@ -37,6 +37,7 @@ async fn run_client(args: Args) {
                 request_id: request_id.clone(),
                 data: payload.to_vec(),
                 no_reply: false,
+                ..Default::default()
             }
         })
         .collect();
@@ -14,6 +14,7 @@ async fn run_server(args: Args) {
         name: "echo-server".to_string(),
         version: "0.0".to_string(),
         instance_id: vec![1, 2, 3, 4],
+        ..Default::default()
     };
     writer
         .send(GsbMessage::Hello(hello))
@@ -88,6 +89,7 @@ async fn run_server(args: Args) {
                         code: CallReplyCode::CallReplyOk as i32,
                         reply_type: CallReplyType::Full as i32,
                         data: msg.data,
+                        ..Default::default()
                     }
                     .into()))
                 }
@@ -19,6 +19,12 @@ pub struct RouterConfig {
     pub high_buffer_mark: usize,
     /// How often to scan for unused resources.
     pub gc_interval: Option<Duration>,
+    /// Reject peers reusing `instance_id` of live connection instead of replacing it.
+    pub reject_duplicate_instance: bool,
+    /// How many calls sent to a connection may wait for the rest of their body frames.
+    pub max_partial_calls: usize,
+    /// Largest call body joined from frames for peers not accepting chunked bodies.
+    pub max_call_body: usize,
 }
 
 impl Default for RouterConfig {
@@ -30,6 +36,9 @@ impl Default for RouterConfig {
             broadcast_backlog: 16,
             gc_interval: None,
             high_buffer_mark: 16,
+            reject_duplicate_instance: false,
+            max_partial_calls: 64,
+            max_call_body: 64 * 1024 * 1024,
         }
     }
 }
@@ -1,6 +1,6 @@
 #![allow(clippy::map_entry)]
 
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -44,6 +44,42 @@ pub struct ForwardCallRequest {
     reply_to: Recipient<ForwardCallResponse>,
 }
 
+#[derive(Message)]
+#[rtype("()")]
+pub struct ForwardStreamCredit {
+    stream_credit: StreamCredit,
+}
+
+#[derive(Message)]
+#[rtype("()")]
+pub struct ForwardCallCancel {
+    call_cancel: CallCancel,
+}
+
+/// Call sent to a connection whose body has more frames to come.
+struct PartialCall {
+    caller: Recipient<ForwardCallResponse>,
+    no_reply: bool,
+    started: Instant,
+    state: PartialState,
+}
+
+enum PartialState {
+    /// Frames are forwarded as they come.
+    Forwarding,
+    /// Frames are joined for peer not accepting chunked bodies.
+    Joining(Vec<u8>),
+    /// Call failed, remaining frames are dropped.
+    Rejected,
+}
+
+/// What to do with frame of call body.
+enum Frame {
+    Forward { first: bool },
+    Hold,
+    Drop,
+}
+
 pub struct Connection<
     W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
     ConnInfo: Debug + Unpin + 'static,
@@ -54,10 +90,19 @@ pub struct Connection<
     services: HashSet<String>,
     output: writer::SinkWrite<GsbMessage, W>,
     reply_map: BTreeMap<String, Recipient<ForwardCallResponse>>,
+    // Callee connections of pending calls made by this one.
+    callee_map: BTreeMap<String, Addr<Self>>,
+    // Pending calls made by this one which did not reach requested instance.
+    fallback_calls: HashSet<String>,
+    // Calls forwarded to this one whose body has more frames to come.
+    partial_calls: HashMap<String, PartialCall>,
+    // Capabilities announced by the peer in `Hello`.
+    peer_capabilities: HashSet<String>,
     hold_queue: Vec<(GsbMessage, oneshot::Sender<()>)>,
     topic_map: BTreeMap<String, SpawnHandle>,
     conn_info: ConnInfo,
     last_packet: Instant,
+    rejected: bool,
 }
 
 impl<
@@ -108,6 +153,11 @@ impl<
                     request_id
                 );
             }
+            act.callee_map.retain(|_, callee| callee.connected());
+            let callee_map = &act.callee_map;
+            act.fallback_calls
+                .retain(|request_id| callee_map.contains_key(request_id));
+            act.expire_partial_calls();
         });
     }
 
@@ -121,6 +171,15 @@ where
     W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
     ConnInfo: Debug + Unpin + 'static,
 {
+    fn reject_hello(&mut self, message: String, code: HelloRejectCode) {
+        self.rejected = true;
+        self.output.write(GsbMessage::HelloReject(HelloReject {
+            message,
+            code: code as i32,
+        }));
+        self.output.close();
+    }
+
     fn cleanup(&mut self, ctx: &mut <Self as Actor>::Context) {
         if let Some(instance_id) = self.instance_id.take() {
             log::trace!("[{:?}] cleanup connection", self.conn_info);
@@ -142,14 +201,142 @@ where
         )
     }
 
+    /// Forgets calls whose caller is gone or whose body frames stopped
+    /// coming for `forward_timeout`.
+    fn expire_partial_calls(&mut self) {
+        let timeout = self.config.forward_timeout();
+        let expired: Vec<_> = self
+            .partial_calls
+            .iter()
+            .filter(|(_, partial)| {
+                !partial.caller.connected() || partial.started.elapsed() > timeout
+            })
+            .map(|(request_id, _)| request_id.clone())
+            .collect();
+        for request_id in expired {
+            if let Some(partial) = self.partial_calls.remove(&request_id) {
+                log::debug!(
+                    "[{:?}] dropping call {} waiting for body frames",
+                    self.conn_info,
+                    request_id
+                );
+                if let PartialState::Joining(_) = partial.state {
+                    fail_call(
+                        &partial.caller,
+                        request_id,
+                        partial.no_reply,
+                        "call body timed out",
+                    );
+                }
+            }
+        }
+    }
+
+    /// Tracks frames of chunked call body. Peers announcing
+    /// `CAP_CHUNKED_BODY` get the frames as they come, for others they are
+    /// joined into single request.
+    fn join_frame(
+        &mut self,
+        call_request: &mut CallRequest,
+        reply_to: &Recipient<ForwardCallResponse>,
+    ) -> Frame {
+        let request_id = call_request.request_id.clone();
+        let (mut partial, first) = match self.partial_calls.remove(&request_id) {
+            Some(partial) => (partial, false),
+            None if !call_request.has_more => return Frame::Forward { first: true },
+            None => {
+                let waiting = self
+                    .partial_calls
+                    .values()
+                    .filter(|partial| !matches!(partial.state, PartialState::Rejected))
+                    .count();
+                let state = if waiting >= self.config.max_partial_calls() {
+                    fail_call(
+                        reply_to,
+                        request_id.clone(),
+                        call_request.no_reply,
+                        "too many partial calls",
+                    );
+                    PartialState::Rejected
+                } else if self.peer_capabilities.contains(CAP_CHUNKED_BODY) {
+                    PartialState::Forwarding
+                } else {
+                    PartialState::Joining(Vec::new())
+                };
+                let partial = PartialCall {
+                    caller: reply_to.clone(),
+                    no_reply: call_request.no_reply,
+                    started: Instant::now(),
+                    state,
+                };
+                (partial, true)
+            }
+        };
+        let frame = match &mut partial.state {
+            PartialState::Forwarding => Frame::Forward { first },
+            PartialState::Rejected => Frame::Drop,
+            PartialState::Joining(body) => {
+                body.append(&mut call_request.data);
+                if body.len() > self.config.max_call_body() {
+                    Frame::Drop
+                } else if call_request.has_more {
+                    Frame::Hold
+                } else {
+                    call_request.data = std::mem::take(body);
+                    Frame::Forward { first: true }
+                }
+            }
+        };
+        if let (Frame::Drop, PartialState::Joining(_)) = (&frame, &partial.state) {
+            fail_call(
+                reply_to,
+                request_id.clone(),
+                call_request.no_reply,
+                "call body too large",
+            );
+            partial.state = PartialState::Rejected;
+        }
+        if call_request.has_more {
+            let _ = self.partial_calls.insert(request_id, partial);
+        }
+        frame
+    }
+
+    /// Picks connection serving the call, honoring `TARGET_INSTANCE_HEADER`.
+    ///
+    /// Calls whose instance is gone or does not serve the address are routed
+    /// as usual, marked with `INSTANCE_FALLBACK_HEADER`. The caller finds it
+    /// in trailers of the reply.
+    fn route_call(&mut self, call_request: &mut CallRequest) -> Option<Addr<Self>> {
+        let router = self.router.read();
+        if let Some(instance_id) = call_request.headers.get(TARGET_INSTANCE_HEADER) {
+            if let Some(dst) = router.resolve_instance(instance_id, &call_request.address) {
+                return Some(dst);
+            }
+            log::debug!(
+                "[{:?}] instance requested for {} is gone, routing as usual",
+                self.conn_info,
+                call_request.address
+            );
+            call_request
+                .headers
+                .insert(INSTANCE_FALLBACK_HEADER.to_string(), vec![1]);
+            if !call_request.no_reply {
+                let _ = self.fallback_calls.insert(call_request.request_id.clone());
+            }
+        }
+        router.resolve_node(&call_request.address)
+    }
+
     fn handle_call_request(
         &mut self,
-        call_request: CallRequest,
+        mut call_request: CallRequest,
         ctx: &mut <Self as Actor>::Context,
     ) -> impl Future<Output = Result<(), CallReply>> + 'static {
         let request_id = call_request.request_id.clone();
 
-        if let Some(dst) = { self.router.read().resolve_node(&call_request.address) } {
+        if let Some(dst) = self.route_call(&mut call_request) {
+            self.callee_map.insert(request_id.clone(), dst.clone());
             let reply_to = ctx.address().recipient();
             let msg = ForwardCallRequest {
                 call_request,
@@ -186,7 +373,7 @@ where
             };
             reply.set_code(CallReplyCode::CallReplyBadRequest);
             reply.set_reply_type(CallReplyType::Full);
-            reply.data = "endpoint address not found".as_bytes().to_vec();
+            reply.data = ENDPOINT_NOT_FOUND.as_bytes().to_vec();
 
             future::err(reply).right_future()
         }
@@ -194,10 +381,10 @@ where
 
     fn handle_push_request(
         &mut self,
-        call_request: CallRequest,
+        mut call_request: CallRequest,
         ctx: &mut <Self as Actor>::Context,
     ) -> impl Future<Output = ()> + 'static {
-        match { self.router.read().resolve_node(&call_request.address) } {
+        match self.route_call(&mut call_request) {
             Some(dst) => {
                 let reply_to = ctx.address().recipient();
                 let msg = ForwardCallRequest {
@@ -275,10 +462,15 @@ pub fn connection<
             services: Default::default(),
             hold_queue: Default::default(),
             reply_map: Default::default(),
+            callee_map: Default::default(),
+            fallback_calls: Default::default(),
+            partial_calls: Default::default(),
+            peer_capabilities: Default::default(),
             topic_map: Default::default(),
             conn_info,
             output,
             last_packet: Instant::now(),
+            rejected: false,
         }
     })
 }
@@ -309,6 +501,18 @@ impl<
             Ok(msg) => msg,
         };
 
+        if self.rejected {
+            return Box::pin(fut::ready(()));
+        }
+        if self.instance_id.is_none()
+            && self.config.requires_auth()
+            && !matches!(msg, GsbMessage::Hello(_))
+        {
+            log::warn!("[{:?}] request before authentication", self.conn_info);
+            ctx.stop();
+            return Box::pin(fut::ready(()));
+        }
+
         match msg {
             GsbMessage::CallRequest(call_request) => {
                 if call_request.no_reply {
@@ -319,6 +523,7 @@ impl<
                         .into_actor(self)
                         .then(|r, act, ctx| {
                             if let Err(error_reply) = r {
+                                let _ = act.fallback_calls.remove(&error_reply.request_id);
                                 act.send_reply(error_reply, ctx);
                             }
                             fut::ready(())
@@ -419,14 +624,14 @@ impl<
             }
 
             GsbMessage::BroadcastRequest(broadcast_request) => {
-                let reply = BroadcastReply::default();
+                let mut reply = BroadcastReply::default();
                 if let Some(sender) = { self.router.read().find_topic(&broadcast_request.topic) } {
                     log::debug!(
                         "[{:?}] sending bcast to {} receivers",
                         self.conn_info,
                         sender.receiver_count()
                     );
-                    let _ = sender.send(broadcast_request);
+                    reply.receivers = sender.send(broadcast_request).unwrap_or_default() as u32;
                 }
                 self.send_reply(GsbMessage::BroadcastReply(reply), ctx);
             }
@@ -434,9 +639,35 @@ impl<
                 if self.instance_id.is_some() {
                     log::error!("[{:?}] duplicate hello send", self.conn_info);
                     ctx.stop();
+                } else if let Err(message) = self.config.authenticate(&hello_request) {
+                    log::warn!(
+                        "[{:?}] rejecting peer {}/{}: {}",
+                        self.conn_info,
+                        hello_request.name,
+                        hello_request.version,
+                        message
+                    );
+                    self.reject_hello(message, HelloRejectCode::HelloRejectUnauthorized);
+                } else if self.config.reject_duplicate_instance()
+                    && self
+                        .router
+                        .read()
+                        .has_live_connection(&hello_request.instance_id)
+                {
+                    log::warn!(
+                        "[{:?}] rejecting peer {}/{}: instance_id in use",
+                        self.conn_info,
+                        hello_request.name,
+                        hello_request.version
+                    );
+                    self.reject_hello(
+                        "instance_id in use".to_string(),
+                        HelloRejectCode::HelloRejectInstanceConflict,
+                    );
                 } else {
                     let instance_id: IdBytes = hello_request.instance_id.into();
                     self.instance_id = Some(instance_id.clone());
+                    self.peer_capabilities = hello_request.capabilities.into_iter().collect();
                     log::debug!(
                         "[{:?}] connection initialized peer {}/{}",
                         self.conn_info,
@@ -454,6 +685,22 @@ impl<
             GsbMessage::Ping(_) => {
                 self.send_reply(GsbMessage::Ping(Default::default()), ctx);
             }
+            GsbMessage::StreamCredit(stream_credit) => {
+                match self.callee_map.get(&stream_credit.request_id) {
+                    Some(callee) => callee.do_send(ForwardStreamCredit { stream_credit }),
+                    None => log::debug!(
+                        "[{:?}] credit for unknown stream {}",
+                        self.conn_info,
+                        stream_credit.request_id
+                    ),
+                }
+            }
+            GsbMessage::CallCancel(call_cancel) => {
+                let _ = self.fallback_calls.remove(&call_cancel.request_id);
+                if let Some(callee) = self.callee_map.remove(&call_cancel.request_id) {
+                    callee.do_send(ForwardCallCancel { call_cancel });
+                }
+            }
             GsbMessage::Pong(_) => {
                 log::trace!("[{:?}] pong recv", self.conn_info);
             }
@@ -529,11 +776,56 @@ where
 {
     type Result = ResponseFuture<Result<(), oneshot::Canceled>>;
 
-    fn handle(&mut self, msg: ForwardCallResponse, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, mut msg: ForwardCallResponse, ctx: &mut Self::Context) -> Self::Result {
+        if msg.call_reply.reply_type() == CallReplyType::Full {
+            let request_id = &msg.call_reply.request_id;
+            let _ = self.callee_map.remove(request_id);
+            if self.fallback_calls.remove(request_id) {
+                let _ = msg
+                    .call_reply
+                    .trailers
+                    .insert(INSTANCE_FALLBACK_HEADER.to_string(), vec![1]);
+            }
+        }
         self.send_message(GsbMessage::CallReply(msg.call_reply), ctx)
     }
 }
 
+impl<S, ConnInfo> Handler<ForwardStreamCredit> for Connection<S, ConnInfo>
+where
+    S: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    ConnInfo: Debug + Unpin + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: ForwardStreamCredit, _ctx: &mut Self::Context) -> Self::Result {
+        if self.reply_map.contains_key(&msg.stream_credit.request_id)
+            && self.peer_capabilities.contains(CAP_STREAM_CREDIT)
+        {
+            self.output
+                .write(GsbMessage::StreamCredit(msg.stream_credit));
+        }
+    }
+}
+
+impl<S, ConnInfo> Handler<ForwardCallCancel> for Connection<S, ConnInfo>
+where
+    S: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    ConnInfo: Debug + Unpin + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: ForwardCallCancel, _ctx: &mut Self::Context) -> Self::Result {
+        let _ = self.partial_calls.remove(&msg.call_cancel.request_id);
+        // late replies of cancelled call are dropped as unmatched
+        if self.reply_map.remove(&msg.call_cancel.request_id).is_some()
+            && self.peer_capabilities.contains(CAP_CALL_CANCEL)
+        {
+            self.output.write(GsbMessage::CallCancel(msg.call_cancel));
+        }
+    }
+}
+
 impl<S, ConnInfo> Handler<ForwardCallRequest> for Connection<S, ConnInfo>
 where
     S: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
@@ -542,18 +834,50 @@ where
     type Result = ResponseFuture<Result<(), oneshot::Canceled>>;
 
     fn handle(&mut self, msg: ForwardCallRequest, ctx: &mut Self::Context) -> Self::Result {
-        if !msg.call_request.no_reply
-            && self
-                .reply_map
-                .insert(msg.call_request.request_id.clone(), msg.reply_to)
-                .is_some()
-        {
-            log::warn!(
-                "[{:?}] duplicate message request id forwarded {}",
-                self.conn_info,
-                msg.call_request.request_id
-            );
+        let ForwardCallRequest {
+            mut call_request,
+            reply_to,
+        } = msg;
+        // frames following the first one of chunked body belong to the
+        // call mapped already
+        match self.join_frame(&mut call_request, &reply_to) {
+            Frame::Forward { first } => {
+                if !call_request.no_reply
+                    && first
+                    && self
+                        .reply_map
+                        .insert(call_request.request_id.clone(), reply_to)
+                        .is_some()
+                {
+                    log::warn!(
+                        "[{:?}] duplicate message request id forwarded {}",
+                        self.conn_info,
+                        call_request.request_id
+                    );
+                }
+                self.send_message(GsbMessage::CallRequest(call_request), ctx)
+            }
+            Frame::Hold | Frame::Drop => Box::pin(future::ok(())),
         }
-        self.send_message(GsbMessage::CallRequest(msg.call_request), ctx)
     }
 }
+
+/// Fails call before it reaches the callee.
+fn fail_call(
+    caller: &Recipient<ForwardCallResponse>,
+    request_id: String,
+    no_reply: bool,
+    reason: &str,
+) {
+    if no_reply {
+        return;
+    }
+    let mut call_reply = CallReply {
+        request_id,
+        data: reason.as_bytes().to_vec(),
+        ..Default::default()
+    };
+    call_reply.set_code(CallReplyCode::ServiceFailure);
+    call_reply.set_reply_type(CallReplyType::Full);
+    caller.do_send(ForwardCallResponse { call_reply });
+}
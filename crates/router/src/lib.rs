@@ -19,7 +19,7 @@ use futures::prelude::*;
 use tokio::net::{TcpStream, ToSocketAddrs};
 
 pub use config::RouterConfig;
-pub use router::InstanceConfig;
+pub use router::{Authenticator, InstanceConfig};
 #[cfg(unix)]
 pub use unix::connect;
 use ya_sb_proto::codec::{GsbMessage, GsbMessageCodec, ProtocolError};
@@ -27,12 +27,16 @@ use super::config::RouterConfig;
 
 pub type RouterRef<W, C> = Arc<RwLock<Router<W, C>>>;
 
+/// Validates peer `Hello`, returning rejection message on failure.
+pub type Authenticator = Arc<dyn Fn(&Hello) -> Result<(), String> + Send + Sync>;
+
 /// Router config with instance identification info.
 pub struct InstanceConfig {
     config: RouterConfig,
     instance_id: uuid::Uuid,
     name: String,
     version: String,
+    authenticator: Option<Authenticator>,
 }
 
 impl InstanceConfig {
@@ -43,6 +47,7 @@ impl InstanceConfig {
             instance_id: Uuid::new_v4(),
             name: env!("CARGO_PKG_NAME").to_string(),
             version: env!("CARGO_PKG_VERSION").to_string(),
+            authenticator: None,
         }
     }
 
@@ -57,14 +62,33 @@ impl InstanceConfig {
             instance_id: Uuid::new_v4(),
             name: name.into(),
             version: version.into(),
+            authenticator: None,
         }
     }
 
+    /// Requires every connecting peer to pass `authenticator` check on `Hello`.
+    pub fn with_authenticator(
+        mut self,
+        authenticator: impl Fn(&Hello) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
     pub(crate) fn hello(&self) -> Hello {
         Hello {
             instance_id: self.instance_id.as_bytes().to_vec(),
             name: self.name.clone(),
             version: self.version.clone(),
+            // chunked call bodies are joined for peers not accepting them
+            capabilities: vec![
+                ya_sb_proto::CAP_CHUNKED_BODY.to_string(),
+                ya_sb_proto::CAP_STREAM_CREDIT.to_string(),
+                ya_sb_proto::CAP_CALL_CANCEL.to_string(),
+            ],
+            proto_version: ya_sb_proto::PROTO_VERSION,
+            min_proto_version: ya_sb_proto::MIN_PROTO_VERSION,
+            ..Default::default()
         }
     }
 
@@ -82,15 +106,39 @@ impl InstanceConfig {
         }))
     }
 
+    #[inline]
+    pub(super) fn requires_auth(&self) -> bool {
+        self.authenticator.is_some()
+    }
+
+    pub(super) fn authenticate(&self, hello: &Hello) -> Result<(), String> {
+        match &self.authenticator {
+            Some(authenticator) => authenticator(hello),
+            None => Ok(()),
+        }
+    }
+
     #[inline]
     pub(super) fn high_buffer_mark(&self) -> usize {
         self.config.high_buffer_mark
     }
 
+    pub(super) fn reject_duplicate_instance(&self) -> bool {
+        self.config.reject_duplicate_instance
+    }
+
     pub(super) fn forward_timeout(&self) -> Duration {
         self.config.forward_timeout
     }
 
+    pub(super) fn max_partial_calls(&self) -> usize {
+        self.config.max_partial_calls
+    }
+
+    pub(super) fn max_call_body(&self) -> usize {
+        self.config.max_call_body
+    }
+
     pub(super) fn ping_interval(&self) -> Duration {
         self.config.ping_interval
     }
@@ -239,6 +287,19 @@ impl<W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static, ConnInfo: Deb
         self.registered_endpoints.get(service_id).cloned()
     }
 
+    /// Live connection of given instance, provided it serves `service_id`.
+    pub fn resolve_instance(
+        &self,
+        instance_id: &[u8],
+        service_id: &str,
+    ) -> Option<Addr<Connection<W, ConnInfo>>> {
+        self.registered_instances
+            .get(instance_id)
+            .filter(|connection| connection.connected())
+            .filter(|connection| self.registered_endpoints.get(service_id) == Some(connection))
+            .cloned()
+    }
+
     pub fn register_service(
         &mut self,
         service_id: String,
@@ -290,6 +351,13 @@ impl<W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static, ConnInfo: Deb
         self.topics.get(topic_id).map(Clone::clone)
     }
 
+    pub fn has_live_connection(&self, instance_id: &[u8]) -> bool {
+        self.registered_instances
+            .get(instance_id)
+            .map(|connection| connection.connected())
+            .unwrap_or_default()
+    }
+
     pub fn new_connection(
         &mut self,
         instance_id: IdBytes,
@@ -48,10 +48,19 @@ impl<T> PrefixLookupBag<T> {
         RevPrefixes(key).find_map(|key| self.dict.get(key))
     }
 
+    /// Returns values for all prefixes of `key`, most specific first.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a T> + 'a {
+        RevPrefixes(key).filter_map(move |key| self.dict.get(key))
+    }
+
     pub fn keys(&self) -> impl Iterator<Item = &String> {
         self.dict.keys()
     }
 
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.dict.values_mut()
+    }
+
     pub fn get_mut(&mut self, key: &str) -> Option<&mut T> {
         if let Some(k) = RevPrefixes(key).find(|&k| self.dict.contains_key(k)) {
             self.dict.get_mut(k)
@@ -60,6 +69,10 @@ impl<T> PrefixLookupBag<T> {
         }
     }
 
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.dict.contains_key(key)
+    }
+
     pub fn insert(&mut self, key: String, v: T) -> Option<T> {
         self.dict.insert(key, v)
     }
@@ -118,5 +131,9 @@ mod test {
         assert_eq!(bag.get("/ala/ma/smoka"), Some(&7));
         assert_eq!(bag.get("/ala/ma/kota"), Some(&1));
         assert_eq!(bag.get("/jola/ma/psa"), None);
+
+        let all: Vec<_> = bag.get_all("/ala/ma/kota/i/psa").collect();
+        assert_eq!(all, [&1, &7]);
+        assert_eq!(bag.get_all("/jola").count(), 0);
     }
 }
@@ -26,6 +26,55 @@ where
     fn buffer_empty(&mut self, _ctx: &mut Self::Context) {}
 }
 
+/// Position in the write queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnqueuePriority {
+    /// Sent after everything queued before.
+    Normal,
+    /// Sent ahead of queued `Normal` items, after earlier `High` ones.
+    High,
+}
+
+/// Queue keeping `High` items ahead of `Normal` ones, each in order of writes.
+struct WriteBuffer<I> {
+    items: VecDeque<I>,
+    // number of `High` items at the front
+    high: usize,
+}
+
+impl<I> WriteBuffer<I> {
+    fn new() -> Self {
+        WriteBuffer {
+            items: VecDeque::new(),
+            high: 0,
+        }
+    }
+
+    fn push(&mut self, item: I, priority: EnqueuePriority) {
+        match priority {
+            EnqueuePriority::Normal => self.items.push_back(item),
+            EnqueuePriority::High => {
+                self.items.insert(self.high, item);
+                self.high += 1;
+            }
+        }
+    }
+
+    fn pop_front(&mut self) -> Option<I> {
+        let item = self.items.pop_front();
+        self.high = self.high.saturating_sub(1);
+        item
+    }
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
 pub struct SinkWrite<I, S: Sink<I> + Unpin> {
     inner: Rc<RefCell<InnerSinkWrite<I, S>>>,
 }
@@ -42,7 +91,7 @@ impl<I: 'static, S: Sink<I> + Unpin + 'static> SinkWrite<I, S> {
             sink,
             task: None,
             handle: SpawnHandle::default(),
-            buffer: VecDeque::new(),
+            buffer: WriteBuffer::new(),
         }));
 
         let handle = ctxt.spawn(SinkWriteFuture {
@@ -58,8 +107,13 @@ impl<I: 'static, S: Sink<I> + Unpin + 'static> SinkWrite<I, S> {
     ///
     /// Returns unsent item if sink is closing or closed.
     pub fn write(&mut self, item: I) -> Option<I> {
+        self.write_with(item, EnqueuePriority::Normal)
+    }
+
+    /// Queues an item ahead of or after already queued ones, see `write`.
+    pub fn write_with(&mut self, item: I, priority: EnqueuePriority) -> Option<I> {
         if self.inner.borrow().closing_flag.is_empty() {
-            self.inner.borrow_mut().buffer.push_back(item);
+            self.inner.borrow_mut().buffer.push(item, priority);
             self.notify_task();
             None
         } else {
@@ -105,7 +159,7 @@ struct InnerSinkWrite<I, S: Sink<I>> {
 
     // buffer of items to be sent so that multiple
     // calls to start_send don't silently skip items
-    buffer: VecDeque<I>,
+    buffer: WriteBuffer<I>,
 }
 
 struct SinkWriteFuture<I: 'static, S: Sink<I>, A> {
@@ -193,3 +247,24 @@ where
         Poll::Pending
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_buffer_priority() {
+        let mut buffer = WriteBuffer::new();
+        buffer.push(1, EnqueuePriority::Normal);
+        buffer.push(2, EnqueuePriority::Normal);
+        buffer.push(3, EnqueuePriority::High);
+        buffer.push(4, EnqueuePriority::High);
+        assert_eq!(buffer.pop_front(), Some(3));
+        buffer.push(5, EnqueuePriority::High);
+        buffer.push(6, EnqueuePriority::Normal);
+
+        let order: Vec<_> = std::iter::from_fn(|| buffer.pop_front()).collect();
+        assert_eq!(order, vec![4, 5, 1, 2, 6]);
+        assert!(buffer.is_empty());
+    }
+}
@@ -5,7 +5,7 @@ use futures::channel::oneshot;
 use std::error::Error;
 use std::{env, path::PathBuf, time::Duration};
 use structopt::StructOpt;
-use ya_service_bus::connection::{CallRequestHandler, ClientInfo};
+use ya_service_bus::connection::{CallRequestHandler, ClientInfo, DisconnectReason};
 use ya_service_bus::{connection, ResponseChunk};
 
 const BAST_TOPIC: &str = "bcastecho";
@@ -94,7 +94,8 @@ impl CallRequestHandler for DebugHandler {
         );
     }
 
-    fn on_disconnect(&mut self) {
+    fn on_disconnect(&mut self, reason: DisconnectReason) {
+        eprintln!("disconnected: {:?}", reason);
         if let Some(tx) = self.0.take() {
             let _ = tx.send(());
         }
@@ -119,25 +120,27 @@ fn main() -> Result<(), Box<dyn Error>> {
         );
         match args {
             Args::EventListener { time } => {
-                connection.subscribe(BAST_TOPIC).await?;
+                let subscription = connection.subscribe(BAST_TOPIC).await?;
                 delay_for(time).await;
-                connection.unsubscribe(BAST_TOPIC).await?;
+                subscription.unsubscribe().await?;
                 Ok(())
             }
             Args::Server {
                 subscribe, time, ..
             } => {
                 connection.bind(SERVICE_ADDR).await.expect("bind echo");
-                if subscribe {
-                    connection.subscribe(BAST_TOPIC).await?;
-                }
+                let subscription = if subscribe {
+                    Some(connection.subscribe(BAST_TOPIC).await?)
+                } else {
+                    None
+                };
 
                 let w = delay_for(time).fuse();
                 futures::pin_mut!(w, rx);
                 future::select(w, rx).await;
 
-                if subscribe {
-                    connection.unsubscribe(BAST_TOPIC).await?;
+                if let Some(subscription) = subscription {
+                    subscription.unsubscribe().await?;
                 }
                 connection.unbind(SERVICE_ADDR).await?;
 
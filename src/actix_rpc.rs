@@ -1,5 +1,7 @@
 /// Using GSB with actix 0.9
-use crate::{RpcRawCall, RpcStreamCall, RpcStreamMessage};
+use crate::{
+    BroadcastMessage, RpcRawCall, RpcRawStreamCall, RpcStreamCall, RpcStreamMessage, StreamReply,
+};
 use actix::prelude::*;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
@@ -10,27 +12,40 @@ use crate::{RpcEnvelope, RpcMessage};
 
 use super::error::Error as BusError;
 use super::Handle;
-use futures::Future;
+use futures::{future, Future, Stream, TryStreamExt};
 
 pub fn bind<M: RpcMessage>(addr: &str, actor: Recipient<RpcEnvelope<M>>) -> Handle
 where
     <RpcEnvelope<M> as Message>::Result: Serialize + DeserializeOwned + Sync + Send,
 {
-    router().lock().unwrap().bind_actor(addr, actor);
-    Handle { _inner: {} }
+    router().lock().unwrap().bind_actor(addr, actor)
 }
 
 pub fn bind_raw(addr: &str, actor: Recipient<RpcRawCall>) -> Handle {
-    router().lock().unwrap().bind_raw(addr, actor);
-    Handle { _inner: {} }
+    router().lock().unwrap().bind_raw(addr, actor)
+}
+
+/// Binds actor receiving raw streaming calls, reply chunks are forwarded
+/// to the caller as they are.
+pub fn bind_raw_stream(addr: &str, actor: Recipient<RpcRawStreamCall>) -> Handle {
+    router().lock().unwrap().bind_raw_stream(addr, actor)
 }
 
 pub fn binds<M: RpcStreamMessage>(addr: &str, actor: Recipient<RpcStreamCall<M>>) -> Handle
 where
     Result<M::Item, M::Error>: Serialize + DeserializeOwned + Sync + Send,
 {
-    router().lock().unwrap().bind_stream_actor(addr, actor);
-    Handle { _inner: {} }
+    router().lock().unwrap().bind_stream_actor(addr, actor)
+}
+
+/// Subscribes actor to broadcasts published on `topic` or its subtopics.
+pub fn subscribe_local(topic: &str, actor: Recipient<BroadcastMessage>) {
+    router().lock().unwrap().subscribe_local(topic, actor)
+}
+
+/// Publishes broadcast to local subscribers, returns number of receivers.
+pub fn publish_local(caller: &str, topic: &str, body: impl Into<bytes::Bytes>) -> usize {
+    router().lock().unwrap().publish_local(caller, topic, body)
 }
 
 pub fn service(addr: &str) -> Endpoint {
@@ -60,7 +75,7 @@ impl Endpoint {
         msg: M,
     ) -> impl Future<Output = Result<<RpcEnvelope<M> as Message>::Result, BusError>> + 'static {
         let mut b = self.router.lock().unwrap();
-        b.forward(self.addr.as_ref(), RpcEnvelope::with_caller(caller, msg))
+        b.forward_from(self.addr.as_ref(), &caller.to_string(), msg)
     }
 
     pub fn push<M: RpcMessage + Serialize + DeserializeOwned + Sync + Send + Unpin>(
@@ -77,14 +92,35 @@ impl Endpoint {
         msg: M,
     ) -> impl Future<Output = Result<(), BusError>> + 'static {
         let mut b = self.router.lock().unwrap();
-        b.push(self.addr.as_ref(), RpcEnvelope::with_caller(caller, msg))
+        b.push_from(self.addr.as_ref(), &caller.to_string(), msg)
     }
 
     pub fn call_stream<M: RpcStreamMessage>(
         &self,
-        // TODO: add caller
         msg: M,
     ) -> impl Stream<Item = Result<Result<M::Item, M::Error>, BusError>> {
+        self.call_stream_items(msg)
+            .try_filter_map(|item| future::ok(item.into_item()))
+    }
+
+    pub fn call_stream_as<M: RpcStreamMessage>(
+        &self,
+        caller: &str,
+        msg: M,
+    ) -> impl Stream<Item = Result<Result<M::Item, M::Error>, BusError>> {
+        self.router
+            .lock()
+            .unwrap()
+            .streaming_forward_from(&self.addr, caller, msg)
+            .try_filter_map(|item| future::ok(item.into_item()))
+    }
+
+    /// Like `call_stream`, but reports graceful end of the response as
+    /// `StreamItem::Complete`.
+    pub fn call_stream_items<M: RpcStreamMessage>(
+        &self,
+        msg: M,
+    ) -> impl Stream<Item = Result<StreamReply<M>, BusError>> {
         self.router
             .lock()
             .unwrap()
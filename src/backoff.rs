@@ -0,0 +1,145 @@
+//! Delays between repeated attempts, like reconnecting to the router.
+use std::time::Duration;
+
+/// Decides how long to wait before next attempt.
+pub trait Backoff {
+    /// Delay before retry number `attempt`, counted from 0. `None` gives up.
+    fn next_delay(&mut self, attempt: u32) -> Option<Duration>;
+}
+
+impl<B: Backoff + ?Sized> Backoff for Box<B> {
+    fn next_delay(&mut self, attempt: u32) -> Option<Duration> {
+        (**self).next_delay(attempt)
+    }
+}
+
+/// Retries immediately.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoBackoff;
+
+impl Backoff for NoBackoff {
+    fn next_delay(&mut self, _attempt: u32) -> Option<Duration> {
+        Some(Duration::ZERO)
+    }
+}
+
+/// Waits the same `delay` before every attempt.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct FixedBackoff {
+    pub delay: Duration,
+    /// Number of attempts after which it gives up, `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl FixedBackoff {
+    pub fn new(delay: Duration) -> Self {
+        FixedBackoff {
+            delay,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff for FixedBackoff {
+    fn next_delay(&mut self, attempt: u32) -> Option<Duration> {
+        match self.max_attempts {
+            Some(max) if attempt >= max => None,
+            _ => Some(self.delay),
+        }
+    }
+}
+
+/// Multiplies the delay by `multiplier` after every attempt, up to `max`.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ExponentialBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+    /// Number of attempts after which it gives up, `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl ExponentialBackoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        ExponentialBackoff {
+            initial,
+            max,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        ExponentialBackoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff for ExponentialBackoff {
+    fn next_delay(&mut self, attempt: u32) -> Option<Duration> {
+        if let Some(max) = self.max_attempts {
+            if attempt >= max {
+                return None;
+            }
+        }
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(attempt.min(i32::MAX as u32) as i32);
+        let delay = self.initial.as_secs_f64() * factor;
+        Some(if delay < self.max.as_secs_f64() {
+            Duration::from_secs_f64(delay)
+        } else {
+            self.max
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_backoff() {
+        let mut backoff = ExponentialBackoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(500),
+            multiplier: 2.0,
+            max_attempts: Some(5),
+        };
+        let delays: Vec<_> = (0..6).map(|attempt| backoff.next_delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(400)),
+                Some(Duration::from_millis(500)),
+                Some(Duration::from_millis(500)),
+                None,
+            ]
+        );
+        assert_eq!(
+            ExponentialBackoff::default().next_delay(u32::MAX),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_fixed_backoff() {
+        let mut backoff = FixedBackoff {
+            delay: Duration::from_secs(1),
+            max_attempts: Some(1),
+        };
+        assert_eq!(backoff.next_delay(0), Some(Duration::from_secs(1)));
+        assert_eq!(backoff.next_delay(1), None);
+        assert_eq!(NoBackoff.next_delay(7), Some(Duration::ZERO));
+    }
+}
@@ -0,0 +1,61 @@
+//! Synchronous facade for code running outside of async runtime.
+//!
+//! Calls are executed on a dedicated background thread running its own
+//! actix system, started on first use and shared by all callers.
+use futures::channel::{mpsc, oneshot};
+use futures::StreamExt;
+
+use crate::error::Error;
+
+struct Job {
+    addr: String,
+    caller: String,
+    body: Vec<u8>,
+    reply: oneshot::Sender<Result<Vec<u8>, Error>>,
+}
+
+lazy_static::lazy_static! {
+    static ref WORKER: mpsc::UnboundedSender<Job> = spawn_worker();
+}
+
+fn spawn_worker() -> mpsc::UnboundedSender<Job> {
+    let (tx, mut rx) = mpsc::unbounded::<Job>();
+    std::thread::Builder::new()
+        .name("gsb-blocking".into())
+        .spawn(move || {
+            actix::System::new().block_on(async move {
+                while let Some(job) = rx.next().await {
+                    actix::spawn(async move {
+                        let result = crate::untyped::send(&job.addr, &job.caller, &job.body).await;
+                        let _ = job.reply.send(result);
+                    });
+                }
+            })
+        })
+        .expect("failed to start gsb-blocking thread");
+    tx
+}
+
+/// Calls `addr` and blocks current thread until the reply arrives.
+///
+/// Fails without calling when used inside async runtime, where blocking
+/// would stall other tasks.
+pub fn call_blocking(addr: &str, caller: &str, body: &[u8]) -> Result<Vec<u8>, Error> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(Error::GsbFailure(
+            "call_blocking used inside async runtime, use untyped::send instead".into(),
+        ));
+    }
+
+    let (reply, rx) = oneshot::channel();
+    WORKER
+        .unbounded_send(Job {
+            addr: addr.to_string(),
+            caller: caller.to_string(),
+            body: body.to_vec(),
+            reply,
+        })
+        .map_err(|_| Error::Closed(addr.to_string()))?;
+
+    futures::executor::block_on(rx).map_err(|_| Error::Cancelled)?
+}
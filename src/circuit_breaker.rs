@@ -0,0 +1,200 @@
+//! Failing fast on calls to remote addresses that keep failing.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// When calls to an address are cut off, see `Router::set_circuit_breaker`.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures that open the circuit.
+    pub failure_threshold: u32,
+    /// Failures further apart than this are not counted together.
+    pub window: Duration,
+    /// How long open circuit fails calls before letting a trial call through.
+    pub cooldown: Duration,
+}
+
+impl CircuitBreakerConfig {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreakerConfig {
+            failure_threshold,
+            cooldown,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            window: Duration::from_secs(30),
+            cooldown: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through.
+    Closed,
+    /// Calls fail with `GsbFailure("circuit open")` until cooldown passes.
+    Open,
+    /// Single trial call goes through, its result closes or reopens the
+    /// circuit.
+    HalfOpen,
+}
+
+struct Circuit {
+    failures: u32,
+    first_failure: Instant,
+    // Since when the circuit is open, or when the last trial call started.
+    opened_at: Option<Instant>,
+    trial: bool,
+}
+
+/// Circuits of addresses that failed recently.
+pub(crate) struct CircuitBreakers {
+    config: CircuitBreakerConfig,
+    circuits: HashMap<String, Circuit>,
+}
+
+impl CircuitBreakers {
+    pub(crate) fn new(config: CircuitBreakerConfig) -> Self {
+        CircuitBreakers {
+            config,
+            circuits: Default::default(),
+        }
+    }
+
+    /// Fails when calls to `addr` are cut off. Call let through on half-open
+    /// circuit becomes its trial.
+    pub(crate) fn try_acquire(&mut self, addr: &str, now: Instant) -> Result<(), Error> {
+        let cooldown = self.config.cooldown;
+        let circuit = match self.circuits.get_mut(addr) {
+            Some(circuit) => circuit,
+            None => return Ok(()),
+        };
+        match circuit.opened_at {
+            None => Ok(()),
+            Some(opened_at) if now.saturating_duration_since(opened_at) < cooldown => {
+                Err(Error::GsbFailure("circuit open".to_string()))
+            }
+            // trial lost without result is replaced after another cooldown
+            Some(_) => {
+                log::debug!("circuit of {} half-open, trying", addr);
+                circuit.opened_at = Some(now);
+                circuit.trial = true;
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn record(&mut self, addr: &str, result: &Result<Vec<u8>, Error>, now: Instant) {
+        match result {
+            Err(e) if is_failure(e) => self.record_failure(addr, now),
+            _ => {
+                if self.circuits.remove(addr).is_some() {
+                    log::debug!("circuit of {} closed", addr);
+                }
+            }
+        }
+    }
+
+    fn record_failure(&mut self, addr: &str, now: Instant) {
+        let config = &self.config;
+        let circuit = self
+            .circuits
+            .entry(addr.to_string())
+            .or_insert_with(|| Circuit {
+                failures: 0,
+                first_failure: now,
+                opened_at: None,
+                trial: false,
+            });
+        if circuit.trial {
+            circuit.trial = false;
+            circuit.opened_at = Some(now);
+            log::warn!("trial call to {} failed, circuit open again", addr);
+            return;
+        }
+        if circuit.opened_at.is_some() {
+            return;
+        }
+        if now.saturating_duration_since(circuit.first_failure) > config.window {
+            circuit.failures = 0;
+            circuit.first_failure = now;
+        }
+        circuit.failures += 1;
+        if circuit.failures >= config.failure_threshold.max(1) {
+            log::warn!("{} failed {} times, circuit open", addr, circuit.failures);
+            circuit.opened_at = Some(now);
+        }
+    }
+
+    pub(crate) fn state(&self, addr: &str, now: Instant) -> CircuitState {
+        match self.circuits.get(addr) {
+            Some(Circuit { trial: true, .. }) => CircuitState::HalfOpen,
+            Some(Circuit {
+                opened_at: Some(opened_at),
+                ..
+            }) => {
+                if now.saturating_duration_since(*opened_at) < self.config.cooldown {
+                    CircuitState::Open
+                } else {
+                    CircuitState::HalfOpen
+                }
+            }
+            _ => CircuitState::Closed,
+        }
+    }
+
+    pub(crate) fn reset(&mut self, addr: &str) {
+        let _ = self.circuits.remove(addr);
+    }
+}
+
+// Failures of the service or the way to it, not rejections of the request.
+fn is_failure(e: &Error) -> bool {
+    e.is_transient() || matches!(e, Error::GsbFailure(_) | Error::RemoteError(..))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let mut breakers = CircuitBreakers::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(5),
+        });
+        let failure = Err(Error::Timeout("/svc".into()));
+        let now = Instant::now();
+
+        // rejection means the service answered, it resets the count
+        breakers.record("/svc", &failure, now);
+        breakers.record("/svc", &Err(Error::GsbBadRequest("no".into())), now);
+        breakers.record("/svc", &failure, now);
+        assert_eq!(breakers.state("/svc", now), CircuitState::Closed);
+        breakers.record("/svc", &failure, now);
+        assert_eq!(breakers.state("/svc", now), CircuitState::Open);
+        assert!(breakers.try_acquire("/svc", now).is_err());
+        assert!(breakers.try_acquire("/other", now).is_ok());
+
+        let later = now + Duration::from_secs(5);
+        assert!(breakers.try_acquire("/svc", later).is_ok());
+        assert_eq!(breakers.state("/svc", later), CircuitState::HalfOpen);
+        assert!(breakers.try_acquire("/svc", later).is_err());
+        breakers.record("/svc", &failure, later);
+        assert_eq!(breakers.state("/svc", later), CircuitState::Open);
+
+        let later = later + Duration::from_secs(5);
+        assert!(breakers.try_acquire("/svc", later).is_ok());
+        breakers.record("/svc", &Ok(Vec::new()), later);
+        assert_eq!(breakers.state("/svc", later), CircuitState::Closed);
+    }
+}
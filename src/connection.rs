@@ -1,27 +1,39 @@
 use actix::prelude::*;
+use bytes::Bytes;
 use futures::{
     channel::{mpsc, oneshot},
+    future::LocalBoxFuture,
     prelude::*,
-    stream::SplitSink,
+    stream::{self, LocalBoxStream, SplitSink},
 };
 use semver::Version;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, VecDeque},
     convert::TryInto,
     pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
-use ya_sb_proto::codec::{GsbMessage, ProtocolError};
+pub use ya_sb_proto::codec::CodecConfig;
+use ya_sb_proto::codec::{GsbMessage, GsbMessageCodec, ProtocolError};
 use ya_sb_proto::{
-    BroadcastReplyCode, BroadcastRequest, CallReply, CallReplyCode, CallReplyType, CallRequest,
-    RegisterReplyCode, RegisterRequest, SubscribeReplyCode, SubscribeRequest, UnregisterReplyCode,
-    UnregisterRequest, UnsubscribeReplyCode, UnsubscribeRequest,
+    BroadcastReplyCode, BroadcastRequest, CallCancel, CallDeadline, CallReply, CallReplyCode,
+    CallReplyType, CallRequest, HelloRejectCode, RegisterReplyCode, RegisterRequest, StreamCredit,
+    SubscribeReplyCode, SubscribeRequest, UnregisterReplyCode, UnregisterRequest,
+    UnsubscribeReplyCode, UnsubscribeRequest,
 };
 use ya_sb_util::writer::*;
 
+use crate::backoff::{Backoff, ExponentialBackoff};
 use crate::local_router::router;
+use crate::spawner;
+use crate::timeout::now;
 use crate::Error;
-use crate::{ResponseChunk, RpcRawCall, RpcRawStreamCall};
+use crate::{Headers, ResponseChunk, RpcRawCall, RpcRawStreamCall, REQUEST_ID_HEADER};
 
 fn gen_id() -> u64 {
     use rand::Rng;
@@ -37,6 +49,8 @@ pub struct ClientInfo {
     pub name: String,
     pub version: Option<Version>,
     pub instance_id: Vec<u8>,
+    /// Token sent to the router in `Hello` for authentication.
+    pub auth_token: Option<Vec<u8>>,
 }
 
 impl ClientInfo {
@@ -45,6 +59,308 @@ impl ClientInfo {
             name: name.to_string(),
             version: Some(Version::parse(env!("CARGO_PKG_VERSION")).unwrap()),
             instance_id: uuid::Uuid::new_v4().as_bytes().to_vec(),
+            auth_token: None,
+        }
+    }
+
+    pub fn with_auth_token(mut self, auth_token: impl Into<Vec<u8>>) -> Self {
+        self.auth_token = Some(auth_token.into());
+        self
+    }
+}
+
+/// Reason why connection to the bus was closed.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum DisconnectReason {
+    /// Peer closed the transport.
+    CleanEof,
+    /// Received data could not be decoded or violated the protocol.
+    ProtocolError(String),
+    /// Writing to the transport failed.
+    WriteError(String),
+    /// Peer replied with an unknown reply code.
+    InvalidReplyCode(i32),
+    /// Connection was stopped from our side.
+    LocalStop,
+    /// Router refused the `Hello` handshake.
+    AuthRejected(String),
+    /// Router has live session with the same `instance_id`.
+    InstanceConflict,
+    /// Nothing was received from the router within `ping_timeout`.
+    HeartbeatTimeout,
+    /// Written frames did not reach the transport within
+    /// `write_stall_timeout`.
+    WriteStalled,
+}
+
+/// Change of what the connection advertises on the router, reported once
+/// the router confirms it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectionEvent {
+    Registered(String),
+    Unregistered(String),
+    Subscribed(String),
+    Unsubscribed(String),
+    BroadcastSent(String),
+}
+
+/// Reply chunks buffered by `ConnectionRef::call_streaming`.
+pub const DEFAULT_REPLY_CAPACITY: usize = 16;
+
+/// Longest wait for queued writes when connection shuts down on signal.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Snapshot of connection counters.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct ConnectionStats {
+    /// Outgoing calls waiting for reply.
+    pub in_flight_calls: usize,
+    pub pending_register: usize,
+    pub pending_unregister: usize,
+    pub pending_subscribe: usize,
+    pub pending_unsubscribe: usize,
+    pub pending_broadcast: usize,
+    /// Replies received without matching request.
+    pub unmatched_replies: usize,
+    /// Commands failed because reply did not arrive within `reply_timeout`.
+    pub reply_timeouts: u64,
+    /// Incoming calls whose handler got ahead of the transport by more than
+    /// `ConnectionConfig::reply_lag_warn` chunks.
+    pub lagging_replies: u64,
+    /// Bytes queued for sending since connect.
+    pub bytes_written: u64,
+    /// Bytes received since connect.
+    pub bytes_read: u64,
+    /// Frames queued for sending since connect.
+    pub messages_written: u64,
+    /// Frames received since connect.
+    pub messages_read: u64,
+    /// Broadcasts confirmed by the router, per topic.
+    pub broadcasts: HashMap<String, BroadcastStats>,
+}
+
+/// Outgoing call waiting for reply, see `ConnectionRef::in_flight`.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct InFlightInfo {
+    pub request_id: String,
+    pub address: String,
+    /// Time since the call was sent.
+    pub age: Duration,
+    pub kind: CallKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CallKind {
+    /// Call answered with single reply.
+    Unary,
+    /// Call answered with stream of replies.
+    Streaming,
+}
+
+/// Copy of a message sent or received on the connection, see
+/// `ConnectionBuilder::with_tap`.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct TappedMessage {
+    pub direction: TapDirection,
+    /// Message type, e.g. `"CallRequest"`.
+    pub kind: &'static str,
+    pub request_id: Option<String>,
+    /// Size of the frame on the wire.
+    pub size: usize,
+    /// Payload of calls, replies and broadcasts, with `tap_bodies` set.
+    pub body: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TapDirection {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Clone, Debug)]
+struct Tap {
+    sender: mpsc::Sender<TappedMessage>,
+    bodies: bool,
+}
+
+impl Tap {
+    fn record(&mut self, direction: TapDirection, msg: &GsbMessage) {
+        let tapped = self.copy(direction, msg);
+        self.send(tapped);
+    }
+
+    fn copy(&self, direction: TapDirection, msg: &GsbMessage) -> TappedMessage {
+        TappedMessage {
+            direction,
+            kind: msg.kind(),
+            request_id: msg.request_id().map(ToString::to_string),
+            size: msg.frame_len(),
+            body: msg.data().filter(|_| self.bodies).map(<[u8]>::to_vec),
+        }
+    }
+
+    // Never waits for the observer, copies it cannot take are dropped.
+    fn send(&mut self, tapped: TappedMessage) {
+        let kind = tapped.kind;
+        if let Err(e) = self.sender.try_send(tapped) {
+            log::trace!("tapped {} dropped: {}", kind, e);
+        }
+    }
+}
+
+/// Counters of broadcasts sent to single topic.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct BroadcastStats {
+    pub sent: u64,
+    /// Subscribers the broadcasts were handed to, counted by the router.
+    /// Stays zero with routers not reporting it.
+    pub receivers: u64,
+}
+
+/// Router identity announced in its `Hello`.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ServerInfo {
+    pub name: String,
+    pub version: String,
+    pub instance_id: Vec<u8>,
+    pub capabilities: Vec<String>,
+    /// Protocol version used on this connection, `0` for routers predating
+    /// version announcement.
+    pub proto_version: u32,
+}
+
+impl From<&ya_sb_proto::Hello> for ServerInfo {
+    fn from(hello: &ya_sb_proto::Hello) -> Self {
+        ServerInfo {
+            name: hello.name.clone(),
+            version: hello.version.clone(),
+            instance_id: hello.instance_id.clone(),
+            capabilities: hello.capabilities.clone(),
+            proto_version: hello.negotiated_proto_version(),
+        }
+    }
+}
+
+/// Connection behaviour settings.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ConnectionConfig {
+    /// Treat repeated `Hello` from the router as protocol error.
+    pub strict_hello: bool,
+    /// Treat messages the connection does not expect from the router as
+    /// protocol error, by default they are logged and ignored. Messages of
+    /// unknown types are skipped by the codec of transports opened with
+    /// `codec_config`, e.g. by `ConnectionBuilder`; codecs built by hand
+    /// follow their own `CodecConfig::skip_unknown`.
+    pub strict_messages: bool,
+    /// Number of tolerated replies without matching request, connection is
+    /// closed when it is exceeded.
+    pub max_unmatched_replies: usize,
+    /// How long register, subscribe and broadcast commands wait for the router
+    /// to reply. `None` waits forever. Replies arriving after the timeout
+    /// are dropped.
+    pub reply_timeout: Option<Duration>,
+    /// Call bodies above this size are sent in several frames, bounding the
+    /// frame size. Takes effect only when the router announces
+    /// `CAP_CHUNKED_BODY`, the router joins the frames again for callees
+    /// not announcing it.
+    ///
+    /// Continuation frames are `CallRequest`s with the call's `request_id`,
+    /// all but the last marked with `has_more`, rather than a side request
+    /// streaming `ResponseChunk::Part`s. The router forwards them like the
+    /// call itself, and cancelling the call drops the rest of its body.
+    pub max_inline_body: Option<usize>,
+    /// Limit of incoming calls per caller, over-limit calls fail with
+    /// `ServiceFailure` and pushes are dropped.
+    pub rate_limit: Option<RateLimit>,
+    /// Number of chunks of streaming reply the callee may send before the
+    /// caller consumes them. Requires router announcing `CAP_STREAM_CREDIT`,
+    /// `None` leaves streams without flow control.
+    pub stream_window: Option<u32>,
+    /// Deadline of outgoing unary calls which don't carry their own.
+    /// Streaming calls are not limited, they may run for long.
+    pub call_timeout: Option<Duration>,
+    /// Number of outgoing calls awaiting reply, calls above it fail at once.
+    pub max_in_flight_calls: Option<usize>,
+    /// How long the connection may stay idle before the router is pinged.
+    /// `None` relies on router's pings only.
+    pub ping_interval: Option<Duration>,
+    /// Connection with nothing received for this long is closed, used
+    /// together with `ping_interval`.
+    pub ping_timeout: Duration,
+    /// Limit of reply bytes received for single outgoing call, streaming
+    /// ones included. Call exceeding it fails with `GsbFailure`.
+    pub max_reply_bytes: Option<usize>,
+    /// Connection whose written frames wait this long with none of them
+    /// taken by the transport is considered dead, e.g. half-open TCP
+    /// connection with full buffers. Progress is checked every half of the
+    /// timeout, slow transport has to take a frame at least that often.
+    pub write_stall_timeout: Option<Duration>,
+    /// Observer getting copy of every message sent and received.
+    pub tap: Option<mpsc::Sender<TappedMessage>>,
+    /// Include payloads in copies sent to `tap`.
+    pub tap_bodies: bool,
+    /// Number of chunks of streaming reply the caller has not taken yet,
+    /// above which the call fails with `GsbFailure` and is cancelled. Set
+    /// `stream_window` to make the callee slow down instead.
+    pub max_queued_reply_chunks: Option<usize>,
+    /// Largest body of incoming call sent in several frames, larger calls
+    /// are rejected. `None` accepts bodies of any size.
+    pub max_call_body: Option<usize>,
+    /// Number of incoming calls sent in several frames which may wait for
+    /// their last frame at once, further ones are rejected.
+    pub max_partial_calls: usize,
+    /// How long incoming call sent in several frames may wait for its last
+    /// frame before it is rejected. Checked every half of the timeout.
+    pub partial_call_timeout: Duration,
+    /// Number of chunks of single reply waiting to be sent, above which its
+    /// handler is reported as outpacing the transport, e.g. because the
+    /// caller reads slowly. `None` disables the check.
+    pub reply_lag_warn: Option<usize>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            strict_hello: false,
+            strict_messages: false,
+            max_unmatched_replies: 10,
+            reply_timeout: None,
+            max_inline_body: None,
+            rate_limit: None,
+            stream_window: None,
+            call_timeout: None,
+            max_in_flight_calls: None,
+            ping_interval: None,
+            ping_timeout: Duration::from_secs(60),
+            max_reply_bytes: None,
+            write_stall_timeout: None,
+            tap: None,
+            tap_bodies: false,
+            max_queued_reply_chunks: Some(1024),
+            max_call_body: Some(64 * 1024 * 1024),
+            max_partial_calls: 16,
+            partial_call_timeout: Duration::from_secs(60),
+            reply_lag_warn: Some(1024),
+        }
+    }
+}
+
+impl ConnectionConfig {
+    /// Codec settings for transports of this connection, skipping messages of
+    /// unknown types unless `strict_messages` is set.
+    pub fn codec_config(&self) -> CodecConfig {
+        CodecConfig {
+            skip_unknown: !self.strict_messages,
+            ..Default::default()
         }
     }
 }
@@ -52,6 +368,10 @@ impl ClientInfo {
 pub trait CallRequestHandler {
     type Reply: Stream<Item = Result<ResponseChunk, Error>> + Unpin;
 
+    /// Handles incoming call.
+    ///
+    /// Failing with `Error::GsbBadRequest` replies with `CallReplyBadRequest`,
+    /// any other error is reported to the caller as `ServiceFailure`.
     fn do_call(
         &mut self,
         request_id: String,
@@ -61,6 +381,37 @@ pub trait CallRequestHandler {
         no_reply: bool,
     ) -> Self::Reply;
 
+    /// Handles incoming call that has to be answered before `deadline`.
+    ///
+    /// Handlers forwarding calls further should pass the deadline along,
+    /// default implementation ignores it.
+    fn do_call_with_deadline(
+        &mut self,
+        request_id: String,
+        caller: String,
+        address: String,
+        data: Vec<u8>,
+        no_reply: bool,
+        _deadline: Option<Instant>,
+    ) -> Self::Reply {
+        self.do_call(request_id, caller, address, data, no_reply)
+    }
+
+    /// Handles incoming call together with its headers.
+    ///
+    /// Default implementation drops the headers and calls
+    /// `do_call_with_deadline`.
+    fn handle_call(&mut self, call: CallContext) -> Self::Reply {
+        self.do_call_with_deadline(
+            call.request_id,
+            call.caller,
+            call.address,
+            call.data,
+            call.no_reply,
+            call.deadline,
+        )
+    }
+
     fn handle_event(&mut self, caller: String, topic: String, data: Vec<u8>) {
         log::warn!("unhandled gsb event from: {}, to: {}", caller, topic,);
         log::trace!(
@@ -69,7 +420,17 @@ pub trait CallRequestHandler {
         )
     }
 
-    fn on_disconnect(&mut self) {}
+    fn on_disconnect(&mut self, _reason: DisconnectReason) {}
+
+    /// Called when the router sends another `Hello` on established connection.
+    fn on_peer_reannounce(&mut self, _old: &ya_sb_proto::Hello, _new: &ya_sb_proto::Hello) {}
+
+    /// Answers liveness probe of `address`, see `Router::ping_service`.
+    ///
+    /// Defaults to `true`, for handlers serving every address routed to them.
+    fn serves(&mut self, _address: &str) -> bool {
+        true
+    }
 }
 
 impl ResponseChunk {
@@ -80,23 +441,32 @@ impl ResponseChunk {
             ResponseChunk::Part(_) => CallReplyType::Partial,
         }
     }
-
-    #[inline]
-    fn into_vec(self) -> Vec<u8> {
-        match self {
-            ResponseChunk::Full(v) => v,
-            ResponseChunk::Part(v) => v,
-        }
-    }
 }
 
+/// Serves calls coming from the router with handlers bound in the local
+/// router.
+///
+/// Connection used by the local router itself is re-established and its
+/// bindings replayed after disconnect, see `RemoteRouter`.
 #[derive(Default)]
 pub struct LocalRouterHandler {
-    disconnect_h: Option<Box<dyn FnOnce()>>,
+    disconnect_h: Option<Box<dyn FnMut(DisconnectReason)>>,
 }
 
 impl LocalRouterHandler {
+    /// Calls `disconnect_fn` when the connection is lost.
     pub fn new<F: FnOnce() + 'static>(disconnect_fn: F) -> Self {
+        let mut disconnect_fn = Some(disconnect_fn);
+        Self::with_disconnect_handler(move |_| {
+            if let Some(f) = disconnect_fn.take() {
+                f()
+            }
+        })
+    }
+
+    /// Calls `disconnect_fn` with reason every time connection using this
+    /// handler is lost, so it may drive reconnects.
+    pub fn with_disconnect_handler<F: FnMut(DisconnectReason) + 'static>(disconnect_fn: F) -> Self {
         Self {
             disconnect_h: Some(Box::new(disconnect_fn)),
         }
@@ -108,24 +478,75 @@ impl CallRequestHandler for LocalRouterHandler {
 
     fn do_call(
         &mut self,
-        _request_id: String,
+        request_id: String,
+        caller: String,
+        address: String,
+        data: Vec<u8>,
+        no_reply: bool,
+    ) -> Self::Reply {
+        self.do_call_with_deadline(request_id, caller, address, data, no_reply, None)
+    }
+
+    fn do_call_with_deadline(
+        &mut self,
+        request_id: String,
         caller: String,
         address: String,
         data: Vec<u8>,
         no_reply: bool,
+        deadline: Option<Instant>,
     ) -> Self::Reply {
+        self.handle_call(CallContext {
+            request_id,
+            caller,
+            address,
+            data,
+            no_reply,
+            deadline,
+            headers: Headers::new(),
+            trailers: Default::default(),
+        })
+    }
+
+    fn handle_call(&mut self, mut call: CallContext) -> Self::Reply {
+        let _ = call.headers.insert(
+            REQUEST_ID_HEADER.to_string(),
+            call.request_id.clone().into_bytes(),
+        );
         router()
             .lock()
             .unwrap()
-            .forward_bytes_local(&address, &caller, data.as_ref(), no_reply)
+            .forward_bytes_local(
+                &call.address,
+                &call.caller,
+                call.data.into(),
+                call.no_reply,
+                call.deadline,
+                call.headers,
+            )
             .boxed_local()
     }
 
-    fn on_disconnect(&mut self) {
-        if let Some(f) = self.disconnect_h.take() {
-            f()
+    fn handle_event(&mut self, caller: String, topic: String, data: Vec<u8>) {
+        let _ = router()
+            .lock()
+            .unwrap()
+            .publish_local(&caller, &topic, data);
+    }
+
+    fn on_disconnect(&mut self, reason: DisconnectReason) {
+        log::debug!("local router disconnected: {:?}", reason);
+        if let Some(f) = self.disconnect_h.as_mut() {
+            f(reason)
         };
     }
+
+    fn serves(&mut self, address: &str) -> bool {
+        matches!(
+            router().lock().unwrap().resolve(address),
+            crate::Route::Local(_)
+        )
+    }
 }
 
 impl<
@@ -171,1007 +592,4460 @@ impl<
     }
 }
 
-type TransportWriter<W> = SinkWrite<GsbMessage, W>;
-type ReplyQueue = VecDeque<oneshot::Sender<Result<(), Error>>>;
-
-struct Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin,
-    H: CallRequestHandler,
-{
-    writer: TransportWriter<W>,
-    register_reply: ReplyQueue,
-    unregister_reply: ReplyQueue,
-    subscribe_reply: ReplyQueue,
-    unsubscribe_reply: ReplyQueue,
-    call_reply: HashMap<String, mpsc::Sender<Result<ResponseChunk, Error>>>,
-    broadcast_reply: ReplyQueue,
-    handler: H,
-    client_info: ClientInfo,
-    server_info: Option<ya_sb_proto::Hello>,
+/// Incoming call passed to `AsyncCallHandler`.
+#[derive(Clone, Debug)]
+pub struct CallContext {
+    pub request_id: String,
+    pub caller: String,
+    pub address: String,
+    pub data: Vec<u8>,
+    pub no_reply: bool,
+    /// Time by which the caller expects the reply.
+    pub deadline: Option<Instant>,
+    pub headers: Headers,
+    /// Metadata sent to the caller with the last reply.
+    pub trailers: Trailers,
 }
 
-impl<W, H> Unpin for Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
-{
-}
+/// Metadata sent with the last successful reply of a call, received with
+/// `ConnectionRef::call_with_trailers`.
+#[derive(Clone, Debug, Default)]
+pub struct Trailers(Arc<Mutex<Headers>>);
 
-fn handle_reply<Ctx: ActorContext, F: FnOnce() -> Result<(), Error>>(
-    cmd_type: &str,
-    queue: &mut ReplyQueue,
-    ctx: &mut Ctx,
-    reply_msg: F,
-) {
-    if let Some(r) = queue.pop_front() {
-        let _ = r.send(reply_msg());
-    } else {
-        log::error!("unmatched {} reply", cmd_type);
-        ctx.stop()
+impl Trailers {
+    pub fn insert(&self, name: impl Into<String>, value: impl Into<Vec<u8>>) {
+        let _ = self.0.lock().unwrap().insert(name.into(), value.into());
+    }
+
+    fn take(&self) -> HashMap<String, Vec<u8>> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+            .into_iter()
+            .collect()
     }
 }
 
-impl<W, H> EmptyBufferHandler for Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
-{
+/// Reply together with trailers set by the callee.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CallResult {
+    pub body: Vec<u8>,
+    pub trailers: Headers,
 }
 
-impl<W, H> Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
-{
-    fn new(client_info: ClientInfo, w: W, handler: H, ctx: &mut <Self as Actor>::Context) -> Self {
-        Connection {
-            writer: SinkWrite::new(w, ctx),
-            register_reply: Default::default(),
-            unregister_reply: Default::default(),
-            subscribe_reply: Default::default(),
-            unsubscribe_reply: Default::default(),
-            call_reply: Default::default(),
-            broadcast_reply: Default::default(),
-            handler,
-            client_info,
-            server_info: Default::default(),
-        }
-    }
+/// Reply of `ConnectionRef::call_with_meta`.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CallResponse {
+    /// Id the call was sent with, seen by the callee as `REQUEST_ID_HEADER`.
+    pub request_id: String,
+    pub body: Vec<u8>,
+}
 
-    fn handle_unregister_reply(
-        &mut self,
-        code: UnregisterReplyCode,
-        ctx: &mut <Self as Actor>::Context,
-    ) {
-        handle_reply(
-            "unregister",
-            &mut self.unregister_reply,
-            ctx,
-            || match code {
-                UnregisterReplyCode::UnregisteredOk => Ok(()),
-                UnregisterReplyCode::NotRegistered => {
-                    Err(Error::GsbBadRequest("unregister".to_string()))
-                }
-            },
-        )
-    }
+/// Future based alternative to `CallRequestHandler`.
+///
+/// Wrap implementation in `AsyncHandler` to pass it to `connect_with_handler`.
+///
+/// ## Example
+///
+/// ```no_run
+/// use futures::future::{FutureExt, LocalBoxFuture};
+/// use ya_service_bus::connection::{AsyncCallHandler, AsyncHandler, CallContext};
+/// use ya_service_bus::Error;
+///
+/// struct Echo;
+///
+/// impl AsyncCallHandler for Echo {
+///     fn handle(&mut self, req: CallContext) -> LocalBoxFuture<'static, Result<Vec<u8>, Error>> {
+///         async move { Ok(req.data) }.boxed_local()
+///     }
+/// }
+///
+/// let _handler = AsyncHandler(Echo);
+/// ```
+pub trait AsyncCallHandler {
+    /// Handles unary call.
+    fn handle(&mut self, req: CallContext) -> LocalBoxFuture<'static, Result<Vec<u8>, Error>>;
 
-    fn handle_broadcast_reply(
+    /// Handles call with streamed response, defaults to single reply from `handle`.
+    fn handle_stream(
         &mut self,
-        code: BroadcastReplyCode,
-        msg: String,
-        ctx: &mut <Self as Actor>::Context,
-    ) {
-        handle_reply("broadcast", &mut self.broadcast_reply, ctx, || match code {
-            BroadcastReplyCode::BroadcastOk => Ok(()),
-            BroadcastReplyCode::BroadcastBadRequest => Err(Error::GsbBadRequest(msg)),
-        })
+        req: CallContext,
+    ) -> LocalBoxStream<'static, Result<ResponseChunk, Error>> {
+        self.handle(req)
+            .map_ok(ResponseChunk::Full)
+            .into_stream()
+            .boxed_local()
     }
 
-    fn handle_register_reply(
-        &mut self,
-        code: RegisterReplyCode,
-        msg: String,
-        ctx: &mut <Self as Actor>::Context,
-    ) {
-        handle_reply("register", &mut self.register_reply, ctx, || match code {
-            RegisterReplyCode::RegisteredOk => Ok(()),
-            RegisterReplyCode::RegisterBadRequest => {
-                log::warn!("bad request: {}", msg);
-                Err(Error::GsbBadRequest(msg))
-            }
-            RegisterReplyCode::RegisterConflict => {
-                log::warn!("already registered: {}", msg);
-                Err(Error::GsbAlreadyRegistered(msg))
-            }
-        })
+    fn handle_event(&mut self, caller: String, topic: String, data: Vec<u8>) {
+        log::warn!("unhandled gsb event from: {}, to: {}", caller, topic);
+        log::trace!(
+            "unhandled gsb event data: {:?}",
+            String::from_utf8_lossy(data.as_ref())
+        )
     }
 
-    fn handle_subscribe_reply(
-        &mut self,
-        code: SubscribeReplyCode,
-        msg: String,
-        ctx: &mut <Self as Actor>::Context,
-    ) {
-        handle_reply("subscribe", &mut self.subscribe_reply, ctx, || match code {
-            SubscribeReplyCode::SubscribedOk => Ok(()),
-            SubscribeReplyCode::SubscribeBadRequest => {
-                log::warn!("bad request: {}", msg);
-                Err(Error::GsbBadRequest(msg))
-            }
-        })
-    }
+    fn on_disconnect(&mut self, _reason: DisconnectReason) {}
+}
 
-    fn handle_unsubscribe_reply(
+/// Adapts `AsyncCallHandler` to `CallRequestHandler`.
+pub struct AsyncHandler<H>(pub H);
+
+impl<H: AsyncCallHandler> CallRequestHandler for AsyncHandler<H> {
+    type Reply = LocalBoxStream<'static, Result<ResponseChunk, Error>>;
+
+    fn do_call(
         &mut self,
-        code: UnsubscribeReplyCode,
-        ctx: &mut <Self as Actor>::Context,
-    ) {
-        handle_reply(
-            "unsubscribe",
-            &mut self.unsubscribe_reply,
-            ctx,
-            || match code {
-                UnsubscribeReplyCode::UnsubscribedOk => Ok(()),
-                UnsubscribeReplyCode::NotSubscribed => {
-                    Err(Error::GsbBadRequest("unsubscribed".to_string()))
-                }
-            },
-        )
+        request_id: String,
+        caller: String,
+        address: String,
+        data: Vec<u8>,
+        no_reply: bool,
+    ) -> Self::Reply {
+        self.do_call_with_deadline(request_id, caller, address, data, no_reply, None)
     }
 
-    fn handle_call_request(
+    fn do_call_with_deadline(
         &mut self,
         request_id: String,
         caller: String,
         address: String,
         data: Vec<u8>,
-        ctx: &mut <Self as Actor>::Context,
-    ) {
-        log::trace!(
-            "handling rpc call from = {}, to = {}, request_id={}, ",
+        no_reply: bool,
+        deadline: Option<Instant>,
+    ) -> Self::Reply {
+        self.handle_call(CallContext {
+            request_id,
             caller,
             address,
-            request_id
-        );
-        let eos_request_id = request_id.clone();
-        let do_call = self
-            .handler
-            .do_call(request_id.clone(), caller, address, data, false)
-            .into_actor(self)
-            .fold(false, move |_got_eos, r, act: &mut Self, _ctx| {
-                let request_id = request_id.clone();
-                let (got_eos, reply) = match r {
-                    Ok(data) => {
-                        let code = CallReplyCode::CallReplyOk as i32;
-                        let reply_type = data.reply_type() as i32;
-                        (
-                            reply_type == 0,
-                            CallReply {
-                                request_id,
-                                code,
-                                reply_type,
-                                data: data.into_vec(),
-                            },
-                        )
-                    }
-                    Err(e) => {
-                        let code = CallReplyCode::ServiceFailure as i32;
-                        let reply_type = Default::default();
-                        let data = format!("{}", e).into_bytes();
-                        (
-                            true,
-                            CallReply {
-                                request_id,
-                                code,
-                                reply_type,
-                                data,
-                            },
-                        )
-                    }
-                };
-                // TODO: handle write error
-                let _ = act.writer.write(GsbMessage::CallReply(reply));
-                fut::ready(got_eos)
-            })
-            .then(|got_eos, act, _ctx| {
-                if !got_eos {
-                    let _ = act.writer.write(GsbMessage::CallReply(CallReply {
-                        request_id: eos_request_id,
-                        code: 0,
-                        reply_type: 0,
-                        data: Default::default(),
-                    }));
-                }
-                fut::ready(())
-            });
-        //do_call.spawn(ctx);
-        ctx.spawn(do_call);
+            data,
+            no_reply,
+            deadline,
+            headers: Headers::new(),
+            trailers: Default::default(),
+        })
     }
 
-    fn handle_push_request(
-        &mut self,
-        request_id: String,
-        caller: String,
-        address: String,
-        data: Vec<u8>,
-        ctx: &mut <Self as Actor>::Context,
-    ) {
-        log::trace!(
-            "handling push call from = {}, to = {}, request_id={}, ",
-            caller,
-            address,
-            request_id
-        );
+    fn handle_call(&mut self, call: CallContext) -> Self::Reply {
+        self.0.handle_stream(call)
+    }
 
-        self.handler
-            .do_call(request_id, caller, address, data, true)
-            .into_actor(self)
-            .fold((), move |_, _, _, _| fut::ready(()))
-            .spawn(ctx);
+    fn handle_event(&mut self, caller: String, topic: String, data: Vec<u8>) {
+        self.0.handle_event(caller, topic, data)
     }
 
-    fn handle_reply(
-        &mut self,
-        request_id: String,
-        code: i32,
-        reply_type: i32,
-        data: Vec<u8>,
-        ctx: &mut <Self as Actor>::Context,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        log::trace!(
-            "handling reply for request_id={}, code={}, reply_type={}",
-            request_id,
-            code,
-            reply_type
-        );
+    fn on_disconnect(&mut self, reason: DisconnectReason) {
+        self.0.on_disconnect(reason)
+    }
+}
 
-        let chunk = if reply_type == CallReplyType::Partial as i32 {
-            ResponseChunk::Part(data)
-        } else {
-            ResponseChunk::Full(data)
-        };
+struct TransportWriter<W: Sink<GsbMessage, Error = ProtocolError> + Unpin> {
+    inner: SinkWrite<GsbMessage, ReplyCountingSink<W>>,
+    bytes_written: u64,
+    messages_written: u64,
+    // Since when written frames wait to be flushed, `None` when everything
+    // written so far reached the transport.
+    unflushed_since: Option<Instant>,
+    // Frames taken by the transport when progress was last seen, and when.
+    progress: (u64, Instant),
+    // Reply chunks still in the write buffer, for calls being tracked.
+    queued_replies: QueuedReplies,
+    tap: Option<Tap>,
+}
 
-        let is_full = chunk.is_full();
+impl<W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static> TransportWriter<W> {
+    fn new<A, C>(sink: W, ctx: &mut C) -> Self
+    where
+        A: Actor<Context = C> + EmptyBufferHandler + io::WriteHandler<ProtocolError>,
+        C: AsyncContext<A>,
+    {
+        let queued_replies = QueuedReplies::default();
+        let sink = ReplyCountingSink {
+            inner: sink,
+            queued_replies: queued_replies.clone(),
+        };
+        TransportWriter {
+            inner: SinkWrite::new(sink, ctx),
+            bytes_written: 0,
+            messages_written: 0,
+            unflushed_since: None,
+            progress: (0, now()),
+            queued_replies,
+            tap: None,
+        }
+    }
 
-        if let Some(r) = self.call_reply.get_mut(&request_id) {
-            // TODO: check error
-            let mut r = (*r).clone();
-            let code: CallReplyCode = code.try_into()?;
-            let item = match code {
-                CallReplyCode::CallReplyOk => Ok(chunk),
-                CallReplyCode::CallReplyBadRequest => {
-                    Err(Error::GsbBadRequest(String::from_utf8(chunk.into_bytes())?))
-                }
-                CallReplyCode::ServiceFailure => {
-                    Err(Error::GsbFailure(String::from_utf8(chunk.into_bytes())?))
-                }
-            };
-            let _ = ctx.spawn(
-                async move {
-                    let s = r.send(item);
-                    s.await
-                        .unwrap_or_else(|e| log::warn!("undelivered reply: {}", e))
+    fn write(&mut self, msg: GsbMessage) -> Option<GsbMessage> {
+        // rejected messages are handed back to the caller, not sent
+        let tapped = self
+            .tap
+            .as_ref()
+            .map(|tap| tap.copy(TapDirection::Outbound, &msg));
+        let len = msg.frame_len() as u64;
+        let priority = write_priority(&msg);
+        let reply_to = match &msg {
+            GsbMessage::CallReply(r) => Some(r.request_id.clone()),
+            _ => None,
+        };
+        let rejected = self.inner.write_with(msg, priority);
+        if rejected.is_none() {
+            self.bytes_written += len;
+            self.messages_written += 1;
+            self.unflushed_since.get_or_insert_with(now);
+            if let (Some(tap), Some(tapped)) = (&mut self.tap, tapped) {
+                tap.send(tapped);
+            }
+            if let Some(request_id) = reply_to {
+                if let Some(queued) = self.queued_replies.borrow_mut().get_mut(&request_id) {
+                    *queued += 1;
                 }
-                .into_actor(self),
-            );
-        } else {
-            log::debug!("unmatched call reply");
+            }
         }
+        rejected
+    }
 
-        if is_full {
-            let _ = self.call_reply.remove(&request_id);
+    /// Starts counting chunks of reply to `request_id` waiting to be sent.
+    fn track_reply(&mut self, request_id: &str) {
+        let _ = self
+            .queued_replies
+            .borrow_mut()
+            .insert(request_id.to_string(), 0);
+    }
+
+    fn untrack_reply(&mut self, request_id: &str) {
+        let _ = self.queued_replies.borrow_mut().remove(request_id);
+    }
+
+    /// Number of chunks of tracked reply not taken by the transport yet.
+    fn queued_reply_chunks(&self, request_id: &str) -> usize {
+        self.queued_replies
+            .borrow()
+            .get(request_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Number of frames written so far which left the write buffer.
+    fn messages_sent(&self) -> u64 {
+        self.messages_written - self.inner.buffer_len() as u64
+    }
+
+    /// How long frames wait to be flushed without any of them taken by the
+    /// transport, `None` when nothing waits.
+    fn stalled_for(&mut self) -> Option<Duration> {
+        let since = self.unflushed_since?;
+        let now = now();
+        let sent = self.messages_sent();
+        if sent != self.progress.0 {
+            self.progress = (sent, now);
         }
+        Some(now.saturating_duration_since(since.max(self.progress.1)))
+    }
+}
 
-        Ok(())
+// Liveness and registration traffic goes ahead of queued calls, replies and
+// broadcasts, so that bursts of data do not cause heartbeat timeouts.
+// Messages tied to a call keep their order relative to it.
+fn write_priority(msg: &GsbMessage) -> EnqueuePriority {
+    match msg {
+        GsbMessage::Hello(_)
+        | GsbMessage::Ping(_)
+        | GsbMessage::Pong(_)
+        | GsbMessage::RegisterRequest(_)
+        | GsbMessage::UnregisterRequest(_)
+        | GsbMessage::SubscribeRequest(_)
+        | GsbMessage::UnsubscribeRequest(_) => EnqueuePriority::High,
+        _ => EnqueuePriority::Normal,
     }
 }
 
-impl<W, H> Actor for Connection<W, H>
+/// Body of incoming call still waiting for its last frame.
+struct PartialBody {
+    started: Instant,
+    no_reply: bool,
+    // `None` for rejected call whose remaining frames are dropped
+    body: Option<Vec<u8>>,
+}
+
+/// Number of reply chunks in the write buffer, by `request_id` of the call.
+type QueuedReplies = std::rc::Rc<std::cell::RefCell<HashMap<String, usize>>>;
+
+/// Transport counting reply chunks it takes from the write buffer.
+struct ReplyCountingSink<W> {
+    inner: W,
+    queued_replies: QueuedReplies,
+}
+
+impl<W: Sink<GsbMessage, Error = ProtocolError> + Unpin> Sink<GsbMessage> for ReplyCountingSink<W> {
+    type Error = ProtocolError;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: GsbMessage) -> Result<(), Self::Error> {
+        if let GsbMessage::CallReply(r) = &item {
+            if let Some(queued) = self.queued_replies.borrow_mut().get_mut(&r.request_id) {
+                *queued = queued.saturating_sub(1);
+            }
+        }
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Reply channel of a command and the address or topic it concerns.
+type Waiter = (oneshot::Sender<Result<(), Error>>, String);
+
+/// Waiters for command replies, in order the commands were sent.
+#[derive(Default)]
+struct ReplyQueue {
+    waiters: VecDeque<Waiter>,
+    // Number of waiters ever taken from the front, used to tell whether
+    // a given waiter is still queued.
+    popped: u64,
+    // Replies the router still owes to waiters failed by `reply_timeout`,
+    // dropped on arrival so they don't resolve waiters queued later.
+    late: u64,
+}
+
+impl ReplyQueue {
+    fn push_back(&mut self, waiter: Waiter) -> u64 {
+        self.waiters.push_back(waiter);
+        self.popped + self.waiters.len() as u64 - 1
+    }
+
+    fn pop_back(&mut self) -> Option<Waiter> {
+        self.waiters.pop_back()
+    }
+
+    fn pop_front(&mut self) -> Option<Waiter> {
+        let r = self.waiters.pop_front();
+        if r.is_some() {
+            self.popped += 1;
+        }
+        r
+    }
+
+    /// Fails this waiter and all queued before it, their replies are
+    /// dropped once they arrive.
+    fn expire_through(&mut self, seq: u64, error: impl Fn() -> Error) -> u64 {
+        let mut expired = 0;
+        while self.is_queued(seq) {
+            if let Some((r, _)) = self.pop_front() {
+                let _ = r.send(Err(error()));
+                expired += 1;
+            }
+        }
+        self.late += expired;
+        expired
+    }
+
+    /// Consumes reply owed to an expired waiter, if any is still due.
+    fn skip_late(&mut self) -> bool {
+        if self.late == 0 {
+            return false;
+        }
+        self.late -= 1;
+        true
+    }
+
+    fn is_queued(&self, seq: u64) -> bool {
+        seq >= self.popped && seq < self.popped + self.waiters.len() as u64
+    }
+
+    fn len(&self) -> usize {
+        self.waiters.len()
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = Waiter> + '_ {
+        self.popped += self.waiters.len() as u64;
+        self.late = 0;
+        self.waiters.drain(..)
+    }
+}
+
+struct Connection<W, H>
 where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin,
+    H: CallRequestHandler,
 {
-    type Context = Context<Self>;
+    writer: TransportWriter<W>,
+    register_reply: ReplyQueue,
+    unregister_reply: ReplyQueue,
+    subscribe_reply: ReplyQueue,
+    unsubscribe_reply: ReplyQueue,
+    call_reply: HashMap<String, PendingCall>,
+    broadcast_reply: ReplyQueue,
+    handler: H,
+    client_info: ClientInfo,
+    config: ConnectionConfig,
+    server_info: Option<ya_sb_proto::Hello>,
+    disconnect_reason: Option<DisconnectReason>,
+    unmatched_replies: UnmatchedReplies,
+    reply_timeouts: u64,
+    lagging_replies: u64,
+    // Bodies of incoming calls still waiting for their last frame.
+    partial_bodies: HashMap<String, PartialBody>,
+    // Credits granted to flow controlled replies being sent.
+    credit_grants: HashMap<String, mpsc::UnboundedSender<u32>>,
+    // Incoming calls being handled, dropped when caller cancels them.
+    running_calls: HashMap<String, SpawnHandle>,
+    last_packet: Instant,
+    limiter: Option<CallerLimiter>,
+    bytes_read: u64,
+    messages_read: u64,
+    event_subscribers: Vec<mpsc::UnboundedSender<ConnectionEvent>>,
+    // Topics the router confirmed subscription to.
+    subscriptions: BTreeSet<String>,
+    broadcasts: HashMap<String, BroadcastStats>,
+    flush_waiters: Vec<oneshot::Sender<()>>,
+    // Reply bytes received per outgoing call, with `max_reply_bytes` set.
+    reply_bytes: HashMap<String, usize>,
+    // Subscription handles sharing single router subscription, per topic.
+    topic_subscriptions: HashMap<String, TopicSubscription>,
+    last_subscription_id: u64,
+}
 
-    fn started(&mut self, _ctx: &mut Self::Context) {
-        log::info!("started connection to gsb");
-        let hello: ya_sb_proto::Hello = ya_sb_proto::Hello {
-            name: self.client_info.name.clone(),
-            version: self
-                .client_info
-                .version
-                .as_ref()
-                .map(|v| v.to_string())
-                .unwrap_or_default(),
-            instance_id: self.client_info.instance_id.clone(),
-        };
+type EventFilter = Box<dyn Fn(&[u8]) -> bool + Send>;
 
-        let _ = self.writer.write(GsbMessage::Hello(hello));
+/// Router subscription to a topic, kept while any of its handles is alive.
+#[derive(Default)]
+struct TopicSubscription {
+    // Handle ids with predicates on broadcast payload they want.
+    handles: Vec<(u64, Option<EventFilter>)>,
+    // Subscribers waiting for the router to confirm the subscription, told
+    // whether it succeeded. `None` once confirmed.
+    pending: Option<Vec<oneshot::Sender<bool>>>,
+}
+
+impl TopicSubscription {
+    /// Whether any handle wants the broadcast `data`.
+    fn accepts(&self, data: &[u8]) -> bool {
+        self.handles.is_empty()
+            || self.handles.iter().any(|(_, filter)| match filter {
+                Some(filter) => filter(data),
+                None => true,
+            })
     }
+}
 
-    fn stopped(&mut self, _ctx: &mut Self::Context) {
-        log::info!("stopped connection to gsb");
-        self.handler.on_disconnect();
+struct PendingCall {
+    reply: mpsc::UnboundedSender<Result<ResponseChunk, Error>>,
+    address: String,
+    started: Instant,
+    kind: CallKind,
+    trailers: Option<oneshot::Sender<Headers>>,
+    // Chunks of streaming reply not yet taken by the caller.
+    queued: Option<Arc<AtomicUsize>>,
+}
+
+impl PendingCall {
+    fn new(
+        reply: mpsc::UnboundedSender<Result<ResponseChunk, Error>>,
+        address: &str,
+        kind: CallKind,
+    ) -> Self {
+        PendingCall {
+            reply,
+            address: address.to_string(),
+            started: now(),
+            kind,
+            trailers: None,
+            queued: None,
+        }
     }
 }
 
-fn register_reply_code(code: i32) -> Option<RegisterReplyCode> {
-    Some(match code {
-        0 => RegisterReplyCode::RegisteredOk,
-        400 => RegisterReplyCode::RegisterBadRequest,
-        409 => RegisterReplyCode::RegisterConflict,
-        _ => return None,
-    })
+impl<W, H> Unpin for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
 }
 
-fn unregister_reply_code(code: i32) -> Option<UnregisterReplyCode> {
-    Some(match code {
-        0 => UnregisterReplyCode::UnregisteredOk,
-        404 => UnregisterReplyCode::NotRegistered,
-        _ => return None,
-    })
+struct UnmatchedReplies {
+    count: usize,
+    limit: usize,
 }
 
-fn subscribe_reply_code(code: i32) -> Option<SubscribeReplyCode> {
-    Some(match code {
-        0 => SubscribeReplyCode::SubscribedOk,
-        400 => SubscribeReplyCode::SubscribeBadRequest,
-        _ => return None,
-    })
+/// Passes reply to the oldest waiter, returns address or topic of the
+/// command when it succeeded.
+fn handle_reply<Ctx: ActorContext, F: FnOnce() -> Result<(), Error>>(
+    cmd_type: &str,
+    queue: &mut ReplyQueue,
+    unmatched: &mut UnmatchedReplies,
+    disconnect_reason: &mut Option<DisconnectReason>,
+    ctx: &mut Ctx,
+    reply_msg: F,
+) -> Option<String> {
+    if queue.skip_late() {
+        log::debug!("dropping late {} reply", cmd_type);
+        return None;
+    }
+    if let Some((r, target)) = queue.pop_front() {
+        let reply = reply_msg();
+        let succeeded = reply.is_ok();
+        let _ = r.send(reply);
+        return succeeded.then_some(target);
+    }
+    unmatched.count += 1;
+    log::warn!(
+        "unmatched {} reply ({}/{})",
+        cmd_type,
+        unmatched.count,
+        unmatched.limit
+    );
+    if unmatched.count > unmatched.limit {
+        disconnect_reason.get_or_insert_with(|| {
+            DisconnectReason::ProtocolError(format!("unmatched {} reply", cmd_type))
+        });
+        ctx.stop()
+    }
+    None
 }
 
-fn unsubscribe_reply_code(code: i32) -> Option<UnsubscribeReplyCode> {
-    Some(match code {
-        0 => UnsubscribeReplyCode::UnsubscribedOk,
-        404 => UnsubscribeReplyCode::NotSubscribed,
-        _ => return None,
-    })
+/// Holds back reply chunks until caller grants credit for them.
+fn with_credits<S>(
+    reply: S,
+    window: u32,
+    credits: mpsc::UnboundedReceiver<u32>,
+) -> LocalBoxStream<'static, S::Item>
+where
+    S: Stream + Unpin + 'static,
+{
+    stream::unfold(
+        (reply, window, credits),
+        |(mut reply, mut available, mut credits)| async move {
+            while available == 0 {
+                // when caller is gone there is nobody to pace for
+                available = credits.next().await.unwrap_or(u32::MAX);
+            }
+            let item = reply.next().await?;
+            Some((item, (reply, available - 1, credits)))
+        },
+    )
+    .boxed_local()
 }
 
-fn broadcast_reply_code(code: i32) -> Option<BroadcastReplyCode> {
-    Some(match code {
-        0 => BroadcastReplyCode::BroadcastOk,
-        400 => BroadcastReplyCode::BroadcastBadRequest,
-        _ => return None,
-    })
+/// Turns handler panic, either in the call itself or while producing the
+/// reply, into failure of that single call.
+fn isolate_panics<S>(
+    reply: std::thread::Result<S>,
+) -> impl Stream<Item = Result<ResponseChunk, Error>> + Unpin
+where
+    S: Stream<Item = Result<ResponseChunk, Error>> + Unpin,
+{
+    match reply {
+        Ok(reply) => std::panic::AssertUnwindSafe(reply)
+            .catch_unwind()
+            .map(|r| r.unwrap_or_else(|panic| Err(Error::from_panic(panic))))
+            .left_stream(),
+        Err(panic) => stream::once(future::err(Error::from_panic(panic))).right_stream(),
+    }
 }
 
-impl<W, H> StreamHandler<Result<GsbMessage, ProtocolError>> for Connection<W, H>
+// Handlers select `CallReplyBadRequest` by failing with `Error::GsbBadRequest`,
+// requests with undecodable body are reported the same way.
+fn error_reply(e: Error) -> (CallReplyCode, Vec<u8>) {
+    match e {
+        Error::GsbBadRequest(msg) => (CallReplyCode::CallReplyBadRequest, msg.into_bytes()),
+        e @ (Error::BadContent(_) | Error::BadRequestContent { .. }) => (
+            CallReplyCode::CallReplyBadRequest,
+            e.to_string().into_bytes(),
+        ),
+        e => (CallReplyCode::ServiceFailure, e.to_string().into_bytes()),
+    }
+}
+
+impl<W, H> EmptyBufferHandler for Connection<W, H>
 where
     W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
     H: CallRequestHandler + 'static,
 {
-    fn handle(&mut self, item: Result<GsbMessage, ProtocolError>, ctx: &mut Self::Context) {
-        if let Err(e) = item.as_ref() {
-            log::error!("protocol error {}", e);
-            ctx.stop();
-            return;
+    fn buffer_empty(&mut self, _ctx: &mut Self::Context) {
+        self.writer.unflushed_since = None;
+        for tx in self.flush_waiters.drain(..) {
+            let _ = tx.send(());
         }
+    }
+}
 
-        match item.unwrap() {
-            GsbMessage::RegisterReply(r) => {
-                if let Some(code) = register_reply_code(r.code) {
-                    self.handle_register_reply(code, r.message, ctx)
-                } else {
-                    log::error!("invalid reply code {}", r.code);
-                    ctx.stop();
-                }
+impl<W, H> Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    fn new(
+        client_info: ClientInfo,
+        config: ConnectionConfig,
+        w: W,
+        handler: H,
+        ctx: &mut <Self as Actor>::Context,
+    ) -> Self {
+        let mut writer = TransportWriter::new(w, ctx);
+        writer.tap = config.tap.clone().map(|sender| Tap {
+            sender,
+            bodies: config.tap_bodies,
+        });
+        Connection {
+            writer,
+            register_reply: Default::default(),
+            unregister_reply: Default::default(),
+            subscribe_reply: Default::default(),
+            unsubscribe_reply: Default::default(),
+            call_reply: Default::default(),
+            broadcast_reply: Default::default(),
+            handler,
+            client_info,
+            unmatched_replies: UnmatchedReplies {
+                count: 0,
+                limit: config.max_unmatched_replies,
+            },
+            limiter: config.rate_limit.clone().map(CallerLimiter::new),
+            config,
+            server_info: Default::default(),
+            disconnect_reason: None,
+            reply_timeouts: 0,
+            lagging_replies: 0,
+            partial_bodies: Default::default(),
+            credit_grants: Default::default(),
+            running_calls: Default::default(),
+            last_packet: now(),
+            bytes_read: 0,
+            messages_read: 0,
+            event_subscribers: Vec::new(),
+            subscriptions: Default::default(),
+            broadcasts: Default::default(),
+            flush_waiters: Vec::new(),
+            reply_bytes: Default::default(),
+            topic_subscriptions: Default::default(),
+            last_subscription_id: 0,
+        }
+    }
+
+    fn reject_pending(&mut self, error: impl Fn() -> Error) {
+        let queues = [
+            &mut self.register_reply,
+            &mut self.unregister_reply,
+            &mut self.subscribe_reply,
+            &mut self.unsubscribe_reply,
+            &mut self.broadcast_reply,
+        ];
+        for queue in queues {
+            for (r, _) in queue.drain() {
+                let _ = r.send(Err(error()));
             }
-            GsbMessage::UnregisterReply(r) => {
-                if let Some(code) = unregister_reply_code(r.code) {
-                    self.handle_unregister_reply(code, ctx)
-                } else {
-                    log::error!("invalid unregister reply code {}", r.code);
-                    ctx.stop();
+        }
+        for (_, r) in self.call_reply.drain() {
+            let _ = r.reply.unbounded_send(Err(error()));
+        }
+    }
+
+    /// Sends command and waits for its reply, failing it with `Error::Timeout`
+    /// when `reply_timeout` expires first.
+    fn send_cmd_async(
+        &mut self,
+        queue: fn(&mut Self) -> &mut ReplyQueue,
+        cmd_type: &'static str,
+        target: String,
+        msg: GsbMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) -> ActorResponse<Self, Result<(), Error>> {
+        let reply = self.send_cmd(queue, cmd_type, target, msg, ctx);
+        ActorResponse::r#async(fut::wrap_future(reply))
+    }
+
+    /// Sends command, resolving once the router replies to it.
+    fn send_cmd(
+        &mut self,
+        queue: fn(&mut Self) -> &mut ReplyQueue,
+        cmd_type: &'static str,
+        target: String,
+        msg: GsbMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) -> LocalBoxFuture<'static, Result<(), Error>> {
+        let (tx, rx) = oneshot::channel();
+        let seq = queue(self).push_back((tx, target));
+
+        if self.writer.write(msg).is_some() {
+            // Nothing was sent, so no reply will ever match this waiter.
+            let _ = queue(self).pop_back();
+            return Box::pin(future::err(Error::GsbFailure("no connection".into())));
+        }
+
+        if let Some(timeout) = self.config.reply_timeout {
+            ctx.run_later(timeout, move |act, _ctx| {
+                let queue = queue(act);
+                if !queue.is_queued(seq) {
+                    return;
                 }
-            }
-            GsbMessage::SubscribeReply(r) => {
-                if let Some(code) = subscribe_reply_code(r.code) {
-                    self.handle_subscribe_reply(code, r.message, ctx)
-                } else {
-                    log::error!("invalid reply code {}", r.code);
-                    ctx.stop();
+                log::warn!(
+                    "no {} reply in {:?}, {} pending, possible protocol desync",
+                    cmd_type,
+                    timeout,
+                    queue.len()
+                );
+                // Replies come in order, so this waiter and everything queued
+                // before it are failed together. Their replies may still come
+                // and are dropped then.
+                let expired = queue.expire_through(seq, || Error::Timeout(cmd_type.into()));
+                act.reply_timeouts += expired;
+            });
+        }
+
+        Box::pin(async move {
+            rx.await.map_err(|_| Error::Cancelled)??;
+            Ok(())
+        })
+    }
+
+    fn emit_event(&mut self, event: ConnectionEvent) {
+        log::trace!("connection event: {:?}", event);
+        self.event_subscribers
+            .retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+
+    fn stop_with(&mut self, reason: DisconnectReason, ctx: &mut <Self as Actor>::Context) {
+        self.disconnect_reason.get_or_insert(reason);
+        ctx.stop();
+    }
+
+    /// Stops once everything queued is written to the transport, waiting at
+    /// most `SHUTDOWN_DRAIN_TIMEOUT`. Outgoing calls waiting for reply are
+    /// not waited for, stopping fails them.
+    fn drain_and_stop(&mut self, ctx: &mut <Self as Actor>::Context) {
+        log::debug!("shutting down gsb connection");
+        if self.writer.unflushed_since.is_none() {
+            return self.stop_with(DisconnectReason::LocalStop, ctx);
+        }
+        let (tx, rx) = oneshot::channel();
+        self.flush_waiters.push(tx);
+        let _ = ctx.spawn(
+            fut::wrap_future(tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT, rx))
+                .map(|_, act: &mut Self, ctx| act.stop_with(DisconnectReason::LocalStop, ctx)),
+        );
+    }
+
+    fn check_heartbeat(&mut self, interval: Duration, ctx: &mut <Self as Actor>::Context) {
+        let idle = now().saturating_duration_since(self.last_packet);
+        if idle > self.config.ping_timeout {
+            log::warn!("no data from gsb for {:?}, disconnecting", idle);
+            self.stop_with(DisconnectReason::HeartbeatTimeout, ctx);
+        } else if idle >= interval {
+            let _ = self.writer.write(GsbMessage::Ping(Default::default()));
+        }
+    }
+
+    /// Forgets outgoing call, asking the peer to stop it when supported.
+    fn cancel_call(&mut self, request_id: String) -> Option<PendingCall> {
+        let _ = self.reply_bytes.remove(&request_id);
+        let call = self.call_reply.remove(&request_id)?;
+        if self.peer_supports(ya_sb_proto::CAP_CALL_CANCEL) {
+            let _ = self
+                .writer
+                .write(GsbMessage::CallCancel(CallCancel { request_id }));
+        }
+        Some(call)
+    }
+
+    fn check_write_progress(&mut self, timeout: Duration, ctx: &mut <Self as Actor>::Context) {
+        let stalled = self.writer.stalled_for();
+        if let Some(stalled) = stalled.filter(|stalled| *stalled > timeout) {
+            log::warn!("writes to gsb stalled for {:?}, disconnecting", stalled);
+            self.stop_with(DisconnectReason::WriteStalled, ctx);
+        }
+    }
+
+    fn in_flight_limit_reached(&self) -> bool {
+        self.config
+            .max_in_flight_calls
+            .map(|limit| self.call_reply.len() >= limit)
+            .unwrap_or(false)
+    }
+
+    fn handle_unregister_reply(
+        &mut self,
+        code: UnregisterReplyCode,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let done = handle_reply(
+            "unregister",
+            &mut self.unregister_reply,
+            &mut self.unmatched_replies,
+            &mut self.disconnect_reason,
+            ctx,
+            || match code {
+                UnregisterReplyCode::UnregisteredOk => Ok(()),
+                UnregisterReplyCode::NotRegistered => {
+                    Err(Error::GsbBadRequest("unregister".to_string()))
                 }
-            }
-            GsbMessage::UnsubscribeReply(r) => {
-                if let Some(code) = unsubscribe_reply_code(r.code) {
-                    self.handle_unsubscribe_reply(code, ctx)
-                } else {
-                    log::error!("invalid unsubscribe reply code {}", r.code);
-                    ctx.stop();
+            },
+        );
+        if let Some(addr) = done {
+            self.emit_event(ConnectionEvent::Unregistered(addr));
+        }
+    }
+
+    fn handle_broadcast_reply(
+        &mut self,
+        code: BroadcastReplyCode,
+        msg: String,
+        receivers: u32,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let done = handle_reply(
+            "broadcast",
+            &mut self.broadcast_reply,
+            &mut self.unmatched_replies,
+            &mut self.disconnect_reason,
+            ctx,
+            || match code {
+                BroadcastReplyCode::BroadcastOk => Ok(()),
+                BroadcastReplyCode::BroadcastBadRequest => Err(Error::GsbBadRequest(msg)),
+            },
+        );
+        if let Some(topic) = done {
+            let stats = self.broadcasts.entry(topic.clone()).or_default();
+            stats.sent += 1;
+            stats.receivers += u64::from(receivers);
+            self.emit_event(ConnectionEvent::BroadcastSent(topic));
+        }
+    }
+
+    fn handle_register_reply(
+        &mut self,
+        code: RegisterReplyCode,
+        msg: String,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let done = handle_reply(
+            "register",
+            &mut self.register_reply,
+            &mut self.unmatched_replies,
+            &mut self.disconnect_reason,
+            ctx,
+            || match code {
+                RegisterReplyCode::RegisteredOk => Ok(()),
+                RegisterReplyCode::RegisterBadRequest => {
+                    log::warn!("bad request: {}", msg);
+                    Err(Error::GsbBadRequest(msg))
                 }
-            }
-            GsbMessage::BroadcastReply(r) => {
-                if let Some(code) = broadcast_reply_code(r.code) {
-                    self.handle_broadcast_reply(code, r.message, ctx)
-                } else {
-                    log::error!("invalid broadcast reply code {}", r.code);
-                    ctx.stop();
+                RegisterReplyCode::RegisterConflict => {
+                    log::warn!("already registered: {}", msg);
+                    Err(Error::GsbAlreadyRegistered(msg))
                 }
-            }
-            GsbMessage::CallRequest(r) => {
-                if r.no_reply {
-                    self.handle_push_request(r.request_id, r.caller, r.address, r.data, ctx)
-                } else {
-                    self.handle_call_request(r.request_id, r.caller, r.address, r.data, ctx)
+            },
+        );
+        if let Some(addr) = done {
+            self.emit_event(ConnectionEvent::Registered(addr));
+        }
+    }
+
+    fn handle_subscribe_reply(
+        &mut self,
+        code: SubscribeReplyCode,
+        msg: String,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let done = handle_reply(
+            "subscribe",
+            &mut self.subscribe_reply,
+            &mut self.unmatched_replies,
+            &mut self.disconnect_reason,
+            ctx,
+            || match code {
+                SubscribeReplyCode::SubscribedOk => Ok(()),
+                SubscribeReplyCode::SubscribeBadRequest => {
+                    log::warn!("bad request: {}", msg);
+                    Err(Error::GsbBadRequest(msg))
                 }
-            }
-            GsbMessage::CallReply(r) => {
-                if let Err(e) = self.handle_reply(r.request_id, r.code, r.reply_type, r.data, ctx) {
-                    log::error!("error on call reply processing: {}", e);
-                    ctx.stop();
+            },
+        );
+        if let Some(topic) = done {
+            self.subscriptions.insert(topic.clone());
+            self.emit_event(ConnectionEvent::Subscribed(topic));
+        }
+    }
+
+    fn handle_unsubscribe_reply(
+        &mut self,
+        code: UnsubscribeReplyCode,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let done = handle_reply(
+            "unsubscribe",
+            &mut self.unsubscribe_reply,
+            &mut self.unmatched_replies,
+            &mut self.disconnect_reason,
+            ctx,
+            || match code {
+                UnsubscribeReplyCode::UnsubscribedOk => Ok(()),
+                UnsubscribeReplyCode::NotSubscribed => {
+                    Err(Error::GsbBadRequest("unsubscribed".to_string()))
                 }
-            }
-            GsbMessage::BroadcastRequest(r) => {
-                self.handler.handle_event(r.caller, r.topic, r.data);
-            }
-            GsbMessage::Ping(_) => {
-                if self.writer.write(GsbMessage::pong()).is_some() {
-                    log::error!("error sending pong");
-                    ctx.stop();
+            },
+        );
+        if let Some(topic) = done {
+            self.subscriptions.remove(&topic);
+            self.emit_event(ConnectionEvent::Unsubscribed(topic));
+        }
+    }
+
+    fn handle_call_request(
+        &mut self,
+        request: CallRequest,
+        deadline: Option<Instant>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        if request.headers.contains_key(ya_sb_proto::PING_HEADER) {
+            log::trace!("answering ping of {}", request.address);
+            let (code, data) = match self.handler.serves(&request.address) {
+                true => (CallReplyCode::CallReplyOk, Vec::new()),
+                false => (
+                    CallReplyCode::CallReplyBadRequest,
+                    ya_sb_proto::ENDPOINT_NOT_FOUND.into(),
+                ),
+            };
+            let _ = self.writer.write(GsbMessage::CallReply(CallReply {
+                request_id: request.request_id,
+                code: code as i32,
+                reply_type: CallReplyType::Full as i32,
+                data,
+                trailers: Default::default(),
+            }));
+            return;
+        }
+        let CallRequest {
+            request_id,
+            caller,
+            address,
+            data,
+            window,
+            headers,
+            ..
+        } = request;
+        log::trace!(
+            "handling rpc call from = {}, to = {}, request_id={}, ",
+            caller,
+            address,
+            request_id
+        );
+        let eos_request_id = request_id.clone();
+        let call_id = request_id.clone();
+        let trailers = Trailers::default();
+        let eos_trailers = trailers.clone();
+        let mut lag_reported = false;
+        let lag_address = address.clone();
+        if self.config.reply_lag_warn.is_some() {
+            self.writer.track_reply(&request_id);
+        }
+        let handler = &mut self.handler;
+        let call = CallContext {
+            request_id: request_id.clone(),
+            caller,
+            address,
+            data,
+            no_reply: false,
+            deadline,
+            headers: headers.into_iter().collect(),
+            trailers: trailers.clone(),
+        };
+        let reply =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| handler.handle_call(call)));
+        let reply = if window > 0 {
+            let (tx, credits) = mpsc::unbounded();
+            let _ = self.credit_grants.insert(eos_request_id.clone(), tx);
+            with_credits(isolate_panics(reply), window, credits).left_stream()
+        } else {
+            isolate_panics(reply).right_stream()
+        };
+        let do_call = reply
+            .into_actor(self)
+            .fold(false, move |_got_eos, r, act: &mut Self, _ctx| {
+                let request_id = request_id.clone();
+                let reply_id = request_id.clone();
+                let (got_eos, reply) = match r {
+                    Ok(data) => {
+                        let code = CallReplyCode::CallReplyOk as i32;
+                        let reply_type = data.reply_type() as i32;
+                        let is_last = reply_type == 0;
+                        (
+                            is_last,
+                            CallReply {
+                                request_id,
+                                code,
+                                reply_type,
+                                data: data.into_bytes(),
+                                trailers: match is_last {
+                                    true => trailers.take(),
+                                    false => Default::default(),
+                                },
+                            },
+                        )
+                    }
+                    Err(e) => {
+                        let (code, data) = error_reply(e);
+                        let code = code as i32;
+                        let reply_type = Default::default();
+                        (
+                            true,
+                            CallReply {
+                                request_id,
+                                code,
+                                reply_type,
+                                data,
+                                trailers: Default::default(),
+                            },
+                        )
+                    }
+                };
+                // TODO: handle write error
+                if act.writer.write(GsbMessage::CallReply(reply)).is_none() {
+                    act.check_reply_lag(&reply_id, &mut lag_reported, &lag_address);
                 }
-            }
-            GsbMessage::Hello(h) => {
-                log::debug!("connected with server: {}/{}", h.name, h.version);
-                if self.server_info.is_some() {
-                    log::error!("invalid packet: {:?}", h);
-                    ctx.stop();
-                } else {
-                    self.server_info = Some(h);
+                fut::ready(got_eos)
+            })
+            .then(move |got_eos, act, _ctx| {
+                let _ = act.credit_grants.remove(&eos_request_id);
+                let _ = act.running_calls.remove(&eos_request_id);
+                act.writer.untrack_reply(&eos_request_id);
+                if !got_eos {
+                    let _ = act.writer.write(GsbMessage::CallReply(CallReply {
+                        request_id: eos_request_id,
+                        code: 0,
+                        reply_type: 0,
+                        data: Default::default(),
+                        trailers: eos_trailers.take(),
+                    }));
                 }
-            }
-            m => {
-                log::error!("unexpected gsb message: {:?}", m);
-                ctx.stop();
-            }
-        }
+                fut::ready(())
+            });
+        let handle = ctx.spawn(do_call);
+        let _ = self.running_calls.insert(call_id, handle);
     }
-}
 
-impl<W, H> io::WriteHandler<ProtocolError> for Connection<W, H>
-where
+    fn check_reply_lag(&mut self, request_id: &str, reported: &mut bool, address: &str) {
+        let limit = match self.config.reply_lag_warn {
+            Some(limit) => limit,
+            None => return,
+        };
+        let waiting = self.writer.queued_reply_chunks(request_id);
+        if waiting > limit && !*reported {
+            *reported = true;
+            self.lagging_replies += 1;
+            log::warn!(
+                "reply of {} outpaces the transport, {} chunks waiting to be sent",
+                address,
+                waiting
+            );
+        }
+    }
+
+    fn handle_push_request(
+        &mut self,
+        request: CallRequest,
+        deadline: Option<Instant>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        log::trace!(
+            "handling push call from = {}, to = {}, request_id={}, ",
+            request.caller,
+            request.address,
+            request.request_id
+        );
+
+        let call = CallContext {
+            request_id: request.request_id,
+            caller: request.caller,
+            address: request.address,
+            data: request.data,
+            no_reply: true,
+            deadline,
+            headers: request.headers.into_iter().collect(),
+            trailers: Default::default(),
+        };
+        let handler = &mut self.handler;
+        let reply =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| handler.handle_call(call)));
+        isolate_panics(reply)
+            .into_actor(self)
+            .fold((), move |_, _, _, _| fut::ready(()))
+            .spawn(ctx);
+    }
+
+    fn peer_supports(&self, capability: &str) -> bool {
+        self.server_info
+            .as_ref()
+            .map(|hello| hello.capabilities.iter().any(|c| c == capability))
+            .unwrap_or(false)
+    }
+
+    /// Writes call request, splitting the body into `max_inline_body` sized
+    /// frames when the router supports it.
+    fn write_call(&mut self, mut request: CallRequest, body: Bytes) {
+        let chunk_size = match self.config.max_inline_body {
+            Some(n)
+                if n > 0 && body.len() > n && self.peer_supports(ya_sb_proto::CAP_CHUNKED_BODY) =>
+            {
+                n
+            }
+            _ => {
+                request.data = Vec::from(body);
+                let _ = self.writer.write(GsbMessage::CallRequest(request));
+                return;
+            }
+        };
+        let mut chunks = body.chunks(chunk_size).peekable();
+        while let Some(chunk) = chunks.next() {
+            let _ = self.writer.write(GsbMessage::CallRequest(CallRequest {
+                data: chunk.to_vec(),
+                has_more: chunks.peek().is_some(),
+                ..request.clone()
+            }));
+        }
+    }
+
+    /// Adds frame of chunked call body, returns `true` once `request` holds
+    /// the whole body.
+    fn collect_body(&mut self, request: &mut CallRequest) -> bool {
+        let data = std::mem::take(&mut request.data);
+        if !self.partial_bodies.contains_key(&request.request_id) {
+            self.expire_partial_bodies();
+            let started = now();
+            let waiting = self
+                .partial_bodies
+                .values()
+                .filter(|partial| partial.body.is_some())
+                .count();
+            let body = if waiting < self.config.max_partial_calls {
+                Some(Vec::new())
+            } else {
+                self.reject_call(request.clone(), "too many partial calls");
+                None
+            };
+            let partial = PartialBody {
+                started,
+                no_reply: request.no_reply,
+                body,
+            };
+            let _ = self
+                .partial_bodies
+                .insert(request.request_id.clone(), partial);
+        }
+        let body = match self.partial_bodies.get_mut(&request.request_id) {
+            Some(partial) => &mut partial.body,
+            None => return false,
+        };
+        let too_large = match body {
+            Some(buf) => {
+                buf.extend_from_slice(&data);
+                self.config
+                    .max_call_body
+                    .map(|limit| buf.len() > limit)
+                    .unwrap_or(false)
+            }
+            None => false,
+        };
+        if too_large {
+            *body = None;
+            self.reject_call(request.clone(), "call body too large");
+        }
+        if request.has_more {
+            return false;
+        }
+        match self.partial_bodies.remove(&request.request_id) {
+            Some(PartialBody {
+                body: Some(body), ..
+            }) => {
+                request.data = body;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Rejects calls waiting for their last frame longer than
+    /// `partial_call_timeout`. Their remaining frames are dropped for
+    /// another timeout, then forgotten.
+    fn expire_partial_bodies(&mut self) {
+        let now = now();
+        let timeout = self.config.partial_call_timeout;
+        let mut expired = Vec::new();
+        self.partial_bodies.retain(|request_id, partial| {
+            if now.duration_since(partial.started) < timeout {
+                return true;
+            }
+            if partial.body.take().is_none() {
+                return false;
+            }
+            partial.started = now;
+            if !partial.no_reply {
+                expired.push(request_id.clone());
+            }
+            true
+        });
+        for request_id in expired {
+            log::debug!("call {} did not get its last frame in time", request_id);
+            let _ = self.writer.write(GsbMessage::CallReply(CallReply {
+                request_id,
+                code: CallReplyCode::ServiceFailure as i32,
+                reply_type: CallReplyType::Full as i32,
+                data: "call body timed out".into(),
+                trailers: Default::default(),
+            }));
+        }
+    }
+
+    /// Fails incoming call without invoking the handler, pushes are dropped.
+    fn reject_call(&mut self, request: CallRequest, reason: &str) {
+        log::debug!(
+            "{} calling {} from {}",
+            reason,
+            request.address,
+            request.caller
+        );
+        if request.no_reply {
+            return;
+        }
+        let _ = self.writer.write(GsbMessage::CallReply(CallReply {
+            request_id: request.request_id,
+            code: CallReplyCode::ServiceFailure as i32,
+            reply_type: CallReplyType::Full as i32,
+            data: reason.into(),
+            trailers: Default::default(),
+        }));
+    }
+
+    fn handle_reply(
+        &mut self,
+        request_id: String,
+        code: i32,
+        reply_type: i32,
+        data: Vec<u8>,
+        trailers: HashMap<String, Vec<u8>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        log::trace!(
+            "handling reply for request_id={}, code={}, reply_type={}",
+            request_id,
+            code,
+            reply_type
+        );
+
+        let chunk = if reply_type == CallReplyType::Partial as i32 {
+            ResponseChunk::Part(data)
+        } else {
+            ResponseChunk::Full(data)
+        };
+
+        let is_full = chunk.is_full();
+
+        let limit = self
+            .config
+            .max_reply_bytes
+            .filter(|_| self.call_reply.contains_key(&request_id));
+        if let Some(limit) = limit {
+            let received = self.reply_bytes.entry(request_id.clone()).or_default();
+            *received += chunk.data().len();
+            if *received > limit {
+                log::warn!("reply to {} exceeded {} bytes", request_id, limit);
+                let _ = self.reply_bytes.remove(&request_id);
+                if let Some(r) = self.call_reply.remove(&request_id) {
+                    let _ = r
+                        .reply
+                        .unbounded_send(Err(Error::GsbFailure("reply too large".into())));
+                    if !is_full && self.peer_supports(ya_sb_proto::CAP_CALL_CANCEL) {
+                        let _ = self
+                            .writer
+                            .write(GsbMessage::CallCancel(CallCancel { request_id }));
+                    }
+                }
+                return Ok(());
+            }
+        }
+
+        let overflow = self
+            .call_reply
+            .get(&request_id)
+            .and_then(|r| r.queued.as_ref())
+            .zip(self.config.max_queued_reply_chunks)
+            .map(|(queued, limit)| queued.fetch_add(1, Ordering::Relaxed) >= limit)
+            .unwrap_or(false);
+        if overflow {
+            log::warn!("caller does not keep up with reply to {}", request_id);
+            if let Some(call) = self.cancel_call(request_id) {
+                let _ = call
+                    .reply
+                    .unbounded_send(Err(Error::GsbFailure("reply queue full".into())));
+            }
+            return Ok(());
+        }
+
+        if let Some(r) = self.call_reply.get(&request_id) {
+            let code: CallReplyCode = code.try_into()?;
+            let item = match code {
+                CallReplyCode::CallReplyOk => Ok(chunk),
+                CallReplyCode::CallReplyBadRequest => {
+                    Err(Error::GsbBadRequest(String::from_utf8(chunk.into_bytes())?))
+                }
+                CallReplyCode::ServiceFailure => {
+                    Err(Error::GsbFailure(String::from_utf8(chunk.into_bytes())?))
+                }
+            };
+            // Queued without spawning, every call has single task draining
+            // its replies in order (see `Handler<RpcRawStreamCall>`).
+            r.reply
+                .unbounded_send(item)
+                .unwrap_or_else(|e| log::warn!("undelivered reply: {}", e));
+        } else {
+            log::debug!("unmatched call reply");
+        }
+
+        if is_full {
+            let call = self.call_reply.remove(&request_id);
+            let _ = self.reply_bytes.remove(&request_id);
+            if let Some(tx) = call.and_then(|call| call.trailers) {
+                let _ = tx.send(trailers.into_iter().collect());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<W, H> Actor for Connection<W, H>
+where
     W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
     H: CallRequestHandler + 'static,
 {
-    fn error(&mut self, err: ProtocolError, _ctx: &mut Self::Context) -> Running {
-        log::error!("protocol error: {}", err);
-        Running::Stop
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        log::info!("started connection to gsb");
+        if let Some(interval) = self.config.ping_interval {
+            let _ = ctx.run_interval(interval, move |act, ctx| act.check_heartbeat(interval, ctx));
+        }
+        let partial_call_timeout = self.config.partial_call_timeout;
+        if !partial_call_timeout.is_zero() {
+            let _ = ctx.run_interval(partial_call_timeout / 2, |act, _ctx| {
+                act.expire_partial_bodies()
+            });
+        }
+        if let Some(timeout) = self.config.write_stall_timeout {
+            let _ = ctx.run_interval(timeout / 2, move |act, ctx| {
+                act.check_write_progress(timeout, ctx)
+            });
+        }
+        let hello: ya_sb_proto::Hello = ya_sb_proto::Hello {
+            name: self.client_info.name.clone(),
+            version: self
+                .client_info
+                .version
+                .as_ref()
+                .map(|v| v.to_string())
+                .unwrap_or_default(),
+            instance_id: self.client_info.instance_id.clone(),
+            auth_token: self.client_info.auth_token.clone().unwrap_or_default(),
+            capabilities: vec![
+                ya_sb_proto::CAP_CHUNKED_BODY.to_string(),
+                ya_sb_proto::CAP_STREAM_CREDIT.to_string(),
+                ya_sb_proto::CAP_CALL_CANCEL.to_string(),
+            ],
+            proto_version: ya_sb_proto::PROTO_VERSION,
+            min_proto_version: ya_sb_proto::MIN_PROTO_VERSION,
+        };
+
+        let _ = self.writer.write(GsbMessage::Hello(hello));
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        let reason = self
+            .disconnect_reason
+            .take()
+            .unwrap_or(DisconnectReason::LocalStop);
+        log::info!("stopped connection to gsb: {:?}", reason);
+        self.handler.on_disconnect(reason);
     }
 }
 
-impl<W, H> Handler<RpcRawCall> for Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
-{
-    type Result = ActorResponse<Self, Result<Vec<u8>, Error>>;
+/// Headers sent with outgoing call, without ones describing the call being
+/// handled.
+fn outgoing_headers(mut headers: Headers) -> HashMap<String, Vec<u8>> {
+    let _ = headers.remove(REQUEST_ID_HEADER);
+    headers.into_iter().collect()
+}
+
+fn register_reply_code(code: i32) -> Option<RegisterReplyCode> {
+    Some(match code {
+        0 => RegisterReplyCode::RegisteredOk,
+        400 => RegisterReplyCode::RegisterBadRequest,
+        409 => RegisterReplyCode::RegisterConflict,
+        _ => return None,
+    })
+}
+
+fn unregister_reply_code(code: i32) -> Option<UnregisterReplyCode> {
+    Some(match code {
+        0 => UnregisterReplyCode::UnregisteredOk,
+        404 => UnregisterReplyCode::NotRegistered,
+        _ => return None,
+    })
+}
+
+fn subscribe_reply_code(code: i32) -> Option<SubscribeReplyCode> {
+    Some(match code {
+        0 => SubscribeReplyCode::SubscribedOk,
+        400 => SubscribeReplyCode::SubscribeBadRequest,
+        _ => return None,
+    })
+}
+
+fn unsubscribe_reply_code(code: i32) -> Option<UnsubscribeReplyCode> {
+    Some(match code {
+        0 => UnsubscribeReplyCode::UnsubscribedOk,
+        404 => UnsubscribeReplyCode::NotSubscribed,
+        _ => return None,
+    })
+}
+
+fn broadcast_reply_code(code: i32) -> Option<BroadcastReplyCode> {
+    Some(match code {
+        0 => BroadcastReplyCode::BroadcastOk,
+        400 => BroadcastReplyCode::BroadcastBadRequest,
+        _ => return None,
+    })
+}
+
+impl<W, H> StreamHandler<Result<GsbMessage, ProtocolError>> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    fn handle(&mut self, item: Result<GsbMessage, ProtocolError>, ctx: &mut Self::Context) {
+        self.last_packet = now();
+        if let Err(e) = item.as_ref() {
+            log::error!("protocol error {}", e);
+            self.stop_with(DisconnectReason::ProtocolError(e.to_string()), ctx);
+            return;
+        }
+
+        let msg = item.unwrap();
+        if let Some(tap) = &mut self.writer.tap {
+            tap.record(TapDirection::Inbound, &msg);
+        }
+        self.bytes_read += msg.frame_len() as u64;
+        self.messages_read += 1;
+
+        match msg {
+            GsbMessage::RegisterReply(r) => {
+                if let Some(code) = register_reply_code(r.code) {
+                    self.handle_register_reply(code, r.message, ctx)
+                } else {
+                    log::error!("invalid reply code {}", r.code);
+                    self.stop_with(DisconnectReason::InvalidReplyCode(r.code), ctx);
+                }
+            }
+            GsbMessage::UnregisterReply(r) => {
+                if let Some(code) = unregister_reply_code(r.code) {
+                    self.handle_unregister_reply(code, ctx)
+                } else {
+                    log::error!("invalid unregister reply code {}", r.code);
+                    self.stop_with(DisconnectReason::InvalidReplyCode(r.code), ctx);
+                }
+            }
+            GsbMessage::SubscribeReply(r) => {
+                if let Some(code) = subscribe_reply_code(r.code) {
+                    self.handle_subscribe_reply(code, r.message, ctx)
+                } else {
+                    log::error!("invalid reply code {}", r.code);
+                    self.stop_with(DisconnectReason::InvalidReplyCode(r.code), ctx);
+                }
+            }
+            GsbMessage::UnsubscribeReply(r) => {
+                if let Some(code) = unsubscribe_reply_code(r.code) {
+                    self.handle_unsubscribe_reply(code, ctx)
+                } else {
+                    log::error!("invalid unsubscribe reply code {}", r.code);
+                    self.stop_with(DisconnectReason::InvalidReplyCode(r.code), ctx);
+                }
+            }
+            GsbMessage::BroadcastReply(r) => {
+                if let Some(code) = broadcast_reply_code(r.code) {
+                    self.handle_broadcast_reply(code, r.message, r.receivers, ctx)
+                } else {
+                    log::error!("invalid broadcast reply code {}", r.code);
+                    self.stop_with(DisconnectReason::InvalidReplyCode(r.code), ctx);
+                }
+            }
+            GsbMessage::CallRequest(mut r) => {
+                if (r.has_more || self.partial_bodies.contains_key(&r.request_id))
+                    && !self.collect_body(&mut r)
+                {
+                    return;
+                }
+                let deadline = match &r.deadline {
+                    Some(CallDeadline { remaining_ms: 0 }) => {
+                        self.reject_call(r, "deadline exceeded");
+                        return;
+                    }
+                    Some(d) => Some(now() + Duration::from_millis(d.remaining_ms)),
+                    None => None,
+                };
+                if let Some(limiter) = &mut self.limiter {
+                    if !limiter.try_acquire(&r.caller, now()) {
+                        self.reject_call(r, "rate limited");
+                        return;
+                    }
+                }
+                if r.no_reply {
+                    self.handle_push_request(r, deadline, ctx)
+                } else {
+                    self.handle_call_request(r, deadline, ctx)
+                }
+            }
+            GsbMessage::CallReply(r) => {
+                if let Err(e) =
+                    self.handle_reply(r.request_id, r.code, r.reply_type, r.data, r.trailers)
+                {
+                    log::error!("error on call reply processing: {}", e);
+                    let reason = match e.downcast_ref::<ya_sb_proto::EnumError>() {
+                        Some(ya_sb_proto::EnumError(code)) => {
+                            DisconnectReason::InvalidReplyCode(*code)
+                        }
+                        None => DisconnectReason::ProtocolError(e.to_string()),
+                    };
+                    self.stop_with(reason, ctx);
+                }
+            }
+            GsbMessage::CallCancel(c) => {
+                let _ = self.partial_bodies.remove(&c.request_id);
+                if let Some(handle) = self.running_calls.remove(&c.request_id) {
+                    log::debug!("call {} cancelled by caller", c.request_id);
+                    let _ = self.credit_grants.remove(&c.request_id);
+                    let _ = ctx.cancel_future(handle);
+                }
+            }
+            GsbMessage::StreamCredit(c) => match self.credit_grants.get(&c.request_id) {
+                Some(tx) => {
+                    let _ = tx.unbounded_send(c.credits);
+                }
+                None => log::debug!("credit for finished stream {}", c.request_id),
+            },
+            GsbMessage::BroadcastRequest(r) => match self.topic_subscriptions.get(&r.topic) {
+                Some(subscription) if !subscription.accepts(&r.data) => {
+                    log::trace!("broadcast on {} filtered out", r.topic)
+                }
+                _ => self.handler.handle_event(r.caller, r.topic, r.data),
+            },
+            // Write queue is unbounded and pong goes ahead of queued data,
+            // so it is refused only once the transport is closing.
+            GsbMessage::Ping(_) => {
+                if self.writer.write(GsbMessage::pong()).is_some() {
+                    log::error!("error sending pong: transport closing");
+                    self.stop_with(
+                        DisconnectReason::WriteError("transport closing".to_string()),
+                        ctx,
+                    );
+                }
+            }
+            GsbMessage::Hello(h) if !h.is_proto_compatible() => {
+                let msg = format!(
+                    "incompatible gsb protocol: router speaks {}..={}, we speak {}..={}",
+                    h.min_proto_version,
+                    h.proto_version,
+                    ya_sb_proto::MIN_PROTO_VERSION,
+                    ya_sb_proto::PROTO_VERSION
+                );
+                log::error!("{}", msg);
+                self.reject_pending(|| Error::GsbFailure(msg.clone()));
+                self.stop_with(DisconnectReason::ProtocolError(msg), ctx);
+            }
+            GsbMessage::Hello(h) => {
+                log::debug!(
+                    "connected with server: {}/{} (protocol {})",
+                    h.name,
+                    h.version,
+                    h.negotiated_proto_version()
+                );
+                match self.server_info.take() {
+                    Some(old) if self.config.strict_hello => {
+                        log::error!("invalid packet: {:?}", h);
+                        self.server_info = Some(old);
+                        self.stop_with(
+                            DisconnectReason::ProtocolError("duplicate hello".to_string()),
+                            ctx,
+                        );
+                    }
+                    Some(old) => {
+                        log::warn!(
+                            "server re-announced: {}/{} -> {}/{}",
+                            old.name,
+                            old.version,
+                            h.name,
+                            h.version
+                        );
+                        self.handler.on_peer_reannounce(&old, &h);
+                        self.server_info = Some(h);
+                    }
+                    None => self.server_info = Some(h),
+                }
+            }
+            GsbMessage::HelloReject(r) => {
+                log::error!("connection rejected by gsb: {}", r.message);
+                if r.code == HelloRejectCode::HelloRejectInstanceConflict as i32 {
+                    let id = uuid::Uuid::from_slice(&self.client_info.instance_id)
+                        .map(|id| id.to_string())
+                        .unwrap_or_default();
+                    self.reject_pending(|| {
+                        Error::GsbAlreadyRegistered(format!("instance_id {}", id))
+                    });
+                    self.stop_with(DisconnectReason::InstanceConflict, ctx);
+                } else {
+                    self.reject_pending(|| Error::AuthRejected(r.message.clone()));
+                    self.stop_with(DisconnectReason::AuthRejected(r.message), ctx);
+                }
+            }
+            m if self.config.strict_messages => {
+                log::error!("unexpected gsb message: {:?}", m);
+                self.stop_with(
+                    DisconnectReason::ProtocolError("unexpected gsb message".to_string()),
+                    ctx,
+                );
+            }
+            m => log::warn!("ignoring unexpected gsb message: {}", m.kind()),
+        }
+    }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        self.stop_with(DisconnectReason::CleanEof, ctx);
+    }
+}
+
+impl<W, H> io::WriteHandler<ProtocolError> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    fn error(&mut self, err: ProtocolError, _ctx: &mut Self::Context) -> Running {
+        log::error!("protocol error: {}", err);
+        self.disconnect_reason
+            .get_or_insert_with(|| DisconnectReason::WriteError(err.to_string()));
+        Running::Stop
+    }
+}
+
+impl<W, H> Handler<RpcRawCall> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ActorResponse<Self, Result<Vec<u8>, Error>>;
+
+    fn handle(&mut self, msg: RpcRawCall, _ctx: &mut Self::Context) -> Self::Result {
+        self.start_call(msg, format!("{}", gen_id()), None)
+    }
+}
+
+struct CallWithTrailers(RpcRawCall, String, oneshot::Sender<Headers>);
+
+impl Message for CallWithTrailers {
+    type Result = Result<Vec<u8>, Error>;
+}
+
+impl<W, H> Handler<CallWithTrailers> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ActorResponse<Self, Result<Vec<u8>, Error>>;
+
+    fn handle(&mut self, msg: CallWithTrailers, _ctx: &mut Self::Context) -> Self::Result {
+        self.start_call(msg.0, msg.1, Some(msg.2))
+    }
+}
+
+impl<W, H> Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    fn start_call(
+        &mut self,
+        msg: RpcRawCall,
+        request_id: String,
+        trailers: Option<oneshot::Sender<Headers>>,
+    ) -> ActorResponse<Self, Result<Vec<u8>, Error>> {
+        if msg.is_expired() {
+            return ActorResponse::reply(Err(Error::Timeout(msg.addr)));
+        }
+        if !msg.no_reply && self.in_flight_limit_reached() {
+            return ActorResponse::reply(Err(Error::GsbFailure(
+                "too many calls in flight".to_string(),
+            )));
+        }
+        let caller = msg.caller;
+        let address = msg.addr;
+        let data = msg.body;
+        let no_reply = msg.no_reply;
+        let collect_parts = msg.collect_parts;
+        let deadline = match self.config.call_timeout {
+            Some(timeout) if !no_reply => msg.deadline.or_else(|| Some(now() + timeout)),
+            _ => msg.deadline,
+        };
+        let headers = outgoing_headers(msg.headers);
+
+        let rx = if no_reply {
+            None
+        } else {
+            let (tx, rx) = mpsc::unbounded();
+            let call = PendingCall {
+                trailers,
+                ..PendingCall::new(tx, &address, CallKind::Unary)
+            };
+            let _ = self.call_reply.insert(request_id.clone(), call);
+            Some(rx)
+        };
+
+        log::trace!("handling caller (rpc): {}, addr:{}", caller, address);
+        let timeout_addr = address.clone();
+        let cancel_id = request_id.clone();
+        let request = CallRequest {
+            request_id,
+            caller,
+            address,
+            no_reply,
+            deadline: deadline.map(|deadline| CallDeadline {
+                remaining_ms: deadline.saturating_duration_since(now()).as_millis() as u64,
+            }),
+            headers,
+            ..Default::default()
+        };
+        self.write_call(request, data);
+
+        match rx {
+            Some(mut rx) => {
+                let fetch_response = async move {
+                    let mut body = Vec::new();
+                    loop {
+                        match futures::StreamExt::next(&mut rx).await {
+                            Some(Ok(ResponseChunk::Full(data))) if body.is_empty() => {
+                                return Ok(data)
+                            }
+                            Some(Ok(ResponseChunk::Full(data))) => {
+                                body.extend_from_slice(&data);
+                                return Ok(body);
+                            }
+                            Some(Ok(ResponseChunk::Part(data))) if collect_parts => {
+                                body.extend_from_slice(&data)
+                            }
+                            Some(Err(e)) => return Err(e),
+                            Some(Ok(ResponseChunk::Part(_))) => {
+                                return Err(Error::GsbFailure("streaming response".to_string()))
+                            }
+                            None => return Err(Error::GsbFailure("unexpected EOS".to_string())),
+                        }
+                    }
+                };
+                let fetch_response = async move {
+                    match deadline {
+                        Some(deadline) => tokio::time::timeout_at(deadline.into(), fetch_response)
+                            .await
+                            .unwrap_or(Err(Error::Timeout(timeout_addr))),
+                        None => fetch_response.await,
+                    }
+                };
+                ActorResponse::r#async(fetch_response.into_actor(self).map(
+                    move |result, act, _ctx| {
+                        // Forget call given up on, e.g. on timeout, otherwise it
+                        // keeps counting towards `max_in_flight_calls`.
+                        if result.is_err() {
+                            let _ = act.cancel_call(cancel_id);
+                        }
+                        result
+                    },
+                ))
+            }
+            None => ActorResponse::reply(Ok(Vec::new())),
+        }
+    }
+}
+
+impl<W, H> Handler<RpcRawStreamCall> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ActorResponse<Self, Result<(), Error>>;
+
+    fn handle(&mut self, msg: RpcRawStreamCall, ctx: &mut Self::Context) -> Self::Result {
+        if self.in_flight_limit_reached() {
+            return ActorResponse::reply(Err(Error::GsbFailure(
+                "too many calls in flight".to_string(),
+            )));
+        }
+        let request_id = format!("{}", gen_id());
+        let window = msg
+            .window
+            .or(self.config.stream_window)
+            .filter(|&n| n > 0 && self.peer_supports(ya_sb_proto::CAP_STREAM_CREDIT));
+        // Chunks are queued in arrival order and drained by single task.
+        // Without window nothing slows the callee down, chunks the caller
+        // does not keep up with wait here up to `max_queued_reply_chunks`.
+        // With window set, callee is granted credit only for delivered chunks.
+        let (tx, mut rx) = mpsc::unbounded();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let taken = queued.clone();
+        let mut reply = msg.reply;
+        let connection = ctx.address();
+        let credit_id = request_id.clone();
+        let batch = window.map(|n| (n / 2).max(1));
+        let _ = ctx.spawn(
+            async move {
+                let mut delivered = 0;
+                while let Some(item) = rx.next().await {
+                    let sent = reply.send(item).await;
+                    let _ = taken.fetch_sub(1, Ordering::Relaxed);
+                    if sent.is_err() {
+                        log::debug!("stream {} dropped by caller", credit_id);
+                        connection.do_send(CancelCall {
+                            request_id: credit_id,
+                        });
+                        break;
+                    }
+                    delivered += 1;
+                    if Some(delivered) == batch {
+                        connection.do_send(GrantCredit {
+                            request_id: credit_id.clone(),
+                            credits: delivered,
+                        });
+                        delivered = 0;
+                    }
+                }
+            }
+            .into_actor(self),
+        );
+        let caller = msg.caller;
+        let address = msg.addr;
+        let data = msg.body;
+        let call = PendingCall {
+            queued: Some(queued),
+            ..PendingCall::new(tx, &address, CallKind::Streaming)
+        };
+        let _ = self.call_reply.insert(request_id.clone(), call);
+        log::trace!("handling caller (stream): {}, addr:{}", caller, address);
+        let request = CallRequest {
+            request_id,
+            caller,
+            address,
+            window: window.unwrap_or_default(),
+            headers: outgoing_headers(msg.headers),
+            ..Default::default()
+        };
+        self.write_call(request, data);
+        ActorResponse::reply(Ok(()))
+    }
+}
+
+struct CancelCall {
+    request_id: String,
+}
+
+impl Message for CancelCall {
+    type Result = ();
+}
+
+impl<W, H> Handler<CancelCall> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: CancelCall, _ctx: &mut Self::Context) -> Self::Result {
+        let _ = self.cancel_call(msg.request_id);
+    }
+}
+
+struct CancelInFlight {
+    request_id: String,
+}
+
+impl Message for CancelInFlight {
+    type Result = bool;
+}
+
+impl<W, H> Handler<CancelInFlight> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = bool;
+
+    fn handle(&mut self, msg: CancelInFlight, _ctx: &mut Self::Context) -> Self::Result {
+        match self.cancel_call(msg.request_id) {
+            Some(call) => {
+                let _ = call.reply.unbounded_send(Err(Error::Cancelled));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+struct GetInFlight;
+
+impl Message for GetInFlight {
+    type Result = Vec<InFlightInfo>;
+}
+
+impl<W, H> Handler<GetInFlight> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = MessageResult<GetInFlight>;
+
+    fn handle(&mut self, _: GetInFlight, _ctx: &mut Self::Context) -> Self::Result {
+        let now = now();
+        MessageResult(
+            self.call_reply
+                .iter()
+                .map(|(request_id, call)| InFlightInfo {
+                    request_id: request_id.clone(),
+                    address: call.address.clone(),
+                    age: now.saturating_duration_since(call.started),
+                    kind: call.kind,
+                })
+                .collect(),
+        )
+    }
+}
+
+struct GrantCredit {
+    request_id: String,
+    credits: u32,
+}
+
+impl Message for GrantCredit {
+    type Result = ();
+}
+
+impl<W, H> Handler<GrantCredit> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: GrantCredit, _ctx: &mut Self::Context) -> Self::Result {
+        // reply already finished
+        if !self.call_reply.contains_key(&msg.request_id) {
+            return;
+        }
+        let _ = self.writer.write(GsbMessage::StreamCredit(StreamCredit {
+            request_id: msg.request_id,
+            credits: msg.credits,
+        }));
+    }
+}
+
+struct Bind {
+    addr: String,
+}
+
+impl Message for Bind {
+    type Result = Result<(), Error>;
+}
+
+impl<W, H> Handler<Bind> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ActorResponse<Self, Result<(), Error>>;
+
+    fn handle(&mut self, msg: Bind, ctx: &mut Self::Context) -> Self::Result {
+        let service_id = msg.addr;
+        self.send_cmd_async(
+            |c| &mut c.register_reply,
+            "register",
+            service_id.clone(),
+            GsbMessage::RegisterRequest(RegisterRequest { service_id }),
+            ctx,
+        )
+    }
+}
+
+struct Unbind {
+    addr: String,
+}
+
+impl Message for Unbind {
+    type Result = Result<(), Error>;
+}
+
+impl<W, H> Handler<Unbind> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ActorResponse<Self, Result<(), Error>>;
+
+    fn handle(&mut self, msg: Unbind, ctx: &mut Self::Context) -> Self::Result {
+        let service_id = msg.addr;
+        self.send_cmd_async(
+            |c| &mut c.unregister_reply,
+            "unregister",
+            service_id.clone(),
+            GsbMessage::UnregisterRequest(UnregisterRequest { service_id }),
+            ctx,
+        )
+    }
+}
+
+struct Subscribe {
+    topic: String,
+    filter: Option<EventFilter>,
+}
+
+impl Message for Subscribe {
+    /// Id of the subscription handle.
+    type Result = Result<u64, Error>;
+}
+
+impl<W, H> Handler<Subscribe> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ActorResponse<Self, Result<u64, Error>>;
+
+    fn handle(&mut self, msg: Subscribe, ctx: &mut Self::Context) -> Self::Result {
+        let topic = msg.topic;
+        let filter = msg.filter;
+        self.last_subscription_id += 1;
+        let id = self.last_subscription_id;
+
+        // Router takes single subscription per topic, further subscribers
+        // share it.
+        if let Some(subscription) = self.topic_subscriptions.get_mut(&topic) {
+            let waiters = match &mut subscription.pending {
+                Some(waiters) => waiters,
+                None => {
+                    subscription.handles.push((id, filter));
+                    return ActorResponse::reply(Ok(id));
+                }
+            };
+            let (tx, rx) = oneshot::channel();
+            waiters.push(tx);
+            return ActorResponse::r#async(fut::wrap_future(rx).map(
+                move |confirmed, act: &mut Self, _ctx| match confirmed {
+                    Ok(true) => {
+                        act.topic_subscriptions
+                            .entry(topic)
+                            .or_default()
+                            .handles
+                            .push((id, filter));
+                        Ok(id)
+                    }
+                    _ => Err(Error::GsbFailure(format!("subscribing {} failed", topic))),
+                },
+            ));
+        }
+
+        let _ = self.topic_subscriptions.insert(
+            topic.clone(),
+            TopicSubscription {
+                handles: Vec::new(),
+                pending: Some(Vec::new()),
+            },
+        );
+        let confirmed = self.send_cmd(
+            |c| &mut c.subscribe_reply,
+            "subscribe",
+            topic.clone(),
+            GsbMessage::SubscribeRequest(SubscribeRequest {
+                topic: topic.clone(),
+            }),
+            ctx,
+        );
+        ActorResponse::r#async(fut::wrap_future(confirmed).map(
+            move |result, act: &mut Self, _ctx| {
+                let waiters = match result {
+                    Ok(()) => {
+                        let subscription = act.topic_subscriptions.entry(topic).or_default();
+                        subscription.handles.push((id, filter));
+                        subscription.pending.take()
+                    }
+                    Err(_) => act
+                        .topic_subscriptions
+                        .remove(&topic)
+                        .and_then(|subscription| subscription.pending),
+                };
+                for waiter in waiters.into_iter().flatten() {
+                    let _ = waiter.send(result.is_ok());
+                }
+                result.map(|()| id)
+            },
+        ))
+    }
+}
+
+struct Unsubscribe {
+    topic: String,
+    // Handle giving up its share of the subscription, `None` drops the
+    // subscription regardless of other handles.
+    handle: Option<u64>,
+}
+
+impl Message for Unsubscribe {
+    type Result = Result<(), Error>;
+}
+
+/// Subscription to broadcasts on a topic, unsubscribed when dropped.
+///
+/// Handles of the same topic share single router subscription, it ends
+/// when the last of them is dropped.
+#[must_use = "dropping the handle unsubscribes"]
+pub struct SubscriptionHandle {
+    topic: String,
+    id: u64,
+    connection: Option<Recipient<Unsubscribe>>,
+}
+
+impl SubscriptionHandle {
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Unsubscribes, reporting whether the router accepted it.
+    pub fn unsubscribe(mut self) -> impl Future<Output = Result<(), Error>> + 'static {
+        let topic = std::mem::take(&mut self.topic);
+        let handle = Some(self.id);
+        let connection = self.connection.take();
+        async move {
+            match connection {
+                Some(connection) => connection
+                    .send(Unsubscribe {
+                        topic: topic.clone(),
+                        handle,
+                    })
+                    .await
+                    .map_err(|e| Error::from_addr(format!("unsubscribing {}", topic), e))?,
+                None => Ok(()),
+            }
+        }
+    }
+
+    /// Keeps the subscription for the lifetime of the connection.
+    pub fn leak(mut self) {
+        self.connection = None;
+    }
+}
+
+impl Drop for SubscriptionHandle {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            connection.do_send(Unsubscribe {
+                topic: std::mem::take(&mut self.topic),
+                handle: Some(self.id),
+            });
+        }
+    }
+}
+
+impl<W, H> Handler<Unsubscribe> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ActorResponse<Self, Result<(), Error>>;
+
+    fn handle(&mut self, msg: Unsubscribe, ctx: &mut Self::Context) -> Self::Result {
+        let topic = msg.topic;
+        if let Some(id) = msg.handle {
+            let last = match self.topic_subscriptions.get_mut(&topic) {
+                Some(subscription) => {
+                    subscription.handles.retain(|(handle, _)| *handle != id);
+                    subscription.handles.is_empty() && subscription.pending.is_none()
+                }
+                // already unsubscribed with `ConnectionRef::unsubscribe`
+                None => false,
+            };
+            if !last {
+                return ActorResponse::reply(Ok(()));
+            }
+        }
+        let _ = self.topic_subscriptions.remove(&topic);
+        self.send_cmd_async(
+            |c| &mut c.unsubscribe_reply,
+            "unsubscribe",
+            topic.clone(),
+            GsbMessage::UnsubscribeRequest(UnsubscribeRequest { topic }),
+            ctx,
+        )
+    }
+}
+
+pub struct BcastCall {
+    pub caller: String,
+    pub topic: String,
+    pub body: Vec<u8>,
+}
+
+impl Message for BcastCall {
+    type Result = Result<(), Error>;
+}
+
+impl<W, H> Handler<BcastCall> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ActorResponse<Self, Result<(), Error>>;
+
+    fn handle(&mut self, msg: BcastCall, ctx: &mut Self::Context) -> Self::Result {
+        let caller = msg.caller;
+        let topic = msg.topic;
+        let data = msg.body;
+        self.send_cmd_async(
+            |c| &mut c.broadcast_reply,
+            "broadcast",
+            topic.clone(),
+            GsbMessage::BroadcastRequest(BroadcastRequest {
+                caller,
+                topic,
+                data,
+            }),
+            ctx,
+        )
+    }
+}
+
+struct GetStats;
+
+impl Message for GetStats {
+    type Result = ConnectionStats;
+}
+
+impl<W, H> Handler<GetStats> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = MessageResult<GetStats>;
+
+    fn handle(&mut self, _: GetStats, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(ConnectionStats {
+            in_flight_calls: self.call_reply.len(),
+            pending_register: self.register_reply.len(),
+            pending_unregister: self.unregister_reply.len(),
+            pending_subscribe: self.subscribe_reply.len(),
+            pending_unsubscribe: self.unsubscribe_reply.len(),
+            pending_broadcast: self.broadcast_reply.len(),
+            unmatched_replies: self.unmatched_replies.count,
+            reply_timeouts: self.reply_timeouts,
+            lagging_replies: self.lagging_replies,
+            bytes_written: self.writer.bytes_written,
+            bytes_read: self.bytes_read,
+            messages_written: self.writer.messages_written,
+            messages_read: self.messages_read,
+            broadcasts: self.broadcasts.clone(),
+        })
+    }
+}
+
+struct SubscribeEvents(mpsc::UnboundedSender<ConnectionEvent>);
+
+impl Message for SubscribeEvents {
+    type Result = ();
+}
+
+impl<W, H> Handler<SubscribeEvents> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribeEvents, _ctx: &mut Self::Context) -> Self::Result {
+        self.event_subscribers.push(msg.0);
+    }
+}
+
+struct Flush(oneshot::Sender<()>);
+
+impl Message for Flush {
+    type Result = ();
+}
+
+impl<W, H> Handler<Flush> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: Flush, _ctx: &mut Self::Context) -> Self::Result {
+        if self.writer.unflushed_since.is_none() {
+            let _ = msg.0.send(());
+        } else {
+            self.flush_waiters.push(msg.0);
+        }
+    }
+}
+
+struct Close;
+
+impl Message for Close {
+    type Result = ();
+}
+
+impl<W, H> Handler<Close> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) -> Self::Result {
+        self.stop_with(DisconnectReason::LocalStop, ctx);
+    }
+}
+
+struct GetSubscriptions;
+
+impl Message for GetSubscriptions {
+    type Result = Vec<String>;
+}
+
+impl<W, H> Handler<GetSubscriptions> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = MessageResult<GetSubscriptions>;
+
+    fn handle(&mut self, _: GetSubscriptions, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.subscriptions.iter().cloned().collect())
+    }
+}
+
+struct GetServerInfo;
+
+impl Message for GetServerInfo {
+    type Result = Option<ServerInfo>;
+}
+
+impl<W, H> Handler<GetServerInfo> for Connection<W, H>
+where
+    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    type Result = MessageResult<GetServerInfo>;
+
+    fn handle(&mut self, _: GetServerInfo, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.server_info.as_ref().map(ServerInfo::from))
+    }
+}
+
+/// Delivery settings for `ConnectionRef::push_with`.
+#[non_exhaustive]
+pub struct PushOptions {
+    /// Wait for the handler to acknowledge the message, retrying on failure.
+    pub acked: bool,
+    /// Number of redelivery attempts after the first one failed.
+    pub max_retries: u32,
+    /// How long single attempt may wait for the acknowledgement.
+    pub timeout: Duration,
+    /// Delay before each redelivery, giving up early when it returns `None`.
+    pub backoff: Box<dyn Backoff + Send>,
+}
+
+impl Default for PushOptions {
+    fn default() -> Self {
+        PushOptions {
+            acked: false,
+            max_retries: 3,
+            timeout: Duration::from_secs(5),
+            backoff: Box::new(ExponentialBackoff::default()),
+        }
+    }
+}
+
+impl std::fmt::Debug for PushOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PushOptions")
+            .field("acked", &self.acked)
+            .field("max_retries", &self.max_retries)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PushOptions {
+    pub fn acked() -> Self {
+        PushOptions {
+            acked: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: impl Backoff + Send + 'static) -> Self {
+        self.backoff = Box::new(backoff);
+        self
+    }
+}
+
+pub struct ConnectionRef<
+    Transport: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+>(
+    Addr<Connection<SplitSink<Transport, GsbMessage>, H>>,
+    Arc<ClientInfo>,
+);
+
+impl<
+        Transport: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+        H: CallRequestHandler + 'static,
+    > Unpin for ConnectionRef<Transport, H>
+{
+}
+
+impl<
+        Transport: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+        H: CallRequestHandler + 'static,
+    > Clone for ConnectionRef<Transport, H>
+{
+    fn clone(&self) -> Self {
+        ConnectionRef(self.0.clone(), self.1.clone())
+    }
+}
+
+impl<
+        Transport: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+        H: CallRequestHandler + Unpin + 'static,
+    > ConnectionRef<Transport, H>
+{
+    pub fn bind(
+        &self,
+        addr: impl Into<String>,
+    ) -> impl Future<Output = Result<(), Error>> + 'static {
+        let addr = addr.into();
+        log::trace!("Binding remote service '{}'", addr);
+        self.0.send(Bind { addr: addr.clone() }).then(|v| async {
+            log::trace!("send bind result: {:?}", v);
+            v.map_err(|e| Error::from_addr(addr, e))?
+        })
+    }
+
+    /// Binds all `addrs` at once. Register requests are sent back to back
+    /// and their replies awaited together, taking single round-trip instead
+    /// of one per address.
+    pub fn bind_all(
+        &self,
+        addrs: impl IntoIterator<Item = impl Into<String>>,
+    ) -> impl Future<Output = Vec<(String, Result<(), Error>)>> + 'static {
+        future::join_all(addrs.into_iter().map(|addr| {
+            let addr = addr.into();
+            self.bind(addr.clone()).map(move |result| (addr, result))
+        }))
+    }
+
+    pub fn unbind(
+        &self,
+        addr: impl Into<String>,
+    ) -> impl Future<Output = Result<(), Error>> + 'static {
+        let addr = addr.into();
+        self.0.send(Unbind { addr: addr.clone() }).then(|v| async {
+            log::trace!("send unbind result: {:?}", v);
+            v.map_err(|e| Error::from_addr(addr, e))?
+        })
+    }
+
+    /// Subscribes to broadcasts on `topic`, until returned handle is dropped.
+    ///
+    /// Dropping the handle right away, as in `connection.subscribe(t).await?;`,
+    /// unsubscribes at once. Keep it, or call `SubscriptionHandle::leak` to
+    /// stay subscribed for the lifetime of the connection.
+    pub fn subscribe(
+        &self,
+        topic: impl Into<String>,
+    ) -> impl Future<Output = Result<SubscriptionHandle, Error>> + 'static {
+        self.subscribe_with(topic.into(), None)
+    }
+
+    /// Like `subscribe`, passing to the handler only broadcasts whose payload
+    /// matches `filter`. With several handles on the topic, broadcast is
+    /// passed when any of them wants it. The filter is dropped with the
+    /// handle, or right away when the subscription fails.
+    pub fn subscribe_filtered(
+        &self,
+        topic: impl Into<String>,
+        filter: impl Fn(&[u8]) -> bool + Send + 'static,
+    ) -> impl Future<Output = Result<SubscriptionHandle, Error>> + 'static {
+        self.subscribe_with(topic.into(), Some(Box::new(filter)))
+    }
+
+    fn subscribe_with(
+        &self,
+        topic: String,
+        filter: Option<EventFilter>,
+    ) -> impl Future<Output = Result<SubscriptionHandle, Error>> + 'static {
+        let fut = self.0.send(Subscribe {
+            topic: topic.clone(),
+            filter,
+        });
+        let connection = self.0.clone().recipient();
+        async move {
+            let id = fut
+                .await
+                .map_err(|e| Error::from_addr(format!("subscribing {}", topic), e))??;
+            Ok(SubscriptionHandle {
+                topic,
+                id,
+                connection: Some(connection),
+            })
+        }
+    }
+
+    pub fn unsubscribe(
+        &self,
+        topic: impl Into<String>,
+    ) -> impl Future<Output = Result<(), Error>> + 'static {
+        let topic = topic.into();
+        let fut = self.0.send(Unsubscribe {
+            topic: topic.clone(),
+            handle: None,
+        });
+        async move {
+            fut.await
+                .map_err(|e| Error::from_addr(format!("unsubscribing {}", topic), e))?
+        }
+    }
+
+    pub fn broadcast(
+        &self,
+        caller: impl Into<String>,
+        topic: impl Into<String>,
+        body: Vec<u8>,
+    ) -> impl Future<Output = Result<(), Error>> + 'static {
+        let topic = topic.into();
+        let fut = self.0.send(BcastCall {
+            caller: caller.into(),
+            topic: topic.clone(),
+            body,
+        });
+        async move {
+            fut.await
+                .map_err(|e| Error::from_addr(format!("broadcasting {}", topic), e))?
+        }
+    }
+
+    pub fn call(
+        &self,
+        caller: impl Into<String>,
+        addr: impl Into<String>,
+        body: impl Into<Bytes>,
+        no_reply: bool,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> {
+        self.call_raw(RpcRawCall::new(caller, addr, body, no_reply))
+    }
+
+    /// Like `call`, also returning id the call was sent with, to match it
+    /// with callee's logs. Ids of failed calls are logged with the error.
+    pub fn call_with_meta(
+        &self,
+        caller: impl Into<String>,
+        addr: impl Into<String>,
+        body: impl Into<Bytes>,
+        no_reply: bool,
+    ) -> impl Future<Output = Result<CallResponse, Error>> {
+        let request_id = format!("{}", gen_id());
+        let result = self.call_with_id(
+            RpcRawCall::new(caller, addr, body, no_reply),
+            request_id.clone(),
+        );
+        async move {
+            match result.await {
+                Ok(CallResult { body, .. }) => Ok(CallResponse { request_id, body }),
+                Err(e) => {
+                    log::debug!("call {} failed: {}", request_id, e);
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    /// Like `call_raw`, also returning trailers the callee attached to its
+    /// reply, see `CallContext::trailers`.
+    pub fn call_with_trailers(
+        &self,
+        msg: RpcRawCall,
+    ) -> impl Future<Output = Result<CallResult, Error>> {
+        self.call_with_id(msg, format!("{}", gen_id()))
+    }
+
+    fn call_with_id(
+        &self,
+        msg: RpcRawCall,
+        request_id: String,
+    ) -> impl Future<Output = Result<CallResult, Error>> {
+        let addr = msg.addr.clone();
+        let (tx, rx) = oneshot::channel();
+        let fut = self.0.send(CallWithTrailers(msg, request_id, tx));
+        async move {
+            let body = fut.await.map_err(|e| Error::from_addr(addr, e))??;
+            let trailers = rx.await.unwrap_or_default();
+            Ok(CallResult { body, trailers })
+        }
+    }
+
+    /// Sends prepared call, keeping its deadline and headers.
+    pub fn call_raw(&self, msg: RpcRawCall) -> impl Future<Output = Result<Vec<u8>, Error>> {
+        let addr = msg.addr.clone();
+        self.0
+            .send(msg)
+            .then(|v| async { v.map_err(|e| Error::from_addr(addr, e))? })
+    }
+
+    /// Sends prepared streaming call, chunks are delivered to `msg.reply`.
+    pub fn call_streaming_raw(
+        &self,
+        msg: RpcRawStreamCall,
+    ) -> impl Future<Output = Result<(), Error>> {
+        let addr = msg.addr.clone();
+        self.0
+            .send(msg)
+            .then(|v| async { v.map_err(|e| Error::from_addr(addr, e))? })
+    }
+
+    /// Fire-and-forget call.
+    pub fn push(
+        &self,
+        caller: impl Into<String>,
+        addr: impl Into<String>,
+        body: impl Into<Bytes>,
+    ) -> impl Future<Output = Result<(), Error>> + 'static {
+        self.push_with(caller, addr, body, PushOptions::default())
+    }
+
+    /// Call without response payload.
+    ///
+    /// With `PushOptions::acked` the message is sent as a regular call and
+    /// redelivered after `PushOptions::backoff` delay until the handler
+    /// replies or the retry budget is exhausted, so the handler may see it
+    /// more than once.
+    pub fn push_with(
+        &self,
+        caller: impl Into<String>,
+        addr: impl Into<String>,
+        body: impl Into<Bytes>,
+        opts: PushOptions,
+    ) -> impl Future<Output = Result<(), Error>> + 'static {
+        let connection = self.0.clone();
+        let caller = caller.into();
+        let addr = addr.into();
+        let body = body.into();
+        let PushOptions {
+            acked,
+            max_retries,
+            timeout,
+            mut backoff,
+        } = opts;
+
+        async move {
+            let mut attempt = 0;
+            loop {
+                // Attempt past its deadline is cancelled by the connection,
+                // so abandoned attempts do not pile up on either side.
+                let mut call = RpcRawCall::new(caller.clone(), addr.clone(), body.clone(), !acked);
+                call.deadline = acked.then(|| now() + timeout);
+                let result = connection
+                    .send(call)
+                    .await
+                    .map_err(|e| Error::from_addr(addr.clone(), e));
+
+                let e = match result {
+                    Ok(Ok(_)) => return Ok(()),
+                    Ok(Err(Error::GsbBadRequest(msg))) => return Err(Error::GsbBadRequest(msg)),
+                    Ok(Err(e)) | Err(e) => e,
+                };
+                if !acked || attempt >= max_retries {
+                    return Err(e);
+                }
+                match backoff.next_delay(attempt) {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => return Err(e),
+                }
+                attempt += 1;
+                log::debug!("redelivering push to {} ({}): {}", addr, attempt, e);
+            }
+        }
+    }
+
+    /// Like `call`, repeating it after `backoff` delay while it fails with
+    /// transient error, see `Error::is_transient`.
+    ///
+    /// The service may see the call more than once.
+    pub fn call_with_retry(
+        &self,
+        caller: impl Into<String>,
+        addr: impl Into<String>,
+        body: impl Into<Bytes>,
+        mut backoff: impl Backoff + 'static,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> + 'static {
+        let connection = self.clone();
+        let caller = caller.into();
+        let addr = addr.into();
+        let body = body.into();
+
+        async move {
+            let mut attempt = 0;
+            loop {
+                let e = match connection
+                    .call(caller.clone(), addr.clone(), body.clone(), false)
+                    .await
+                {
+                    Err(e) if e.is_transient() => e,
+                    result => return result,
+                };
+                match backoff.next_delay(attempt) {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => return Err(e),
+                }
+                attempt += 1;
+                log::debug!("retrying call to {} ({}): {}", addr, attempt, e);
+            }
+        }
+    }
+
+    /// Returns current connection counters.
+    ///
+    /// Empty stats are returned when the connection is already closed.
+    pub fn stats(&self) -> impl Future<Output = ConnectionStats> + 'static {
+        self.0.send(GetStats).map(|v| v.unwrap_or_default())
+    }
+
+    /// Stream of confirmed registrations, subscriptions and broadcasts made
+    /// on this connection from now on. Ends when the connection closes.
+    pub fn events(&self) -> impl Stream<Item = ConnectionEvent> + 'static {
+        let (tx, rx) = mpsc::unbounded();
+        self.0.do_send(SubscribeEvents(tx));
+        rx
+    }
+
+    /// Resolves once everything queued so far, like pushes and broadcasts,
+    /// is written to the transport. Fails when the connection closes first.
+    ///
+    /// Short lived processes should await it before exiting.
+    pub fn flush(&self) -> impl Future<Output = Result<(), Error>> + 'static {
+        let (tx, rx) = oneshot::channel();
+        self.0.do_send(Flush(tx));
+        rx.map_err(|_| Error::Closed("gsb connection".to_string()))
+    }
+
+    /// Stops the connection, reporting `DisconnectReason::LocalStop` to
+    /// its handler.
+    pub fn close(&self) {
+        self.0.do_send(Close);
+    }
+
+    /// Outgoing calls waiting for reply, empty when the connection is
+    /// already closed.
+    pub fn in_flight(&self) -> impl Future<Output = Vec<InFlightInfo>> + 'static {
+        self.0.send(GetInFlight).map(|v| v.unwrap_or_default())
+    }
+
+    /// Fails outgoing call `request_id` with `Error::Cancelled` and asks the
+    /// peer to stop it. Resolves to `false` when no such call is waiting.
+    pub fn cancel(&self, request_id: impl Into<String>) -> impl Future<Output = bool> + 'static {
+        let request_id = request_id.into();
+        self.0
+            .send(CancelInFlight { request_id })
+            .map(|v| v.unwrap_or(false))
+    }
+
+    /// Topics this connection is subscribed to, empty when the connection
+    /// is already closed.
+    pub fn subscriptions(&self) -> impl Future<Output = Vec<String>> + 'static {
+        self.0.send(GetSubscriptions).map(|v| v.unwrap_or_default())
+    }
+
+    /// Returns router identity, `None` until its `Hello` arrives or when
+    /// the connection is already closed.
+    pub fn server_info(&self) -> impl Future<Output = Option<ServerInfo>> + 'static {
+        self.0.send(GetServerInfo).map(|v| v.ok().flatten())
+    }
+
+    pub fn call_streaming(
+        &self,
+        caller: impl Into<String>,
+        addr: impl Into<String>,
+        body: impl Into<Bytes>,
+    ) -> impl Stream<Item = Result<ResponseChunk, Error>> {
+        self.call_streaming_with_capacity(caller, addr, body, DEFAULT_REPLY_CAPACITY)
+    }
+
+    /// Like `call_streaming`, buffering up to `capacity` reply chunks before
+    /// the callee is held back.
+    pub fn call_streaming_with_capacity(
+        &self,
+        caller: impl Into<String>,
+        addr: impl Into<String>,
+        body: impl Into<Bytes>,
+        capacity: usize,
+    ) -> impl Stream<Item = Result<ResponseChunk, Error>> {
+        let addr = addr.into();
+        let (tx, rx) = futures::channel::mpsc::channel(capacity);
+
+        let args = RpcRawStreamCall {
+            caller: caller.into(),
+            addr: addr.clone(),
+            body: body.into(),
+            reply: tx.clone(),
+            window: None,
+            headers: Default::default(),
+        };
+        let connection = self.0.clone();
+        spawner::spawn(async move {
+            let mut tx = tx;
+            match connection.send(args).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => {
+                    tx.send(Err(e))
+                        .await
+                        .unwrap_or_else(|e| log::error!("fail: {}", e));
+                }
+                Err(e) => {
+                    tx.send(Err(Error::from_addr(addr, e)))
+                        .await
+                        .unwrap_or_else(|e| log::error!("fail: {}", e));
+                }
+            }
+        });
+        rx
+    }
+
+    pub fn connected(&self) -> bool {
+        self.0.connected()
+    }
+
+    /// Client info the connection was opened with, including generated
+    /// `instance_id`.
+    pub fn client_info(&self) -> ClientInfo {
+        (*self.1).clone()
+    }
+}
+
+pub fn connect<Transport, H>(
+    client_info: ClientInfo,
+    transport: Transport,
+) -> ConnectionRef<Transport, H>
+where
+    Transport: Sink<GsbMessage, Error = ProtocolError>
+        + Stream<Item = Result<GsbMessage, ProtocolError>>
+        + Unpin
+        + 'static,
+    H: CallRequestHandler + 'static + Default + Unpin,
+{
+    connect_with_handler(client_info, transport, Default::default())
+}
+
+pub fn connect_with_handler<Transport, H>(
+    client_info: ClientInfo,
+    transport: Transport,
+    handler: H,
+) -> ConnectionRef<Transport, H>
+where
+    Transport: Sink<GsbMessage, Error = ProtocolError>
+        + Stream<Item = Result<GsbMessage, ProtocolError>>
+        + Unpin
+        + 'static,
+    H: CallRequestHandler + 'static,
+{
+    connect_with_config(client_info, Default::default(), transport, handler)
+}
+
+pub fn connect_with_config<Transport, H>(
+    client_info: ClientInfo,
+    config: ConnectionConfig,
+    transport: Transport,
+    handler: H,
+) -> ConnectionRef<Transport, H>
+where
+    Transport: Sink<GsbMessage, Error = ProtocolError>
+        + Stream<Item = Result<GsbMessage, ProtocolError>>
+        + Unpin
+        + 'static,
+    H: CallRequestHandler + 'static,
+{
+    start_connection(client_info, config, transport, handler, None)
+}
+
+/// Like `connect_with_handler`, closing the connection when `shutdown`
+/// resolves, e.g. on `tokio::signal::ctrl_c`.
+///
+/// Messages queued by then are written to the transport first, for at most
+/// 5 seconds. Only the write buffer is drained: outgoing calls still waiting
+/// for reply fail when the connection stops. Uses default `ConnectionConfig`,
+/// see `ConnectionBuilder::shutdown_on` for other settings.
+pub fn connect_with_shutdown<Transport, H>(
+    client_info: ClientInfo,
+    transport: Transport,
+    handler: H,
+    shutdown: impl Future + 'static,
+) -> ConnectionRef<Transport, H>
+where
+    Transport: Sink<GsbMessage, Error = ProtocolError>
+        + Stream<Item = Result<GsbMessage, ProtocolError>>
+        + Unpin
+        + 'static,
+    H: CallRequestHandler + 'static,
+{
+    let shutdown = shutdown.map(|_| ()).boxed_local();
+    start_connection(
+        client_info,
+        Default::default(),
+        transport,
+        handler,
+        Some(shutdown),
+    )
+}
+
+fn start_connection<Transport, H>(
+    client_info: ClientInfo,
+    config: ConnectionConfig,
+    transport: Transport,
+    handler: H,
+    shutdown: Option<LocalBoxFuture<'static, ()>>,
+) -> ConnectionRef<Transport, H>
+where
+    Transport: Sink<GsbMessage, Error = ProtocolError>
+        + Stream<Item = Result<GsbMessage, ProtocolError>>
+        + Unpin
+        + 'static,
+    H: CallRequestHandler + 'static,
+{
+    let (split_sink, split_stream) = transport.split();
+    let info = Arc::new(client_info.clone());
+    let addr = Connection::create(move |ctx| {
+        let _h = Connection::add_stream(split_stream, ctx);
+        if let Some(shutdown) = shutdown {
+            let _ = ctx.spawn(
+                fut::wrap_future(shutdown)
+                    .map(|(), act: &mut Connection<_, H>, ctx| act.drain_and_stop(ctx)),
+            );
+        }
+        Connection::new(client_info, config, split_sink, handler, ctx)
+    });
+    ConnectionRef(addr, info)
+}
+
+pub type TcpTransport =
+    tokio_util::codec::Framed<tokio::net::TcpStream, ya_sb_proto::codec::GsbMessageCodec>;
+
+/// Socket options for the TCP transport.
+#[derive(Clone, Debug, Default)]
+pub struct TcpOpts {
+    /// Disables Nagle's algorithm (`TCP_NODELAY`).
+    pub nodelay: bool,
+    /// Enables `SO_KEEPALIVE` with given idle time before the first probe is sent.
+    pub keepalive: Option<Duration>,
+    /// Buffer sizing of the framed transport.
+    pub codec: CodecConfig,
+}
+
+pub async fn tcp(addr: impl tokio::net::ToSocketAddrs) -> Result<TcpTransport, std::io::Error> {
+    tcp_with_opts(addr, TcpOpts::default()).await
+}
+
+pub async fn tcp_with_opts(
+    addr: impl tokio::net::ToSocketAddrs,
+    opts: TcpOpts,
+) -> Result<TcpTransport, std::io::Error> {
+    let s = tokio::net::TcpStream::connect(addr).await?;
+    if opts.nodelay {
+        s.set_nodelay(true)?;
+    }
+    if let Some(time) = opts.keepalive {
+        let keepalive = socket2::TcpKeepalive::new().with_time(time);
+        socket2::SockRef::from(&s).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(framed(s, &opts.codec))
+}
+
+fn framed<T: tokio::io::AsyncRead + tokio::io::AsyncWrite>(
+    io: T,
+    config: &CodecConfig,
+) -> tokio_util::codec::Framed<T, GsbMessageCodec> {
+    tokio_util::codec::Framed::with_capacity(
+        io,
+        GsbMessageCodec::new(config),
+        config.initial_capacity,
+    )
+}
+
+/// Source of incoming connections for `serve`.
+pub trait Listener {
+    type Io: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + 'static;
+
+    fn accept(&mut self) -> LocalBoxFuture<'_, std::io::Result<Self::Io>>;
+}
+
+impl Listener for tokio::net::TcpListener {
+    type Io = tokio::net::TcpStream;
+
+    fn accept(&mut self) -> LocalBoxFuture<'_, std::io::Result<Self::Io>> {
+        Box::pin(async move {
+            let (s, peer) = tokio::net::TcpListener::accept(self).await?;
+            log::debug!("accepted connection from {}", peer);
+            Ok(s)
+        })
+    }
+}
+
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Accepts connections from `listener` and drives `Connection` for each of
+/// them with handler created by `handler_factory`.
+///
+/// Runs until the listening future is dropped, must be polled inside actix
+/// system.
+pub async fn serve<L, H, F>(client_info: ClientInfo, mut listener: L, mut handler_factory: F)
+where
+    L: Listener,
+    H: CallRequestHandler + 'static,
+    F: FnMut() -> H,
+{
+    loop {
+        let io = match listener.accept().await {
+            Ok(io) => io,
+            Err(e) => {
+                // e.g. out of file descriptors, next attempt may succeed
+                log::warn!("accept failed: {}", e);
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let transport = framed(io, &ConnectionConfig::default().codec_config());
+        let _ = connect_with_handler(client_info.clone(), transport, handler_factory());
+    }
+}
+
+mod builder;
+pub use builder::ConnectionBuilder;
+
+mod dispatch;
+pub use dispatch::DispatchHandler;
+
+mod intercept;
+pub use intercept::Intercept;
+
+mod pool;
+pub use pool::ConnectionPool;
+
+mod rate_limit;
+use rate_limit::CallerLimiter;
+pub use rate_limit::RateLimit;
+
+#[cfg(feature = "tower")]
+mod service;
+#[cfg(feature = "tower")]
+pub use service::GsbService;
+
+#[cfg(feature = "quic")]
+mod quic;
+#[cfg(feature = "quic")]
+pub use quic::{quic, QuicConfig, QuicStream, QuicTransport};
+
+#[cfg(unix)]
+mod unix {
+
+    use super::*;
+    use std::task::Poll;
+
+    pub type UnixTransport =
+        tokio_util::codec::Framed<tokio::net::UnixStream, ya_sb_proto::codec::GsbMessageCodec>;
+
+    pub async fn unix<P>(path: P) -> Result<UnixTransport, std::io::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        unix_with_codec(path, CodecConfig::default()).await
+    }
+
+    pub async fn unix_with_codec<P>(
+        path: P,
+        codec: CodecConfig,
+    ) -> Result<UnixTransport, std::io::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let s = tokio::net::UnixStream::connect(path).await?;
+        Ok(framed(s, &codec))
+    }
+
+    impl Listener for tokio::net::UnixListener {
+        type Io = tokio::net::UnixStream;
+
+        fn accept(&mut self) -> LocalBoxFuture<'_, std::io::Result<Self::Io>> {
+            Box::pin(async move { Ok(tokio::net::UnixListener::accept(self).await?.0) })
+        }
+    }
+
+    /// This trait exists to annotate the return type of Transport::inner()
+    trait ITransport:
+        Sink<GsbMessage, Error = ProtocolError>
+        + Stream<Item = Result<GsbMessage, ProtocolError>>
+        + Unpin
+        + 'static
+    {
+    }
+
+    impl ITransport for TcpTransport {}
+    impl ITransport for UnixTransport {}
+
+    pub enum Transport {
+        Tcp(TcpTransport),
+        Unix(UnixTransport),
+    }
+
+    impl Transport {
+        fn inner(self: Pin<&mut Self>) -> Pin<&mut (dyn ITransport)> {
+            match self.get_mut() {
+                Transport::Tcp(tcp_transport) => Pin::new(tcp_transport),
+                Transport::Unix(unix_transport) => Pin::new(unix_transport),
+            }
+        }
+    }
+
+    impl Sink<GsbMessage> for Transport {
+        type Error = ProtocolError;
+
+        fn poll_ready(
+            self: Pin<&mut Self>,
+            cx: &mut core::task::Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            self.inner().poll_ready(cx)
+        }
+
+        fn start_send(self: Pin<&mut Self>, item: GsbMessage) -> Result<(), Self::Error> {
+            self.inner().start_send(item)
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            cx: &mut core::task::Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            self.inner().poll_flush(cx)
+        }
+
+        fn poll_close(
+            self: Pin<&mut Self>,
+            cx: &mut core::task::Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            self.inner().poll_close(cx)
+        }
+    }
+
+    impl Stream for Transport {
+        type Item = Result<GsbMessage, ProtocolError>;
+
+        fn poll_next(
+            self: Pin<&mut Self>,
+            cx: &mut core::task::Context<'_>,
+        ) -> Poll<Option<Self::Item>> {
+            self.inner().poll_next(cx)
+        }
+    }
+
+    impl Unpin for Transport {}
+
+    pub async fn transport(addr: ya_sb_proto::GsbAddr) -> Result<Transport, std::io::Error> {
+        transport_with_codec(addr, CodecConfig::default()).await
+    }
+
+    pub async fn transport_with_codec(
+        addr: ya_sb_proto::GsbAddr,
+        codec: CodecConfig,
+    ) -> Result<Transport, std::io::Error> {
+        match addr {
+            ya_sb_proto::GsbAddr::Tcp(addr) => {
+                let opts = TcpOpts {
+                    codec,
+                    ..Default::default()
+                };
+                Ok(Transport::Tcp(tcp_with_opts(addr, opts).await?))
+            }
+            ya_sb_proto::GsbAddr::Unix(path) => {
+                Ok(Transport::Unix(unix_with_codec(path, codec).await?))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use unix::*;
+
+#[cfg(not(unix))]
+pub type Transport = TcpTransport;
+
+#[cfg(not(unix))]
+pub async fn transport(addr: ya_sb_proto::GsbAddr) -> Result<TcpTransport, std::io::Error> {
+    transport_with_codec(addr, CodecConfig::default()).await
+}
+
+#[cfg(not(unix))]
+pub async fn transport_with_codec(
+    addr: ya_sb_proto::GsbAddr,
+    codec: CodecConfig,
+) -> Result<TcpTransport, std::io::Error> {
+    match addr {
+        ya_sb_proto::GsbAddr::Tcp(addr) => {
+            let opts = TcpOpts {
+                codec,
+                ..Default::default()
+            };
+            Ok(tcp_with_opts(addr, opts).await?)
+        }
+        ya_sb_proto::GsbAddr::Unix(_) => panic!("Unix sockets not supported on this OS"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+    use tokio_util::codec::Framed;
+
+    type Peer = Framed<DuplexStream, GsbMessageCodec>;
+
+    /// Connection over in-memory transport and its router side.
+    fn connect_pair(
+        config: ConnectionConfig,
+    ) -> (
+        ConnectionRef<Framed<DuplexStream, GsbMessageCodec>, LocalRouterHandler>,
+        Peer,
+    ) {
+        connect_pair_with_buffer(config, 64 * 1024)
+    }
+
+    /// Like `connect_pair`, transport holds at most `buffer` bytes the
+    /// peer did not read yet.
+    fn connect_pair_with_buffer(
+        config: ConnectionConfig,
+        buffer: usize,
+    ) -> (
+        ConnectionRef<Framed<DuplexStream, GsbMessageCodec>, LocalRouterHandler>,
+        Peer,
+    ) {
+        let (client, router) = tokio::io::duplex(buffer);
+        let codec = CodecConfig::default();
+        let connection = connect_with_config(
+            ClientInfo::new("test"),
+            config,
+            framed(client, &codec),
+            LocalRouterHandler::default(),
+        );
+        (connection, framed(router, &codec))
+    }
+
+    /// Like `connect_pair`, with the client side of the transport wrapped
+    /// by `middleware`, e.g. with `Intercept`.
+    fn connect_pair_with<T>(
+        middleware: impl FnOnce(Peer) -> T,
+    ) -> (ConnectionRef<T, LocalRouterHandler>, Peer)
+    where
+        T: Sink<GsbMessage, Error = ProtocolError>
+            + Stream<Item = Result<GsbMessage, ProtocolError>>
+            + Unpin
+            + 'static,
+    {
+        let (client, router) = tokio::io::duplex(64 * 1024);
+        let codec = CodecConfig::default();
+        let connection = connect_with_config(
+            ClientInfo::new("test"),
+            Default::default(),
+            middleware(framed(client, &codec)),
+            LocalRouterHandler::default(),
+        );
+        (connection, framed(router, &codec))
+    }
+
+    async fn hello(peer: &mut Peer) {
+        let hello = ya_sb_proto::Hello {
+            name: "router".to_string(),
+            capabilities: vec![ya_sb_proto::CAP_CALL_CANCEL.to_string()],
+            proto_version: ya_sb_proto::PROTO_VERSION,
+            ..Default::default()
+        };
+        peer.send(GsbMessage::Hello(hello)).await.unwrap();
+    }
+
+    async fn next_message(peer: &mut Peer) -> GsbMessage {
+        loop {
+            match peer.next().await.unwrap().unwrap() {
+                GsbMessage::Hello(_) | GsbMessage::Ping(_) => continue,
+                msg => return msg,
+            }
+        }
+    }
+
+    async fn next_call(peer: &mut Peer) -> CallRequest {
+        match next_message(peer).await {
+            GsbMessage::CallRequest(r) => r,
+            msg => panic!("expected call request, got {}", msg.kind()),
+        }
+    }
+
+    async fn reply(peer: &mut Peer, request_id: &str, code: CallReplyCode, data: &[u8]) {
+        peer.send(GsbMessage::CallReply(CallReply {
+            request_id: request_id.to_string(),
+            code: code as i32,
+            reply_type: CallReplyType::Full as i32,
+            data: data.to_vec(),
+            trailers: Default::default(),
+        }))
+        .await
+        .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_timed_out_call_forgotten() {
+        tokio::time::pause();
+        let (connection, mut peer) = connect_pair(ConnectionConfig {
+            call_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        });
+        hello(&mut peer).await;
+
+        let call = connection.call("test", "/dead/service", Vec::new(), false);
+        let (reply, request) = future::join(call, next_call(&mut peer)).await;
+        assert!(matches!(reply, Err(Error::Timeout(_))), "{:?}", reply);
+        assert!(connection.in_flight().await.is_empty());
+        match next_message(&mut peer).await {
+            GsbMessage::CallCancel(c) => assert_eq!(c.request_id, request.request_id),
+            msg => panic!("expected call cancel, got {}", msg.kind()),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_close() {
+        let (connection, mut peer) = connect_pair(Default::default());
+        hello(&mut peer).await;
+        let call = connection.call("test", "/remote/service", Vec::new(), false);
+        let _ = next_call(&mut peer).await;
+
+        connection.close();
+        assert!(call.await.is_err());
+        assert!(!connection.connected());
+        assert!(peer.next().await.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_cancel_in_flight() {
+        let (connection, mut peer) = connect_pair(Default::default());
+        hello(&mut peer).await;
 
-    fn handle(&mut self, msg: RpcRawCall, _ctx: &mut Self::Context) -> Self::Result {
-        let request_id = format!("{}", gen_id());
-        let caller = msg.caller;
-        let address = msg.addr;
-        let data = msg.body;
-        let no_reply = msg.no_reply;
+        let mut stream = connection.call_streaming("test", "/remote/stream", Vec::new());
+        let request = next_call(&mut peer).await;
+        let in_flight = connection.in_flight().await;
+        assert_eq!(in_flight.len(), 1);
+        assert_eq!(in_flight[0].request_id, request.request_id);
+        assert_eq!(in_flight[0].address, "/remote/stream");
+        assert_eq!(in_flight[0].kind, CallKind::Streaming);
 
-        let rx = if no_reply {
-            None
-        } else {
-            let (tx, rx) = mpsc::channel(1);
-            let _ = self.call_reply.insert(request_id.clone(), tx);
-            Some(rx)
+        assert!(connection.cancel(request.request_id.clone()).await);
+        assert!(matches!(stream.next().await, Some(Err(Error::Cancelled))));
+        assert!(connection.in_flight().await.is_empty());
+        match next_message(&mut peer).await {
+            GsbMessage::CallCancel(c) => assert_eq!(c.request_id, request.request_id),
+            msg => panic!("expected call cancel, got {}", msg.kind()),
+        }
+        assert!(!connection.cancel(request.request_id).await);
+    }
+
+    #[actix_rt::test]
+    async fn test_tap_bodies() {
+        let (tap, mut tapped) = mpsc::channel(16);
+        let (connection, mut peer) = connect_pair(ConnectionConfig {
+            tap: Some(tap),
+            tap_bodies: true,
+            ..Default::default()
+        });
+        hello(&mut peer).await;
+
+        let call = connection.call("test", "/remote/service", b"ping".to_vec(), false);
+        let reply = async {
+            let request = next_call(&mut peer).await;
+            reply(
+                &mut peer,
+                &request.request_id,
+                CallReplyCode::CallReplyOk,
+                b"pong",
+            )
+            .await;
+            request.request_id
         };
+        let (result, request_id) = future::join(call, reply).await;
+        assert_eq!(result.unwrap(), b"pong".to_vec());
+        connection.close();
 
-        log::trace!("handling caller (rpc): {}, addr:{}", caller, address);
-        let _r = self.writer.write(GsbMessage::CallRequest(CallRequest {
-            request_id,
-            caller,
-            address,
-            data,
-            no_reply,
-        }));
+        let mut calls = Vec::new();
+        while let Some(msg) = tapped.next().await {
+            if matches!(msg.kind, "CallRequest" | "CallReply") {
+                calls.push(msg);
+            }
+        }
+        assert_eq!(calls.len(), 2, "{:?}", calls);
+        assert_eq!(calls[0].direction, TapDirection::Outbound);
+        assert_eq!(calls[0].request_id.as_deref(), Some(request_id.as_str()));
+        assert_eq!(calls[0].body.as_deref(), Some(&b"ping"[..]));
+        assert_eq!(calls[1].direction, TapDirection::Inbound);
+        assert_eq!(calls[1].request_id.as_deref(), Some(request_id.as_str()));
+        assert_eq!(calls[1].body.as_deref(), Some(&b"pong"[..]));
+    }
 
-        match rx {
-            Some(mut rx) => {
-                let fetch_response = async move {
-                    match futures::StreamExt::next(&mut rx).await {
-                        Some(Ok(ResponseChunk::Full(data))) => Ok(data),
-                        Some(Err(e)) => Err(e),
-                        Some(Ok(ResponseChunk::Part(_))) => {
-                            Err(Error::GsbFailure("streaming response".to_string()))
-                        }
-                        None => Err(Error::GsbFailure("unexpected EOS".to_string())),
-                    }
-                };
-                ActorResponse::r#async(fetch_response.into_actor(self))
+    #[actix_rt::test]
+    async fn test_call_with_meta_request_id() {
+        let (connection, mut peer) = connect_pair(Default::default());
+        hello(&mut peer).await;
+
+        for code in [CallReplyCode::CallReplyOk, CallReplyCode::ServiceFailure] {
+            let call = connection.call_with_meta("test", "/remote/service", Vec::new(), false);
+            let reply = async {
+                let request = next_call(&mut peer).await;
+                peer.send(GsbMessage::CallReply(CallReply {
+                    request_id: request.request_id.clone(),
+                    code: code as i32,
+                    reply_type: CallReplyType::Full as i32,
+                    data: b"reply".to_vec(),
+                    trailers: Default::default(),
+                }))
+                .await
+                .unwrap();
+                request
+            };
+            let (response, request) = future::join(call, reply).await;
+            match code {
+                CallReplyCode::CallReplyOk => {
+                    let response = response.unwrap();
+                    assert_eq!(response.request_id, request.request_id);
+                    assert_eq!(response.body, b"reply");
+                }
+                _ => assert!(response.is_err(), "{:?}", response),
             }
-            None => ActorResponse::reply(Ok(Vec::new())),
         }
     }
-}
 
-impl<W, H> Handler<RpcRawStreamCall> for Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
-{
-    type Result = ActorResponse<Self, Result<(), Error>>;
+    #[actix_rt::test]
+    async fn test_flush_waits_for_transport() {
+        let (connection, mut peer) = connect_pair_with_buffer(Default::default(), 256);
+        hello(&mut peer).await;
 
-    fn handle(&mut self, msg: RpcRawStreamCall, _ctx: &mut Self::Context) -> Self::Result {
-        let request_id = format!("{}", gen_id());
-        let rx = msg.reply;
-        let _ = self.call_reply.insert(request_id.clone(), rx);
-        let caller = msg.caller;
-        let address = msg.addr;
-        let data = msg.body;
-        log::trace!("handling caller (stream): {}, addr:{}", caller, address);
-        let _r = self.writer.write(GsbMessage::CallRequest(CallRequest {
-            request_id,
-            caller,
-            address,
-            data,
-            no_reply: false,
-        }));
-        ActorResponse::reply(Ok(()))
+        let call = connection.call("test", "/remote/service", vec![0; 4096], true);
+        let flush = connection.flush();
+        futures::pin_mut!(flush);
+        // Call does not fit the transport until the peer reads it.
+        let stalled = tokio::time::timeout(Duration::from_millis(100), &mut flush).await;
+        assert!(stalled.is_err(), "flushed before the peer read the call");
+
+        let (request, flushed) = future::join(next_call(&mut peer), flush).await;
+        assert_eq!(request.data.len(), 4096);
+        flushed.unwrap();
+        call.await.unwrap();
     }
-}
 
-fn send_cmd_async<A: Actor, W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static>(
-    writer: &mut TransportWriter<W>,
-    queue: &mut VecDeque<oneshot::Sender<Result<(), Error>>>,
-    msg: GsbMessage,
-) -> ActorResponse<A, Result<(), Error>> {
-    let (tx, rx) = oneshot::channel();
-    queue.push_back(tx);
+    #[actix_rt::test]
+    async fn test_write_stall_measures_progress() {
+        tokio::time::pause();
+        let config = || ConnectionConfig {
+            write_stall_timeout: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        // Calls larger than the transport buffer are taken one by one.
+        let push_all = |connection: &ConnectionRef<_, _>| {
+            for _ in 0..8 {
+                let call = connection.call("test", "/remote/service", vec![0; 64 * 1024], true);
+                actix_rt::spawn(call);
+            }
+        };
+
+        // Writing all of them takes longer than the timeout, each one is
+        // taken well within it.
+        let (connection, mut peer) = connect_pair_with_buffer(config(), 1024);
+        hello(&mut peer).await;
+        push_all(&connection);
+        for _ in 0..8 {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            assert!(connection.connected(), "slow transport taken for stalled");
+            let _ = next_call(&mut peer).await;
+        }
 
-    if writer.write(msg).is_some() {
-        ActorResponse::reply(Err(Error::GsbFailure("no connection".into())))
-    } else {
-        ActorResponse::r#async(fut::wrap_future(async move {
-            rx.await.map_err(|_| Error::Cancelled)??;
-            Ok(())
-        }))
+        let (connection, mut peer) = connect_pair_with_buffer(config(), 1024);
+        hello(&mut peer).await;
+        push_all(&connection);
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(!connection.connected(), "stalled transport not detected");
     }
-}
 
-struct Bind {
-    addr: String,
-}
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Probe;
 
-impl Message for Bind {
-    type Result = Result<(), Error>;
-}
+    impl crate::RpcMessage for Probe {
+        const ID: &'static str = "Probe";
+        type Item = ();
+        type Error = ();
+    }
 
-impl<W, H> Handler<Bind> for Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
-{
-    type Result = ActorResponse<Self, Result<(), Error>>;
+    #[actix_rt::test]
+    async fn test_ping_checks_handler_serves_address() {
+        let _handle = crate::typed::bind("/local/ping-test", |_: Probe| async { Ok(()) });
+        let (_connection, mut peer) = connect_pair(Default::default());
+        hello(&mut peer).await;
 
-    fn handle(&mut self, msg: Bind, _ctx: &mut Self::Context) -> Self::Result {
-        let service_id = msg.addr;
-        send_cmd_async(
-            &mut self.writer,
-            &mut self.register_reply,
-            GsbMessage::RegisterRequest(RegisterRequest { service_id }),
-        )
+        let ping = |request_id: &str, address: &str| {
+            GsbMessage::CallRequest(CallRequest {
+                request_id: request_id.to_string(),
+                caller: "caller".to_string(),
+                address: address.to_string(),
+                headers: std::iter::once((ya_sb_proto::PING_HEADER.to_string(), Vec::new()))
+                    .collect(),
+                ..Default::default()
+            })
+        };
+        peer.send(ping("bound", "/local/ping-test/Probe"))
+            .await
+            .unwrap();
+        match next_message(&mut peer).await {
+            GsbMessage::CallReply(r) => {
+                assert_eq!(r.request_id, "bound");
+                assert_eq!(r.code, CallReplyCode::CallReplyOk as i32);
+            }
+            msg => panic!("expected call reply, got {}", msg.kind()),
+        }
+        peer.send(ping("unbound", "/local/ping-test/Other"))
+            .await
+            .unwrap();
+        match next_message(&mut peer).await {
+            GsbMessage::CallReply(r) => {
+                assert_eq!(r.request_id, "unbound");
+                assert_eq!(r.code, CallReplyCode::CallReplyBadRequest as i32);
+                assert_eq!(r.data, ya_sb_proto::ENDPOINT_NOT_FOUND.as_bytes());
+            }
+            msg => panic!("expected call reply, got {}", msg.kind()),
+        }
     }
-}
 
-struct Unbind {
-    addr: String,
-}
+    /// Handler setting a trailer, replying in one chunk to `/full` and in
+    /// parts ended by EOS to anything else.
+    struct TrailerHandler;
 
-impl Message for Unbind {
-    type Result = Result<(), Error>;
-}
+    impl CallRequestHandler for TrailerHandler {
+        type Reply = stream::Iter<std::vec::IntoIter<Result<ResponseChunk, Error>>>;
 
-impl<W, H> Handler<Unbind> for Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
-{
-    type Result = ActorResponse<Self, Result<(), Error>>;
+        fn do_call(&mut self, _: String, _: String, _: String, _: Vec<u8>, _: bool) -> Self::Reply {
+            unreachable!("calls come through handle_call")
+        }
 
-    fn handle(&mut self, msg: Unbind, _ctx: &mut Self::Context) -> Self::Result {
-        let service_id = msg.addr;
-        send_cmd_async(
-            &mut self.writer,
-            &mut self.unregister_reply,
-            GsbMessage::UnregisterRequest(UnregisterRequest { service_id }),
-        )
+        fn handle_call(&mut self, call: CallContext) -> Self::Reply {
+            call.trailers.insert("served-by", "trailer-handler");
+            let chunks = match call.address.as_str() {
+                "/full" => vec![Ok(ResponseChunk::full("done"))],
+                _ => vec![Ok(ResponseChunk::part("a")), Ok(ResponseChunk::part("b"))],
+            };
+            stream::iter(chunks)
+        }
     }
-}
 
-struct Subscribe {
-    topic: String,
-}
+    #[actix_rt::test]
+    async fn test_trailers_sent_with_last_reply() {
+        let (client, router) = tokio::io::duplex(64 * 1024);
+        let codec = CodecConfig::default();
+        let _connection = connect_with_config(
+            ClientInfo::new("test"),
+            Default::default(),
+            framed(client, &codec),
+            TrailerHandler,
+        );
+        let mut peer = framed(router, &codec);
+        hello(&mut peer).await;
 
-impl Message for Subscribe {
-    type Result = Result<(), Error>;
-}
+        let call = |request_id: &str, address: &str| {
+            GsbMessage::CallRequest(CallRequest {
+                request_id: request_id.to_string(),
+                caller: "caller".to_string(),
+                address: address.to_string(),
+                ..Default::default()
+            })
+        };
+        let served_by = || {
+            std::iter::once(("served-by".to_string(), b"trailer-handler".to_vec()))
+                .collect::<HashMap<_, _>>()
+        };
 
-impl<W, H> Handler<Subscribe> for Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
-{
-    type Result = ActorResponse<Self, Result<(), Error>>;
+        peer.send(call("full", "/full")).await.unwrap();
+        match next_message(&mut peer).await {
+            GsbMessage::CallReply(r) => {
+                assert_eq!(r.reply_type, CallReplyType::Full as i32);
+                assert_eq!(r.data, b"done");
+                assert_eq!(r.trailers, served_by());
+            }
+            msg => panic!("expected call reply, got {}", msg.kind()),
+        }
 
-    fn handle(&mut self, msg: Subscribe, _ctx: &mut Self::Context) -> Self::Result {
-        let topic = msg.topic;
-        send_cmd_async(
-            &mut self.writer,
-            &mut self.subscribe_reply,
-            GsbMessage::SubscribeRequest(SubscribeRequest { topic }),
-        )
+        peer.send(call("streamed", "/streamed")).await.unwrap();
+        for data in [&b"a"[..], b"b"] {
+            match next_message(&mut peer).await {
+                GsbMessage::CallReply(r) => {
+                    assert_eq!(r.reply_type, CallReplyType::Partial as i32);
+                    assert_eq!(r.data, data);
+                    assert!(r.trailers.is_empty());
+                }
+                msg => panic!("expected call reply, got {}", msg.kind()),
+            }
+        }
+        match next_message(&mut peer).await {
+            GsbMessage::CallReply(r) => {
+                assert_eq!(r.reply_type, CallReplyType::Full as i32);
+                assert!(r.data.is_empty());
+                assert_eq!(r.trailers, served_by());
+            }
+            msg => panic!("expected call reply, got {}", msg.kind()),
+        }
     }
-}
 
-struct Unsubscribe {
-    topic: String,
-}
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Whoami;
 
-impl Message for Unsubscribe {
-    type Result = Result<(), Error>;
-}
+    impl crate::RpcMessage for Whoami {
+        const ID: &'static str = "Whoami";
+        type Item = String;
+        type Error = ();
+    }
 
-impl<W, H> Handler<Unsubscribe> for Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
-{
-    type Result = ActorResponse<Self, Result<(), Error>>;
+    /// Raw and raw-stream handler replying with the caller.
+    struct RawEcho;
 
-    fn handle(&mut self, msg: Unsubscribe, _ctx: &mut Self::Context) -> Self::Result {
-        let topic = msg.topic;
-        send_cmd_async(
-            &mut self.writer,
-            &mut self.unsubscribe_reply,
-            GsbMessage::UnsubscribeRequest(UnsubscribeRequest { topic }),
-        )
+    impl Actor for RawEcho {
+        type Context = Context<Self>;
     }
-}
 
-pub struct BcastCall {
-    pub caller: String,
-    pub topic: String,
-    pub body: Vec<u8>,
-}
+    impl Handler<RpcRawCall> for RawEcho {
+        type Result = Result<Vec<u8>, Error>;
 
-impl Message for BcastCall {
-    type Result = Result<(), Error>;
-}
+        fn handle(&mut self, msg: RpcRawCall, _ctx: &mut Self::Context) -> Self::Result {
+            Ok(msg.caller.into_bytes())
+        }
+    }
 
-impl<W, H> Handler<BcastCall> for Connection<W, H>
-where
-    W: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
-{
-    type Result = ActorResponse<Self, Result<(), Error>>;
+    impl Handler<RpcRawStreamCall> for RawEcho {
+        type Result = ResponseFuture<Result<(), Error>>;
 
-    fn handle(&mut self, msg: BcastCall, _ctx: &mut Self::Context) -> Self::Result {
-        let caller = msg.caller;
-        let topic = msg.topic;
-        let data = msg.body;
-        send_cmd_async(
-            &mut self.writer,
-            &mut self.broadcast_reply,
-            GsbMessage::BroadcastRequest(BroadcastRequest {
-                caller,
-                topic,
-                data,
-            }),
-        )
+        fn handle(&mut self, mut msg: RpcRawStreamCall, _ctx: &mut Self::Context) -> Self::Result {
+            Box::pin(async move {
+                let caller = msg.caller.into_bytes();
+                msg.reply
+                    .send(Ok(ResponseChunk::Full(caller)))
+                    .await
+                    .map_err(|e| Error::GsbFailure(e.to_string()))
+            })
+        }
     }
-}
 
-pub struct ConnectionRef<
-    Transport: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-    H: CallRequestHandler + 'static,
->(Addr<Connection<SplitSink<Transport, GsbMessage>, H>>);
+    #[actix_rt::test]
+    async fn test_caller_preserved_over_connection() {
+        let _typed =
+            crate::typed::bind_with_caller("/local/caller-test/typed", |caller, _: Whoami| {
+                future::ok(caller)
+            });
+        let _raw = router()
+            .lock()
+            .unwrap()
+            .bind_raw("/local/caller-test/raw", RawEcho.start().recipient());
+        let _raw_stream = router()
+            .lock()
+            .unwrap()
+            .bind_raw_stream("/local/caller-test/raw-stream", RawEcho.start().recipient());
+        let (connection, mut peer) = connect_pair(Default::default());
+        hello(&mut peer).await;
 
-impl<
-        Transport: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-        H: CallRequestHandler + 'static,
-    > Unpin for ConnectionRef<Transport, H>
-{
-}
+        // inbound calls reach local handlers with caller given by the router
+        let body = crate::serialization::to_vec(&Whoami).unwrap();
+        for (address, data) in [
+            ("/local/caller-test/typed/Whoami", body),
+            ("/local/caller-test/raw", Vec::new()),
+            ("/local/caller-test/raw-stream", Vec::new()),
+        ] {
+            peer.send(GsbMessage::CallRequest(CallRequest {
+                request_id: address.to_string(),
+                caller: "alice".to_string(),
+                address: address.to_string(),
+                data,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+            let r = match next_message(&mut peer).await {
+                GsbMessage::CallReply(r) => r,
+                msg => panic!("expected reply, got {}", msg.kind()),
+            };
+            assert_eq!(r.request_id, address);
+            assert_eq!(r.code, CallReplyCode::CallReplyOk as i32);
+            if address.ends_with("Whoami") {
+                let reply: Result<String, ()> = crate::serialization::from_slice(&r.data).unwrap();
+                assert_eq!(reply, Ok("alice".to_string()));
+            } else {
+                assert_eq!(r.data, b"alice", "{}", address);
+            }
+        }
 
-impl<
-        Transport: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-        H: CallRequestHandler + 'static,
-    > Clone for ConnectionRef<Transport, H>
-{
-    fn clone(&self) -> Self {
-        ConnectionRef(self.0.clone())
-    }
-}
+        // raw call forwarded locally
+        let forwarded = router().lock().unwrap().forward_raw(RpcRawCall::new(
+            "bob",
+            "/local/caller-test/raw",
+            Vec::new(),
+            false,
+        ));
+        assert_eq!(forwarded.await.unwrap(), b"bob");
 
-impl<
-        Transport: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
-        H: CallRequestHandler + Unpin + 'static,
-    > ConnectionRef<Transport, H>
-{
-    pub fn bind(
-        &self,
-        addr: impl Into<String>,
-    ) -> impl Future<Output = Result<(), Error>> + 'static {
-        let addr = addr.into();
-        log::trace!("Binding remote service '{}'", addr);
-        self.0.send(Bind { addr: addr.clone() }).then(|v| async {
-            log::trace!("send bind result: {:?}", v);
-            v.map_err(|e| Error::from_addr(addr, e))?
-        })
+        // outbound call carries caller to the router
+        let call = connection.call("carol", "/remote/who", Vec::new(), false);
+        let answer = async {
+            let request = next_call(&mut peer).await;
+            reply(
+                &mut peer,
+                &request.request_id,
+                CallReplyCode::CallReplyOk,
+                b"ok",
+            )
+            .await;
+            request
+        };
+        let (result, request) = future::join(call, answer).await;
+        assert_eq!(request.caller, "carol");
+        assert_eq!(result.unwrap(), b"ok");
     }
 
-    pub fn unbind(
-        &self,
-        addr: impl Into<String>,
-    ) -> impl Future<Output = Result<(), Error>> + 'static {
-        let addr = addr.into();
-        self.0.send(Unbind { addr: addr.clone() }).then(|v| async {
-            log::trace!("send unbind result: {:?}", v);
-            v.map_err(|e| Error::from_addr(addr, e))?
-        })
-    }
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Flood;
 
-    pub fn subscribe(
-        &self,
-        topic: impl Into<String>,
-    ) -> impl Future<Output = Result<(), Error>> + 'static {
-        let topic = topic.into();
-        let fut = self.0.send(Subscribe {
-            topic: topic.clone(),
-        });
-        async move {
-            fut.await
-                .map_err(|e| Error::from_addr(format!("subscribing {}", topic), e))?
+    impl crate::RpcStreamMessage for Flood {
+        const ID: &'static str = "Flood";
+        type Item = Vec<u8>;
+        type Error = ();
+    }
+
+    struct FloodHandler;
+
+    impl crate::RpcStreamHandler<Flood> for FloodHandler {
+        type Result = stream::Iter<std::vec::IntoIter<Result<Vec<u8>, ()>>>;
+
+        fn handle(&mut self, _caller: &str, _msg: Flood) -> Self::Result {
+            stream::iter(vec![Ok(vec![0; 1024]); 64])
         }
     }
 
-    pub fn unsubscribe(
-        &self,
-        topic: impl Into<String>,
-    ) -> impl Future<Output = Result<(), Error>> + 'static {
-        let topic = topic.into();
-        let fut = self.0.send(Unsubscribe {
-            topic: topic.clone(),
-        });
-        async move {
-            fut.await
-                .map_err(|e| Error::from_addr(format!("unsubscribing {}", topic), e))?
+    #[actix_rt::test]
+    async fn test_slow_caller_reported_as_lagging() {
+        let _handle = crate::typed::bind_stream("/local/lag-test", FloodHandler);
+        let config = ConnectionConfig {
+            reply_lag_warn: Some(8),
+            ..Default::default()
+        };
+        let (connection, mut peer) = connect_pair_with_buffer(config, 1024);
+        hello(&mut peer).await;
+
+        peer.send(GsbMessage::CallRequest(CallRequest {
+            request_id: "flood".to_string(),
+            caller: "caller".to_string(),
+            address: "/local/lag-test/Flood".to_string(),
+            data: crate::serialization::to_vec(&Flood).unwrap(),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        // the peer reads nothing until the handler is done
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(connection.stats().await.lagging_replies, 1);
+
+        let mut chunks = 0;
+        loop {
+            match next_message(&mut peer).await {
+                GsbMessage::CallReply(r) if r.reply_type == CallReplyType::Partial as i32 => {
+                    chunks += 1
+                }
+                GsbMessage::CallReply(_) => break,
+                msg => panic!("expected call reply, got {}", msg.kind()),
+            }
         }
+        assert_eq!(chunks, 64);
+        assert_eq!(connection.stats().await.lagging_replies, 1);
     }
 
-    pub fn broadcast(
-        &self,
-        caller: impl Into<String>,
-        topic: impl Into<String>,
-        body: Vec<u8>,
-    ) -> impl Future<Output = Result<(), Error>> + 'static {
-        let topic = topic.into();
-        let fut = self.0.send(BcastCall {
-            caller: caller.into(),
-            topic: topic.clone(),
-            body,
+    #[actix_rt::test]
+    async fn test_reply_queue_overflow_cancels_stream() {
+        let (connection, mut peer) = connect_pair(ConnectionConfig {
+            max_queued_reply_chunks: Some(2),
+            ..Default::default()
         });
-        async move {
-            fut.await
-                .map_err(|e| Error::from_addr(format!("broadcasting {}", topic), e))?
+        hello(&mut peer).await;
+
+        let (tx, rx) = mpsc::channel(0);
+        connection
+            .call_streaming_raw(RpcRawStreamCall::new(
+                "test",
+                "/fast/stream",
+                Vec::new(),
+                tx,
+            ))
+            .await
+            .unwrap();
+        let request = next_call(&mut peer).await;
+        for _ in 0..10 {
+            peer.send(GsbMessage::CallReply(CallReply {
+                request_id: request.request_id.clone(),
+                code: CallReplyCode::CallReplyOk as i32,
+                reply_type: CallReplyType::Partial as i32,
+                data: vec![1],
+                trailers: Default::default(),
+            }))
+            .await
+            .unwrap();
+        }
+        match next_message(&mut peer).await {
+            GsbMessage::CallCancel(c) => assert_eq!(c.request_id, request.request_id),
+            msg => panic!("expected call cancel, got {}", msg.kind()),
         }
+        let items: Vec<_> = rx.collect().await;
+        assert!(items.len() <= 4, "{:?}", items);
+        assert!(
+            matches!(items.last(), Some(Err(Error::GsbFailure(e))) if e == "reply queue full"),
+            "{:?}",
+            items
+        );
     }
 
-    pub fn call(
-        &self,
-        caller: impl Into<String>,
-        addr: impl Into<String>,
-        body: impl Into<Vec<u8>>,
-        no_reply: bool,
-    ) -> impl Future<Output = Result<Vec<u8>, Error>> {
-        let addr = addr.into();
-        self.0
-            .send(RpcRawCall {
-                caller: caller.into(),
-                addr: addr.clone(),
-                body: body.into(),
-                no_reply,
+    #[actix_rt::test]
+    async fn test_chunked_call_body_limit() {
+        let (_connection, mut peer) = connect_pair(ConnectionConfig {
+            max_call_body: Some(4),
+            ..Default::default()
+        });
+        hello(&mut peer).await;
+
+        let frame = |request_id: &str, has_more| {
+            GsbMessage::CallRequest(CallRequest {
+                request_id: request_id.to_string(),
+                caller: "caller".to_string(),
+                address: "/local/unbound".to_string(),
+                data: vec![0; 3],
+                has_more,
+                ..Default::default()
             })
-            .then(|v| async { v.map_err(|e| Error::from_addr(addr, e))? })
+        };
+        peer.send(frame("big", true)).await.unwrap();
+        peer.send(frame("big", true)).await.unwrap();
+        match next_message(&mut peer).await {
+            GsbMessage::CallReply(r) => {
+                assert_eq!(r.request_id, "big");
+                assert_eq!(r.code, CallReplyCode::ServiceFailure as i32);
+                assert_eq!(r.data, b"call body too large");
+            }
+            msg => panic!("expected call reply, got {}", msg.kind()),
+        }
+        // rest of rejected body is dropped without another reply
+        peer.send(frame("big", false)).await.unwrap();
+        peer.send(frame("small", false)).await.unwrap();
+        match next_message(&mut peer).await {
+            GsbMessage::CallReply(r) => assert_eq!(r.request_id, "small"),
+            msg => panic!("expected call reply, got {}", msg.kind()),
+        }
     }
 
-    pub fn call_streaming(
-        &self,
-        caller: impl Into<String>,
-        addr: impl Into<String>,
-        body: impl Into<Vec<u8>>,
-    ) -> impl Stream<Item = Result<ResponseChunk, Error>> {
-        let addr = addr.into();
-        let (tx, rx) = futures::channel::mpsc::channel(16);
+    #[actix_rt::test]
+    async fn test_partial_calls_bounded() {
+        tokio::time::pause();
+        let (_connection, mut peer) = connect_pair(ConnectionConfig {
+            max_partial_calls: 1,
+            partial_call_timeout: Duration::from_secs(5),
+            ..Default::default()
+        });
+        hello(&mut peer).await;
 
-        let args = RpcRawStreamCall {
-            caller: caller.into(),
-            addr: addr.clone(),
-            body: body.into(),
-            reply: tx.clone(),
+        let frame = |request_id: &str| {
+            GsbMessage::CallRequest(CallRequest {
+                request_id: request_id.to_string(),
+                caller: "caller".to_string(),
+                address: "/local/unbound".to_string(),
+                data: vec![0; 3],
+                has_more: true,
+                ..Default::default()
+            })
         };
-        let connection = self.0.clone();
-        let _ = Arbiter::current().spawn(async move {
-            let mut tx = tx;
-            match connection.send(args).await {
-                Ok(Ok(())) => (),
-                Ok(Err(e)) => {
-                    tx.send(Err(e))
-                        .await
-                        .unwrap_or_else(|e| log::error!("fail: {}", e));
-                }
-                Err(e) => {
-                    tx.send(Err(Error::from_addr(addr, e)))
-                        .await
-                        .unwrap_or_else(|e| log::error!("fail: {}", e));
+        async fn expect_failure(peer: &mut Peer, request_id: &str, reason: &str) {
+            match next_message(peer).await {
+                GsbMessage::CallReply(r) => {
+                    assert_eq!(r.request_id, request_id);
+                    assert_eq!(r.code, CallReplyCode::ServiceFailure as i32);
+                    assert_eq!(r.data, reason.as_bytes());
                 }
+                msg => panic!("expected call reply, got {}", msg.kind()),
             }
-        });
-        rx
+        }
+        peer.send(frame("first")).await.unwrap();
+        peer.send(frame("second")).await.unwrap();
+        expect_failure(&mut peer, "second", "too many partial calls").await;
+
+        tokio::time::advance(Duration::from_secs(6)).await;
+        peer.send(frame("third")).await.unwrap();
+        expect_failure(&mut peer, "first", "call body timed out").await;
     }
 
-    pub fn connected(&self) -> bool {
-        self.0.connected()
+    #[actix_rt::test]
+    async fn test_lone_partial_call_expires() {
+        tokio::time::pause();
+        let (_connection, mut peer) = connect_pair(ConnectionConfig {
+            partial_call_timeout: Duration::from_secs(5),
+            ..Default::default()
+        });
+        hello(&mut peer).await;
+
+        peer.send(GsbMessage::CallRequest(CallRequest {
+            request_id: "stalled".to_string(),
+            caller: "caller".to_string(),
+            address: "/local/unbound".to_string(),
+            data: vec![0; 3],
+            has_more: true,
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        let started = tokio::time::Instant::now();
+        // no other call arrives to trigger the expiry
+        match next_message(&mut peer).await {
+            GsbMessage::CallReply(r) => {
+                assert_eq!(r.request_id, "stalled");
+                assert_eq!(r.code, CallReplyCode::ServiceFailure as i32);
+                assert_eq!(r.data, b"call body timed out");
+            }
+            msg => panic!("expected call reply, got {}", msg.kind()),
+        }
+        let waited = started.elapsed();
+        assert!(waited >= Duration::from_secs(5), "{:?}", waited);
+        assert!(waited <= Duration::from_millis(7500), "{:?}", waited);
     }
-}
 
-pub fn connect<Transport, H>(
-    client_info: ClientInfo,
-    transport: Transport,
-) -> ConnectionRef<Transport, H>
-where
-    Transport: Sink<GsbMessage, Error = ProtocolError>
-        + Stream<Item = Result<GsbMessage, ProtocolError>>
-        + Unpin
-        + 'static,
-    H: CallRequestHandler + 'static + Default + Unpin,
-{
-    connect_with_handler(client_info, transport, Default::default())
-}
+    #[actix_rt::test]
+    async fn test_acked_push_retry_cancels_attempt() {
+        tokio::time::pause();
+        let (connection, mut peer) = connect_pair(Default::default());
+        hello(&mut peer).await;
 
-pub fn connect_with_handler<Transport, H>(
-    client_info: ClientInfo,
-    transport: Transport,
-    handler: H,
-) -> ConnectionRef<Transport, H>
-where
-    Transport: Sink<GsbMessage, Error = ProtocolError>
-        + Stream<Item = Result<GsbMessage, ProtocolError>>
-        + Unpin
-        + 'static,
-    H: CallRequestHandler + 'static,
-{
-    let (split_sink, split_stream) = transport.split();
-    ConnectionRef(Connection::create(move |ctx| {
-        let _h = Connection::add_stream(split_stream, ctx);
-        Connection::new(client_info, split_sink, handler, ctx)
-    }))
-}
+        let opts = PushOptions {
+            max_retries: 1,
+            timeout: Duration::from_secs(1),
+            ..PushOptions::acked()
+        };
+        let push = connection.push_with("test", "/slow/service", Vec::new(), opts);
+        let router = async {
+            let first = next_call(&mut peer).await;
+            assert!(first.deadline.is_some());
+            match next_message(&mut peer).await {
+                GsbMessage::CallCancel(c) => assert_eq!(c.request_id, first.request_id),
+                msg => panic!("expected call cancel, got {}", msg.kind()),
+            }
+            let second = next_call(&mut peer).await;
+            assert_ne!(second.request_id, first.request_id);
+            peer.send(GsbMessage::CallReply(CallReply {
+                request_id: second.request_id,
+                code: CallReplyCode::CallReplyOk as i32,
+                reply_type: CallReplyType::Full as i32,
+                data: Vec::new(),
+                trailers: Default::default(),
+            }))
+            .await
+            .unwrap();
+        };
+        let (result, ()) = future::join(push, router).await;
+        result.unwrap();
+        assert!(connection.in_flight().await.is_empty());
+    }
 
-pub type TcpTransport =
-    tokio_util::codec::Framed<tokio::net::TcpStream, ya_sb_proto::codec::GsbMessageCodec>;
+    #[actix_rt::test]
+    async fn test_acked_push_backs_off() {
+        use crate::backoff::FixedBackoff;
 
-pub async fn tcp(addr: impl tokio::net::ToSocketAddrs) -> Result<TcpTransport, std::io::Error> {
-    let s = tokio::net::TcpStream::connect(addr).await?;
-    Ok(tokio_util::codec::Framed::new(
-        s,
-        ya_sb_proto::codec::GsbMessageCodec::default(),
-    ))
-}
+        tokio::time::pause();
+        let (connection, mut peer) = connect_pair(Default::default());
+        hello(&mut peer).await;
 
-#[cfg(unix)]
-mod unix {
+        let opts = PushOptions {
+            max_retries: 5,
+            ..PushOptions::acked()
+        }
+        .with_backoff(FixedBackoff {
+            max_attempts: Some(1),
+            ..FixedBackoff::new(Duration::from_secs(2))
+        });
+        let push = connection.push_with("test", "/failing/service", Vec::new(), opts);
+        let router = async {
+            let first = next_call(&mut peer).await;
+            let failed_at = tokio::time::Instant::now();
+            reply(
+                &mut peer,
+                &first.request_id,
+                CallReplyCode::ServiceFailure,
+                b"",
+            )
+            .await;
+            let second = next_call(&mut peer).await;
+            assert!(failed_at.elapsed() >= Duration::from_secs(2));
+            reply(
+                &mut peer,
+                &second.request_id,
+                CallReplyCode::ServiceFailure,
+                b"",
+            )
+            .await;
+        };
+        let (result, ()) = future::join(push, router).await;
+        assert!(result.is_err(), "backoff gave up before max_retries");
+        let more = tokio::time::timeout(Duration::from_secs(10), next_call(&mut peer)).await;
+        assert!(more.is_err(), "redelivered after backoff gave up");
+    }
 
-    use super::*;
-    use std::task::Poll;
+    #[actix_rt::test]
+    async fn test_call_with_retry() {
+        use crate::backoff::FixedBackoff;
 
-    pub type UnixTransport =
-        tokio_util::codec::Framed<tokio::net::UnixStream, ya_sb_proto::codec::GsbMessageCodec>;
+        tokio::time::pause();
+        let (connection, mut peer) = connect_pair(ConnectionConfig {
+            call_timeout: Some(Duration::from_secs(1)),
+            ..Default::default()
+        });
+        hello(&mut peer).await;
+        let backoff = || FixedBackoff {
+            max_attempts: Some(2),
+            ..FixedBackoff::new(Duration::from_secs(2))
+        };
 
-    pub async fn unix<P>(path: P) -> Result<UnixTransport, std::io::Error>
-    where
-        P: AsRef<std::path::Path>,
-    {
-        let s = tokio::net::UnixStream::connect(path).await?;
-        Ok(tokio_util::codec::Framed::new(
-            s,
-            ya_sb_proto::codec::GsbMessageCodec::default(),
-        ))
+        // timed out call is repeated after the delay
+        let call = connection.call_with_retry("test", "/slow/service", Vec::new(), backoff());
+        let router = async {
+            let first = next_call(&mut peer).await;
+            let sent_at = tokio::time::Instant::now();
+            match next_message(&mut peer).await {
+                GsbMessage::CallCancel(c) => assert_eq!(c.request_id, first.request_id),
+                msg => panic!("expected call cancel, got {}", msg.kind()),
+            }
+            let second = next_call(&mut peer).await;
+            assert_ne!(second.request_id, first.request_id);
+            assert!(sent_at.elapsed() >= Duration::from_secs(3));
+            reply(
+                &mut peer,
+                &second.request_id,
+                CallReplyCode::CallReplyOk,
+                b"done",
+            )
+            .await;
+        };
+        let (result, ()) = future::join(call, router).await;
+        assert_eq!(result.unwrap(), b"done");
+
+        // rejection by the service is not retried
+        let call = connection.call_with_retry("test", "/failing/service", Vec::new(), backoff());
+        let router = async {
+            let request = next_call(&mut peer).await;
+            reply(
+                &mut peer,
+                &request.request_id,
+                CallReplyCode::ServiceFailure,
+                b"",
+            )
+            .await;
+        };
+        let (result, ()) = future::join(call, router).await;
+        assert!(result.is_err());
+        let more = tokio::time::timeout(Duration::from_secs(10), next_call(&mut peer)).await;
+        assert!(more.is_err(), "rejected call retried");
     }
 
-    /// This trait exists to annotate the return type of Transport::inner()
-    trait ITransport:
-        Sink<GsbMessage, Error = ProtocolError>
-        + Stream<Item = Result<GsbMessage, ProtocolError>>
-        + Unpin
-        + 'static
-    {
+    async fn expect_subscribe(peer: &mut Peer, topic: &str) {
+        match next_message(peer).await {
+            GsbMessage::SubscribeRequest(r) => assert_eq!(r.topic, topic),
+            msg => panic!("expected subscribe, got {}", msg.kind()),
+        }
+        peer.send(GsbMessage::SubscribeReply(ya_sb_proto::SubscribeReply {
+            code: SubscribeReplyCode::SubscribedOk as i32,
+            message: String::new(),
+        }))
+        .await
+        .unwrap();
     }
 
-    impl ITransport for TcpTransport {}
-    impl ITransport for UnixTransport {}
+    #[actix_rt::test]
+    async fn test_broadcast_receivers_and_subscriptions() {
+        let (connection, mut peer) = connect_pair(Default::default());
+        hello(&mut peer).await;
 
-    pub enum Transport {
-        Tcp(TcpTransport),
-        Unix(UnixTransport),
+        let (news, ()) = future::join(
+            connection.subscribe("news"),
+            expect_subscribe(&mut peer, "news"),
+        )
+        .await;
+        let (sport, ()) = future::join(
+            connection.subscribe("sport"),
+            expect_subscribe(&mut peer, "sport"),
+        )
+        .await;
+        let (_news, sport) = (news.unwrap(), sport.unwrap());
+        let mut topics = connection.subscriptions().await;
+        topics.sort();
+        assert_eq!(topics, vec!["news".to_string(), "sport".to_string()]);
+
+        for receivers in [2, 3] {
+            let router = async {
+                match next_message(&mut peer).await {
+                    GsbMessage::BroadcastRequest(r) => assert_eq!(r.topic, "news"),
+                    msg => panic!("expected broadcast, got {}", msg.kind()),
+                }
+                peer.send(GsbMessage::BroadcastReply(ya_sb_proto::BroadcastReply {
+                    code: BroadcastReplyCode::BroadcastOk as i32,
+                    message: String::new(),
+                    receivers,
+                }))
+                .await
+                .unwrap();
+            };
+            let (result, ()) =
+                future::join(connection.broadcast("test", "news", Vec::new()), router).await;
+            result.unwrap();
+        }
+        let stats = connection.stats().await;
+        assert_eq!(stats.broadcasts["news"].sent, 2);
+        assert_eq!(stats.broadcasts["news"].receivers, 5);
+
+        let router = async {
+            match next_message(&mut peer).await {
+                GsbMessage::UnsubscribeRequest(r) => assert_eq!(r.topic, "sport"),
+                msg => panic!("expected unsubscribe, got {}", msg.kind()),
+            }
+            peer.send(GsbMessage::UnsubscribeReply(
+                ya_sb_proto::UnsubscribeReply {
+                    code: UnsubscribeReplyCode::UnsubscribedOk as i32,
+                },
+            ))
+            .await
+            .unwrap();
+        };
+        let (result, ()) = future::join(sport.unsubscribe(), router).await;
+        result.unwrap();
+        assert_eq!(connection.subscriptions().await, vec!["news".to_string()]);
+        connection.close();
+        assert!(connection.subscriptions().await.is_empty());
     }
 
-    impl Transport {
-        fn inner(self: Pin<&mut Self>) -> Pin<&mut (dyn ITransport)> {
-            match self.get_mut() {
-                Transport::Tcp(tcp_transport) => Pin::new(tcp_transport),
-                Transport::Unix(unix_transport) => Pin::new(unix_transport),
+    #[actix_rt::test]
+    async fn test_late_reply_after_timeout_dropped() {
+        let (connection, mut peer) = connect_pair(ConnectionConfig {
+            reply_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        });
+        hello(&mut peer).await;
+        let mut events = connection.events();
+
+        let (first, request) =
+            future::join(connection.bind("/slow"), next_message(&mut peer)).await;
+        assert!(matches!(first, Err(Error::Timeout(_))), "{:?}", first);
+        assert!(matches!(request, GsbMessage::RegisterRequest(_)));
+
+        // reply to the expired command comes while another one is queued
+        let second = connection.bind("/fast");
+        let router = async {
+            match next_message(&mut peer).await {
+                GsbMessage::RegisterRequest(r) => assert_eq!(r.service_id, "/fast"),
+                msg => panic!("expected register, got {}", msg.kind()),
             }
+            for code in [
+                RegisterReplyCode::RegisterBadRequest,
+                RegisterReplyCode::RegisteredOk,
+            ] {
+                peer.send(GsbMessage::RegisterReply(ya_sb_proto::RegisterReply {
+                    code: code as i32,
+                    message: String::new(),
+                }))
+                .await
+                .unwrap();
+            }
+        };
+        let (second, ()) = future::join(second, router).await;
+        second.unwrap();
+        match events.next().await {
+            Some(ConnectionEvent::Registered(addr)) => assert_eq!(addr, "/fast"),
+            event => panic!("expected registration of /fast, got {:?}", event),
         }
+        assert_eq!(connection.stats().await.reply_timeouts, 1);
     }
 
-    impl Sink<GsbMessage> for Transport {
-        type Error = ProtocolError;
+    #[actix_rt::test]
+    async fn test_subscription_handles_share_topic() {
+        let (connection, mut peer) = connect_pair(Default::default());
+        hello(&mut peer).await;
 
-        fn poll_ready(
-            self: Pin<&mut Self>,
-            cx: &mut core::task::Context<'_>,
-        ) -> Poll<Result<(), Self::Error>> {
-            self.inner().poll_ready(cx)
-        }
+        // second subscriber waits for the first one's request
+        let (first, second, ()) = future::join3(
+            connection.subscribe("news"),
+            connection.subscribe("news"),
+            expect_subscribe(&mut peer, "news"),
+        )
+        .await;
+        let (first, second) = (first.unwrap(), second.unwrap());
+        let third = connection.subscribe("news").await.unwrap();
 
-        fn start_send(self: Pin<&mut Self>, item: GsbMessage) -> Result<(), Self::Error> {
-            self.inner().start_send(item)
+        drop(first);
+        drop(second);
+        assert_eq!(connection.subscriptions().await, vec!["news".to_string()]);
+        drop(third);
+        match next_message(&mut peer).await {
+            GsbMessage::UnsubscribeRequest(r) => assert_eq!(r.topic, "news"),
+            msg => panic!("expected unsubscribe, got {}", msg.kind()),
         }
+        peer.send(GsbMessage::UnsubscribeReply(
+            ya_sb_proto::UnsubscribeReply {
+                code: UnsubscribeReplyCode::UnsubscribedOk as i32,
+            },
+        ))
+        .await
+        .unwrap();
+        let more = tokio::time::timeout(Duration::from_millis(100), next_message(&mut peer)).await;
+        assert!(more.is_err(), "unsubscribed more than once");
+        assert!(connection.subscriptions().await.is_empty());
+    }
 
-        fn poll_flush(
-            self: Pin<&mut Self>,
-            cx: &mut core::task::Context<'_>,
-        ) -> Poll<Result<(), Self::Error>> {
-            self.inner().poll_flush(cx)
-        }
+    #[actix_rt::test]
+    async fn test_filtered_subscription() {
+        let (events, mut received) = mpsc::unbounded();
+        let handler = (
+            |_: String, _: String, _: String, _: Vec<u8>| stream::empty(),
+            move |_: String, topic: String, data: Vec<u8>| {
+                let _ = events.unbounded_send((topic, data));
+            },
+        );
+        let (client, router) = tokio::io::duplex(64 * 1024);
+        let codec = CodecConfig::default();
+        let connection = connect_with_config(
+            ClientInfo::new("test"),
+            Default::default(),
+            framed(client, &codec),
+            handler,
+        );
+        let mut peer = framed(router, &codec);
+        hello(&mut peer).await;
+        let broadcast = |topic: &str, data: &[u8]| {
+            GsbMessage::BroadcastRequest(BroadcastRequest {
+                caller: "caller".to_string(),
+                topic: topic.to_string(),
+                data: data.to_vec(),
+            })
+        };
 
-        fn poll_close(
-            self: Pin<&mut Self>,
-            cx: &mut core::task::Context<'_>,
-        ) -> Poll<Result<(), Self::Error>> {
-            self.inner().poll_close(cx)
-        }
+        let (filtered, ()) = future::join(
+            connection.subscribe_filtered("news", |data| data == b"wanted"),
+            expect_subscribe(&mut peer, "news"),
+        )
+        .await;
+        let _filtered = filtered.unwrap();
+        // plain subscription does not replace the filter once dropped
+        drop(connection.subscribe("news").await.unwrap());
+        peer.send(broadcast("news", b"other")).await.unwrap();
+        peer.send(broadcast("news", b"wanted")).await.unwrap();
+        let event = received.next().await.unwrap();
+        assert_eq!(event, ("news".to_string(), b"wanted".to_vec()));
+
+        // filter of failed subscription is not installed
+        let failed = connection.subscribe_filtered("alerts", |_| false);
+        let router = async {
+            let _ = next_message(&mut peer).await;
+            peer.send(GsbMessage::SubscribeReply(ya_sb_proto::SubscribeReply {
+                code: SubscribeReplyCode::SubscribeBadRequest as i32,
+                message: "denied".to_string(),
+            }))
+            .await
+            .unwrap();
+        };
+        let (failed, ()) = future::join(failed, router).await;
+        assert!(failed.is_err());
+        peer.send(broadcast("alerts", b"any")).await.unwrap();
+        let event = received.next().await.unwrap();
+        assert_eq!(event, ("alerts".to_string(), b"any".to_vec()));
     }
 
-    impl Stream for Transport {
-        type Item = Result<GsbMessage, ProtocolError>;
+    #[actix_rt::test]
+    async fn test_shutdown_flushes_queued_writes() {
+        let (client, router) = tokio::io::duplex(256);
+        let codec = CodecConfig::default();
+        let (reasons_tx, mut reasons) = mpsc::unbounded();
+        let (shutdown_tx, shutdown) = oneshot::channel::<()>();
+        let connection = ConnectionBuilder::new(ClientInfo::new("test"))
+            .shutdown_on(shutdown)
+            .connect(
+                framed(client, &codec),
+                LocalRouterHandler::with_disconnect_handler(move |reason| {
+                    let _ = reasons_tx.unbounded_send(reason);
+                }),
+            );
+        let mut peer = framed(router, &codec);
 
-        fn poll_next(
-            self: Pin<&mut Self>,
-            cx: &mut core::task::Context<'_>,
-        ) -> Poll<Option<Self::Item>> {
-            self.inner().poll_next(cx)
+        // the peer reads nothing yet, pushes wait in the write buffer
+        for i in 0..8u8 {
+            connection
+                .push("test", "/remote/sink", vec![i; 1024])
+                .await
+                .unwrap();
         }
-    }
+        shutdown_tx.send(()).unwrap();
 
-    impl Unpin for Transport {}
+        let mut pushed = Vec::new();
+        while let Some(msg) = peer.next().await {
+            if let GsbMessage::CallRequest(r) = msg.unwrap() {
+                pushed.push(r.data[0]);
+            }
+        }
+        assert_eq!(pushed, (0..8).collect::<Vec<_>>());
+        assert!(matches!(
+            reasons.next().await,
+            Some(DisconnectReason::LocalStop)
+        ));
+    }
 
-    pub async fn transport(addr: ya_sb_proto::GsbAddr) -> Result<Transport, std::io::Error> {
-        match addr {
-            ya_sb_proto::GsbAddr::Tcp(addr) => Ok(Transport::Tcp(tcp(addr).await?)),
-            ya_sb_proto::GsbAddr::Unix(path) => Ok(Transport::Unix(unix(path).await?)),
+    /// Sends calls to `/old/...` to `/new/...` instead.
+    fn rewrite_address(msg: GsbMessage) -> Option<GsbMessage> {
+        match msg {
+            GsbMessage::CallRequest(mut r) => {
+                r.address = r.address.replace("/old/", "/new/");
+                Some(GsbMessage::CallRequest(r))
+            }
+            msg => Some(msg),
         }
     }
-}
 
-#[cfg(unix)]
-pub use unix::*;
+    #[actix_rt::test]
+    async fn test_intercept() {
+        let mut inject = None;
+        let (connection, mut peer) = connect_pair_with(|transport| {
+            let (transport, tx) = Intercept::new(transport)
+                .outbound(rewrite_address)
+                .inbound(|msg| match &msg {
+                    GsbMessage::CallReply(r) if r.data == b"stale" => None,
+                    _ => Some(msg),
+                })
+                .with_injector();
+            inject = Some(tx);
+            transport
+        });
+        let inject = inject.unwrap();
+        hello(&mut peer).await;
 
-#[cfg(not(unix))]
-pub type Transport = TcpTransport;
+        // rewritten on the way out, stale reply dropped on the way in
+        let call = connection.call("test", "/old/service", Vec::new(), false);
+        let router = async {
+            let request = next_call(&mut peer).await;
+            assert_eq!(request.address, "/new/service");
+            reply(
+                &mut peer,
+                &request.request_id,
+                CallReplyCode::CallReplyOk,
+                b"stale",
+            )
+            .await;
+            reply(
+                &mut peer,
+                &request.request_id,
+                CallReplyCode::CallReplyOk,
+                b"fresh",
+            )
+            .await;
+        };
+        let (result, ()) = future::join(call, router).await;
+        assert_eq!(result.unwrap(), b"fresh".to_vec());
 
-#[cfg(not(unix))]
-pub async fn transport(addr: ya_sb_proto::GsbAddr) -> Result<TcpTransport, std::io::Error> {
-    match addr {
-        ya_sb_proto::GsbAddr::Tcp(addr) => Ok(tcp(addr).await?),
-        ya_sb_proto::GsbAddr::Unix(_) => panic!("Unix sockets not supported on this OS"),
+        // injected reply is taken as if the peer sent it
+        let call = connection.call("test", "/remote/service", Vec::new(), false);
+        let router = async {
+            let request = next_call(&mut peer).await;
+            inject
+                .unbounded_send(GsbMessage::CallReply(CallReply {
+                    request_id: request.request_id,
+                    code: CallReplyCode::CallReplyOk as i32,
+                    reply_type: CallReplyType::Full as i32,
+                    data: b"injected".to_vec(),
+                    trailers: Default::default(),
+                }))
+                .unwrap();
+        };
+        let (result, ()) = future::join(call, router).await;
+        assert_eq!(result.unwrap(), b"injected".to_vec());
+    }
+
+    #[actix_rt::test]
+    async fn test_connect_to_with() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = ya_sb_proto::GsbAddr::Tcp(listener.local_addr().unwrap().to_string());
+        let (connection, stream) = future::join(
+            ConnectionBuilder::new(ClientInfo::new("test")).connect_to_with(
+                addr,
+                LocalRouterHandler::default(),
+                |transport| Intercept::new(transport).outbound(rewrite_address),
+            ),
+            listener.accept(),
+        )
+        .await;
+        let connection = connection.unwrap();
+        let mut peer = Framed::new(stream.unwrap().0, GsbMessageCodec::default());
+        let hello = ya_sb_proto::Hello {
+            proto_version: ya_sb_proto::PROTO_VERSION,
+            ..Default::default()
+        };
+        peer.send(GsbMessage::Hello(hello)).await.unwrap();
+
+        let call = connection.call("test", "/old/service", Vec::new(), false);
+        let router = async {
+            let request = loop {
+                match peer.next().await.unwrap().unwrap() {
+                    GsbMessage::CallRequest(r) => break r,
+                    _ => continue,
+                }
+            };
+            assert_eq!(request.address, "/new/service");
+            peer.send(GsbMessage::CallReply(CallReply {
+                request_id: request.request_id,
+                code: CallReplyCode::CallReplyOk as i32,
+                reply_type: CallReplyType::Full as i32,
+                data: b"ok".to_vec(),
+                trailers: Default::default(),
+            }))
+            .await
+            .unwrap();
+        };
+        let (result, ()) = future::join(call, router).await;
+        assert_eq!(result.unwrap(), b"ok".to_vec());
     }
 }
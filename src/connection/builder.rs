@@ -0,0 +1,198 @@
+use futures::channel::mpsc;
+use futures::future::{BoxFuture, Shared};
+use futures::prelude::*;
+use std::time::Duration;
+
+use ya_sb_proto::codec::{CodecConfig, GsbMessage, ProtocolError};
+
+use super::{
+    start_connection, transport_with_codec, CallRequestHandler, ClientInfo, ConnectionConfig,
+    ConnectionRef, TappedMessage, Transport,
+};
+
+/// Collects connection settings in one place.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use ya_service_bus::connection::{ClientInfo, ConnectionBuilder, LocalRouterHandler};
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let connection = ConnectionBuilder::new(ClientInfo::new("my-service"))
+///     .call_timeout(Duration::from_secs(30))
+///     .heartbeat(Duration::from_secs(10), Duration::from_secs(30))
+///     .max_frame_size(16 * 1024 * 1024)
+///     .connect_to(ya_sb_proto::GsbAddr::default(), LocalRouterHandler::default())
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ConnectionBuilder {
+    client_info: ClientInfo,
+    config: ConnectionConfig,
+    codec: CodecConfig,
+    shutdown: Option<Shared<BoxFuture<'static, ()>>>,
+}
+
+impl ConnectionBuilder {
+    pub fn new(client_info: ClientInfo) -> Self {
+        let config = ConnectionConfig::default();
+        ConnectionBuilder {
+            client_info,
+            codec: config.codec_config(),
+            config,
+            shutdown: None,
+        }
+    }
+
+    /// Replaces all connection settings, including ones set before.
+    pub fn config(mut self, config: ConnectionConfig) -> Self {
+        self.codec.skip_unknown = !config.strict_messages;
+        self.config = config;
+        self
+    }
+
+    /// Deadline of unary calls made without one, streaming calls are not
+    /// limited.
+    pub fn call_timeout(mut self, timeout: Duration) -> Self {
+        self.config.call_timeout = Some(timeout);
+        self
+    }
+
+    /// Pings the router after `interval` of silence and disconnects when
+    /// nothing arrives for `timeout`.
+    pub fn heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.config.ping_interval = Some(interval);
+        self.config.ping_timeout = timeout;
+        self
+    }
+
+    /// Largest frame accepted from the router.
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.codec.max_frame_size = max_frame_size;
+        self
+    }
+
+    pub fn max_in_flight_calls(mut self, max_in_flight_calls: usize) -> Self {
+        self.config.max_in_flight_calls = Some(max_in_flight_calls);
+        self
+    }
+
+    /// Largest reply accepted for single call, see `ConnectionConfig::max_reply_bytes`.
+    pub fn max_reply_bytes(mut self, max_reply_bytes: usize) -> Self {
+        self.config.max_reply_bytes = Some(max_reply_bytes);
+        self
+    }
+
+    /// Largest body of incoming call, see `ConnectionConfig::max_call_body`.
+    pub fn max_call_body(mut self, max_call_body: usize) -> Self {
+        self.config.max_call_body = Some(max_call_body);
+        self
+    }
+
+    /// Disconnects when written frames are not flushed for `timeout`.
+    pub fn write_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.config.write_stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Sends copy of every message going through the connection to `tap`.
+    ///
+    /// Copies are dropped when the channel is full, a slow observer never
+    /// holds up the connection.
+    pub fn with_tap(mut self, tap: mpsc::Sender<TappedMessage>) -> Self {
+        self.config.tap = Some(tap);
+        self
+    }
+
+    /// Includes payloads of calls, replies and broadcasts in tapped messages.
+    pub fn tap_bodies(mut self, tap_bodies: bool) -> Self {
+        self.config.tap_bodies = tap_bodies;
+        self
+    }
+
+    /// Disconnects on messages unknown or unexpected from the router, instead
+    /// of ignoring them.
+    pub fn strict_messages(mut self, strict: bool) -> Self {
+        self.config.strict_messages = strict;
+        self.codec.skip_unknown = !strict;
+        self
+    }
+
+    /// Warns when more than `chunks` of single reply wait to be sent,
+    /// `None` disables the warning.
+    pub fn reply_lag_warn(mut self, chunks: Option<usize>) -> Self {
+        self.config.reply_lag_warn = chunks;
+        self
+    }
+
+    /// Closes the connection when `shutdown` resolves, e.g. on
+    /// `tokio::signal::ctrl_c`, as `connect_with_shutdown` does. Applies to
+    /// every connection started by this builder and its clones.
+    pub fn shutdown_on(mut self, shutdown: impl Future + Send + 'static) -> Self {
+        self.shutdown = Some(shutdown.map(|_| ()).boxed().shared());
+        self
+    }
+
+    /// Replaces codec settings, including `skip_unknown` set by
+    /// `strict_messages`.
+    pub fn codec(mut self, codec: CodecConfig) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Starts connection over already established transport.
+    ///
+    /// Codec settings are not applied, they are part of the transport.
+    pub fn connect<T, H>(self, transport: T, handler: H) -> ConnectionRef<T, H>
+    where
+        T: Sink<GsbMessage, Error = ProtocolError>
+            + Stream<Item = Result<GsbMessage, ProtocolError>>
+            + Unpin
+            + 'static,
+        H: CallRequestHandler + 'static,
+    {
+        start_connection(
+            self.client_info,
+            self.config,
+            transport,
+            handler,
+            self.shutdown.map(|shutdown| shutdown.boxed_local()),
+        )
+    }
+
+    /// Opens transport to `addr` and starts connection over it.
+    pub async fn connect_to<H>(
+        self,
+        addr: ya_sb_proto::GsbAddr,
+        handler: H,
+    ) -> std::io::Result<ConnectionRef<Transport, H>>
+    where
+        H: CallRequestHandler + 'static,
+    {
+        let transport = transport_with_codec(addr, self.codec).await?;
+        Ok(self.connect(transport, handler))
+    }
+
+    /// Opens transport to `addr`, wraps it with `middleware` and starts
+    /// connection over the result, see [`Intercept`](super::Intercept).
+    pub async fn connect_to_with<H, T, F>(
+        self,
+        addr: ya_sb_proto::GsbAddr,
+        handler: H,
+        middleware: F,
+    ) -> std::io::Result<ConnectionRef<T, H>>
+    where
+        H: CallRequestHandler + 'static,
+        F: FnOnce(Transport) -> T,
+        T: Sink<GsbMessage, Error = ProtocolError>
+            + Stream<Item = Result<GsbMessage, ProtocolError>>
+            + Unpin
+            + 'static,
+    {
+        let transport = transport_with_codec(addr, self.codec).await?;
+        Ok(self.connect(middleware(transport), handler))
+    }
+}
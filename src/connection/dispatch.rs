@@ -0,0 +1,187 @@
+use futures::prelude::*;
+use futures::stream::LocalBoxStream;
+use std::time::Instant;
+
+use ya_sb_util::PrefixLookupBag;
+
+use super::{CallContext, CallRequestHandler, DisconnectReason};
+use crate::{Error, Headers, ResponseChunk};
+
+type Reply = LocalBoxStream<'static, Result<ResponseChunk, Error>>;
+
+type BoxHandler = Box<dyn CallRequestHandler<Reply = Reply>>;
+
+struct Boxed<H>(H);
+
+impl<H> CallRequestHandler for Boxed<H>
+where
+    H: CallRequestHandler,
+    H::Reply: 'static,
+{
+    type Reply = Reply;
+
+    fn do_call(
+        &mut self,
+        request_id: String,
+        caller: String,
+        address: String,
+        data: Vec<u8>,
+        no_reply: bool,
+    ) -> Self::Reply {
+        self.0
+            .do_call(request_id, caller, address, data, no_reply)
+            .boxed_local()
+    }
+
+    fn do_call_with_deadline(
+        &mut self,
+        request_id: String,
+        caller: String,
+        address: String,
+        data: Vec<u8>,
+        no_reply: bool,
+        deadline: Option<Instant>,
+    ) -> Self::Reply {
+        self.0
+            .do_call_with_deadline(request_id, caller, address, data, no_reply, deadline)
+            .boxed_local()
+    }
+
+    fn handle_call(&mut self, call: CallContext) -> Self::Reply {
+        self.0.handle_call(call).boxed_local()
+    }
+
+    fn handle_event(&mut self, caller: String, topic: String, data: Vec<u8>) {
+        self.0.handle_event(caller, topic, data)
+    }
+
+    fn on_disconnect(&mut self, reason: DisconnectReason) {
+        self.0.on_disconnect(reason)
+    }
+
+    fn on_peer_reannounce(&mut self, old: &ya_sb_proto::Hello, new: &ya_sb_proto::Hello) {
+        self.0.on_peer_reannounce(old, new)
+    }
+
+    fn serves(&mut self, address: &str) -> bool {
+        self.0.serves(address)
+    }
+}
+
+/// Serves several handlers over single connection.
+///
+/// Calls and events are passed to the handler registered under the longest
+/// matching prefix of their address or topic, calls without one fail with
+/// `GsbBadRequest`.
+///
+/// ## Example
+///
+/// ```no_run
+/// use futures::{future, stream};
+/// use ya_service_bus::connection::DispatchHandler;
+/// use ya_service_bus::{Error, ResponseChunk};
+///
+/// let mut handler = DispatchHandler::default();
+/// handler.register(
+///     "/public/echo",
+///     |_: String, _: String, _: String, data: Vec<u8>| {
+///         stream::once(future::ok::<_, Error>(ResponseChunk::Full(data)))
+///     },
+/// );
+/// ```
+#[derive(Default)]
+pub struct DispatchHandler {
+    handlers: PrefixLookupBag<BoxHandler>,
+}
+
+impl DispatchHandler {
+    /// Registers `handler` for addresses under `prefix`, replacing handler
+    /// previously registered with the same prefix.
+    pub fn register<H>(&mut self, prefix: impl Into<String>, handler: H) -> &mut Self
+    where
+        H: CallRequestHandler + 'static,
+        H::Reply: 'static,
+    {
+        let _ = self
+            .handlers
+            .insert(prefix.into(), Box::new(Boxed(handler)));
+        self
+    }
+
+    pub fn unregister(&mut self, prefix: &str) -> bool {
+        self.handlers.remove(prefix).is_some()
+    }
+}
+
+impl CallRequestHandler for DispatchHandler {
+    type Reply = Reply;
+
+    fn do_call(
+        &mut self,
+        request_id: String,
+        caller: String,
+        address: String,
+        data: Vec<u8>,
+        no_reply: bool,
+    ) -> Self::Reply {
+        self.do_call_with_deadline(request_id, caller, address, data, no_reply, None)
+    }
+
+    fn do_call_with_deadline(
+        &mut self,
+        request_id: String,
+        caller: String,
+        address: String,
+        data: Vec<u8>,
+        no_reply: bool,
+        deadline: Option<Instant>,
+    ) -> Self::Reply {
+        self.handle_call(CallContext {
+            request_id,
+            caller,
+            address,
+            data,
+            no_reply,
+            deadline,
+            headers: Headers::new(),
+            trailers: Default::default(),
+        })
+    }
+
+    fn handle_call(&mut self, call: CallContext) -> Self::Reply {
+        match self.handlers.get_mut(&call.address) {
+            Some(handler) => handler.handle_call(call),
+            None => stream::once(future::err(Error::GsbBadRequest(format!(
+                "no handler for {}",
+                call.address
+            ))))
+            .boxed_local(),
+        }
+    }
+
+    fn handle_event(&mut self, caller: String, topic: String, data: Vec<u8>) {
+        match self.handlers.get_mut(&topic) {
+            Some(handler) => handler.handle_event(caller, topic, data),
+            None => log::warn!("unhandled gsb event from: {}, to: {}", caller, topic),
+        }
+    }
+
+    fn on_disconnect(&mut self, reason: DisconnectReason) {
+        for handler in self.handlers.values_mut() {
+            handler.on_disconnect(reason.clone());
+        }
+    }
+
+    fn on_peer_reannounce(&mut self, old: &ya_sb_proto::Hello, new: &ya_sb_proto::Hello) {
+        for handler in self.handlers.values_mut() {
+            handler.on_peer_reannounce(old, new);
+        }
+    }
+
+    fn serves(&mut self, address: &str) -> bool {
+        match self.handlers.get_mut(address) {
+            Some(handler) => handler.serves(address),
+            None => false,
+        }
+    }
+}
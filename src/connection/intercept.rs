@@ -0,0 +1,139 @@
+use futures::channel::mpsc;
+use futures::prelude::*;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ya_sb_proto::codec::{GsbMessage, ProtocolError};
+
+type Hook = Box<dyn FnMut(GsbMessage) -> Option<GsbMessage>>;
+
+/// Transport wrapper passing every message through user hooks, e.g. to
+/// inject faults or craft messages in tests and proxies.
+///
+/// Hooks may change a message or drop it by returning `None`. Wrapped
+/// transport is used like any other, e.g. with `connect_with_handler`.
+///
+/// ## Example
+///
+/// ```no_run
+/// use ya_service_bus::connection::{self, ClientInfo, Intercept, LocalRouterHandler};
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let transport = connection::tcp("127.0.0.1:7464").await?;
+/// let (transport, _inject) = Intercept::new(transport)
+///     .outbound(|msg| {
+///         log::debug!("sending {}", msg.kind());
+///         Some(msg)
+///     })
+///     .with_injector();
+/// let _connection = connection::connect_with_handler(
+///     ClientInfo::new("chaos"),
+///     transport,
+///     LocalRouterHandler::default(),
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub struct Intercept<T> {
+    inner: T,
+    inbound: Option<Hook>,
+    outbound: Option<Hook>,
+    injected: Option<mpsc::UnboundedReceiver<GsbMessage>>,
+}
+
+impl<T> Intercept<T> {
+    pub fn new(inner: T) -> Self {
+        Intercept {
+            inner,
+            inbound: None,
+            outbound: None,
+            injected: None,
+        }
+    }
+
+    /// Hook applied to messages received from the peer.
+    pub fn inbound(mut self, hook: impl FnMut(GsbMessage) -> Option<GsbMessage> + 'static) -> Self {
+        self.inbound = Some(Box::new(hook));
+        self
+    }
+
+    /// Hook applied to messages sent by the connection.
+    pub fn outbound(
+        mut self,
+        hook: impl FnMut(GsbMessage) -> Option<GsbMessage> + 'static,
+    ) -> Self {
+        self.outbound = Some(Box::new(hook));
+        self
+    }
+
+    /// Returns sender of messages delivered to the connection as if they
+    /// came from the peer, ahead of received ones.
+    pub fn with_injector(mut self) -> (Self, mpsc::UnboundedSender<GsbMessage>) {
+        let (tx, rx) = mpsc::unbounded();
+        self.injected = Some(rx);
+        (self, tx)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T> Sink<GsbMessage> for Intercept<T>
+where
+    T: Sink<GsbMessage, Error = ProtocolError> + Unpin,
+{
+    type Error = ProtocolError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: GsbMessage) -> Result<(), Self::Error> {
+        let item = match &mut self.outbound {
+            Some(hook) => match hook(item) {
+                Some(item) => item,
+                None => return Ok(()),
+            },
+            None => item,
+        };
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl<T> Stream for Intercept<T>
+where
+    T: Stream<Item = Result<GsbMessage, ProtocolError>> + Unpin,
+{
+    type Item = Result<GsbMessage, ProtocolError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(injected) = &mut self.injected {
+            if let Poll::Ready(Some(msg)) = injected.poll_next_unpin(cx) {
+                return Poll::Ready(Some(Ok(msg)));
+            }
+        }
+        loop {
+            let msg = match futures::ready!(self.inner.poll_next_unpin(cx)) {
+                Some(Ok(msg)) => msg,
+                other => return Poll::Ready(other),
+            };
+            match &mut self.inbound {
+                Some(hook) => {
+                    if let Some(msg) = hook(msg) {
+                        return Poll::Ready(Some(Ok(msg)));
+                    }
+                }
+                None => return Poll::Ready(Some(Ok(msg))),
+            }
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use futures::channel::oneshot;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use ya_sb_proto::GsbAddr;
+
+use super::{CallRequestHandler, ConnectionBuilder, ConnectionRef, Transport};
+use crate::Error;
+
+type ConnectReply<H> = Result<ConnectionRef<Transport, H>, Error>;
+
+enum Entry<H: CallRequestHandler + 'static> {
+    /// Connect attempt in progress, with callers waiting for its result.
+    Connecting(u64, Vec<oneshot::Sender<ConnectReply<H>>>),
+    Connected(ConnectionRef<Transport, H>),
+}
+
+type Entries<H> = Arc<Mutex<HashMap<GsbAddr, Entry<H>>>>;
+
+static CONNECT_ATTEMPTS: AtomicU64 = AtomicU64::new(0);
+
+/// Connections to several gsb routers, keyed by router address.
+///
+/// Connects on first `get` and hands out the same connection until it
+/// closes, concurrent `get`s of one address share single connect attempt.
+/// Dead connections are replaced on next `get`, configure
+/// `ConnectionBuilder::heartbeat` to detect routers that stopped answering.
+pub struct ConnectionPool<H: CallRequestHandler + 'static> {
+    builder: ConnectionBuilder,
+    handler_factory: Arc<dyn Fn() -> H + Send + Sync>,
+    entries: Entries<H>,
+}
+
+impl<H: CallRequestHandler + 'static> Clone for ConnectionPool<H> {
+    fn clone(&self) -> Self {
+        ConnectionPool {
+            builder: self.builder.clone(),
+            handler_factory: self.handler_factory.clone(),
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<H: CallRequestHandler + Unpin + 'static> ConnectionPool<H> {
+    /// Opens connections with `builder` settings, each serving incoming calls
+    /// with handler created by `handler_factory`.
+    pub fn new(
+        builder: ConnectionBuilder,
+        handler_factory: impl Fn() -> H + Send + Sync + 'static,
+    ) -> Self {
+        ConnectionPool {
+            builder,
+            handler_factory: Arc::new(handler_factory),
+            entries: Default::default(),
+        }
+    }
+
+    /// Connection to router at `addr`, opened when there is no live one.
+    pub async fn get(&self, addr: GsbAddr) -> Result<ConnectionRef<Transport, H>, Error> {
+        let waiting = {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get_mut(&addr) {
+                Some(Entry::Connected(connection)) if connection.connected() => {
+                    return Ok(connection.clone())
+                }
+                Some(Entry::Connecting(_, waiters)) => {
+                    let (tx, rx) = oneshot::channel();
+                    waiters.push(tx);
+                    Err(rx)
+                }
+                _ => {
+                    let attempt = CONNECT_ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+                    entries.insert(addr.clone(), Entry::Connecting(attempt, Vec::new()));
+                    Ok(attempt)
+                }
+            }
+        };
+        let attempt = match waiting {
+            Ok(attempt) => attempt,
+            Err(rx) => return rx.await.map_err(|_| Error::Cancelled)?,
+        };
+
+        let mut guard = ConnectGuard {
+            entries: &self.entries,
+            addr: &addr,
+            attempt,
+            done: false,
+        };
+        let result = self
+            .builder
+            .clone()
+            .connect_to(addr.clone(), (self.handler_factory)())
+            .await;
+        guard.done = true;
+        drop(guard);
+
+        let mut entries = self.entries.lock().unwrap();
+        if !matches!(entries.get(&addr), Some(Entry::Connecting(a, _)) if *a == attempt) {
+            // Removed while connecting, waiters are already cancelled.
+            drop(entries);
+            if let Ok(connection) = &result {
+                connection.close();
+            }
+            return Err(Error::Cancelled);
+        }
+        let previous = match &result {
+            Ok(connection) => entries.insert(addr.clone(), Entry::Connected(connection.clone())),
+            Err(_) => entries.remove(&addr),
+        };
+        drop(entries);
+        if let Some(Entry::Connecting(_, waiters)) = previous {
+            for tx in waiters {
+                let _ = tx.send(match &result {
+                    Ok(connection) => Ok(connection.clone()),
+                    Err(e) => Err(Error::ConnectionFail(
+                        addr.clone(),
+                        io::Error::new(e.kind(), e.to_string()),
+                    )),
+                });
+            }
+        }
+        result.map_err(|e| Error::ConnectionFail(addr, e))
+    }
+
+    /// Closes and forgets connection to `addr`, returns whether there was one.
+    ///
+    /// Connect attempt in progress is abandoned, its callers get
+    /// `Error::Cancelled`.
+    pub fn remove(&self, addr: &GsbAddr) -> bool {
+        match self.entries.lock().unwrap().remove(addr) {
+            Some(Entry::Connected(connection)) => {
+                connection.close();
+                true
+            }
+            Some(Entry::Connecting(..)) => true,
+            None => false,
+        }
+    }
+
+    /// Forgets closed connections, returns how many were dropped.
+    pub fn evict_dead(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, entry| match entry {
+            Entry::Connected(connection) => connection.connected(),
+            Entry::Connecting(..) => true,
+        });
+        before - entries.len()
+    }
+
+    /// Addresses of live connections.
+    pub fn addrs(&self) -> Vec<GsbAddr> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(addr, entry)| match entry {
+                Entry::Connected(connection) if connection.connected() => Some(addr.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// Clears connect attempt abandoned by its caller, so that waiters fail with
+// `Error::Cancelled` and next `get` connects again.
+struct ConnectGuard<'a, H: CallRequestHandler + 'static> {
+    entries: &'a Entries<H>,
+    addr: &'a GsbAddr,
+    attempt: u64,
+    done: bool,
+}
+
+impl<H: CallRequestHandler + 'static> Drop for ConnectGuard<'_, H> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(Entry::Connecting(attempt, _)) = entries.get(self.addr) {
+            if *attempt == self.attempt {
+                entries.remove(self.addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::{ClientInfo, LocalRouterHandler};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    fn pool() -> ConnectionPool<LocalRouterHandler> {
+        ConnectionPool::new(
+            ConnectionBuilder::new(ClientInfo::new("test")),
+            LocalRouterHandler::default,
+        )
+    }
+
+    async fn listen() -> (TcpListener, GsbAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = GsbAddr::Tcp(listener.local_addr().unwrap().to_string());
+        (listener, addr)
+    }
+
+    #[actix_rt::test]
+    async fn test_concurrent_get_connects_once() {
+        let pool = pool();
+        let (listener, addr) = listen().await;
+
+        let (a, b) = futures::join!(pool.get(addr.clone()), pool.get(addr.clone()));
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(a.client_info().instance_id, b.client_info().instance_id);
+        let (_router, _) = listener.accept().await.unwrap();
+        let second = tokio::time::timeout(Duration::from_millis(100), listener.accept()).await;
+        assert!(second.is_err(), "connected twice");
+        assert_eq!(pool.addrs(), vec![addr]);
+    }
+
+    #[actix_rt::test]
+    async fn test_failed_connect_reported_to_all_waiters() {
+        let pool = pool();
+        let (listener, addr) = listen().await;
+        drop(listener);
+
+        let (a, b) = futures::join!(pool.get(addr.clone()), pool.get(addr.clone()));
+        assert!(matches!(a, Err(Error::ConnectionFail(..))), "{:?}", a.err());
+        assert!(matches!(b, Err(Error::ConnectionFail(..))), "{:?}", b.err());
+        assert!(pool.addrs().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_dead_connection_evicted() {
+        let pool = pool();
+        let (listener, addr) = listen().await;
+
+        let connection = pool.get(addr.clone()).await.unwrap();
+        let (router, _) = listener.accept().await.unwrap();
+        drop(router);
+        for _ in 0..100 {
+            if !connection.connected() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!connection.connected());
+        assert!(pool.addrs().is_empty());
+        assert_eq!(pool.evict_dead(), 1);
+        assert_eq!(pool.evict_dead(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_remove_during_connect() {
+        let pool = pool();
+        let (_listener, addr) = listen().await;
+
+        let get = pool.get(addr.clone());
+        futures::pin_mut!(get);
+        assert!(futures::poll!(&mut get).is_pending());
+        assert!(pool.remove(&addr));
+        let result = get.await;
+        assert!(
+            matches!(result, Err(Error::Cancelled)),
+            "{:?}",
+            result.err()
+        );
+        assert!(pool.addrs().is_empty());
+        assert!(!pool.remove(&addr));
+    }
+}
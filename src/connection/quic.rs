@@ -0,0 +1,95 @@
+//! Experimental QUIC transport.
+//!
+//! Whole GSB session is carried over a single bidirectional QUIC stream.
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use ya_sb_proto::codec::{CodecConfig, GsbMessageCodec};
+
+/// QUIC client settings.
+#[derive(Clone)]
+pub struct QuicConfig {
+    pub client: quinn::ClientConfig,
+    /// Name used to verify router certificate.
+    pub server_name: String,
+    /// Buffer sizing of the framed transport.
+    pub codec: CodecConfig,
+}
+
+impl QuicConfig {
+    pub fn new(client: quinn::ClientConfig, server_name: impl Into<String>) -> Self {
+        QuicConfig {
+            client,
+            server_name: server_name.into(),
+            codec: CodecConfig::default(),
+        }
+    }
+}
+
+pub struct QuicStream {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+    _connection: quinn::Connection,
+    _endpoint: quinn::Endpoint,
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().send).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().send).poll_shutdown(cx)
+    }
+}
+
+pub type QuicTransport = tokio_util::codec::Framed<QuicStream, GsbMessageCodec>;
+
+pub async fn quic(addr: SocketAddr, config: QuicConfig) -> Result<QuicTransport, io::Error> {
+    let bind_addr: SocketAddr = match addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let mut endpoint = quinn::Endpoint::client(bind_addr)?;
+    endpoint.set_default_client_config(config.client);
+
+    let connection = endpoint
+        .connect(addr, &config.server_name)
+        .map_err(io_error)?
+        .await
+        .map_err(io_error)?;
+    let (send, recv) = connection.open_bi().await.map_err(io_error)?;
+
+    let stream = QuicStream {
+        send,
+        recv,
+        _connection: connection,
+        _endpoint: endpoint,
+    };
+    Ok(super::framed(stream, &config.codec))
+}
+
+fn io_error(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::other(e)
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Per caller limit of incoming calls.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RateLimit {
+    /// Sustained number of calls accepted from single caller.
+    pub max_calls_per_sec: u32,
+    /// Number of calls caller may make at once before being limited.
+    pub burst: u32,
+    /// Number of callers tracked, least recently seen ones are forgotten.
+    pub max_callers: usize,
+}
+
+impl RateLimit {
+    pub fn new(max_calls_per_sec: u32, burst: u32) -> Self {
+        RateLimit {
+            max_calls_per_sec,
+            burst,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            max_calls_per_sec: 100,
+            burst: 100,
+            max_callers: 1024,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    last_seen: Instant,
+}
+
+/// Token bucket per caller.
+pub(super) struct CallerLimiter {
+    limit: RateLimit,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl CallerLimiter {
+    pub(super) fn new(limit: RateLimit) -> Self {
+        CallerLimiter {
+            limit,
+            buckets: Default::default(),
+        }
+    }
+
+    pub(super) fn try_acquire(&mut self, caller: &str, now: Instant) -> bool {
+        let burst = f64::from(self.limit.burst.max(1));
+        let rate = f64::from(self.limit.max_calls_per_sec);
+
+        if !self.buckets.contains_key(caller) {
+            if self.buckets.len() >= self.limit.max_callers.max(1) {
+                self.evict_oldest();
+            }
+            self.buckets.insert(
+                caller.to_string(),
+                Bucket {
+                    tokens: burst,
+                    last_seen: now,
+                },
+            );
+        }
+
+        let bucket = self.buckets.get_mut(caller).unwrap();
+        let elapsed = now.saturating_duration_since(bucket.last_seen);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(burst);
+        bucket.last_seen = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .buckets
+            .iter()
+            .min_by_key(|(_, bucket)| bucket.last_seen)
+            .map(|(caller, _)| caller.clone());
+        if let Some(caller) = oldest {
+            self.buckets.remove(&caller);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_caller_limiter() {
+        let mut limiter = CallerLimiter::new(RateLimit {
+            max_calls_per_sec: 10,
+            burst: 2,
+            max_callers: 2,
+        });
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire("a", now));
+        assert!(limiter.try_acquire("a", now));
+        assert!(!limiter.try_acquire("a", now));
+        assert!(limiter.try_acquire("b", now));
+
+        let later = now + Duration::from_millis(100);
+        assert!(limiter.try_acquire("a", later));
+        assert!(!limiter.try_acquire("a", later));
+
+        // "b" is least recently seen and gets evicted
+        assert!(limiter.try_acquire("c", later));
+        assert_eq!(limiter.buckets.len(), 2);
+        assert!(!limiter.buckets.contains_key("b"));
+    }
+}
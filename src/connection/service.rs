@@ -0,0 +1,85 @@
+use bytes::Bytes;
+use futures::future::LocalBoxFuture;
+use futures::prelude::*;
+use std::task::{Context, Poll};
+
+use ya_sb_proto::codec::{GsbMessage, ProtocolError};
+
+use super::{CallRequestHandler, ConnectionRef};
+use crate::{Error, RpcRawCall};
+
+/// `tower::Service` calling single address over the connection.
+///
+/// Requests are call bodies, responses are reply bodies. Timeouts, retries
+/// and concurrency limits are left to tower layers.
+pub struct GsbService<T, H>
+where
+    T: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    connection: ConnectionRef<T, H>,
+    caller: String,
+    addr: String,
+}
+
+impl<T, H> GsbService<T, H>
+where
+    T: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + Unpin + 'static,
+{
+    pub fn new(connection: ConnectionRef<T, H>, addr: impl Into<String>) -> Self {
+        GsbService {
+            connection,
+            caller: "local".to_string(),
+            addr: addr.into(),
+        }
+    }
+
+    pub fn with_caller(mut self, caller: impl Into<String>) -> Self {
+        self.caller = caller.into();
+        self
+    }
+}
+
+impl<T, H> Clone for GsbService<T, H>
+where
+    T: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + 'static,
+{
+    fn clone(&self) -> Self {
+        GsbService {
+            connection: self.connection.clone(),
+            caller: self.caller.clone(),
+            addr: self.addr.clone(),
+        }
+    }
+}
+
+impl<T, H, B> tower_service::Service<B> for GsbService<T, H>
+where
+    T: Sink<GsbMessage, Error = ProtocolError> + Unpin + 'static,
+    H: CallRequestHandler + Unpin + 'static,
+    B: Into<Bytes>,
+{
+    type Response = Vec<u8>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Vec<u8>, Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(match self.connection.connected() {
+            true => Ok(()),
+            false => Err(Error::Closed(self.addr.clone())),
+        })
+    }
+
+    fn call(&mut self, body: B) -> Self::Future {
+        self.connection
+            .call_raw(RpcRawCall::new(
+                self.caller.clone(),
+                self.addr.clone(),
+                body,
+                false,
+            ))
+            .boxed_local()
+    }
+}
@@ -6,10 +6,20 @@ use std::io;
 #[error("Timeout connecting GSB at `{0}`")]
 pub struct ConnectionTimeout(pub ya_sb_proto::GsbAddr);
 
+/// Why a call found no handler, see `Error::no_endpoint_kind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoEndpointKind {
+    /// Nothing is bound at the address.
+    NotBound,
+    /// Handler bound at the address could not decode the request: it
+    /// expects different message type or the body is corrupt.
+    Undecodable,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Connecting GSB at `{0}` failure: {1}")]
-    ConnectionFail(ya_sb_proto::GsbAddr, io::Error),
+    ConnectionFail(ya_sb_proto::GsbAddr, #[source] io::Error),
     #[error(transparent)]
     ConnectionTimeout(#[from] ConnectionTimeout),
     #[error("Called service `{0}` is unavailable")]
@@ -20,6 +30,12 @@ pub enum Error {
     NoEndpoint(String),
     #[error("Bad content: {0}")]
     BadContent(#[from] DecodeError),
+    #[error("Bad content of `{msg_type}` request to `{addr}`: {source}")]
+    BadRequestContent {
+        addr: String,
+        msg_type: String,
+        source: DecodeError,
+    },
     #[error("Encoding problem: {0}")]
     EncodingProblem(String),
     #[error("Timeout calling `{0}` service")]
@@ -32,6 +48,8 @@ pub enum Error {
     GsbFailure(String),
     #[error("Remote service at `{0}` error: {1}")]
     RemoteError(String, String),
+    #[error("Connection rejected by GSB: {0}")]
+    AuthRejected(String),
 }
 
 impl From<MailboxError> for Error {
@@ -44,12 +62,60 @@ impl From<MailboxError> for Error {
 }
 
 impl Error {
+    /// Whether the same call may succeed when retried later: connection
+    /// problems and timeouts, not rejections by the service.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::ConnectionFail(..)
+                | Error::ConnectionTimeout(_)
+                | Error::Closed(_)
+                | Error::Timeout(_)
+        )
+    }
+
+    /// Classifies failures caused by missing handler: `NoEndpoint`, or
+    /// `GsbBadRequest` the router answers for unknown remote address, when
+    /// nothing is bound, `BadRequestContent` when the bound handler could
+    /// not decode the request.
+    pub fn no_endpoint_kind(&self) -> Option<NoEndpointKind> {
+        match self {
+            Error::NoEndpoint(_) => Some(NoEndpointKind::NotBound),
+            Error::GsbBadRequest(msg) if msg == ya_sb_proto::ENDPOINT_NOT_FOUND => {
+                Some(NoEndpointKind::NotBound)
+            }
+            Error::BadRequestContent { .. } => Some(NoEndpointKind::Undecodable),
+            _ => None,
+        }
+    }
+
     pub(crate) fn from_addr(addr: String, e: MailboxError) -> Self {
         match e {
             MailboxError::Closed => Error::Closed(addr),
             MailboxError::Timeout => Error::Timeout(addr),
         }
     }
+
+    /// Like `from_addr`, for handlers surviving their own panics: reply
+    /// dropped while the handler is still `connected` means it panicked.
+    pub(crate) fn from_handler(addr: String, e: MailboxError, connected: bool) -> Self {
+        match e {
+            MailboxError::Closed if connected => {
+                Error::GsbFailure(format!("handler of {} panicked", addr))
+            }
+            e => Error::from_addr(addr, e),
+        }
+    }
+
+    pub(crate) fn from_panic(panic: Box<dyn std::any::Any + Send>) -> Self {
+        let msg = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown".into());
+        log::error!("call handler panicked: {}", msg);
+        Error::GsbFailure(format!("handler panicked: {}", msg))
+    }
 }
 
 impl From<EncodeError> for Error {
@@ -0,0 +1,42 @@
+//! Hop counting used to break call loops.
+//!
+//! Every forward made while handling a call counts one hop more than the
+//! call being handled. The count travels to remote peers in `HOPS_HEADER`
+//! and to local handlers with the call, which run with it in scope.
+use std::future::Future;
+
+use crate::Headers;
+
+/// Header carrying number of hops the call went through.
+pub const HOPS_HEADER: &str = "gsb-hops";
+
+tokio::task_local! {
+    static CALL_HOPS: u32;
+}
+
+/// Hops of the call currently handled, 0 outside of any handler.
+pub(crate) fn current() -> u32 {
+    CALL_HOPS.try_with(|hops| *hops).unwrap_or(0)
+}
+
+pub(crate) fn scope<F: Future>(hops: u32, f: F) -> impl Future<Output = F::Output> {
+    CALL_HOPS.scope(hops, f)
+}
+
+pub(crate) fn sync_scope<R>(hops: u32, f: impl FnOnce() -> R) -> R {
+    CALL_HOPS.sync_scope(hops, f)
+}
+
+pub(crate) fn from_headers(headers: &Headers) -> u32 {
+    headers
+        .get(HOPS_HEADER)
+        .and_then(|v| std::str::from_utf8(v).ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+pub(crate) fn to_headers(hops: u32, headers: &mut Headers) {
+    if hops > 0 {
+        let _ = headers.insert(HOPS_HEADER.to_string(), hops.to_string().into_bytes());
+    }
+}
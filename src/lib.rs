@@ -1,21 +1,42 @@
 #![allow(clippy::redundant_closure)]
 use actix::Message;
+use bytes::Bytes;
 use futures::prelude::Stream;
 use serde::{de::DeserializeOwned, Serialize};
-use std::{fmt::Debug, future::Future};
+use std::{collections::BTreeMap, fmt::Debug, future::Future, time::Instant};
 
 pub mod actix_rpc;
+pub mod backoff;
+pub mod blocking;
+mod circuit_breaker;
 pub mod connection;
 pub mod error;
+mod hops;
 mod local_router;
 mod remote_router;
+mod request_id;
 pub mod serialization;
+mod spawner;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod timeout;
 #[allow(clippy::needless_doctest_main)]
 pub mod typed;
 pub mod untyped;
 
-pub use error::Error;
+pub use circuit_breaker::{CircuitBreakerConfig, CircuitState};
+pub use error::{Error, NoEndpointKind};
+pub use hops::HOPS_HEADER;
+pub use local_router::{EmptyReplyPolicy, EndpointKind, InstanceReply, Route};
+pub use remote_router::{CallQueue, QueueOverflow};
+pub use request_id::{current_request_id, REQUEST_ID_HEADER};
+pub use spawner::Spawner;
+pub use ya_sb_proto::{INSTANCE_FALLBACK_HEADER, TARGET_INSTANCE_HEADER};
+
+/// Version of the gsb wire protocol spoken by this crate.
+pub const fn proto_version() -> u32 {
+    ya_sb_proto::PROTO_VERSION
+}
 
 pub trait RpcMessage: Serialize + DeserializeOwned + 'static + Sync + Send {
     const ID: &'static str;
@@ -32,6 +53,8 @@ pub trait RpcStreamMessage: Serialize + DeserializeOwned + 'static + Sync + Send
 pub struct RpcEnvelope<T> {
     caller: String,
     body: T,
+    hops: u32,
+    request_id: Option<String>,
 }
 
 #[derive(Debug)]
@@ -40,15 +63,59 @@ pub struct RpcStreamCall<T: RpcStreamMessage> {
     pub addr: String,
     pub body: T,
     pub reply: futures::channel::mpsc::Sender<Result<T::Item, T::Error>>,
+    request_id: Option<String>,
+    hops: u32,
+}
+
+impl<T: RpcStreamMessage> RpcStreamCall<T> {
+    /// Id of the call received from the router, `None` for local calls.
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    /// Whether the caller is gone and further items would be dropped.
+    ///
+    /// Long running producers should check it between items and stop early.
+    pub fn is_cancelled(&self) -> bool {
+        self.reply.is_closed()
+    }
 }
 
-// Represents raw response chunk
+/// Raw response chunk.
+///
+/// Streamed response is a sequence of `Part` chunks terminated by a single
+/// `Full` one. Empty `Full` chunk carries no data and only marks the end of
+/// stream (EOS); when handler's stream ends without `Full` chunk the
+/// connection sends EOS on its behalf.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ResponseChunk {
+    /// Non-final chunk of streamed response.
     Part(Vec<u8>),
+    /// Last chunk of the response, nothing follows it.
     Full(Vec<u8>),
 }
 
 impl ResponseChunk {
+    pub fn part(data: impl Into<Vec<u8>>) -> Self {
+        ResponseChunk::Part(data.into())
+    }
+
+    pub fn full(data: impl Into<Vec<u8>>) -> Self {
+        ResponseChunk::Full(data.into())
+    }
+
+    /// End-of-stream marker, i.e. empty `Full` chunk.
+    pub fn eos() -> Self {
+        ResponseChunk::Full(Vec::new())
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match self {
+            ResponseChunk::Part(data) => data,
+            ResponseChunk::Full(data) => data,
+        }
+    }
+
     pub fn into_bytes(self) -> Vec<u8> {
         match self {
             ResponseChunk::Part(data) => data,
@@ -65,6 +132,7 @@ impl ResponseChunk {
         }
     }
 
+    /// Whether chunk is the end-of-stream marker, see `ResponseChunk::eos`.
     pub fn is_eos(&self) -> bool {
         match self {
             ResponseChunk::Full(data) => data.is_empty(),
@@ -73,36 +141,174 @@ impl ResponseChunk {
     }
 }
 
+/// Element of streamed response that tells graceful end from truncation.
+///
+/// Stream cut short, e.g. by dropped connection, ends with an error
+/// instead of `Complete`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamItem<T> {
+    Item(T),
+    /// Responder finished the stream, nothing more will follow.
+    Complete,
+}
+
+impl<T> StreamItem<T> {
+    pub fn is_complete(&self) -> bool {
+        matches!(self, StreamItem::Complete)
+    }
+
+    pub fn into_item(self) -> Option<T> {
+        match self {
+            StreamItem::Item(item) => Some(item),
+            StreamItem::Complete => None,
+        }
+    }
+
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> StreamItem<U> {
+        match self {
+            StreamItem::Item(item) => StreamItem::Item(f(item)),
+            StreamItem::Complete => StreamItem::Complete,
+        }
+    }
+}
+
+/// Decoded element of `RpcStreamMessage` response.
+///
+/// `Item(Err(_))` is domain error sent by the handler, more items may follow.
+/// Stream error, i.e. `Err` in place of `StreamReply`, ends the stream.
+pub type StreamReply<T> =
+    StreamItem<Result<<T as RpcStreamMessage>::Item, <T as RpcStreamMessage>::Error>>;
+
+/// Call metadata passed along with the body, keyed by header name.
+pub type Headers = BTreeMap<String, Vec<u8>>;
+
 pub struct RpcRawStreamCall {
     pub caller: String,
     pub addr: String,
-    pub body: Vec<u8>,
+    pub body: Bytes,
     pub reply: futures::channel::mpsc::Sender<Result<ResponseChunk, error::Error>>,
+    /// Number of chunks callee may send ahead of being consumed by `reply`,
+    /// `None` falls back to `ConnectionConfig::stream_window`.
+    pub window: Option<u32>,
+    pub headers: Headers,
+}
+
+impl RpcRawStreamCall {
+    pub fn new(
+        caller: impl Into<String>,
+        addr: impl Into<String>,
+        body: impl Into<Bytes>,
+        reply: futures::channel::mpsc::Sender<Result<ResponseChunk, error::Error>>,
+    ) -> Self {
+        RpcRawStreamCall {
+            caller: caller.into(),
+            addr: addr.into(),
+            body: body.into(),
+            reply,
+            window: None,
+            headers: Headers::new(),
+        }
+    }
+
+    pub fn with_window(mut self, window: u32) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<Vec<u8>>) -> Self {
+        let _ = self.headers.insert(name.into(), value.into());
+        self
+    }
 }
 
 impl Message for RpcRawStreamCall {
     type Result = Result<(), error::Error>;
 }
 
+/// Call with serialized body, built with `RpcRawCall::new`.
 pub struct RpcRawCall {
     pub caller: String,
     pub addr: String,
-    pub body: Vec<u8>,
+    pub body: Bytes,
     pub no_reply: bool,
+    /// End-to-end deadline, passed along when the call is forwarded remotely.
+    pub deadline: Option<Instant>,
+    pub headers: Headers,
+    // Set by `with_collected_parts`.
+    collect_parts: bool,
 }
 
 impl RpcRawCall {
-    fn from_envelope_addr<T: Serialize>(
-        envelope: RpcEnvelope<T>,
-        addr: String,
+    pub fn new(
+        caller: impl Into<String>,
+        addr: impl Into<String>,
+        body: impl Into<Bytes>,
         no_reply: bool,
     ) -> Self {
         RpcRawCall {
+            caller: caller.into(),
+            addr: addr.into(),
+            body: body.into(),
+            no_reply,
+            deadline: None,
+            headers: Headers::new(),
+            collect_parts: false,
+        }
+    }
+
+    /// Accepts reply sent in parts, returning them concatenated. By default
+    /// partial reply fails the call.
+    pub fn with_collected_parts(mut self) -> Self {
+        self.collect_parts = true;
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<Vec<u8>>) -> Self {
+        let _ = self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Whether the call can no longer be answered in time.
+    pub fn is_expired(&self) -> bool {
+        self.deadline
+            .map(|deadline| deadline <= timeout::now())
+            .unwrap_or(false)
+    }
+
+    /// Raw call to `addr` carrying serialized body of `envelope`, with its
+    /// caller and hop count.
+    pub fn from_envelope_addr<T: Serialize>(
+        envelope: RpcEnvelope<T>,
+        addr: String,
+        no_reply: bool,
+    ) -> Result<Self, error::Error> {
+        let mut headers = Headers::new();
+        hops::to_headers(envelope.hops, &mut headers);
+        Ok(RpcRawCall {
             caller: envelope.caller,
             addr,
-            body: crate::serialization::to_vec(&envelope.body).unwrap(),
+            body: crate::serialization::to_vec(&envelope.body)?.into(),
             no_reply,
-        }
+            deadline: None,
+            headers,
+            collect_parts: false,
+        })
+    }
+
+    /// Like `from_envelope_addr`, for bodies of `RpcMessage` calls.
+    ///
+    /// Panics when the body cannot be serialized.
+    pub(crate) fn from_envelope<T: Serialize>(
+        envelope: RpcEnvelope<T>,
+        addr: String,
+        no_reply: bool,
+    ) -> Self {
+        Self::from_envelope_addr(envelope, addr, no_reply).unwrap()
     }
 }
 
@@ -123,6 +329,8 @@ impl<T: RpcMessage> RpcEnvelope<T> {
         RpcEnvelope {
             caller: caller.to_string(),
             body,
+            hops: 0,
+            request_id: None,
         }
     }
 
@@ -130,12 +338,65 @@ impl<T: RpcMessage> RpcEnvelope<T> {
         RpcEnvelope {
             caller: "local".into(),
             body,
+            hops: 0,
+            request_id: None,
         }
     }
 
+    /// Identity of the calling service as given by the sender.
+    ///
+    /// Every path, local typed, local raw and remote, passes it to the
+    /// handler unchanged. Calls made without one get `"local"`.
     pub fn caller(&self) -> &str {
         self.caller.as_str()
     }
+
+    /// Id of the call received from the router, `None` for local calls.
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+}
+
+/// Typed message serialized once, for sending the same body to many
+/// addresses with `typed::Endpoint::call_prepared` or `push_prepared`.
+pub struct PreparedCall<T: RpcMessage> {
+    caller: String,
+    body: Bytes,
+    _msg: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T: RpcMessage> PreparedCall<T> {
+    pub fn new(msg: &T) -> Result<Self, error::Error> {
+        Self::with_caller("local", msg)
+    }
+
+    pub fn with_caller(caller: impl ToString, msg: &T) -> Result<Self, error::Error> {
+        Ok(PreparedCall {
+            caller: caller.to_string(),
+            body: serialization::to_vec(msg)?.into(),
+            _msg: std::marker::PhantomData,
+        })
+    }
+
+    pub fn caller(&self) -> &str {
+        self.caller.as_str()
+    }
+
+    pub(crate) fn to_raw_call(&self, addr: String, hops: u32, no_reply: bool) -> RpcRawCall {
+        let mut call = RpcRawCall::new(self.caller.clone(), addr, self.body.clone(), no_reply);
+        hops::to_headers(hops, &mut call.headers);
+        call
+    }
+}
+
+impl<T: RpcMessage> Clone for PreparedCall<T> {
+    fn clone(&self) -> Self {
+        PreparedCall {
+            caller: self.caller.clone(),
+            body: self.body.clone(),
+            _msg: std::marker::PhantomData,
+        }
+    }
 }
 
 impl<T: RpcMessage> Message for RpcEnvelope<T> {
@@ -188,6 +449,91 @@ pub trait RpcStreamHandler<T: RpcStreamMessage> {
     fn handle(&mut self, caller: &str, msg: T) -> Self::Result;
 }
 
+/// Streaming handler able to end the stream with fatal error.
+///
+/// `Ok(Err(_))` is domain error delivered as stream item, the stream goes on.
+/// `Err(_)` ends the stream, the caller gets it as stream error after items
+/// sent before.
+pub trait RpcTryStreamHandler<T: RpcStreamMessage> {
+    type Result: Stream<Item = Result<Result<T::Item, T::Error>, Error>> + Unpin;
+
+    fn handle(&mut self, caller: &str, msg: T) -> Self::Result;
+}
+
+/// Broadcast delivered to local topic subscribers.
+#[derive(Clone, Debug)]
+pub struct BroadcastMessage {
+    pub caller: String,
+    pub topic: String,
+    pub body: Bytes,
+}
+
+impl Message for BroadcastMessage {
+    type Result = ();
+}
+
+/// Admission limits for a streaming endpoint.
+///
+/// Calls exceeding any of the limits are rejected with `Error::GsbFailure("busy")`.
+#[derive(Clone, Copy, Debug)]
+pub struct StreamPolicy {
+    /// Maximum number of concurrent streams for single caller.
+    pub max_in_flight_per_caller: usize,
+    /// Maximum number of concurrent streams for endpoint.
+    pub max_in_flight: usize,
+}
+
 pub struct Handle {
-    pub(crate) _inner: (),
+    pub(crate) registration: futures::channel::oneshot::Receiver<Result<(), error::Error>>,
+}
+
+impl Handle {
+    /// Handle of binding that was refused, `registered` reports `error`.
+    pub(crate) fn failed(error: error::Error) -> Self {
+        let (tx, registration) = futures::channel::oneshot::channel();
+        let _ = tx.send(Err(error));
+        Handle { registration }
+    }
+
+    /// Waits until the binding is handed over to the remote router.
+    ///
+    /// Fails when the remote router is not running, the endpoint is then
+    /// reachable only from this process.
+    pub async fn registered(self) -> Result<(), error::Error> {
+        self.registration
+            .await
+            .unwrap_or(Err(error::Error::Cancelled))
+    }
+}
+
+/// Binding of one handler under several addresses, see `typed::bind_aliases`.
+///
+/// Dropping it unbinds the aliases, except those bound again since.
+#[must_use = "aliases are unbound when the handle is dropped"]
+pub struct AliasHandle {
+    pub(crate) addrs: Vec<String>,
+    pub(crate) registrations: Vec<Handle>,
+    pub(crate) owner: std::sync::Arc<()>,
+}
+
+impl AliasHandle {
+    /// Full addresses the handler is bound under, empty when the binding
+    /// was refused.
+    pub fn addrs(&self) -> &[String] {
+        &self.addrs
+    }
+
+    /// Waits until all aliases are handed over to the remote router.
+    pub async fn registered(&mut self) -> Result<(), error::Error> {
+        for handle in std::mem::take(&mut self.registrations) {
+            handle.registered().await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AliasHandle {
+    fn drop(&mut self) {
+        local_router::drop_aliases(std::mem::take(&mut self.addrs), self.owner.clone());
+    }
 }
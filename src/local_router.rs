@@ -1,18 +1,28 @@
-use actix::{Actor, Arbiter, Message, Recipient, SystemService};
+use actix::{Actor, Message, Recipient, System, SystemService};
+use bytes::Bytes;
 use futures::{prelude::*, FutureExt, StreamExt};
-use std::any::Any;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use ya_sb_util::futures::IntoFlatten;
 use ya_sb_util::PrefixLookupBag;
 
 use crate::{
-    remote_router::{RemoteRouter, UpdateService},
-    Error, Handle, ResponseChunk, RpcEnvelope, RpcHandler, RpcMessage, RpcRawCall,
-    RpcRawStreamCall, RpcStreamCall, RpcStreamHandler, RpcStreamMessage,
+    circuit_breaker::CircuitBreakers,
+    hops,
+    remote_router::{CallWithTrailers, RemoteRouter, RemoveService, UpdateService},
+    request_id, spawner, AliasHandle, BroadcastMessage, CircuitBreakerConfig, CircuitState, Error,
+    Handle, Headers, PreparedCall, ResponseChunk, RpcEnvelope, RpcHandler, RpcMessage, RpcRawCall,
+    RpcRawStreamCall, RpcStreamCall, RpcStreamHandler, RpcStreamMessage, RpcTryStreamHandler,
+    StreamItem, StreamPolicy, StreamReply,
+};
+use futures::channel::{mpsc, oneshot};
+use ya_sb_proto::{
+    ENDPOINT_NOT_FOUND, INSTANCE_FALLBACK_HEADER, PING_HEADER, TARGET_INSTANCE_HEADER,
 };
-use futures::channel::mpsc;
 
 mod into_actix;
 
@@ -27,6 +37,101 @@ impl DualRawEndpoint {
     }
 }
 
+/// Type of handler bound at given address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EndpointKind {
+    /// Typed request-reply handler.
+    Rpc,
+    /// Typed streaming handler.
+    Stream,
+    /// Raw request-reply handler.
+    Raw,
+    /// Raw handler serving both request-reply and streaming calls.
+    RawDual,
+    /// Raw streaming handler.
+    RawStream,
+}
+
+/// Where calls to an address go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Route {
+    /// Served by handler bound in this process.
+    Local(EndpointKind),
+    /// Sent to the gsb router.
+    Remote,
+}
+
+impl Route {
+    pub fn is_local(&self) -> bool {
+        matches!(self, Route::Local(_))
+    }
+}
+
+/// How `Router` treats empty reply body of a typed call.
+///
+/// Replies of typed calls carry serialized `Result<T::Item, T::Error>`, so an
+/// empty body means the handler did not answer properly, e.g. raw or remote
+/// handler replying with nothing for a message whose `Item` is `()`. Affects
+/// `RpcMessage` calls served by raw handlers or sent to the gsb router
+/// (`forward`, `forward_to_instance`, `forward_prepared`), typed local
+/// handlers and `untyped` calls never see encoded replies.
+///
+/// Streaming calls keep failing on a stream that ends before its first item
+/// (`forward_first`, and request-reply calls served by streaming handlers)
+/// whatever the policy is.
+///
+/// ## Migration
+///
+/// `Error` keeps the behavior of earlier versions. Services whose handlers
+/// legitimately reply with empty body for `()` results switch to `Ok` with
+/// `Router::empty_reply_means`, non-unit messages still fail to decode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptyReplyPolicy {
+    /// Empty body fails the call with `Error::GsbFailure`.
+    #[default]
+    Error,
+    /// Empty body is a successful reply of messages whose `Item` is `()`,
+    /// other messages still fail as with `Error`.
+    Ok,
+}
+
+/// Reply of call pinned to an instance, see `Router::forward_to_instance`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct InstanceReply<R> {
+    pub reply: R,
+    /// The instance was gone or did not serve the address, another one
+    /// handled the call.
+    pub fallback: bool,
+}
+
+pub(crate) type PinnedReply<T> =
+    Result<InstanceReply<Result<<T as RpcMessage>::Item, <T as RpcMessage>::Error>>, Error>;
+
+pub(crate) type RoutedReply<T> = (
+    Route,
+    Result<Result<<T as RpcMessage>::Item, <T as RpcMessage>::Error>, Error>,
+);
+
+fn first_chunk(
+    chunks: Pin<Box<dyn Stream<Item = Result<ResponseChunk, Error>>>>,
+    addr: String,
+) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>>>> {
+    chunks
+        .into_future()
+        .map(move |(first, _rest)| match first {
+            Some(Ok(chunk)) if !chunk.is_eos() => Ok(chunk.into_bytes()),
+            Some(Err(e)) => Err(e),
+            Some(Ok(_)) | None => Err(Error::GsbFailure(format!(
+                "empty response from streaming endpoint: {}",
+                addr
+            ))),
+        })
+        .boxed_local()
+}
+
 trait RawEndpoint: Any {
     fn send(&self, msg: RpcRawCall) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>>>>;
 
@@ -36,19 +141,47 @@ trait RawEndpoint: Any {
     ) -> Pin<Box<dyn Stream<Item = Result<ResponseChunk, Error>>>>;
 
     fn recipient(&self) -> &dyn Any;
+
+    fn kind(&self) -> EndpointKind;
+}
+
+/// Decodes body of incoming request, failure names the target address and
+/// expected message type.
+fn decode_request<T: serde::de::DeserializeOwned>(
+    msg: &RpcRawCall,
+    msg_type: &str,
+) -> Result<T, Error> {
+    crate::serialization::from_slice(msg.body.as_ref()).map_err(|source| {
+        log::debug!(
+            "undecodable {} request to {}: {}",
+            msg_type,
+            msg.addr,
+            source
+        );
+        Error::BadRequestContent {
+            addr: msg.addr.clone(),
+            msg_type: msg_type.to_string(),
+            source,
+        }
+    })
 }
 
 // Implementation for non-streaming service
 impl<T: RpcMessage> RawEndpoint for Recipient<RpcEnvelope<T>> {
     fn send(&self, msg: RpcRawCall) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>>>> {
-        let body: T =
-            match crate::serialization::from_slice(msg.body.as_slice()).map_err(Error::from) {
-                Ok(v) => v,
-                Err(e) => return future::err(e).boxed_local(),
-            };
+        let body: T = match decode_request(&msg, T::ID) {
+            Ok(v) => v,
+            Err(e) => return future::err(e).boxed_local(),
+        };
+        let envelope = RpcEnvelope {
+            hops: hops::from_headers(&msg.headers),
+            request_id: request_id::from_headers(&msg.headers),
+            ..RpcEnvelope::with_caller(&msg.caller, body)
+        };
+        let me = self.clone();
         Box::pin(
-            Recipient::send(self, RpcEnvelope::with_caller(&msg.caller, body))
-                .map_err(|e| Error::from_addr(msg.addr, e))
+            Recipient::send(self, envelope)
+                .map_err(move |e| Error::from_handler(msg.addr, e, me.connected()))
                 .and_then(|r| async move { crate::serialization::to_vec(&r).map_err(Error::from) }),
         )
     }
@@ -57,15 +190,20 @@ impl<T: RpcMessage> RawEndpoint for Recipient<RpcEnvelope<T>> {
         &self,
         msg: RpcRawCall,
     ) -> Pin<Box<dyn Stream<Item = Result<ResponseChunk, Error>>>> {
-        let body: T =
-            match crate::serialization::from_slice(msg.body.as_slice()).map_err(Error::from) {
-                Ok(v) => v,
-                Err(e) => return Box::pin(stream::once(async { Err::<ResponseChunk, Error>(e) })),
-            };
+        let body: T = match decode_request(&msg, T::ID) {
+            Ok(v) => v,
+            Err(e) => return Box::pin(stream::once(async { Err::<ResponseChunk, Error>(e) })),
+        };
+        let envelope = RpcEnvelope {
+            hops: hops::from_headers(&msg.headers),
+            request_id: request_id::from_headers(&msg.headers),
+            ..RpcEnvelope::with_caller(&msg.caller, body)
+        };
 
+        let me = self.clone();
         Box::pin(
-            Recipient::send(self, RpcEnvelope::with_caller(&msg.caller, body))
-                .map_err(|e| Error::from_addr(msg.addr, e))
+            Recipient::send(self, envelope)
+                .map_err(move |e| Error::from_handler(msg.addr, e, me.connected()))
                 .and_then(|r| future::ready(crate::serialization::to_vec(&r).map_err(Error::from)))
                 .map_ok(|v| ResponseChunk::Full(v))
                 .into_stream(),
@@ -75,33 +213,43 @@ impl<T: RpcMessage> RawEndpoint for Recipient<RpcEnvelope<T>> {
     fn recipient(&self) -> &dyn Any {
         self
     }
+
+    fn kind(&self) -> EndpointKind {
+        EndpointKind::Rpc
+    }
 }
 
 impl<T: RpcStreamMessage> RawEndpoint for Recipient<RpcStreamCall<T>> {
+    // Unary call gets the first streamed item, encoded the same way as reply
+    // of `RpcMessage` with matching `Item` and `Error`. Remaining items are
+    // dropped.
     fn send(&self, msg: RpcRawCall) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>>>> {
-        Box::pin(future::err(Error::GsbBadRequest(format!(
-            "non-streaming-request on streaming endpoint: {}",
-            msg.addr
-        ))))
+        let addr = msg.addr.clone();
+        first_chunk(self.call_stream(msg), addr)
     }
 
     fn call_stream(
         &self,
         msg: RpcRawCall,
     ) -> Pin<Box<dyn Stream<Item = Result<ResponseChunk, Error>>>> {
-        let body: T = crate::serialization::from_slice(msg.body.as_slice()).unwrap();
+        let body: T = match decode_request(&msg, T::ID) {
+            Ok(v) => v,
+            Err(e) => return Box::pin(stream::once(async { Err::<ResponseChunk, Error>(e) })),
+        };
         let (tx, rx) = futures::channel::mpsc::channel(16);
         let (txe, rxe) = futures::channel::oneshot::channel();
 
         let addr = msg.addr.clone();
         let call = RpcStreamCall {
+            request_id: request_id::from_headers(&msg.headers),
+            hops: hops::from_headers(&msg.headers),
             caller: msg.caller,
             addr: msg.addr,
             body,
             reply: tx,
         };
         let me = self.clone();
-        Arbiter::current().spawn(async move {
+        spawner::spawn(async move {
             match me.send(call).await {
                 Err(e) => {
                     let _ = txe.send(Err(Error::from_addr(addr, e)));
@@ -109,7 +257,9 @@ impl<T: RpcStreamMessage> RawEndpoint for Recipient<RpcStreamCall<T>> {
                 Ok(Err(e)) => {
                     let _ = txe.send(Err(e));
                 }
-                Ok(Ok(())) => (),
+                Ok(Ok(())) => {
+                    let _ = txe.send(Ok(ResponseChunk::eos()));
+                }
             };
         });
 
@@ -129,6 +279,10 @@ impl<T: RpcStreamMessage> RawEndpoint for Recipient<RpcStreamCall<T>> {
     fn recipient(&self) -> &dyn Any {
         self
     }
+
+    fn kind(&self) -> EndpointKind {
+        EndpointKind::Stream
+    }
 }
 
 impl RawEndpoint for Recipient<RpcRawCall> {
@@ -158,18 +312,25 @@ impl RawEndpoint for Recipient<RpcRawCall> {
     fn recipient(&self) -> &dyn Any {
         self
     }
+
+    fn kind(&self) -> EndpointKind {
+        EndpointKind::Raw
+    }
 }
 
 impl RawEndpoint for Recipient<RpcRawStreamCall> {
     fn send(&self, msg: RpcRawCall) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>>>> {
         let (tx, rx) = futures::channel::mpsc::channel(1);
+        let collect_parts = msg.collect_parts;
         // TODO: send error to caller
-        Arbiter::current().spawn(
+        spawner::spawn(
             self.send(RpcRawStreamCall {
                 caller: msg.caller,
                 addr: msg.addr,
                 body: msg.body,
                 reply: tx,
+                window: None,
+                headers: msg.headers,
             })
             .flatten_fut()
             .map_err(|e| eprintln!("cell error={}", e))
@@ -177,13 +338,21 @@ impl RawEndpoint for Recipient<RpcRawStreamCall> {
         );
         async move {
             futures::pin_mut!(rx);
-            match StreamExt::next(&mut rx).await {
-                Some(Ok(ResponseChunk::Full(v))) => Ok(v),
-                Some(Ok(ResponseChunk::Part(_))) => {
-                    Err(Error::GsbBadRequest("partial response".into()))
+            let mut body = Vec::new();
+            loop {
+                match StreamExt::next(&mut rx).await {
+                    Some(Ok(ResponseChunk::Full(v))) if body.is_empty() => return Ok(v),
+                    Some(Ok(ResponseChunk::Full(v))) => {
+                        body.extend_from_slice(&v);
+                        return Ok(body);
+                    }
+                    Some(Ok(ResponseChunk::Part(v))) if collect_parts => body.extend_from_slice(&v),
+                    Some(Ok(ResponseChunk::Part(_))) => {
+                        return Err(Error::GsbBadRequest("partial response".into()))
+                    }
+                    Some(Err(e)) => return Err(e),
+                    None => return Err(Error::GsbBadRequest("unexpected EOS".into())),
                 }
-                Some(Err(e)) => Err(e),
-                None => Err(Error::GsbBadRequest("unexpected EOS".into())),
             }
         }
         .boxed_local()
@@ -195,12 +364,14 @@ impl RawEndpoint for Recipient<RpcRawStreamCall> {
     ) -> Pin<Box<dyn Stream<Item = Result<ResponseChunk, Error>>>> {
         let (tx, rx) = futures::channel::mpsc::channel(16);
         // TODO: send error to caller
-        Arbiter::current().spawn(
+        spawner::spawn(
             self.send(RpcRawStreamCall {
                 caller: msg.caller,
                 addr: msg.addr,
                 body: msg.body,
                 reply: tx,
+                window: None,
+                headers: msg.headers,
             })
             .flatten_fut()
             .map_err(|e| eprintln!("cell error={}", e))
@@ -212,6 +383,10 @@ impl RawEndpoint for Recipient<RpcRawStreamCall> {
     fn recipient(&self) -> &dyn Any {
         self
     }
+
+    fn kind(&self) -> EndpointKind {
+        EndpointKind::RawStream
+    }
 }
 
 impl RawEndpoint for DualRawEndpoint {
@@ -229,6 +404,185 @@ impl RawEndpoint for DualRawEndpoint {
     fn recipient(&self) -> &dyn Any {
         self
     }
+
+    fn kind(&self) -> EndpointKind {
+        EndpointKind::RawDual
+    }
+}
+
+#[derive(Default)]
+struct InFlight {
+    total: usize,
+    per_caller: HashMap<String, usize>,
+}
+
+impl InFlight {
+    fn try_acquire(&mut self, policy: &StreamPolicy, caller: &str) -> bool {
+        let caller_count = self.per_caller.get(caller).copied().unwrap_or_default();
+        if self.total >= policy.max_in_flight || caller_count >= policy.max_in_flight_per_caller {
+            return false;
+        }
+        self.total += 1;
+        *self.per_caller.entry(caller.to_string()).or_default() += 1;
+        true
+    }
+
+    fn release(&mut self, caller: &str) {
+        self.total = self.total.saturating_sub(1);
+        if let Some(count) = self.per_caller.get_mut(caller) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_caller.remove(caller);
+            }
+        }
+    }
+}
+
+struct InFlightGuard {
+    in_flight: Arc<Mutex<InFlight>>,
+    caller: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().release(&self.caller);
+    }
+}
+
+// Streaming endpoint with admission limits.
+//
+// `recipient` intentionally returns the wrapper itself, so typed local calls
+// can't bypass the policy and go through `call_stream` instead.
+struct PolicyStreamEndpoint<T: RpcStreamMessage> {
+    inner: Recipient<RpcStreamCall<T>>,
+    policy: StreamPolicy,
+    in_flight: Arc<Mutex<InFlight>>,
+}
+
+impl<T: RpcStreamMessage> RawEndpoint for PolicyStreamEndpoint<T> {
+    fn send(&self, msg: RpcRawCall) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>>>> {
+        let addr = msg.addr.clone();
+        first_chunk(self.call_stream(msg), addr)
+    }
+
+    fn call_stream(
+        &self,
+        msg: RpcRawCall,
+    ) -> Pin<Box<dyn Stream<Item = Result<ResponseChunk, Error>>>> {
+        if !self
+            .in_flight
+            .lock()
+            .unwrap()
+            .try_acquire(&self.policy, &msg.caller)
+        {
+            log::debug!("rejecting stream call to {} from {}", msg.addr, msg.caller);
+            return Box::pin(stream::once(future::err(Error::GsbFailure(
+                "busy".to_string(),
+            ))));
+        }
+        let guard = InFlightGuard {
+            in_flight: self.in_flight.clone(),
+            caller: msg.caller.clone(),
+        };
+        Box::pin(self.inner.call_stream(msg).map(move |chunk| {
+            let _ = &guard;
+            chunk
+        }))
+    }
+
+    fn recipient(&self) -> &dyn Any {
+        self
+    }
+
+    fn kind(&self) -> EndpointKind {
+        EndpointKind::Stream
+    }
+}
+
+// Handler created by `factory` on first call, dropped after `idle_ttl`
+// without calls and created again when needed.
+//
+// `recipient` returns the wrapper itself, typed local calls are encoded and
+// served through `send`.
+struct LazyEndpoint<T: RpcMessage, H> {
+    factory: Arc<dyn Fn() -> H + Send + Sync>,
+    idle_ttl: Option<Duration>,
+    state: Arc<Mutex<LazyState<T>>>,
+}
+
+struct LazyState<T: RpcMessage> {
+    recipient: Option<Recipient<RpcEnvelope<T>>>,
+    last_used: Instant,
+}
+
+impl<T: RpcMessage, H: RpcHandler<T> + 'static> LazyEndpoint<T, H> {
+    fn new(factory: Arc<dyn Fn() -> H + Send + Sync>, idle_ttl: Option<Duration>) -> Self {
+        LazyEndpoint {
+            factory,
+            idle_ttl,
+            state: Arc::new(Mutex::new(LazyState {
+                recipient: None,
+                last_used: crate::timeout::now(),
+            })),
+        }
+    }
+
+    fn get(&self) -> Recipient<RpcEnvelope<T>> {
+        let mut state = self.state.lock().unwrap();
+        state.last_used = crate::timeout::now();
+        if let Some(recipient) = &state.recipient {
+            return recipient.clone();
+        }
+        log::debug!("starting lazy {} handler", T::ID);
+        let recipient = into_actix::RpcHandlerWrapper::new((self.factory)())
+            .start()
+            .recipient();
+        state.recipient = Some(recipient.clone());
+        if let Some(ttl) = self.idle_ttl {
+            Self::evict_when_idle(Arc::downgrade(&self.state), ttl);
+        }
+        recipient
+    }
+
+    // Handler actor stops once its last recipient is dropped.
+    fn evict_when_idle(state: std::sync::Weak<Mutex<LazyState<T>>>, ttl: Duration) {
+        spawner::spawn(async move {
+            loop {
+                tokio::time::sleep(ttl).await;
+                let state = match state.upgrade() {
+                    Some(state) => state,
+                    None => return,
+                };
+                let mut state = state.lock().unwrap();
+                if crate::timeout::now().saturating_duration_since(state.last_used) >= ttl {
+                    log::debug!("evicting idle lazy {} handler", T::ID);
+                    state.recipient = None;
+                    return;
+                }
+            }
+        });
+    }
+}
+
+impl<T: RpcMessage, H: RpcHandler<T> + 'static> RawEndpoint for LazyEndpoint<T, H> {
+    fn send(&self, msg: RpcRawCall) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>>>> {
+        RawEndpoint::send(&self.get(), msg)
+    }
+
+    fn call_stream(
+        &self,
+        msg: RpcRawCall,
+    ) -> Pin<Box<dyn Stream<Item = Result<ResponseChunk, Error>>>> {
+        self.get().call_stream(msg)
+    }
+
+    fn recipient(&self) -> &dyn Any {
+        self
+    }
+
+    fn kind(&self) -> EndpointKind {
+        EndpointKind::Rpc
+    }
 }
 
 struct Slot {
@@ -246,6 +600,15 @@ impl Slot {
         }
     }
 
+    fn from_handler_factory<T: RpcMessage, H: RpcHandler<T> + 'static>(
+        factory: Arc<dyn Fn() -> H + Send + Sync>,
+        idle_ttl: Option<Duration>,
+    ) -> Self {
+        Slot {
+            inner: Box::new(LazyEndpoint::new(factory, idle_ttl)),
+        }
+    }
+
     fn from_stream_handler<T: RpcStreamMessage, H: RpcStreamHandler<T> + 'static>(
         handler: H,
     ) -> Self {
@@ -258,11 +621,42 @@ impl Slot {
         }
     }
 
+    fn from_try_stream_handler<T: RpcStreamMessage, H: RpcTryStreamHandler<T> + 'static>(
+        handler: H,
+    ) -> Self {
+        Slot {
+            inner: Box::new(
+                into_actix::RpcTryStreamHandlerWrapper::new(handler)
+                    .start()
+                    .recipient(),
+            ),
+        }
+    }
+
+    fn from_stream_handler_with_policy<T: RpcStreamMessage, H: RpcStreamHandler<T> + 'static>(
+        handler: H,
+        policy: StreamPolicy,
+    ) -> Self {
+        Slot {
+            inner: Box::new(PolicyStreamEndpoint {
+                inner: into_actix::RpcStreamHandlerWrapper::new(handler)
+                    .start()
+                    .recipient(),
+                policy,
+                in_flight: Default::default(),
+            }),
+        }
+    }
+
     #[allow(unused)]
     fn from_raw(r: Recipient<RpcRawCall>) -> Self {
         Slot { inner: Box::new(r) }
     }
 
+    fn from_raw_stream(r: Recipient<RpcRawStreamCall>) -> Self {
+        Slot { inner: Box::new(r) }
+    }
+
     fn from_raw_dual(r: DualRawEndpoint) -> Self {
         Slot { inner: Box::new(r) }
     }
@@ -316,25 +710,39 @@ impl Slot {
         caller: String,
         addr: String,
         body: T,
-    ) -> impl Stream<Item = Result<Result<T::Item, T::Error>, Error>> {
+        hops: u32,
+    ) -> impl Stream<Item = Result<StreamReply<T>, Error>> {
         let no_reply = false;
+        let mut headers = Headers::new();
+        hops::to_headers(hops, &mut headers);
 
         if let Some(h) = self.stream_recipient() {
             let (reply, rx) = futures::channel::mpsc::channel(16);
             let call = RpcStreamCall {
                 caller,
-                addr,
+                addr: addr.clone(),
                 body,
                 reply,
+                request_id: None,
+                hops,
             };
 
-            Arbiter::current().spawn(async move {
-                h.send(call)
-                    .await
-                    .unwrap_or_else(|e| Ok(log::error!("streaming forward error: {}", e)))
-                    .unwrap_or_else(|e| log::error!("streaming forward error: {}", e));
+            let (txe, rxe) = oneshot::channel();
+            spawner::spawn(async move {
+                let result = h.send(call).await;
+                let _ = txe.send(result);
             });
-            rx.map(|v| Ok(v)).boxed_local().left_stream()
+            // handler failure ends the stream in place of `Complete`
+            let end = rxe.map(move |result| match result {
+                Ok(Ok(Ok(()))) => Ok(StreamItem::Complete),
+                Ok(Ok(Err(e))) => Err(e),
+                Ok(Err(e)) => Err(Error::from_addr(addr, e)),
+                Err(_) => Err(Error::Cancelled),
+            });
+            rx.map(|v| Ok(StreamItem::Item(v)))
+                .chain(end.into_stream())
+                .boxed_local()
+                .left_stream()
         } else if let Some(h) = self.raw_stream_recipient() {
             (move || {
                 let (reply, rx) = futures::channel::mpsc::channel(16);
@@ -345,27 +753,20 @@ impl Slot {
                 let call = RpcRawStreamCall {
                     caller,
                     addr,
-                    body,
+                    body: body.into(),
                     reply,
+                    window: None,
+                    headers,
                 };
 
-                Arbiter::current().spawn(async move {
+                spawner::spawn(async move {
                     h.send(call)
                         .await
                         .unwrap_or_else(|e| Ok(log::error!("streaming raw forward error: {}", e)))
                         .unwrap_or_else(|e| log::error!("streaming raw forward error: {}", e));
                 });
-                rx.filter(|s| future::ready(s.as_ref().map(|s| !s.is_eos()).unwrap_or(true)))
-                    .map(|chunk_result| {
-                        (move || -> Result<Result<T::Item, T::Error>, Error> {
-                            let chunk = match chunk_result {
-                                Ok(ResponseChunk::Part(chunk)) => chunk,
-                                Ok(ResponseChunk::Full(chunk)) => chunk,
-                                Err(e) => return Err(e),
-                            };
-                            Ok(crate::serialization::from_slice(&chunk)?)
-                        })()
-                    })
+                with_completion(rx, None)
+                    .map(decode_stream_item::<T>)
                     .left_stream()
             })()
             .boxed_local()
@@ -376,23 +777,19 @@ impl Slot {
                     Ok(body) => body,
                     Err(e) => return stream::once(future::err(Error::from(e))).right_stream(),
                 };
-                self.send_streaming(RpcRawCall {
-                    caller,
-                    addr,
-                    body,
-                    no_reply,
-                })
-                .filter(|s| future::ready(s.as_ref().map(|s| !s.is_eos()).unwrap_or(true)))
-                .map(|chunk_result| {
-                    (move || -> Result<Result<T::Item, T::Error>, Error> {
-                        let chunk = match chunk_result {
-                            Ok(ResponseChunk::Part(chunk)) => chunk,
-                            Ok(ResponseChunk::Full(chunk)) => chunk,
-                            Err(e) => return Err(e),
-                        };
-                        Ok(crate::serialization::from_slice(&chunk)?)
-                    })()
-                })
+                with_completion(
+                    self.send_streaming(RpcRawCall {
+                        caller,
+                        addr,
+                        body: body.into(),
+                        no_reply,
+                        deadline: None,
+                        headers,
+                        collect_parts: false,
+                    }),
+                    None,
+                )
+                .map(decode_stream_item::<T>)
                 .left_stream()
             })()
             .boxed_local()
@@ -401,15 +798,238 @@ impl Slot {
     }
 }
 
+/// Canonicalizes service addresses before they are bound or looked up.
+pub type AddressNormalizer = Box<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Announces local binding to the remote router, outcome is reported
+/// through returned handle.
+fn register_remote(addr: String) -> Handle {
+    let (tx, registration) = oneshot::channel();
+    let request = RemoteRouter::from_registry().send(UpdateService::Add(addr.clone()));
+    spawner::spawn(async move {
+        let result = request.await.map_err(|e| {
+            log::warn!("remote router did not accept binding of {}: {}", addr, e);
+            Error::from_addr(addr, e)
+        });
+        let _ = tx.send(result);
+    });
+    Handle { registration }
+}
+
 pub struct Router {
     handlers: PrefixLookupBag<Slot>,
+    topics: PrefixLookupBag<Vec<Recipient<BroadcastMessage>>>,
+    normalizer: Option<AddressNormalizer>,
+    max_hops: Option<u32>,
+    empty_reply: EmptyReplyPolicy,
+    breakers: Option<Arc<Mutex<CircuitBreakers>>>,
+    // Which `AliasHandle` bound the address, cleared by any later binding.
+    alias_owners: HashMap<String, Arc<()>>,
 }
 
 impl Router {
     fn new() -> Self {
         Router {
             handlers: PrefixLookupBag::default(),
+            topics: PrefixLookupBag::default(),
+            normalizer: None,
+            max_hops: None,
+            empty_reply: EmptyReplyPolicy::default(),
+            breakers: None,
+            alias_owners: HashMap::new(),
+        }
+    }
+
+    /// Registers in-process receiver for broadcasts on `topic` and its subtopics.
+    pub fn subscribe_local(&mut self, topic: &str, recipient: Recipient<BroadcastMessage>) {
+        log::debug!("local subscribe {}", topic);
+        let mut subscribers = self.topics.remove(topic).unwrap_or_default();
+        subscribers.retain(|r| r.connected());
+        subscribers.push(recipient);
+        self.topics.insert(topic.to_string(), subscribers);
+    }
+
+    /// Delivers broadcast to all local subscribers of `topic` or any of its prefixes.
+    ///
+    /// Returns number of recipients the message was delivered to.
+    pub fn publish_local(&mut self, caller: &str, topic: &str, body: impl Into<Bytes>) -> usize {
+        let msg = BroadcastMessage {
+            caller: caller.to_string(),
+            topic: topic.to_string(),
+            body: body.into(),
+        };
+        let mut delivered = 0;
+        for recipient in self.topics.get_all(topic).flatten() {
+            if recipient.connected() {
+                recipient.do_send(msg.clone());
+                delivered += 1;
+            }
+        }
+        log::trace!("local broadcast {} delivered to {}", topic, delivered);
+        delivered
+    }
+
+    /// Sets address canonicalization applied on bind, unbind and every call.
+    ///
+    /// Should be set before anything is bound, existing bindings are not
+    /// re-normalized.
+    pub fn set_normalizer(&mut self, normalizer: AddressNormalizer) {
+        self.normalizer = Some(normalizer);
+    }
+
+    /// Limits number of forwards made within single call chain, calls going
+    /// deeper fail with `GsbFailure`. Catches loops of handlers calling each
+    /// other, `None` disables the check.
+    pub fn set_max_hops(&mut self, max_hops: Option<u32>) {
+        self.max_hops = max_hops;
+    }
+
+    /// Sets how empty replies of typed calls are decoded, see `EmptyReplyPolicy`.
+    pub fn empty_reply_means(&mut self, policy: EmptyReplyPolicy) {
+        self.empty_reply = policy;
+    }
+
+    /// Fails calls to remote addresses that keep failing without sending
+    /// them, see `CircuitBreakerConfig`. `None` disables it and forgets
+    /// state of all circuits.
+    pub fn set_circuit_breaker(&mut self, config: Option<CircuitBreakerConfig>) {
+        self.breakers = config.map(|config| Arc::new(Mutex::new(CircuitBreakers::new(config))));
+    }
+
+    /// State of circuit breaker of full address `addr`, e.g. `/svc/Msg`.
+    pub fn circuit_state(&self, addr: &str) -> CircuitState {
+        match &self.breakers {
+            Some(breakers) => breakers
+                .lock()
+                .unwrap()
+                .state(&self.normalize(addr), crate::timeout::now()),
+            None => CircuitState::Closed,
+        }
+    }
+
+    /// Closes circuit of full address `addr`, letting calls through again.
+    pub fn reset_circuit(&mut self, addr: &str) {
+        if let Some(breakers) = &self.breakers {
+            breakers.lock().unwrap().reset(&self.normalize(addr));
+        }
+    }
+
+    /// Sends call to the gsb router, through circuit breaker when enabled.
+    fn send_remote_guarded(
+        &self,
+        call: RpcRawCall,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> {
+        let breakers = self.breakers.clone();
+        let addr = call.addr.clone();
+        if let Some(breakers) = &breakers {
+            if let Err(e) = breakers
+                .lock()
+                .unwrap()
+                .try_acquire(&addr, crate::timeout::now())
+            {
+                log::debug!("not calling {}: {}", addr, e);
+                return future::err(e).left_future();
+            }
+        }
+        send_remote(call)
+            .map(move |result| {
+                if let Some(breakers) = breakers {
+                    breakers
+                        .lock()
+                        .unwrap()
+                        .record(&addr, &result, crate::timeout::now());
+                }
+                result
+            })
+            .right_future()
+    }
+
+    /// Hop count of the call to `addr` made from the current handler.
+    fn next_hop(&self, addr: &str) -> Result<u32, Error> {
+        let hops = hops::current().saturating_add(1);
+        match self.max_hops {
+            Some(max_hops) if hops > max_hops => {
+                log::warn!("call to {} exceeded {} hops", addr, max_hops);
+                Err(Error::GsbFailure(format!(
+                    "call loop / max hops exceeded: {}",
+                    addr
+                )))
+            }
+            _ => Ok(hops),
+        }
+    }
+
+    /// Checks shape of service address: `/` followed by non-empty segments
+    /// separated with `/`, without whitespace or control characters.
+    ///
+    /// All bind methods refuse invalid addresses.
+    pub fn validate_addr(&self, addr: &str) -> Result<(), Error> {
+        let invalid = |reason: &str| {
+            Err(Error::GsbBadRequest(format!(
+                "invalid address `{}`: {}",
+                addr.escape_debug(),
+                reason
+            )))
+        };
+        let path = match addr.strip_prefix('/') {
+            Some(path) => path,
+            None => return invalid("has to start with `/`"),
+        };
+        if path.split('/').any(str::is_empty) {
+            return invalid("empty segment");
+        }
+        if addr.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return invalid("whitespace or control character");
         }
+        Ok(())
+    }
+
+    fn normalize(&self, addr: &str) -> String {
+        match &self.normalizer {
+            Some(f) => f(addr),
+            None => addr.to_string(),
+        }
+    }
+
+    /// Binds `slot` at full `addr`, all bind methods go through it. Invalid
+    /// address is refused with failed handle.
+    fn insert_slot(&mut self, addr: String, slot: Slot, replace: bool) -> Handle {
+        if let Err(e) = self.validate_addr(&addr) {
+            log::warn!("refusing to bind: {}", e);
+            return Handle::failed(e);
+        }
+        if self.handlers.insert(addr.clone(), slot).is_some() && !replace {
+            log::warn!("replacing already bound handler for {}", addr);
+        }
+        let _ = self.alias_owners.remove(&addr);
+        register_remote(addr)
+    }
+
+    /// Binds handler, failing when given address is already bound.
+    pub fn try_bind<T: RpcMessage>(
+        &mut self,
+        addr: &str,
+        endpoint: impl RpcHandler<T> + 'static,
+    ) -> Result<Handle, Error> {
+        let addr = self.normalize(addr);
+        self.validate_addr(&addr)?;
+        let addr = format!("{}/{}", addr, T::ID);
+        if self.handlers.contains_key(&addr) {
+            return Err(Error::GsbAlreadyRegistered(addr));
+        }
+        log::debug!("binding {}", addr);
+        Ok(self.insert_slot(addr, Slot::from_handler(endpoint), false))
+    }
+
+    /// Binds handler, explicitly replacing the previous one if any.
+    pub fn rebind<T: RpcMessage>(
+        &mut self,
+        addr: &str,
+        endpoint: impl RpcHandler<T> + 'static,
+    ) -> Handle {
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
+        log::debug!("rebinding {}", addr);
+        self.insert_slot(addr, Slot::from_handler(endpoint), true)
     }
 
     pub fn bind<T: RpcMessage>(
@@ -417,15 +1037,119 @@ impl Router {
         addr: &str,
         endpoint: impl RpcHandler<T> + 'static,
     ) -> Handle {
+        let addr = self.normalize(addr);
         let slot = Slot::from_handler(endpoint);
         let addr = format!("{}/{}", addr, T::ID);
         log::debug!("binding {}", addr);
-        let _ = self.handlers.insert(addr.clone(), slot);
-        RemoteRouter::from_registry().do_send(UpdateService::Add(addr));
-        Handle { _inner: () }
+        self.insert_slot(addr, slot, false)
     }
 
-    pub fn unbind(&mut self, addr: &str) -> impl Future<Output = Result<bool, Error>> + Unpin {
+    /// Binds handler created by `factory` on first call to `addr` instead of
+    /// right away. The address is registered with the gsb router immediately.
+    pub fn bind_lazy<T: RpcMessage, H: RpcHandler<T> + 'static>(
+        &mut self,
+        addr: &str,
+        factory: impl Fn() -> H + Send + Sync + 'static,
+    ) -> Handle {
+        self.bind_lazy_with_idle_ttl(addr, factory, None)
+    }
+
+    /// Like `bind_lazy`, dropping the handler after `idle_ttl` without calls.
+    /// Next call creates new one.
+    pub fn bind_lazy_with_idle_ttl<T: RpcMessage, H: RpcHandler<T> + 'static>(
+        &mut self,
+        addr: &str,
+        factory: impl Fn() -> H + Send + Sync + 'static,
+        idle_ttl: Option<Duration>,
+    ) -> Handle {
+        let addr = self.normalize(addr);
+        let slot = Slot::from_handler_factory::<T, H>(Arc::new(factory), idle_ttl);
+        let addr = format!("{}/{}", addr, T::ID);
+        log::debug!("binding lazy {}", addr);
+        self.insert_slot(addr, slot, false)
+    }
+
+    /// Binds handler at `addr` suffixed with `type_id` instead of `T::ID`,
+    /// e.g. to keep answering old message id after it was renamed.
+    pub fn bind_as<T: RpcMessage>(
+        &mut self,
+        addr: &str,
+        type_id: &str,
+        endpoint: impl RpcHandler<T> + 'static,
+    ) -> Handle {
+        let addr = format!("{}/{}", self.normalize(addr), type_id);
+        let slot = Slot::from_handler(endpoint);
+        log::debug!("binding {} as {}", addr, T::ID);
+        self.insert_slot(addr, slot, false)
+    }
+
+    /// Binds single handler under all `addrs`, every alias is served by
+    /// the same actor. Nothing is bound when any of the addresses is
+    /// invalid. Aliases are unbound when returned handle is dropped, unless
+    /// bound again in the meantime.
+    pub fn bind_aliases<T: RpcMessage>(
+        &mut self,
+        addrs: &[&str],
+        endpoint: impl RpcHandler<T> + 'static,
+    ) -> AliasHandle {
+        let owner = Arc::new(());
+        let addrs = addrs
+            .iter()
+            .map(|addr| format!("{}/{}", self.normalize(addr), T::ID))
+            .collect::<Vec<_>>();
+        if let Err(e) = addrs.iter().try_for_each(|addr| self.validate_addr(addr)) {
+            log::warn!("refusing to bind aliases: {}", e);
+            return AliasHandle {
+                addrs: Vec::new(),
+                registrations: vec![Handle::failed(e)],
+                owner,
+            };
+        }
+        let recipient = into_actix::RpcHandlerWrapper::new(endpoint)
+            .start()
+            .recipient::<RpcEnvelope<T>>();
+        let registrations = addrs
+            .iter()
+            .map(|addr| {
+                log::debug!("binding alias {}", addr);
+                let handle =
+                    self.insert_slot(addr.clone(), Slot::from_actor(recipient.clone()), false);
+                let _ = self.alias_owners.insert(addr.clone(), owner.clone());
+                handle
+            })
+            .collect();
+        AliasHandle {
+            addrs,
+            registrations,
+            owner,
+        }
+    }
+
+    /// Drops bindings made by `bind_aliases` which are still `owner`'s.
+    fn unbind_aliases(
+        &mut self,
+        addrs: &[String],
+        owner: &Arc<()>,
+        remote: Option<&actix::Addr<RemoteRouter>>,
+    ) {
+        for addr in addrs {
+            match self.alias_owners.get(addr) {
+                Some(bound_by) if Arc::ptr_eq(bound_by, owner) => {}
+                _ => continue,
+            }
+            let _ = self.alias_owners.remove(addr);
+            if self.handlers.remove(addr).is_some() {
+                log::debug!("unbinding alias {}", addr);
+                if let Some(router) = remote {
+                    router.do_send(UpdateService::Remove(addr.clone()));
+                }
+            }
+        }
+    }
+
+    /// Removes handlers bound under `addr`, returning their addresses.
+    fn remove_under(&mut self, addr: &str) -> Vec<String> {
+        let addr = &self.normalize(addr);
         let pattern = match addr.ends_with('/') {
             true => addr.to_string(),
             false => format!("{}/", addr),
@@ -441,60 +1165,156 @@ impl Router {
             log::debug!("unbinding {}", addr);
             self.handlers.remove(addr);
         });
+        addrs
+    }
+
+    pub fn unbind(&mut self, addr: &str) -> impl Future<Output = Result<bool, Error>> + Unpin {
+        let addrs = self.remove_under(addr);
 
         Box::pin(async move {
             let router = RemoteRouter::from_registry();
             let success = !addrs.is_empty();
+            let mut result = Ok(success);
             for addr in addrs {
-                router
-                    .send(UpdateService::Remove(addr.clone()))
-                    .await
-                    .map_err(|e| Error::from_addr(addr, e))?;
+                if let Err(e) = router.send(UpdateService::Remove(addr.clone())).await {
+                    log::warn!("failed to unbind {}: {}", addr, e);
+                    if result.is_ok() {
+                        result = Err(Error::from_addr(addr, e));
+                    }
+                }
             }
-            Ok(success)
+            result
         })
     }
 
-    pub fn bind_stream<T: RpcStreamMessage>(
+    /// Like `unbind`, waits for the gsb router to drop every removed address
+    /// and reports outcome for each of them.
+    ///
+    /// All addresses are removed locally regardless, failed ones may be
+    /// still registered on the router and can be retried with `unbind_raw`.
+    pub fn unbind_with_report(
+        &mut self,
+        addr: &str,
+    ) -> impl Future<Output = Vec<(String, Result<(), Error>)>> {
+        let addrs = self.remove_under(addr);
+
+        let router = RemoteRouter::from_registry();
+        future::join_all(addrs.into_iter().map(move |addr| {
+            router.send(RemoveService(addr.clone())).map(move |r| {
+                let result = r
+                    .map_err(|e| Error::from_addr(addr.clone(), e))
+                    .and_then(|r| r);
+                (addr, result)
+            })
+        }))
+    }
+
+    /// Removes raw binding registered at exact `addr`.
+    pub fn unbind_raw(&mut self, addr: &str) -> impl Future<Output = Result<bool, Error>> + Unpin {
+        let addr = &self.normalize(addr);
+        let removed = self.handlers.remove(addr).is_some();
+        if removed {
+            log::debug!("unbinding raw {}", addr);
+        }
+        let addr = addr.to_string();
+        Box::pin(async move {
+            if removed {
+                RemoteRouter::from_registry()
+                    .send(UpdateService::Remove(addr.clone()))
+                    .await
+                    .map_err(|e| Error::from_addr(addr, e))?;
+            }
+            Ok(removed)
+        })
+    }
+
+    /// Returns kind of the handler which would serve calls to `addr`.
+    pub fn endpoint_kind(&self, addr: &str) -> Option<EndpointKind> {
+        self.handlers
+            .get(&self.normalize(addr))
+            .map(|slot| slot.inner.kind())
+    }
+
+    /// Reports where calls to full `addr` would be routed, without calling.
+    pub fn resolve(&self, addr: &str) -> Route {
+        self.endpoint_kind(addr)
+            .map(Route::Local)
+            .unwrap_or(Route::Remote)
+    }
+
+    pub fn bind_stream<T: RpcStreamMessage>(
         &mut self,
         addr: &str,
         endpoint: impl RpcStreamHandler<T> + Unpin + 'static,
     ) -> Handle {
+        let addr = self.normalize(addr);
         let slot = Slot::from_stream_handler(endpoint);
         let addr = format!("{}/{}", addr, T::ID);
         log::debug!("binding stream {}", addr);
-        let _ = self.handlers.insert(addr.clone(), slot);
-        RemoteRouter::from_registry().do_send(UpdateService::Add(addr));
-        Handle { _inner: () }
+        self.insert_slot(addr, slot, false)
+    }
+
+    /// Binds streaming handler that may end the stream with fatal error,
+    /// see `RpcTryStreamHandler`.
+    pub fn bind_try_stream<T: RpcStreamMessage>(
+        &mut self,
+        addr: &str,
+        endpoint: impl RpcTryStreamHandler<T> + Unpin + 'static,
+    ) -> Handle {
+        let addr = self.normalize(addr);
+        let slot = Slot::from_try_stream_handler(endpoint);
+        let addr = format!("{}/{}", addr, T::ID);
+        log::debug!("binding try stream {}", addr);
+        self.insert_slot(addr, slot, false)
+    }
+
+    pub fn bind_stream_with_policy<T: RpcStreamMessage>(
+        &mut self,
+        addr: &str,
+        endpoint: impl RpcStreamHandler<T> + Unpin + 'static,
+        policy: StreamPolicy,
+    ) -> Handle {
+        let slot = Slot::from_stream_handler_with_policy(endpoint, policy);
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
+        log::debug!("binding stream {} with {:?}", addr, policy);
+        self.insert_slot(addr, slot, false)
     }
 
     pub fn bind_stream_actor<T: RpcStreamMessage>(
         &mut self,
         addr: &str,
         endpoint: Recipient<RpcStreamCall<T>>,
-    ) {
+    ) -> Handle {
         let slot = Slot::from_stream_actor(endpoint);
-        let addr = format!("{}/{}", addr, T::ID);
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
         log::debug!("binding stream actor {}", addr);
-        let _ = self.handlers.insert(addr.clone(), slot);
-        RemoteRouter::from_registry().do_send(UpdateService::Add(addr));
+        self.insert_slot(addr, slot, false)
     }
 
-    pub fn bind_actor<T: RpcMessage>(&mut self, addr: &str, endpoint: Recipient<RpcEnvelope<T>>) {
+    pub fn bind_actor<T: RpcMessage>(
+        &mut self,
+        addr: &str,
+        endpoint: Recipient<RpcEnvelope<T>>,
+    ) -> Handle {
         let slot = Slot::from_actor(endpoint);
-        let addr = format!("{}/{}", addr, T::ID);
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
         log::debug!("binding actor {}", addr);
-        let _ = self.handlers.insert(addr.clone(), slot);
-        RemoteRouter::from_registry().do_send(UpdateService::Add(addr));
+        self.insert_slot(addr, slot, false)
     }
 
     #[allow(unused)]
     pub fn bind_raw(&mut self, addr: &str, endpoint: Recipient<RpcRawCall>) -> Handle {
         let slot = Slot::from_raw(endpoint);
         log::debug!("binding raw {}", addr);
-        let _ = self.handlers.insert(addr.to_string(), slot);
-        RemoteRouter::from_registry().do_send(UpdateService::Add(addr.into()));
-        Handle { _inner: () }
+        self.insert_slot(self.normalize(addr), slot, false)
+    }
+
+    /// Binds raw streaming endpoint, request body and reply chunks are
+    /// passed through without serialization.
+    pub fn bind_raw_stream(&mut self, addr: &str, endpoint: Recipient<RpcRawStreamCall>) -> Handle {
+        let slot = Slot::from_raw_stream(endpoint);
+        log::debug!("binding raw stream {}", addr);
+        self.insert_slot(self.normalize(addr), slot, false)
     }
 
     pub fn bind_raw_dual(
@@ -505,74 +1325,238 @@ impl Router {
     ) -> Handle {
         let slot = Slot::from_raw_dual(DualRawEndpoint::new(rpc, stream));
         log::debug!("binding raw + stream {}", addr);
-        let _ = self.handlers.insert(addr.to_string(), slot);
-        RemoteRouter::from_registry().do_send(UpdateService::Add(addr.into()));
-        Handle { _inner: () }
+        self.insert_slot(self.normalize(addr), slot, false)
     }
 
     pub fn forward<T: RpcMessage + Unpin>(
         &mut self,
         addr: &str,
-        msg: RpcEnvelope<T>,
+        mut msg: RpcEnvelope<T>,
     ) -> impl Future<Output = Result<Result<T::Item, T::Error>, Error>> {
-        let addr = format!("{}/{}", addr, T::ID);
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
+        msg.hops = match self.next_hop(&addr) {
+            Ok(hops) => hops,
+            Err(e) => return future::err(e).left_future(),
+        };
+        let empty_reply = self.empty_reply;
         if let Some(slot) = self.handlers.get_mut(&addr) {
             (if let Some(h) = slot.recipient() {
                 h.send(msg)
-                    .map_err(|e| Error::from_addr(addr, e))
+                    .map_err(move |e| Error::from_handler(addr, e, h.connected()))
                     .left_future()
             } else {
-                slot.send(RpcRawCall::from_envelope_addr(msg, addr, false))
-                    .then(|b| {
-                        future::ready(match b {
-                            Ok(b) => {
-                                if b.is_empty() {
-                                    Err(Error::GsbFailure(
-                                        "empty response from remote service".to_string(),
-                                    ))
-                                } else {
-                                    crate::serialization::from_slice(&b).map_err(From::from)
-                                }
-                            }
-                            Err(e) => Err(e),
-                        })
-                    })
+                slot.send(RpcRawCall::from_envelope(msg, addr, false))
+                    .map(move |reply| decode_reply::<T>(reply, empty_reply))
                     .right_future()
             })
             .left_future()
         } else {
-            RemoteRouter::from_registry()
-                .send(RpcRawCall::from_envelope_addr(msg, addr.clone(), false))
-                .then(|v| {
-                    future::ready(match v {
-                        Ok(v) => v,
-                        Err(e) => Err(Error::from_addr(addr, e)),
-                    })
+            self.send_remote_guarded(RpcRawCall::from_envelope(msg, addr, false))
+                .map(move |reply| decode_reply::<T>(reply, empty_reply))
+                .right_future()
+        }
+        .right_future()
+    }
+
+    /// Like `forward`, also reporting where the call was routed.
+    pub fn forward_with_route<T: RpcMessage + Unpin>(
+        &mut self,
+        addr: &str,
+        msg: RpcEnvelope<T>,
+    ) -> impl Future<Output = RoutedReply<T>> {
+        let route = self.resolve(&format!("{}/{}", self.normalize(addr), T::ID));
+        self.forward(addr, msg).map(move |result| (route, result))
+    }
+
+    /// Calls `addr` served by router connection of given `instance_id`.
+    ///
+    /// The call always goes through the router. When the instance is gone
+    /// or does not serve `addr`, the call is routed as usual and its
+    /// successful reply is marked with `InstanceReply::fallback`.
+    pub fn forward_to_instance<T: RpcMessage + Unpin>(
+        &mut self,
+        addr: &str,
+        instance_id: &[u8],
+        mut msg: RpcEnvelope<T>,
+    ) -> impl Future<Output = PinnedReply<T>> {
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
+        msg.hops = match self.next_hop(&addr) {
+            Ok(hops) => hops,
+            Err(e) => return future::err(e).left_future(),
+        };
+        let call = RpcRawCall::from_envelope(msg, addr.clone(), false)
+            .with_header(TARGET_INSTANCE_HEADER, instance_id);
+        let empty_reply = self.empty_reply;
+        RemoteRouter::from_registry()
+            .send(CallWithTrailers(call))
+            .map(move |v| {
+                let result = v.map_err(|e| Error::from_addr(addr, e))??;
+                Ok(InstanceReply {
+                    fallback: result.trailers.contains_key(INSTANCE_FALLBACK_HEADER),
+                    reply: decode_reply::<T>(Ok(result.body), empty_reply)?,
                 })
-                .then(|b| {
-                    future::ready(match b {
-                        Ok(b) => {
-                            if b.is_empty() {
-                                Err(Error::GsbFailure(
-                                    "empty response from remote service".to_string(),
-                                ))
-                            } else {
-                                crate::serialization::from_slice(&b).map_err(From::from)
-                            }
-                        }
-                        Err(e) => Err(e),
+            })
+            .right_future()
+    }
+
+    /// Checks that `addr` is bound and its owner answers within `timeout`,
+    /// returning the round-trip time.
+    ///
+    /// Remote owner answers through its connection once its handler confirms
+    /// the address is served, the bound handler itself is not called. Address
+    /// bound locally is not probed at all, it succeeds at once.
+    ///
+    /// Fails with `Error::NoEndpoint` when the address is not bound anywhere
+    /// and with `Error::Timeout` when its owner does not answer.
+    pub fn ping_service(
+        &mut self,
+        addr: &str,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Duration, Error>> {
+        let addr = self.normalize(addr);
+        let started = crate::timeout::now();
+        if self.handlers.get(&addr).is_some() {
+            return future::ok(crate::timeout::now().saturating_duration_since(started))
+                .left_future();
+        }
+        let call = RpcRawCall::new("local", addr.clone(), Bytes::new(), false)
+            .with_deadline(started + timeout)
+            .with_header(PING_HEADER, Vec::new());
+        tokio::time::timeout(timeout, send_remote(call))
+            .map(move |r| match r {
+                Ok(Ok(_)) => Ok(crate::timeout::now().saturating_duration_since(started)),
+                Ok(Err(Error::GsbBadRequest(msg))) if msg == ENDPOINT_NOT_FOUND => {
+                    Err(Error::NoEndpoint(addr))
+                }
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(Error::Timeout(addr)),
+            })
+            .right_future()
+    }
+
+    /// Calls `addr` with body serialized beforehand.
+    pub fn forward_prepared<T: RpcMessage>(
+        &mut self,
+        addr: &str,
+        prepared: &PreparedCall<T>,
+    ) -> impl Future<Output = Result<Result<T::Item, T::Error>, Error>> {
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
+        let hops = match self.next_hop(&addr) {
+            Ok(hops) => hops,
+            Err(e) => return future::err(e).left_future(),
+        };
+        let call = prepared.to_raw_call(addr.clone(), hops, false);
+        let empty_reply = self.empty_reply;
+        match self.handlers.get(&addr) {
+            Some(slot) => slot.send(call).left_future(),
+            None => self.send_remote_guarded(call).right_future(),
+        }
+        .map(move |reply| decode_reply::<T>(reply, empty_reply))
+        .right_future()
+    }
+
+    /// Pushes body serialized beforehand to `addr`.
+    pub fn push_prepared<T: RpcMessage>(
+        &mut self,
+        addr: &str,
+        prepared: &PreparedCall<T>,
+    ) -> impl Future<Output = Result<(), Error>> {
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
+        let hops = match self.next_hop(&addr) {
+            Ok(hops) => hops,
+            Err(e) => return future::err(e).left_future(),
+        };
+        let call = prepared.to_raw_call(addr.clone(), hops, true);
+        match self.handlers.get(&addr) {
+            Some(slot) => slot.send(call).left_future(),
+            None => send_remote(call).right_future(),
+        }
+        .map_ok(|_| ())
+        .right_future()
+    }
+
+    /// Calls `addr` on behalf of `from`.
+    ///
+    /// Caller is passed unchanged to typed and raw handlers, local or remote.
+    pub fn forward_from<T: RpcMessage + Unpin>(
+        &mut self,
+        addr: &str,
+        from: &str,
+        msg: T,
+    ) -> impl Future<Output = Result<Result<T::Item, T::Error>, Error>> {
+        self.forward(addr, RpcEnvelope::with_caller(from, msg))
+    }
+
+    /// Forwards all messages at once, yielding replies in request order.
+    ///
+    /// Target is resolved once for the whole batch. Calls are pipelined: every
+    /// request is dispatched before any reply is awaited.
+    pub fn forward_batch<T: RpcMessage + Unpin>(
+        &mut self,
+        addr: &str,
+        msgs: Vec<RpcEnvelope<T>>,
+    ) -> impl Stream<Item = Result<Result<T::Item, T::Error>, Error>> {
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
+        let empty_reply = self.empty_reply;
+        let hops = match self.next_hop(&addr) {
+            Ok(hops) => hops,
+            Err(e) => {
+                // Every call of the batch fails the same way.
+                let reason = match e {
+                    Error::GsbFailure(reason) => reason,
+                    e => e.to_string(),
+                };
+                return msgs
+                    .iter()
+                    .map(|_| future::err(Error::GsbFailure(reason.clone())).boxed_local())
+                    .collect::<stream::FuturesOrdered<_>>();
+            }
+        };
+        let calls: Vec<_> = if let Some(slot) = self.handlers.get_mut(&addr) {
+            if let Some(h) = slot.recipient::<T>() {
+                msgs.into_iter()
+                    .map(|mut msg| {
+                        msg.hops = hops;
+                        let addr = addr.clone();
+                        let h = h.clone();
+                        h.send(msg)
+                            .map_err(move |e| Error::from_handler(addr, e, h.connected()))
+                            .boxed_local()
+                    })
+                    .collect()
+            } else {
+                msgs.into_iter()
+                    .map(|mut msg| {
+                        msg.hops = hops;
+                        slot.send(RpcRawCall::from_envelope(msg, addr.clone(), false))
+                            .map(move |reply| decode_reply::<T>(reply, empty_reply))
+                            .boxed_local()
                     })
+                    .collect()
+            }
+        } else {
+            msgs.into_iter()
+                .map(|mut msg| {
+                    msg.hops = hops;
+                    self.send_remote_guarded(RpcRawCall::from_envelope(msg, addr.clone(), false))
+                        .map(move |reply| decode_reply::<T>(reply, empty_reply))
+                        .boxed_local()
                 })
-                .right_future()
-        }
+                .collect()
+        };
+        calls.into_iter().collect::<stream::FuturesOrdered<_>>()
     }
 
     pub fn push<T: RpcMessage + Unpin>(
         &mut self,
         addr: &str,
-        msg: RpcEnvelope<T>,
+        mut msg: RpcEnvelope<T>,
     ) -> impl Future<Output = Result<(), Error>> {
-        let addr = format!("{}/{}", addr, T::ID);
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
+        msg.hops = match self.next_hop(&addr) {
+            Ok(hops) => hops,
+            Err(e) => return future::err(e).left_future(),
+        };
         if let Some(slot) = self.handlers.get_mut(&addr) {
             if let Some(h) = slot.recipient() {
                 h.send(msg)
@@ -584,14 +1568,14 @@ impl Router {
                     })
                     .left_future()
             } else {
-                slot.send(RpcRawCall::from_envelope_addr(msg, addr.clone(), true))
+                slot.send(RpcRawCall::from_envelope(msg, addr.clone(), true))
                     .then(|v| future::ready(v.map(|_| ())))
                     .right_future()
             }
             .left_future()
         } else {
             RemoteRouter::from_registry()
-                .send(RpcRawCall::from_envelope_addr(msg, addr.clone(), true))
+                .send(RpcRawCall::from_envelope(msg, addr.clone(), true))
                 .then(|v| {
                     future::ready(match v {
                         Ok(_) => Ok(()),
@@ -600,40 +1584,98 @@ impl Router {
                 })
                 .right_future()
         }
+        .right_future()
     }
 
+    /// Pushes `msg` to `addr` on behalf of `from`, see `forward_from`.
+    pub fn push_from<T: RpcMessage + Unpin>(
+        &mut self,
+        addr: &str,
+        from: &str,
+        msg: T,
+    ) -> impl Future<Output = Result<(), Error>> {
+        self.push(addr, RpcEnvelope::with_caller(from, msg))
+    }
+
+    /// Calls streaming endpoint and returns its first item, the rest of the
+    /// stream is dropped.
+    pub fn forward_first<T: RpcStreamMessage>(
+        &mut self,
+        addr: &str,
+        msg: T,
+    ) -> impl Future<Output = Result<Result<T::Item, T::Error>, Error>> {
+        let err_addr = format!("{}/{}", addr, T::ID);
+        self.streaming_forward(addr, msg)
+            .try_filter_map(|item| future::ok(item.into_item()))
+            .boxed_local()
+            .into_future()
+            .map(move |(first, _rest)| {
+                first.unwrap_or_else(|| {
+                    Err(Error::GsbFailure(format!(
+                        "empty response from streaming endpoint: {}",
+                        err_addr
+                    )))
+                })
+            })
+    }
+
+    /// Calls streaming endpoint, the stream ends with `StreamItem::Complete`
+    /// or with an error when the response got truncated.
     pub fn streaming_forward<T: RpcStreamMessage>(
         &mut self,
         addr: &str,
-        // TODO: add `from: &str` as in `forward_bytes` below
         msg: T,
-    ) -> impl Stream<Item = Result<Result<T::Item, T::Error>, Error>> {
-        let caller = "local".to_string();
-        let addr = format!("{}/{}", addr, T::ID);
+    ) -> impl Stream<Item = Result<StreamReply<T>, Error>> {
+        self.streaming_forward_from(addr, "local", msg)
+    }
+
+    /// Calls streaming endpoint on behalf of `from`, see `streaming_forward`.
+    pub fn streaming_forward_from<T: RpcStreamMessage>(
+        &mut self,
+        addr: &str,
+        from: &str,
+        msg: T,
+    ) -> impl Stream<Item = Result<StreamReply<T>, Error>> {
+        let caller = from.to_string();
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
+        let hops = match self.next_hop(&addr) {
+            Ok(hops) => hops,
+            Err(e) => return stream::once(future::err(e)).boxed_local(),
+        };
         if let Some(slot) = self.handlers.get_mut(&addr) {
-            slot.streaming_forward(caller, addr, msg).left_stream()
+            slot.streaming_forward(caller, addr, msg, hops)
+                .boxed_local()
         } else {
             //use futures::StreamExt;
             log::trace!("call remote (stream) {}", addr);
-            let body = crate::serialization::to_vec(&msg).unwrap();
+            let body = crate::serialization::to_vec(&msg).unwrap().into();
             let (reply, tx) = futures::channel::mpsc::channel(16);
+            let mut headers = Headers::new();
+            hops::to_headers(hops, &mut headers);
+            let mut errors = reply.clone();
             let call = RpcRawStreamCall {
                 caller,
-                addr,
+                addr: addr.clone(),
                 body,
                 reply,
+                window: None,
+                headers,
             };
-            let _ = Arbiter::current().spawn(async move {
-                let v = RemoteRouter::from_registry().send(call).await;
-                log::trace!("call result={:?}", v);
+            let call_addr = addr.clone();
+            spawner::spawn(async move {
+                let result = match RemoteRouter::from_registry().send(call).await {
+                    Ok(v) => v,
+                    Err(e) => Err(Error::from_addr(call_addr, e)),
+                };
+                if let Err(e) = result {
+                    log::debug!("remote stream call failed: {}", e);
+                    let _ = errors.send(Err(e)).await;
+                }
             });
 
-            tx.filter(|s| future::ready(s.as_ref().map(|s| !s.is_eos()).unwrap_or(true)))
-                .map(|b| {
-                    let body = b?.into_bytes();
-                    Ok(crate::serialization::from_slice(&body)?)
-                })
-                .right_stream()
+            with_completion(tx, Some(addr))
+                .map(decode_stream_item::<T>)
+                .boxed_local()
         }
     }
 
@@ -641,48 +1683,72 @@ impl Router {
         &mut self,
         addr: &str,
         caller: &str,
-        msg: Vec<u8>,
+        msg: impl Into<Bytes>,
         no_reply: bool,
     ) -> impl Future<Output = Result<Vec<u8>, Error>> {
-        let addr = addr.to_string();
-        if let Some(slot) = self.handlers.get_mut(&addr) {
-            slot.send(RpcRawCall {
-                caller: caller.into(),
-                addr: addr.clone(),
-                body: msg,
-                no_reply,
-            })
-            .left_future()
-        } else {
-            RemoteRouter::from_registry()
-                .send(RpcRawCall {
-                    caller: caller.into(),
-                    addr: addr.clone(),
-                    body: msg,
-                    no_reply,
-                })
-                .then(|v| match v {
-                    Ok(r) => future::ready(r),
-                    Err(e) => future::err(Error::from_addr(addr, e)),
-                })
-                .right_future()
+        self.forward_raw(RpcRawCall::new(caller, addr, msg, no_reply))
+    }
+
+    /// Calls `addr` suffixed with `T::ID` with body already serialized as `T`,
+    /// returning serialized reply. Neither body nor reply is decoded.
+    pub fn forward_raw_typed<T: RpcMessage>(
+        &mut self,
+        addr: &str,
+        caller: &str,
+        body: impl Into<Bytes>,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> {
+        let addr = format!("{}/{}", self.normalize(addr), T::ID);
+        self.forward_raw(RpcRawCall::new(caller, addr, body, false))
+    }
+
+    /// Forwards call built by the caller, e.g. proxy relaying calls from
+    /// other protocol. Address is normalized and hop count set as for calls
+    /// made from the current handler, other fields are passed as they are.
+    pub fn forward_raw(
+        &mut self,
+        mut call: RpcRawCall,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> {
+        call.addr = self.normalize(&call.addr);
+        match self.next_hop(&call.addr) {
+            Ok(hops) => hops::to_headers(hops, &mut call.headers),
+            Err(e) => return future::err(e).left_future(),
+        }
+        match self.handlers.get_mut(&call.addr) {
+            Some(slot) => slot.send(call).left_future(),
+            None => self.send_remote_guarded(call).right_future(),
         }
+        .right_future()
     }
 
+    /// Raw counterpart of `streaming_forward`, end-of-stream marker is
+    /// reported as `StreamItem::Complete`.
     pub fn streaming_forward_bytes(
         &mut self,
         addr: &str,
         caller: &str,
-        msg: Vec<u8>,
-    ) -> impl Stream<Item = Result<ResponseChunk, Error>> {
+        msg: impl Into<Bytes>,
+    ) -> impl Stream<Item = Result<StreamItem<ResponseChunk>, Error>> {
+        let addr = &self.normalize(addr);
+        let msg = msg.into();
+        let mut headers = Headers::new();
+        match self.next_hop(addr) {
+            Ok(hops) => hops::to_headers(hops, &mut headers),
+            Err(e) => return stream::once(future::err(e)).boxed_local(),
+        }
         if let Some(slot) = self.handlers.get_mut(addr) {
-            slot.send_streaming(RpcRawCall {
-                caller: caller.into(),
-                addr: addr.into(),
-                body: msg,
-                no_reply: false,
-            })
-            .left_stream()
+            with_completion(
+                slot.send_streaming(RpcRawCall {
+                    caller: caller.into(),
+                    addr: addr.into(),
+                    body: msg,
+                    no_reply: false,
+                    deadline: None,
+                    headers,
+                    collect_parts: false,
+                }),
+                None,
+            )
+            .boxed_local()
         } else {
             let (tx, rx) = mpsc::channel(16);
             let call = RpcRawStreamCall {
@@ -690,15 +1756,19 @@ impl Router {
                 addr: addr.into(),
                 body: msg,
                 reply: tx,
+                window: None,
+                headers,
             };
+            let addr = addr.to_string();
             async move {
                 match RemoteRouter::from_registry().send(call).await {
-                    Ok(_) => rx.boxed_local(),
+                    Ok(Ok(())) => with_completion(rx, Some(addr)).boxed_local(),
+                    Ok(Err(e)) => futures::stream::once(future::err(e)).boxed_local(),
                     Err(e) => futures::stream::once(future::err(e.into())).boxed_local(),
                 }
             }
             .flatten_stream()
-            .right_stream()
+            .boxed_local()
         }
     }
 
@@ -706,16 +1776,27 @@ impl Router {
         &mut self,
         addr: &str,
         caller: &str,
-        msg: &[u8],
+        msg: Bytes,
         no_reply: bool,
+        deadline: Option<Instant>,
+        headers: Headers,
     ) -> impl Stream<Item = Result<ResponseChunk, Error>> {
-        let addr = addr.to_string();
+        let addr = self.normalize(addr);
+        if deadline
+            .map(|d| d <= crate::timeout::now())
+            .unwrap_or(false)
+        {
+            return futures::stream::once(async { Err(Error::Timeout(addr)) }).boxed_local();
+        }
         if let Some(slot) = self.handlers.get_mut(&addr) {
             let msg = RpcRawCall {
                 caller: caller.into(),
                 addr,
-                body: msg.into(),
+                body: msg,
                 no_reply,
+                deadline,
+                headers,
+                collect_parts: false,
             };
 
             if no_reply {
@@ -736,6 +1817,885 @@ lazy_static::lazy_static! {
 static ref ROUTER: Arc<Mutex<Router>> = Arc::new(Mutex::new(Router::new()));
 }
 
+/// Turns end-of-stream marker into `StreamItem::Complete`.
+///
+/// Local handlers may just end the stream, while for remote ones (`remote` set
+/// to called address) missing marker means the response was cut short.
+fn with_completion<S>(
+    chunks: S,
+    mut remote: Option<String>,
+) -> impl Stream<Item = Result<StreamItem<ResponseChunk>, Error>>
+where
+    S: Stream<Item = Result<ResponseChunk, Error>>,
+{
+    let mut ended = false;
+    chunks
+        .map(Some)
+        .chain(stream::once(future::ready(None)))
+        .flat_map(move |chunk| {
+            let items = match chunk {
+                _ if ended => Vec::new(),
+                Some(Ok(chunk)) if chunk.is_eos() => {
+                    ended = true;
+                    vec![Ok(StreamItem::Complete)]
+                }
+                Some(Ok(chunk)) if chunk.is_full() => {
+                    ended = true;
+                    vec![Ok(StreamItem::Item(chunk)), Ok(StreamItem::Complete)]
+                }
+                Some(Ok(chunk)) => vec![Ok(StreamItem::Item(chunk))],
+                Some(Err(e)) => {
+                    ended = true;
+                    vec![Err(e)]
+                }
+                None => match remote.take() {
+                    Some(addr) => {
+                        log::debug!("stream from {} ended without end-of-stream marker", addr);
+                        vec![Err(Error::Closed(addr))]
+                    }
+                    None => vec![Ok(StreamItem::Complete)],
+                },
+            };
+            stream::iter(items)
+        })
+}
+
+fn send_remote(call: RpcRawCall) -> impl Future<Output = Result<Vec<u8>, Error>> {
+    let addr = call.addr.clone();
+    RemoteRouter::from_registry().send(call).map(|v| match v {
+        Ok(v) => v,
+        Err(e) => Err(Error::from_addr(addr, e)),
+    })
+}
+
+fn decode_reply<T: RpcMessage>(
+    reply: Result<Vec<u8>, Error>,
+    empty_reply: EmptyReplyPolicy,
+) -> Result<Result<T::Item, T::Error>, Error> {
+    let body = reply?;
+    if body.is_empty() {
+        let empty = || Error::GsbFailure("empty response from remote service".to_string());
+        return match empty_reply {
+            EmptyReplyPolicy::Error => Err(empty()),
+            // `Option` and the like would decode from unit too
+            EmptyReplyPolicy::Ok if TypeId::of::<T::Item>() == TypeId::of::<()>() => {
+                let unit = crate::serialization::to_vec(&Ok::<(), ()>(()))?;
+                Ok(crate::serialization::from_slice(&unit)?)
+            }
+            EmptyReplyPolicy::Ok => Err(empty()),
+        };
+    }
+    Ok(crate::serialization::from_slice(&body)?)
+}
+
+fn decode_stream_item<T: RpcStreamMessage>(
+    item: Result<StreamItem<ResponseChunk>, Error>,
+) -> Result<StreamReply<T>, Error> {
+    Ok(match item? {
+        StreamItem::Item(chunk) => {
+            StreamItem::Item(crate::serialization::from_slice(&chunk.into_bytes())?)
+        }
+        StreamItem::Complete => StreamItem::Complete,
+    })
+}
+
 pub fn router() -> Arc<Mutex<Router>> {
     (*ROUTER).clone()
 }
+
+/// Unbinds aliases of dropped `AliasHandle`.
+///
+/// The handle may be dropped while the router is locked, even by this
+/// thread, e.g. `router().lock().unwrap().bind_aliases(..);`. Unbinding is
+/// then left to another thread, waiting for the lock to be released.
+pub(crate) fn drop_aliases(addrs: Vec<String>, owner: Arc<()>) {
+    if addrs.is_empty() {
+        return;
+    }
+    let remote = System::try_current().map(|_| RemoteRouter::from_registry());
+    match ROUTER.try_lock() {
+        Ok(mut router) => router.unbind_aliases(&addrs, &owner, remote.as_ref()),
+        Err(std::sync::TryLockError::WouldBlock) => {
+            let _ = std::thread::spawn(move || {
+                if let Ok(mut router) = ROUTER.lock() {
+                    router.unbind_aliases(&addrs, &owner, remote.as_ref());
+                }
+            });
+        }
+        Err(std::sync::TryLockError::Poisoned(_)) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_in_flight_limits() {
+        let policy = StreamPolicy {
+            max_in_flight_per_caller: 2,
+            max_in_flight: 3,
+        };
+        let mut in_flight = InFlight::default();
+
+        assert!(in_flight.try_acquire(&policy, "a"));
+        assert!(in_flight.try_acquire(&policy, "a"));
+        assert!(!in_flight.try_acquire(&policy, "a"));
+        assert!(in_flight.try_acquire(&policy, "b"));
+        assert!(!in_flight.try_acquire(&policy, "c"));
+
+        in_flight.release("a");
+        assert!(in_flight.try_acquire(&policy, "c"));
+        assert!(!in_flight.try_acquire(&policy, "b"));
+
+        in_flight.release("a");
+        in_flight.release("b");
+        in_flight.release("c");
+        assert_eq!(in_flight.total, 0);
+        assert!(in_flight.per_caller.is_empty());
+    }
+
+    #[test]
+    fn test_stream_completion() {
+        let chunks = || {
+            stream::iter(vec![
+                Ok(ResponseChunk::Part(vec![1])),
+                Ok(ResponseChunk::Part(vec![2])),
+            ])
+        };
+        let eos = || stream::once(future::ok(ResponseChunk::eos()));
+
+        let items: Vec<_> = futures::executor::block_on(
+            with_completion(chunks().chain(eos()), Some("remote".into())).collect(),
+        );
+        assert_eq!(items.len(), 3);
+        assert!(items[2].as_ref().unwrap().is_complete());
+
+        let items: Vec<_> =
+            futures::executor::block_on(with_completion(chunks(), Some("remote".into())).collect());
+        assert!(matches!(items.last(), Some(Err(Error::Closed(_)))));
+
+        let items: Vec<_> = futures::executor::block_on(with_completion(chunks(), None).collect());
+        assert!(items.last().unwrap().as_ref().unwrap().is_complete());
+    }
+
+    #[test]
+    fn test_max_hops() {
+        let mut router = Router::new();
+        assert_eq!(router.next_hop("/a").unwrap(), 1);
+        assert_eq!(hops::sync_scope(5, || router.next_hop("/a")).unwrap(), 6);
+
+        router.set_max_hops(Some(2));
+        assert_eq!(hops::sync_scope(1, || router.next_hop("/a")).unwrap(), 2);
+        assert!(matches!(
+            hops::sync_scope(2, || router.next_hop("/a")),
+            Err(Error::GsbFailure(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_addr() {
+        let router = Router::new();
+        assert!(router.validate_addr("/local/svc").is_ok());
+        assert!(router.validate_addr("/a").is_ok());
+        for addr in [
+            "",
+            "/",
+            "local/svc",
+            "/local//svc",
+            "/local/svc/",
+            "/local/s vc",
+        ] {
+            assert!(
+                matches!(router.validate_addr(addr), Err(Error::GsbBadRequest(_))),
+                "{:?}",
+                addr
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_request_context() {
+        let msg = RpcRawCall::new("caller", "/local/svc/Ping", vec![0xff, 0x00, 0x13], false);
+        match decode_request::<(u64, String)>(&msg, "Ping") {
+            Err(Error::BadRequestContent { addr, msg_type, .. }) => {
+                assert_eq!(addr, "/local/svc/Ping");
+                assert_eq!(msg_type, "Ping");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_no_endpoint_kind() {
+        use crate::NoEndpointKind;
+
+        let kind = |e: Error| e.no_endpoint_kind();
+        assert_eq!(
+            kind(Error::NoEndpoint("/local/svc".into())),
+            Some(NoEndpointKind::NotBound)
+        );
+        assert_eq!(
+            kind(Error::GsbBadRequest(ENDPOINT_NOT_FOUND.into())),
+            Some(NoEndpointKind::NotBound)
+        );
+        assert_eq!(kind(Error::GsbBadRequest("invalid address".into())), None);
+        assert_eq!(kind(Error::Timeout("/local/svc".into())), None);
+
+        let msg = RpcRawCall::new("caller", "/local/svc/Ping", vec![0xff, 0x00, 0x13], false);
+        let e = decode_request::<(u64, String)>(&msg, "Ping").unwrap_err();
+        assert_eq!(kind(e), Some(NoEndpointKind::Undecodable));
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Touch;
+
+    impl RpcMessage for Touch {
+        const ID: &'static str = "Touch";
+        type Item = ();
+        type Error = String;
+    }
+
+    #[test]
+    fn test_empty_reply_policy() {
+        assert!(matches!(
+            decode_reply::<Touch>(Ok(Vec::new()), EmptyReplyPolicy::Error),
+            Err(Error::GsbFailure(_))
+        ));
+        assert!(matches!(
+            decode_reply::<Touch>(Ok(Vec::new()), EmptyReplyPolicy::Ok),
+            Ok(Ok(()))
+        ));
+        assert!(matches!(
+            decode_reply::<Whoami>(Ok(Vec::new()), EmptyReplyPolicy::Ok),
+            Err(Error::GsbFailure(_))
+        ));
+        assert!(matches!(
+            decode_reply::<Lookup>(Ok(Vec::new()), EmptyReplyPolicy::Ok),
+            Err(Error::GsbFailure(_))
+        ));
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Lookup;
+
+    impl RpcMessage for Lookup {
+        const ID: &'static str = "Lookup";
+        type Item = Option<String>;
+        type Error = String;
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Whoami(u8);
+
+    impl RpcMessage for Whoami {
+        const ID: &'static str = "Whoami";
+        type Item = String;
+        type Error = ();
+    }
+
+    impl RpcStreamMessage for Whoami {
+        const ID: &'static str = "WhoamiStream";
+        type Item = String;
+        type Error = ();
+    }
+
+    struct EchoCaller;
+
+    impl RpcHandler<Whoami> for EchoCaller {
+        type Result = future::Ready<Result<String, ()>>;
+
+        fn handle(&mut self, caller: String, _msg: Whoami) -> Self::Result {
+            future::ok(caller)
+        }
+    }
+
+    impl RpcStreamHandler<Whoami> for EchoCaller {
+        type Result = stream::Once<future::Ready<Result<String, ()>>>;
+
+        fn handle(&mut self, caller: &str, _msg: Whoami) -> Self::Result {
+            stream::once(future::ok(caller.to_string()))
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_caller_preserved() {
+        let mut router = Router::new();
+        let _rpc = router.bind::<Whoami>("/local/who", EchoCaller);
+        let _stream = router.bind_stream::<Whoami>("/local/who", EchoCaller);
+
+        let reply = router.forward_from("/local/who", "alice", Whoami(0)).await;
+        assert_eq!(reply.unwrap(), Ok("alice".to_string()));
+
+        let body = crate::serialization::to_vec(&Whoami(0)).unwrap();
+        let reply = router
+            .forward_bytes("/local/who/Whoami", "alice", body, false)
+            .await
+            .unwrap();
+        let reply: Result<String, ()> = crate::serialization::from_slice(&reply).unwrap();
+        assert_eq!(reply, Ok("alice".to_string()));
+
+        let items: Vec<_> = router
+            .streaming_forward_from("/local/who", "alice", Whoami(0))
+            .collect()
+            .await;
+        assert!(
+            matches!(&items[0], Ok(StreamItem::Item(Ok(c))) if c == "alice"),
+            "{:?}",
+            items
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_forward_raw_typed() {
+        use tokio_util::codec::Framed;
+        use ya_sb_proto::codec::{GsbMessage, GsbMessageCodec};
+
+        let mut router = Router::new();
+        let _rpc = router.bind::<Whoami>("/local/who", EchoCaller);
+        let body = crate::serialization::to_vec(&Whoami(0)).unwrap();
+        let reply = router
+            .forward_raw_typed::<Whoami>("/local/who", "bob", body.clone())
+            .await
+            .unwrap();
+        let reply: Result<String, ()> = crate::serialization::from_slice(&reply).unwrap();
+        assert_eq!(reply, Ok("bob".to_string()));
+
+        // the remote callee gets the address suffixed with message id
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        crate::untyped::set_gsb_endpoint(ya_sb_proto::GsbAddr::Tcp(addr));
+        let gsb = async move {
+            let (io, _) = listener.accept().await.unwrap();
+            let mut peer = Framed::new(io, GsbMessageCodec::default());
+            let hello = ya_sb_proto::Hello {
+                name: "router".to_string(),
+                proto_version: ya_sb_proto::PROTO_VERSION,
+                ..Default::default()
+            };
+            peer.send(GsbMessage::Hello(hello)).await.unwrap();
+            loop {
+                match peer.next().await.unwrap().unwrap() {
+                    // replayed registration of `/local/who`
+                    GsbMessage::RegisterRequest(_) => peer
+                        .send(GsbMessage::RegisterReply(ya_sb_proto::RegisterReply {
+                            code: ya_sb_proto::RegisterReplyCode::RegisteredOk as i32,
+                            message: String::new(),
+                        }))
+                        .await
+                        .unwrap(),
+                    GsbMessage::CallRequest(r) => {
+                        let data = crate::serialization::to_vec(&Ok::<_, ()>(r.caller)).unwrap();
+                        peer.send(GsbMessage::CallReply(ya_sb_proto::CallReply {
+                            request_id: r.request_id,
+                            code: ya_sb_proto::CallReplyCode::CallReplyOk as i32,
+                            reply_type: ya_sb_proto::CallReplyType::Full as i32,
+                            data,
+                            trailers: Default::default(),
+                        }))
+                        .await
+                        .unwrap();
+                        return (r.address, peer);
+                    }
+                    _ => (),
+                }
+            }
+        };
+        let call = router.forward_raw_typed::<Whoami>("/remote/who", "carol", body);
+        let (reply, (address, _peer)) = future::join(call, gsb).await;
+        let reply: Result<String, ()> = crate::serialization::from_slice(&reply.unwrap()).unwrap();
+        assert_eq!(reply, Ok("carol".to_string()));
+        assert_eq!(address, "/remote/who/Whoami");
+    }
+
+    #[actix_rt::test]
+    async fn test_unbind_with_report() {
+        use tokio_util::codec::Framed;
+        use ya_sb_proto::codec::{GsbMessage, GsbMessageCodec};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        crate::untyped::set_gsb_endpoint(ya_sb_proto::GsbAddr::Tcp(addr));
+        let mut router = Router::new();
+        let _a = router.bind::<Whoami>("/local/report/a", EchoCaller);
+        let _b = router.bind::<Whoami>("/local/report/b", EchoCaller);
+
+        let (io, _) = listener.accept().await.unwrap();
+        let mut peer = Framed::new(io, GsbMessageCodec::default());
+        let hello = ya_sb_proto::Hello {
+            name: "router".to_string(),
+            proto_version: ya_sb_proto::PROTO_VERSION,
+            ..Default::default()
+        };
+        peer.send(GsbMessage::Hello(hello)).await.unwrap();
+        // both bindings are replayed on the new connection
+        let mut registered = 0;
+        while registered < 2 {
+            if let GsbMessage::RegisterRequest(_) = peer.next().await.unwrap().unwrap() {
+                peer.send(GsbMessage::RegisterReply(ya_sb_proto::RegisterReply {
+                    code: ya_sb_proto::RegisterReplyCode::RegisteredOk as i32,
+                    message: String::new(),
+                }))
+                .await
+                .unwrap();
+                registered += 1;
+            }
+        }
+
+        // router drops `/a` and does not know `/b`
+        let gsb = async {
+            let mut unregistered = 0;
+            while unregistered < 2 {
+                if let GsbMessage::UnregisterRequest(r) = peer.next().await.unwrap().unwrap() {
+                    let code = match r.service_id.as_str() {
+                        "/local/report/a/Whoami" => {
+                            ya_sb_proto::UnregisterReplyCode::UnregisteredOk
+                        }
+                        _ => ya_sb_proto::UnregisterReplyCode::NotRegistered,
+                    };
+                    peer.send(GsbMessage::UnregisterReply(ya_sb_proto::UnregisterReply {
+                        code: code as i32,
+                    }))
+                    .await
+                    .unwrap();
+                    unregistered += 1;
+                }
+            }
+        };
+        let (mut report, ()) = future::join(router.unbind_with_report("/local/report"), gsb).await;
+        report.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].0, "/local/report/a/Whoami");
+        assert!(report[0].1.is_ok(), "{:?}", report[0].1);
+        assert_eq!(report[1].0, "/local/report/b/Whoami");
+        assert!(report[1].1.is_err());
+        // both are gone locally regardless
+        assert!(router.endpoint_kind("/local/report/a/Whoami").is_none());
+        assert!(router.endpoint_kind("/local/report/b/Whoami").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_forward_raw() {
+        let mut router = Router::new();
+        let _rpc = router.bind::<Whoami>("/local/raw-fwd", EchoCaller);
+
+        let call = RpcRawCall::from_envelope_addr(
+            RpcEnvelope::with_caller("alice", Whoami(0)),
+            "/local/raw-fwd/Whoami".to_string(),
+            false,
+        )
+        .unwrap();
+        let reply = router.forward_raw(call).await.unwrap();
+        let reply: Result<String, ()> = crate::serialization::from_slice(&reply).unwrap();
+        assert_eq!(reply, Ok("alice".to_string()));
+
+        // hop count is checked as for typed forwards
+        router.set_max_hops(Some(2));
+        let call = RpcRawCall::new("alice", "/local/raw-fwd/Whoami", Vec::new(), false);
+        let reply = hops::sync_scope(2, || router.forward_raw(call));
+        assert!(matches!(reply.await, Err(Error::GsbFailure(_))));
+    }
+
+    #[actix_rt::test]
+    async fn test_forward_prepared() {
+        let mut router = Router::new();
+        let _first = router.bind::<Whoami>("/local/prepared/1", EchoCaller);
+        let _second = router.bind::<Whoami>("/local/prepared/2", EchoCaller);
+
+        let prepared = PreparedCall::with_caller("dave", &Whoami(0)).unwrap();
+        for addr in ["/local/prepared/1", "/local/prepared/2"] {
+            let reply = router.forward_prepared(addr, &prepared).await;
+            assert_eq!(reply.unwrap(), Ok("dave".to_string()));
+        }
+        router
+            .push_prepared("/local/prepared/1", &prepared)
+            .await
+            .unwrap();
+
+        // hop count is checked as for typed forwards
+        router.set_max_hops(Some(2));
+        let reply = hops::sync_scope(2, || {
+            router.forward_prepared("/local/prepared/1", &prepared)
+        });
+        assert!(matches!(reply.await, Err(Error::GsbFailure(_))));
+
+        let _global = crate::typed::bind("/local/prepared/global", EchoCaller);
+        let reply = crate::typed::service("/local/prepared/global")
+            .call_prepared(&PreparedCall::new(&Whoami(0)).unwrap())
+            .await;
+        assert_eq!(reply.unwrap(), Ok("local".to_string()));
+    }
+
+    struct WaitForAll {
+        seen: Arc<std::sync::atomic::AtomicUsize>,
+        batch: usize,
+    }
+
+    impl RpcHandler<Whoami> for WaitForAll {
+        type Result = future::LocalBoxFuture<'static, Result<String, ()>>;
+
+        fn handle(&mut self, _caller: String, msg: Whoami) -> Self::Result {
+            use std::sync::atomic::Ordering;
+
+            let seen = self.seen.clone();
+            let batch = self.batch;
+            seen.fetch_add(1, Ordering::SeqCst);
+            async move {
+                // Replies only once whole batch arrived.
+                while seen.load(Ordering::SeqCst) < batch {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+                Ok(msg.0.to_string())
+            }
+            .boxed_local()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_forward_batch_pipelined() {
+        let mut router = Router::new();
+        let _handle = router.bind::<Whoami>(
+            "/local/batch",
+            WaitForAll {
+                seen: Default::default(),
+                batch: 5,
+            },
+        );
+        let msgs = (0..5).map(|i| RpcEnvelope::local(Whoami(i))).collect();
+        let replies: Vec<_> = tokio::time::timeout(
+            Duration::from_secs(5),
+            router
+                .forward_batch("/local/batch", msgs)
+                .collect::<Vec<_>>(),
+        )
+        .await
+        .expect("requests not pipelined");
+        let replies: Vec<_> = replies.into_iter().map(|r| r.unwrap().unwrap()).collect();
+        assert_eq!(replies, ["0", "1", "2", "3", "4"]);
+    }
+
+    /// Panics serving its first call.
+    #[derive(Default)]
+    struct PanicOnce(bool);
+
+    impl RpcHandler<Whoami> for PanicOnce {
+        type Result = future::Ready<Result<String, ()>>;
+
+        fn handle(&mut self, caller: String, _msg: Whoami) -> Self::Result {
+            if !std::mem::replace(&mut self.0, true) {
+                panic!("first call");
+            }
+            future::ok(caller)
+        }
+    }
+
+    impl RpcStreamHandler<Whoami> for PanicOnce {
+        type Result = stream::BoxStream<'static, Result<String, ()>>;
+
+        fn handle(&mut self, caller: &str, _msg: Whoami) -> Self::Result {
+            let first = !std::mem::replace(&mut self.0, true);
+            let caller = caller.to_string();
+            stream::once(async move {
+                if first {
+                    panic!("first stream");
+                }
+                Ok(caller)
+            })
+            .boxed()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_handler_panic_isolated() {
+        let mut router = Router::new();
+        let _rpc = router.bind::<Whoami>("/local/panic", PanicOnce::default());
+        let _stream = router.bind_stream::<Whoami>("/local/panic", PanicOnce::default());
+
+        let reply = router
+            .forward_from("/local/panic", "alice", Whoami(0))
+            .await;
+        assert!(matches!(reply, Err(Error::GsbFailure(_))), "{:?}", reply);
+        let reply = router
+            .forward_from("/local/panic", "alice", Whoami(0))
+            .await;
+        assert_eq!(reply.unwrap(), Ok("alice".to_string()));
+
+        let items: Vec<_> = router
+            .streaming_forward_from("/local/panic", "alice", Whoami(0))
+            .collect()
+            .await;
+        assert!(
+            matches!(items.last(), Some(Err(Error::GsbFailure(_)))),
+            "{:?}",
+            items
+        );
+        let items: Vec<_> = router
+            .streaming_forward_from("/local/panic", "alice", Whoami(0))
+            .collect()
+            .await;
+        assert!(
+            matches!(&items[0], Ok(StreamItem::Item(Ok(c))) if c == "alice"),
+            "{:?}",
+            items
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_every_bind_validates_addr() {
+        let mut router = Router::new();
+        let policy = StreamPolicy {
+            max_in_flight_per_caller: 1,
+            max_in_flight: 1,
+        };
+        let handles = vec![
+            router.rebind::<Whoami>("/local//who", EchoCaller),
+            router.bind_stream_with_policy::<Whoami>("/local/w ho", EchoCaller, policy),
+        ];
+        for handle in handles {
+            let result = handle.registered().await;
+            assert!(
+                matches!(result, Err(Error::GsbBadRequest(_))),
+                "{:?}",
+                result
+            );
+        }
+        let mut aliases = router.bind_aliases::<Whoami>(&["local/who"], EchoCaller);
+        let result = aliases.registered().await;
+        assert!(
+            matches!(result, Err(Error::GsbBadRequest(_))),
+            "{:?}",
+            result
+        );
+        assert_eq!(router.handlers.len(), 0);
+    }
+
+    static LOOP_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Streams replies of the same call made to itself.
+    struct StreamLoop(&'static str);
+
+    impl RpcStreamHandler<Whoami> for StreamLoop {
+        type Result = stream::LocalBoxStream<'static, Result<String, ()>>;
+
+        fn handle(&mut self, _caller: &str, msg: Whoami) -> Self::Result {
+            LOOP_CALLS.fetch_add(1, Ordering::SeqCst);
+            let inner = router().lock().unwrap().streaming_forward(self.0, msg);
+            inner
+                .filter_map(|item| {
+                    future::ready(match item {
+                        Ok(StreamItem::Item(item)) => Some(item),
+                        Ok(StreamItem::Complete) => None,
+                        Err(_) => Some(Err(())),
+                    })
+                })
+                .boxed_local()
+        }
+    }
+
+    impl RpcTryStreamHandler<Whoami> for StreamLoop {
+        type Result = stream::LocalBoxStream<'static, Result<Result<String, ()>, Error>>;
+
+        fn handle(&mut self, _caller: &str, msg: Whoami) -> Self::Result {
+            LOOP_CALLS.fetch_add(1, Ordering::SeqCst);
+            let inner = router().lock().unwrap().streaming_forward(self.0, msg);
+            inner
+                .try_filter_map(|item| future::ok(item.into_item()))
+                .boxed_local()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_streaming_loop_stopped() {
+        router().lock().unwrap().set_max_hops(Some(3));
+        let _stream = router()
+            .lock()
+            .unwrap()
+            .bind_stream::<Whoami>("/local/stream-loop", StreamLoop("/local/stream-loop"));
+        let _try_stream = router().lock().unwrap().bind_try_stream::<Whoami>(
+            "/local/try-stream-loop",
+            StreamLoop("/local/try-stream-loop"),
+        );
+
+        let call = router()
+            .lock()
+            .unwrap()
+            .streaming_forward("/local/stream-loop", Whoami(0));
+        let items: Vec<_> = tokio::time::timeout(Duration::from_secs(5), call.collect())
+            .await
+            .expect("loop not stopped");
+        assert!(
+            matches!(
+                &items[..],
+                [Ok(StreamItem::Item(Err(()))), Ok(StreamItem::Complete)]
+            ),
+            "{:?}",
+            items
+        );
+        assert_eq!(LOOP_CALLS.swap(0, Ordering::SeqCst), 3);
+
+        let call = router()
+            .lock()
+            .unwrap()
+            .streaming_forward("/local/try-stream-loop", Whoami(0));
+        let items: Vec<_> = tokio::time::timeout(Duration::from_secs(5), call.collect())
+            .await
+            .expect("loop not stopped");
+        assert!(
+            matches!(&items[..], [Err(Error::GsbFailure(_))]),
+            "{:?}",
+            items
+        );
+        assert_eq!(LOOP_CALLS.swap(0, Ordering::SeqCst), 3);
+        router().lock().unwrap().set_max_hops(None);
+    }
+
+    #[actix_rt::test]
+    async fn test_bind_aliases() {
+        let bound = |addr: &str| {
+            router()
+                .lock()
+                .unwrap()
+                .handlers
+                .contains_key(&format!("{}/Whoami", addr))
+        };
+
+        let handle = router()
+            .lock()
+            .unwrap()
+            .bind_aliases::<Whoami>(&["/local/alias/a", "/local/alias/b"], EchoCaller);
+        for addr in ["/local/alias/a", "/local/alias/b"] {
+            let reply = router()
+                .lock()
+                .unwrap()
+                .forward_from(addr, "alice", Whoami(0));
+            assert_eq!(reply.await.unwrap(), Ok("alice".to_string()));
+        }
+        drop(handle);
+        assert!(!bound("/local/alias/a") && !bound("/local/alias/b"));
+
+        // one invalid alias and none is bound
+        let mut handle = router()
+            .lock()
+            .unwrap()
+            .bind_aliases::<Whoami>(&["/local/alias/c", "/local//c"], EchoCaller);
+        assert!(handle.registered().await.is_err());
+        assert!(handle.addrs().is_empty());
+        assert!(!bound("/local/alias/c"));
+
+        // alias bound again by someone else is left in place
+        let handle = router()
+            .lock()
+            .unwrap()
+            .bind_aliases::<Whoami>(&["/local/alias/d"], EchoCaller);
+        let _rebound = router()
+            .lock()
+            .unwrap()
+            .rebind::<Whoami>("/local/alias/d", EchoCaller);
+        drop(handle);
+        assert!(bound("/local/alias/d"));
+
+        // dropped while the router is still locked
+        let _ = router()
+            .lock()
+            .unwrap()
+            .bind_aliases::<Whoami>(&["/local/alias/e"], EchoCaller);
+        for _ in 0..100 {
+            if !bound("/local/alias/e") {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("alias dropped under the router lock stayed bound");
+    }
+
+    #[actix_rt::test]
+    async fn test_lazy_handler() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        tokio::time::pause();
+        let created = Arc::new(AtomicUsize::new(0));
+        let counter = created.clone();
+        let mut router = Router::new();
+        let _handle = router.bind_lazy_with_idle_ttl::<Whoami, _>(
+            "/local/lazy",
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                EchoCaller
+            },
+            Some(Duration::from_secs(10)),
+        );
+        assert_eq!(created.load(Ordering::SeqCst), 0);
+
+        for _ in 0..2 {
+            let reply = router
+                .forward("/local/lazy", RpcEnvelope::with_caller("alice", Whoami(0)))
+                .await;
+            assert_eq!(reply.unwrap(), Ok("alice".to_string()));
+        }
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        let reply = router
+            .forward("/local/lazy", RpcEnvelope::with_caller("bob", Whoami(0)))
+            .await;
+        assert_eq!(reply.unwrap(), Ok("bob".to_string()));
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+    }
+
+    struct FailMidStream;
+
+    impl RpcTryStreamHandler<Whoami> for FailMidStream {
+        type Result = stream::Iter<std::vec::IntoIter<Result<Result<String, ()>, Error>>>;
+
+        fn handle(&mut self, _caller: &str, _msg: Whoami) -> Self::Result {
+            stream::iter(vec![
+                Ok(Ok("a".to_string())),
+                Ok(Err(())),
+                Err(Error::GsbFailure("fatal".into())),
+                Ok(Ok("never".to_string())),
+            ])
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_stream_fatal_error() {
+        let mut router = Router::new();
+        let _stream = router.bind_try_stream::<Whoami>("/local/fail", FailMidStream);
+
+        let items: Vec<_> = router
+            .streaming_forward("/local/fail", Whoami(0))
+            .collect()
+            .await;
+        assert_eq!(items.len(), 3, "{:?}", items);
+        assert!(matches!(&items[0], Ok(StreamItem::Item(Ok(a))) if a == "a"));
+        assert!(matches!(&items[1], Ok(StreamItem::Item(Err(())))));
+        assert!(matches!(&items[2], Err(Error::GsbFailure(e)) if e == "fatal"));
+
+        let body = crate::serialization::to_vec(&Whoami(0)).unwrap();
+        let chunks: Vec<_> = router
+            .forward_bytes_local(
+                "/local/fail/WhoamiStream",
+                "alice",
+                body.into(),
+                false,
+                None,
+                Default::default(),
+            )
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 3, "{:?}", chunks);
+        assert!(matches!(&chunks[2], Err(Error::GsbFailure(e)) if e == "fatal"));
+    }
+
+    #[actix_rt::test]
+    async fn test_deadline_follows_paused_clock() {
+        tokio::time::pause();
+        let msg = RpcRawCall::new("caller", "/local/svc/Ping", vec![], false)
+            .with_deadline(crate::timeout::now() + Duration::from_secs(5));
+        assert!(!msg.is_expired());
+
+        tokio::time::advance(Duration::from_secs(6)).await;
+        assert!(msg.is_expired());
+    }
+}
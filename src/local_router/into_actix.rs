@@ -1,6 +1,9 @@
+use actix::dev::{MessageResponse, OneshotSender};
 use actix::prelude::*;
-use futures::SinkExt;
+use futures::future::{self, LocalBoxFuture};
+use futures::{FutureExt, SinkExt};
 use std::marker::PhantomData;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 
 use crate::*;
 
@@ -18,13 +21,52 @@ impl<T, H> RpcHandlerWrapper<T, H> {
     }
 }
 
+/// Reply of typed handler, dropped when the handler panics. The caller
+/// fails while the wrapper keeps serving later calls.
+pub struct CatchUnwind<I>(LocalBoxFuture<'static, std::thread::Result<I>>);
+
+impl<A, M> MessageResponse<A, M> for CatchUnwind<M::Result>
+where
+    A: Actor,
+    A::Context: AsyncContext<A>,
+    M: Message,
+    M::Result: 'static,
+{
+    fn handle(self, ctx: &mut A::Context, tx: Option<OneshotSender<M::Result>>) {
+        ctx.spawn(fut::wrap_future(self.0.map(move |reply| match reply {
+            Ok(reply) => {
+                if let Some(tx) = tx {
+                    let _ = tx.send(reply);
+                }
+            }
+            Err(panic) => {
+                let _ = Error::from_panic(panic);
+            }
+        })));
+    }
+}
+
 impl<T: RpcMessage, H: RpcHandler<T> + 'static> Handler<RpcEnvelope<T>>
     for RpcHandlerWrapper<T, H>
 {
-    type Result = ActorResponse<Self, Result<T::Item, T::Error>>;
+    type Result = CatchUnwind<Result<T::Item, T::Error>>;
 
     fn handle(&mut self, msg: RpcEnvelope<T>, _ctx: &mut Self::Context) -> Self::Result {
-        ActorResponse::r#async(self.0.handle(msg.caller, msg.body).into_actor(self))
+        let hops = msg.hops;
+        let id = msg.request_id;
+        let handler = &mut self.0;
+        let reply = catch_unwind(AssertUnwindSafe(|| {
+            request_id::sync_scope(id.clone(), || {
+                hops::sync_scope(hops, || handler.handle(msg.caller, msg.body))
+            })
+        }));
+        CatchUnwind(match reply {
+            Ok(reply) => Box::pin(request_id::scope(
+                id,
+                hops::scope(hops, AssertUnwindSafe(reply).catch_unwind()),
+            )),
+            Err(panic) => Box::pin(future::err(panic)),
+        })
     }
 }
 
@@ -51,8 +93,78 @@ impl<T: RpcStreamMessage, H: RpcStreamHandler<T> + 'static> Handler<RpcStreamCal
         use futures::stream::StreamExt;
 
         let mut reply = msg.reply.sink_map_err(|e| Error::GsbFailure(e.to_string()));
-        let mut result = self.0.handle(&msg.caller, msg.body).map(Ok);
-        let send_all = async move { reply.send_all(&mut result).await };
+        let hops = msg.hops;
+        let id = msg.request_id;
+        let handler = &mut self.0;
+        let result = match catch_unwind(AssertUnwindSafe(|| {
+            request_id::sync_scope(id.clone(), || {
+                hops::sync_scope(hops, || handler.handle(&msg.caller, msg.body))
+            })
+        })) {
+            Ok(result) => result,
+            Err(panic) => return ActorResponse::reply(Err(Error::from_panic(panic))),
+        };
+        // panic ends the stream with failure of the call
+        let send_all = request_id::scope(
+            id,
+            hops::scope(hops, async move {
+                let mut result = AssertUnwindSafe(result).catch_unwind();
+                while let Some(item) = result.next().await {
+                    reply.send(item.map_err(Error::from_panic)?).await?;
+                }
+                Ok(())
+            }),
+        );
+
+        ActorResponse::r#async(send_all.into_actor(self))
+    }
+}
+
+pub struct RpcTryStreamHandlerWrapper<T, H>(pub(super) H, PhantomData<T>);
+
+impl<T, H> RpcTryStreamHandlerWrapper<T, H> {
+    pub fn new(h: H) -> Self {
+        RpcTryStreamHandlerWrapper(h, PhantomData)
+    }
+}
+
+impl<T: 'static, H: 'static> Actor for RpcTryStreamHandlerWrapper<T, H> {
+    type Context = Context<Self>;
+}
+
+impl<T: 'static, H: 'static> Unpin for RpcTryStreamHandlerWrapper<T, H> {}
+
+impl<T: RpcStreamMessage, H: RpcTryStreamHandler<T> + 'static> Handler<RpcStreamCall<T>>
+    for RpcTryStreamHandlerWrapper<T, H>
+{
+    type Result = ActorResponse<Self, Result<(), Error>>;
+
+    fn handle(&mut self, msg: RpcStreamCall<T>, _ctx: &mut Self::Context) -> Self::Result {
+        use futures::stream::StreamExt;
+
+        let mut reply = msg.reply.sink_map_err(|e| Error::GsbFailure(e.to_string()));
+        let hops = msg.hops;
+        let id = msg.request_id;
+        let handler = &mut self.0;
+        let result = match catch_unwind(AssertUnwindSafe(|| {
+            request_id::sync_scope(id.clone(), || {
+                hops::sync_scope(hops, || handler.handle(&msg.caller, msg.body))
+            })
+        })) {
+            Ok(result) => result,
+            Err(panic) => return ActorResponse::reply(Err(Error::from_panic(panic))),
+        };
+        // fatal error or panic becomes result of the call, ending the stream
+        let send_all = request_id::scope(
+            id,
+            hops::scope(hops, async move {
+                let mut result = AssertUnwindSafe(result).catch_unwind();
+                while let Some(item) = result.next().await {
+                    reply.send(item.map_err(Error::from_panic)??).await?;
+                }
+                Ok(())
+            }),
+        );
 
         ActorResponse::r#async(send_all.into_actor(self))
     }
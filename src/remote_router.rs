@@ -1,25 +1,83 @@
 use actix::{prelude::*, WrapFuture};
 use futures::{channel::oneshot, future::Either, prelude::*, FutureExt, SinkExt};
 use std::ops::Not;
-use std::{collections::HashSet, time::Duration};
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
 
+use crate::backoff::{Backoff, FixedBackoff};
 use crate::connection::ClientInfo;
+use crate::spawner;
 use crate::{
-    connection::{self, ConnectionRef, LocalRouterHandler, Transport},
+    connection::{self, CallResult, ConnectionRef, LocalRouterHandler, Transport},
     error::ConnectionTimeout,
     Error, RpcRawCall, RpcRawStreamCall,
 };
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
 const RECONNECT_DELAY: Duration = Duration::from_millis(1000);
+/// Longest wait for the router to confirm local services after connect.
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(10);
 
 type RemoteConnection = ConnectionRef<Transport, LocalRouterHandler>;
 
+/// Which queued call fails when calls made while disconnected exceed
+/// `CallQueue::max_len`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum QueueOverflow {
+    /// The call waiting longest fails, the new one is queued.
+    FailOldest,
+    /// The new call fails.
+    FailNewest,
+}
+
+/// Limits calls waiting for connection to the router.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CallQueue {
+    /// Number of calls held while disconnected, `None` for no limit.
+    pub max_len: Option<usize>,
+    pub overflow: QueueOverflow,
+}
+
+impl CallQueue {
+    pub fn bounded(max_len: usize, overflow: QueueOverflow) -> Self {
+        CallQueue {
+            max_len: Some(max_len),
+            overflow,
+        }
+    }
+}
+
+impl Default for CallQueue {
+    fn default() -> Self {
+        CallQueue {
+            max_len: None,
+            overflow: QueueOverflow::FailNewest,
+        }
+    }
+}
+
+/// Connection to the gsb router shared by all local services.
+///
+/// Calls made while disconnected are queued. After reconnect all local
+/// services are registered again first, and only then queued calls are
+/// sent in the order they were made.
 pub struct RemoteRouter {
     client_info: ClientInfo,
     local_bindings: HashSet<String>,
-    pending_calls: Vec<oneshot::Sender<Result<RemoteConnection, ConnectionTimeout>>>,
+    pending_calls: VecDeque<oneshot::Sender<Result<RemoteConnection, Error>>>,
+    call_queue: CallQueue,
     connection: Option<RemoteConnection>,
+    // Local services are registered on `connection`, calls may use it.
+    ready: bool,
+    endpoint: Option<ya_sb_proto::GsbAddr>,
+    reconnect_backoff: Box<dyn Backoff + Send>,
+    reconnect_attempt: u32,
+    gave_up: bool,
+    replay_timeout: Duration,
     shutdown_rx: Option<oneshot::Receiver<()>>,
 }
 
@@ -47,24 +105,23 @@ impl Actor for RemoteRouter {
 
 impl RemoteRouter {
     fn try_connect(&mut self, ctx: &mut <Self as Actor>::Context) {
-        // FIXME: this is `SystemService` and as such cannot get input being initialized
-        // FIXME: but we need to pass gsb_url from yagnad CLI
-        let addr = ya_sb_proto::GsbAddr::default();
+        // `SystemService` cannot get input being initialized, address set
+        // later with `SetEndpoint` takes precedence over the default one
+        let addr = self.endpoint.clone().unwrap_or_default();
         let client_info = self.client_info.clone();
 
         log::info!("trying to connect to: {}", addr);
 
-        let timeout_h = ctx.run_later(CONNECT_TIMEOUT, |act, ctx| {
+        let timeout_addr = addr.clone();
+        let timeout_h = ctx.run_later(CONNECT_TIMEOUT, move |act, ctx| {
             if act.connection.is_none() {
-                act.clean_pending_calls(
-                    Err(ConnectionTimeout(ya_sb_proto::GsbAddr::default())),
-                    ctx,
-                );
+                act.clean_pending_calls(Err(ConnectionTimeout(timeout_addr)), ctx);
                 log::warn!("connection timed out after {:?}", CONNECT_TIMEOUT);
                 ctx.stop();
             }
         });
-        let connect_fut = connection::transport(addr.clone())
+        let codec = connection::ConnectionConfig::default().codec_config();
+        let connect_fut = connection::transport_with_codec(addr.clone(), codec)
             .map_err(move |e| Error::ConnectionFail(addr, e))
             .into_actor(self)
             .then(|transport, act, ctx| {
@@ -74,24 +131,41 @@ impl RemoteRouter {
                 };
                 let connection =
                     connection::connect_with_handler(client_info, transport, act.handler(ctx));
+                // registrations made meanwhile go to the new connection too
                 act.connection = Some(connection.clone());
-                act.clean_pending_calls(Ok(connection.clone()), ctx);
+                let replay = replay_bindings(connection, act.local_bindings.clone());
+                let replay_timeout = act.replay_timeout;
                 Either::Right(
-                    future::try_join_all(
-                        act.local_bindings
-                            .clone()
-                            .into_iter()
-                            .map(move |service_id| connection.bind(service_id)),
-                    )
-                    .and_then(|_| async { Ok(log::debug!("registered all services")) })
-                    .into_actor(act),
+                    tokio::time::timeout(replay_timeout, replay)
+                        .map(move |r| {
+                            r.unwrap_or_else(|_| {
+                                Err(Error::Timeout(format!(
+                                    "registering services took over {:?}",
+                                    replay_timeout
+                                )))
+                            })
+                        })
+                        .into_actor(act),
                 )
             })
-            .then(move |result: Result<(), Error>, _, ctx| {
+            .then(move |result: Result<(), Error>, act, ctx| {
                 ctx.cancel_future(timeout_h);
-                if let Err(e) = result {
-                    log::warn!("routing error: {}", e);
-                    ctx.run_later(RECONNECT_DELAY, |_, ctx| ctx.stop());
+                match (result, act.connection.clone()) {
+                    (Ok(()), Some(connection)) => {
+                        act.reconnect_attempt = 0;
+                        act.ready = true;
+                        act.clean_pending_calls(Ok(connection), ctx);
+                    }
+                    (Ok(()), None) => (),
+                    (Err(e), _) => {
+                        log::warn!("routing error: {}", e);
+                        match act.connection.take() {
+                            // reconnect is scheduled once the connection reports
+                            // it is closed, do not leave it half registered
+                            Some(connection) => connection.close(),
+                            None => act.schedule_reconnect(ctx),
+                        }
+                    }
                 }
                 fut::ready(())
             });
@@ -99,6 +173,24 @@ impl RemoteRouter {
         ctx.spawn(connect_fut);
     }
 
+    /// Restarts the actor after delay given by the backoff, or fails pending
+    /// calls when it gives up.
+    fn schedule_reconnect(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let attempt = self.reconnect_attempt;
+        self.reconnect_attempt = attempt.saturating_add(1);
+        match self.reconnect_backoff.next_delay(attempt) {
+            Some(delay) => {
+                ctx.run_later(delay, |_, ctx| ctx.stop());
+            }
+            None => {
+                log::error!("giving up connecting to gsb after {} attempts", attempt + 1);
+                self.gave_up = true;
+                let addr = self.endpoint.clone().unwrap_or_default();
+                self.clean_pending_calls(Err(ConnectionTimeout(addr)), ctx);
+            }
+        }
+    }
+
     fn clean_pending_calls(
         &mut self,
         connection: Result<ConnectionRef<Transport, LocalRouterHandler>, ConnectionTimeout>,
@@ -109,7 +201,7 @@ impl RemoteRouter {
             self.pending_calls.len()
         );
         for tx in std::mem::take(&mut self.pending_calls) {
-            let connection = connection.clone();
+            let connection = connection.clone().map_err(Error::from);
             let send_fut = async move {
                 let _v = tx.send(connection);
             }
@@ -119,15 +211,37 @@ impl RemoteRouter {
     }
 
     fn connection(&mut self) -> impl Future<Output = Result<RemoteConnection, Error>> + 'static {
-        if let Some(c) = &self.connection {
-            return future::ok((*c).clone()).left_future();
+        match &self.connection {
+            Some(c) if self.ready => return future::ok((*c).clone()).left_future(),
+            _ => (),
+        }
+        if self.gave_up {
+            let addr = self.endpoint.clone().unwrap_or_default();
+            return future::err(ConnectionTimeout(addr).into()).left_future();
+        }
+        if let Some(max_len) = self.call_queue.max_len {
+            if self.pending_calls.len() >= max_len {
+                let err = || Error::GsbFailure("gsb call queue full while reconnecting".into());
+                log::warn!(
+                    "{} calls waiting for gsb connection",
+                    self.pending_calls.len()
+                );
+                match self.call_queue.overflow {
+                    QueueOverflow::FailNewest => return future::err(err()).left_future(),
+                    QueueOverflow::FailOldest => {
+                        if let Some(tx) = self.pending_calls.pop_front() {
+                            let _ = tx.send(Err(err()));
+                        }
+                    }
+                }
+            }
         }
         log::debug!("wait for connection");
         let (tx, rx) = oneshot::channel();
-        self.pending_calls.push(tx);
+        self.pending_calls.push_back(tx);
         rx.map(|r| match r {
             Err(_) => Err(Error::Cancelled),
-            Ok(c) => c.map_err(From::from),
+            Ok(c) => c,
         })
         .right_future()
     }
@@ -140,9 +254,11 @@ impl RemoteRouter {
                 if let Some(c) = this.connection.as_ref() {
                     c.connected().not().then(|| log::warn!("connection lost"));
                 }
+                // calls wait for reconnect instead of failing on closed connection
+                this.ready = false;
 
                 // restarts the actor
-                ctx.run_later(RECONNECT_DELAY, |_, ctx| ctx.stop());
+                this.schedule_reconnect(ctx);
             })
             .spawn(ctx);
 
@@ -152,12 +268,43 @@ impl RemoteRouter {
     }
 }
 
+/// Registers retained local services on fresh connection.
+///
+/// Router may still hold our registrations from before reconnect, such
+/// conflicts are not treated as failure.
+async fn replay_bindings(
+    connection: RemoteConnection,
+    services: HashSet<String>,
+) -> Result<(), Error> {
+    let count = services.len();
+    let results = connection.bind_all(services).await;
+
+    for (service_id, result) in results {
+        match result {
+            Ok(()) => (),
+            Err(Error::GsbAlreadyRegistered(m)) => {
+                log::warn!("service '{}' already registered: {}", service_id, m)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    log::debug!("registered all {} services", count);
+    Ok(())
+}
+
 impl Default for RemoteRouter {
     fn default() -> Self {
         Self {
             connection: Default::default(),
+            endpoint: Default::default(),
+            reconnect_backoff: Box::new(FixedBackoff::new(RECONNECT_DELAY)),
+            reconnect_attempt: 0,
+            gave_up: false,
+            replay_timeout: REPLAY_TIMEOUT,
             local_bindings: Default::default(),
             pending_calls: Default::default(),
+            call_queue: Default::default(),
+            ready: false,
             client_info: ClientInfo::new("sb-client"),
             shutdown_rx: Default::default(),
         }
@@ -167,6 +314,7 @@ impl Default for RemoteRouter {
 impl Supervised for RemoteRouter {
     fn restarting(&mut self, _ctx: &mut Self::Context) {
         let _ = self.connection.take();
+        self.ready = false;
     }
 }
 
@@ -188,7 +336,7 @@ impl Handler<UpdateService> for RemoteRouter {
         match msg {
             UpdateService::Add(service_id) => {
                 if let Some(c) = &mut self.connection {
-                    Arbiter::current().spawn(c.bind(service_id.clone()).then(|v| async {
+                    spawner::spawn(c.bind(service_id.clone()).then(|v| async {
                         v.unwrap_or_else(|err| match err {
                             Error::GsbAlreadyRegistered(m) => {
                                 log::warn!("already registered: {}", m)
@@ -202,7 +350,7 @@ impl Handler<UpdateService> for RemoteRouter {
             }
             UpdateService::Remove(service_id) => {
                 if let Some(c) = &mut self.connection {
-                    Arbiter::current().spawn(c.unbind(service_id.clone()).then(|v| async {
+                    spawner::spawn(c.unbind(service_id.clone()).then(|v| async {
                         v.unwrap_or_else(|e| log::error!("unbind error: {}", e))
                     }));
                 }
@@ -214,6 +362,113 @@ impl Handler<UpdateService> for RemoteRouter {
     }
 }
 
+/// Removes local service from the router, reporting the router's answer.
+///
+/// Unlike `UpdateService::Remove` it waits for the unregistration. Service
+/// is forgotten locally even when it fails, so it is not registered again
+/// on reconnect.
+pub struct RemoveService(pub String);
+
+impl Message for RemoveService {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<RemoveService> for RemoteRouter {
+    type Result = ActorResponse<Self, Result<(), Error>>;
+
+    fn handle(&mut self, msg: RemoveService, _ctx: &mut Self::Context) -> Self::Result {
+        log::trace!("Unbinding local service '{}'", msg.0);
+        self.local_bindings.remove(&msg.0);
+        match &self.connection {
+            Some(c) => ActorResponse::r#async(c.unbind(msg.0).into_actor(self)),
+            None => ActorResponse::reply(Ok(())),
+        }
+    }
+}
+
+/// Repoints the router to new address.
+///
+/// Current connection is closed and a new one is made to given address, with local
+/// services registered again. Calls made meanwhile wait for it.
+pub struct SetEndpoint(pub ya_sb_proto::GsbAddr);
+
+impl Message for SetEndpoint {
+    type Result = ();
+}
+
+impl Handler<SetEndpoint> for RemoteRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetEndpoint, ctx: &mut Self::Context) -> Self::Result {
+        if self.endpoint.as_ref() == Some(&msg.0) {
+            return;
+        }
+        log::info!("gsb endpoint changed to: {}", msg.0);
+        self.endpoint = Some(msg.0);
+        self.reconnect_attempt = 0;
+        self.gave_up = false;
+        self.ready = false;
+        if let Some(c) = self.connection.take() {
+            c.close();
+        }
+        // restarts the actor, which connects to the new address
+        ctx.stop();
+    }
+}
+
+/// Replaces delays between reconnect attempts, by default 1s forever.
+///
+/// Once the backoff gives up, calls fail until the endpoint is changed.
+pub struct SetReconnectBackoff(pub Box<dyn Backoff + Send>);
+
+impl Message for SetReconnectBackoff {
+    type Result = ();
+}
+
+impl Handler<SetReconnectBackoff> for RemoteRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetReconnectBackoff, _ctx: &mut Self::Context) -> Self::Result {
+        self.reconnect_backoff = msg.0;
+        self.reconnect_attempt = 0;
+    }
+}
+
+/// Raw call whose reply comes with its trailers, see
+/// `ConnectionRef::call_with_trailers`.
+pub(crate) struct CallWithTrailers(pub RpcRawCall);
+
+impl Message for CallWithTrailers {
+    type Result = Result<CallResult, Error>;
+}
+
+impl Handler<CallWithTrailers> for RemoteRouter {
+    type Result = ActorResponse<Self, Result<CallResult, Error>>;
+
+    fn handle(&mut self, msg: CallWithTrailers, _ctx: &mut Self::Context) -> Self::Result {
+        ActorResponse::r#async(
+            self.connection()
+                .and_then(move |connection| connection.call_with_trailers(msg.0))
+                .into_actor(self),
+        )
+    }
+}
+
+/// Replaces limit of calls waiting for connection, unbounded by default.
+pub struct SetCallQueue(pub CallQueue);
+
+impl Message for SetCallQueue {
+    type Result = ();
+}
+
+impl Handler<SetCallQueue> for RemoteRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetCallQueue, _ctx: &mut Self::Context) -> Self::Result {
+        self.call_queue = msg.0;
+    }
+}
+
 impl Handler<RpcRawCall> for RemoteRouter {
     type Result = ActorResponse<Self, Result<Vec<u8>, Error>>;
 
@@ -224,9 +479,7 @@ impl Handler<RpcRawCall> for RemoteRouter {
 
         ActorResponse::r#async(
             self.connection()
-                .and_then(move |connection| {
-                    connection.call(msg.caller, msg.addr, msg.body, msg.no_reply)
-                })
+                .and_then(move |connection| connection.call_raw(msg))
                 .into_actor(self),
         )
     }
@@ -240,29 +493,217 @@ impl Handler<RpcRawStreamCall> for RemoteRouter {
         let fut = async move {
             let connection = match conn.await {
                 Ok(c) => c,
-                Err(e) => return log::error!("Remote router connection error: {}", e),
+                Err(e) => {
+                    log::error!("Remote router connection error: {}", e);
+                    let _ = msg.reply.clone().send(Err(e)).await;
+                    return;
+                }
             };
 
-            let reply = msg.reply.sink_map_err(|e| Error::GsbFailure(e.to_string()));
-            futures::pin_mut!(reply);
-
             ya_packet_trace::packet_trace_maybe!("RemoteRouter::Handler<RpcRawStreamCall>", {
                 &{ &ya_packet_trace::try_extract_from_ip_frame(&msg.body) }
             });
 
-            let result = SinkExt::send_all(
-                &mut reply,
-                &mut connection
-                    .call_streaming(msg.caller, msg.addr, msg.body)
-                    .map(|v| Ok(v)),
-            )
-            .await;
-
-            if let Err(e) = result {
+            // window and headers are passed to the connection as they are
+            let mut reply = msg.reply.clone();
+            if let Err(e) = connection.call_streaming_raw(msg).await {
                 log::error!("Remote router RpcRawStreamCall handler error: {}", e);
+                let _ = reply.send(Err(e)).await;
             }
         };
         ctx.spawn(fut.into_actor(self));
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_util::codec::Framed;
+    use ya_sb_proto::codec::{GsbMessage, GsbMessageCodec};
+
+    type Peer = Framed<tokio::net::TcpStream, GsbMessageCodec>;
+
+    /// Starts router actor with `services` bound, connecting to returned peer.
+    async fn start_with_services(
+        services: &[&str],
+        replay_timeout: Duration,
+    ) -> (Addr<RemoteRouter>, Peer) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let router = RemoteRouter {
+            endpoint: Some(ya_sb_proto::GsbAddr::Tcp(addr)),
+            local_bindings: services.iter().map(|s| s.to_string()).collect(),
+            replay_timeout,
+            ..Default::default()
+        }
+        .start();
+        (router, accept_peer(&listener).await)
+    }
+
+    /// Accepts connection from the router actor and answers its hello.
+    async fn accept_peer(listener: &tokio::net::TcpListener) -> Peer {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut peer = Framed::new(stream, GsbMessageCodec::default());
+        match peer.next().await {
+            Some(Ok(GsbMessage::Hello(_))) => (),
+            msg => panic!("expected hello, got {:?}", msg.map(|m| m.map(|m| m.kind()))),
+        }
+        peer.send(GsbMessage::Hello(ya_sb_proto::Hello {
+            proto_version: ya_sb_proto::PROTO_VERSION,
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        peer
+    }
+
+    /// Answers next call coming from the router with `data`.
+    async fn reply_to_call(peer: &mut Peer, data: &[u8]) {
+        let request = match next_message(peer).await {
+            Some(GsbMessage::CallRequest(r)) => r,
+            msg => panic!("expected call, got {:?}", msg.map(|m| m.kind())),
+        };
+        peer.send(GsbMessage::CallReply(ya_sb_proto::CallReply {
+            request_id: request.request_id,
+            code: ya_sb_proto::CallReplyCode::CallReplyOk as i32,
+            reply_type: ya_sb_proto::CallReplyType::Full as i32,
+            data: data.to_vec(),
+            trailers: Default::default(),
+        }))
+        .await
+        .unwrap();
+    }
+
+    async fn next_message(peer: &mut Peer) -> Option<GsbMessage> {
+        loop {
+            match peer.next().await {
+                Some(Ok(GsbMessage::Ping(_))) => continue,
+                Some(Ok(msg)) => return Some(msg),
+                _ => return None,
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_calls_wait_for_replayed_bindings() {
+        let (router, mut peer) = start_with_services(&["/local/svc"], REPLAY_TIMEOUT).await;
+        let call = router.send(RpcRawCall::new("me", "/remote/svc/Echo", Vec::new(), false));
+        let call = tokio::task::spawn_local(call);
+
+        let register = match next_message(&mut peer).await {
+            Some(GsbMessage::RegisterRequest(r)) => r,
+            msg => panic!("expected register, got {:?}", msg.map(|m| m.kind())),
+        };
+        assert_eq!(register.service_id, "/local/svc");
+        // call is held until the router confirms the registration
+        let early = tokio::time::timeout(Duration::from_millis(200), next_message(&mut peer)).await;
+        assert!(early.is_err(), "{:?}", early.map(|m| m.map(|m| m.kind())));
+
+        peer.send(GsbMessage::RegisterReply(ya_sb_proto::RegisterReply {
+            code: ya_sb_proto::RegisterReplyCode::RegisteredOk as i32,
+            message: String::new(),
+        }))
+        .await
+        .unwrap();
+        let request = match next_message(&mut peer).await {
+            Some(GsbMessage::CallRequest(r)) => r,
+            msg => panic!("expected call, got {:?}", msg.map(|m| m.kind())),
+        };
+        assert_eq!(request.address, "/remote/svc/Echo");
+        peer.send(GsbMessage::CallReply(ya_sb_proto::CallReply {
+            request_id: request.request_id,
+            code: ya_sb_proto::CallReplyCode::CallReplyOk as i32,
+            reply_type: ya_sb_proto::CallReplyType::Full as i32,
+            data: b"ok".to_vec(),
+            trailers: Default::default(),
+        }))
+        .await
+        .unwrap();
+        assert_eq!(call.await.unwrap().unwrap().unwrap(), b"ok".to_vec());
+    }
+
+    #[actix_rt::test]
+    async fn test_unanswered_replay_closes_connection() {
+        let (_router, mut peer) =
+            start_with_services(&["/local/svc"], Duration::from_millis(100)).await;
+        assert!(matches!(
+            next_message(&mut peer).await,
+            Some(GsbMessage::RegisterRequest(_))
+        ));
+        let closed = tokio::time::timeout(Duration::from_secs(5), next_message(&mut peer)).await;
+        assert!(
+            matches!(closed, Ok(None)),
+            "{:?}",
+            closed.map(|m| m.map(|m| m.kind()))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_call_reports_instance_fallback() {
+        let (router, mut peer) = start_with_services(&[], REPLAY_TIMEOUT).await;
+        let call = RpcRawCall::new("me", "/remote/svc/Echo", Vec::new(), false)
+            .with_header(crate::TARGET_INSTANCE_HEADER, b"gone".to_vec());
+        let call = tokio::task::spawn_local(router.send(CallWithTrailers(call)));
+
+        let request = match next_message(&mut peer).await {
+            Some(GsbMessage::CallRequest(r)) => r,
+            msg => panic!("expected call, got {:?}", msg.map(|m| m.kind())),
+        };
+        assert_eq!(
+            request.headers.get(crate::TARGET_INSTANCE_HEADER),
+            Some(&b"gone".to_vec())
+        );
+        // router routed the call elsewhere and says so in trailers
+        peer.send(GsbMessage::CallReply(ya_sb_proto::CallReply {
+            request_id: request.request_id,
+            code: ya_sb_proto::CallReplyCode::CallReplyOk as i32,
+            reply_type: ya_sb_proto::CallReplyType::Full as i32,
+            data: b"ok".to_vec(),
+            trailers: std::iter::once((crate::INSTANCE_FALLBACK_HEADER.to_string(), vec![1]))
+                .collect(),
+        }))
+        .await
+        .unwrap();
+        let result = call.await.unwrap().unwrap().unwrap();
+        assert_eq!(result.body, b"ok");
+        assert!(result
+            .trailers
+            .contains_key(crate::INSTANCE_FALLBACK_HEADER));
+    }
+
+    #[actix_rt::test]
+    async fn test_set_endpoint_moves_connection() {
+        let first = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let first_addr = ya_sb_proto::GsbAddr::Tcp(first.local_addr().unwrap().to_string());
+        let second_addr = ya_sb_proto::GsbAddr::Tcp(second.local_addr().unwrap().to_string());
+        let endpoint = Some(first_addr.clone());
+        // supervised, as from registry, so stopping restarts it
+        let router = Supervisor::start(move |_| RemoteRouter {
+            endpoint,
+            ..Default::default()
+        });
+        let mut peer = accept_peer(&first).await;
+        let call = router.send(RpcRawCall::new("me", "/remote/svc/Echo", Vec::new(), false));
+        let call = tokio::task::spawn_local(call);
+        reply_to_call(&mut peer, b"first").await;
+        assert_eq!(call.await.unwrap().unwrap().unwrap(), b"first".to_vec());
+
+        // same endpoint keeps the connection
+        router.send(SetEndpoint(first_addr)).await.unwrap();
+        let call = router.send(RpcRawCall::new("me", "/remote/svc/Echo", Vec::new(), false));
+        let call = tokio::task::spawn_local(call);
+        reply_to_call(&mut peer, b"again").await;
+        assert_eq!(call.await.unwrap().unwrap().unwrap(), b"again".to_vec());
+
+        router.send(SetEndpoint(second_addr)).await.unwrap();
+        // old connection is closed, calls go to the new endpoint
+        assert!(next_message(&mut peer).await.is_none());
+        let mut peer = accept_peer(&second).await;
+        let call = router.send(RpcRawCall::new("me", "/remote/svc/Echo", Vec::new(), false));
+        let call = tokio::task::spawn_local(call);
+        reply_to_call(&mut peer, b"second").await;
+        assert_eq!(call.await.unwrap().unwrap().unwrap(), b"second".to_vec());
+    }
+}
@@ -0,0 +1,34 @@
+//! Id of the call received from the router, made available to handlers.
+use std::future::Future;
+
+use crate::Headers;
+
+/// Header with `request_id` of the call received from the router, set on
+/// calls passed to local handlers. It is not sent further.
+pub const REQUEST_ID_HEADER: &str = "gsb-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: Option<String>;
+}
+
+/// Id of the call received from the router that is currently handled.
+///
+/// Available to typed handlers while they run, `None` for local calls and
+/// outside of any handler.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok().flatten()
+}
+
+pub(crate) fn scope<F: Future>(id: Option<String>, f: F) -> impl Future<Output = F::Output> {
+    REQUEST_ID.scope(id, f)
+}
+
+pub(crate) fn sync_scope<R>(id: Option<String>, f: impl FnOnce() -> R) -> R {
+    REQUEST_ID.sync_scope(id, f)
+}
+
+pub(crate) fn from_headers(headers: &Headers) -> Option<String> {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| String::from_utf8(v.clone()).ok())
+}
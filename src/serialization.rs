@@ -1,96 +1,264 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-
-#[cfg(feature = "flex")]
-pub use flex::{DecodeError, EncodeError};
-#[cfg(feature = "json")]
-pub use json::{DecodeError, EncodeError};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
 lazy_static::lazy_static! {
     pub static ref CONFIG: Config = Config::default();
 }
 
-#[derive(Default)]
+/// Serialization format of message bodies, one of those enabled with the
+/// `flex`, `json` and `cbor` features.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format {
+    #[cfg(feature = "flex")]
+    Flex,
+    #[cfg(feature = "json")]
+    Json,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+#[cfg(feature = "flex")]
+const DEFAULT_FORMAT: Format = Format::Flex;
+#[cfg(all(feature = "json", not(feature = "flex")))]
+const DEFAULT_FORMAT: Format = Format::Json;
+#[cfg(all(feature = "cbor", not(any(feature = "flex", feature = "json"))))]
+const DEFAULT_FORMAT: Format = Format::Cbor;
+
+impl Default for Format {
+    fn default() -> Self {
+        DEFAULT_FORMAT
+    }
+}
+
+impl Format {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            #[cfg(feature = "flex")]
+            v if v == Format::Flex as u8 => Format::Flex,
+            #[cfg(feature = "json")]
+            v if v == Format::Json as u8 => Format::Json,
+            #[cfg(feature = "cbor")]
+            v if v == Format::Cbor as u8 => Format::Cbor,
+            _ => Format::default(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct DecodeError(Box<dyn std::error::Error + Send + Sync>);
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct EncodeError(Box<dyn std::error::Error + Send + Sync>);
+
 pub struct Config {
     compress: AtomicBool,
+    format: AtomicU8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            compress: AtomicBool::new(false),
+            format: AtomicU8::new(Format::default() as u8),
+        }
+    }
 }
 
 impl Config {
     pub fn set_compress(&self, val: bool) {
         self.compress.store(val, Ordering::SeqCst);
     }
+
+    /// Sets format of bodies encoded from now on and expected in received
+    /// ones, peers have to use the same.
+    ///
+    /// The format is process wide, typed calls encode bodies before it is
+    /// known which connection carries them.
+    pub fn set_format(&self, format: Format) {
+        self.format.store(format as u8, Ordering::SeqCst);
+    }
+
+    pub fn format(&self) -> Format {
+        Format::from_u8(self.format.load(Ordering::SeqCst))
+    }
 }
 
 pub fn to_vec<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, EncodeError> {
-    #[cfg(feature = "flex")]
-    use flex::to_vec;
-    #[cfg(feature = "json")]
-    use json::to_vec;
+    to_vec_as(CONFIG.format(), value)
+}
 
-    to_vec(value).map(|vec| {
-        if CONFIG.compress.load(Ordering::SeqCst) {
-            miniz_oxide::deflate::compress_to_vec_zlib(vec.as_slice(), 6)
-        } else {
-            vec
-        }
+/// Like `to_vec`, in given format instead of the configured one.
+pub fn to_vec_as<T: serde::Serialize>(format: Format, value: &T) -> Result<Vec<u8>, EncodeError> {
+    let vec = match format {
+        #[cfg(feature = "flex")]
+        Format::Flex => flex::to_vec(value),
+        #[cfg(feature = "json")]
+        Format::Json => json::to_vec(value),
+        #[cfg(feature = "cbor")]
+        Format::Cbor => cbor::to_vec(value),
+    }?;
+    Ok(if CONFIG.compress.load(Ordering::SeqCst) {
+        miniz_oxide::deflate::compress_to_vec_zlib(vec.as_slice(), 6)
+    } else {
+        vec
     })
 }
 
 pub fn from_slice<T: serde::de::DeserializeOwned>(slice: &[u8]) -> Result<T, DecodeError> {
-    #[cfg(feature = "flex")]
-    use flex::from_slice;
-    #[cfg(feature = "json")]
-    use json::from_slice;
+    from_slice_as(CONFIG.format(), slice)
+}
 
+/// Like `from_slice`, in given format instead of the configured one.
+pub fn from_slice_as<T: serde::de::DeserializeOwned>(
+    format: Format,
+    slice: &[u8],
+) -> Result<T, DecodeError> {
+    let from_slice = match format {
+        #[cfg(feature = "flex")]
+        Format::Flex => flex::from_slice,
+        #[cfg(feature = "json")]
+        Format::Json => json::from_slice,
+        #[cfg(feature = "cbor")]
+        Format::Cbor => cbor::from_slice,
+    };
     match miniz_oxide::inflate::decompress_to_vec_zlib(slice) {
         Ok(vec) => from_slice(vec.as_slice()),
         Err(_) => from_slice(slice),
     }
 }
 
-#[allow(dead_code)]
 #[cfg(feature = "flex")]
 mod flex {
-    use flexbuffers::{DeserializationError, SerializationError};
-
-    #[derive(Debug, thiserror::Error)]
-    #[error("{0}")]
-    pub struct DecodeError(DeserializationError);
-
-    #[derive(Debug, thiserror::Error)]
-    #[error("{0}")]
-    pub struct EncodeError(SerializationError);
+    use super::{DecodeError, EncodeError};
 
     #[inline]
     pub fn to_vec<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, EncodeError> {
-        flexbuffers::to_vec(value).map_err(EncodeError)
+        flexbuffers::to_vec(value).map_err(|e| EncodeError(e.into()))
     }
 
     #[inline]
     pub fn from_slice<T: serde::de::DeserializeOwned>(slice: &[u8]) -> Result<T, DecodeError> {
-        flexbuffers::from_slice(slice).map_err(DecodeError)
+        flexbuffers::from_slice(slice).map_err(|e| DecodeError(e.into()))
     }
 }
 
-#[allow(dead_code)]
 #[cfg(feature = "json")]
 mod json {
-    use serde_json::Error;
+    use super::{DecodeError, EncodeError};
+
+    #[inline]
+    pub fn to_vec<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, EncodeError> {
+        serde_json::to_vec(value).map_err(|e| EncodeError(e.into()))
+    }
 
-    #[derive(Debug, thiserror::Error)]
-    #[error("{0}")]
-    pub struct DecodeError(Error);
+    #[inline]
+    pub fn from_slice<T: serde::de::DeserializeOwned>(slice: &[u8]) -> Result<T, DecodeError> {
+        serde_json::from_slice(slice).map_err(|e| DecodeError(e.into()))
+    }
+}
 
-    #[derive(Debug, thiserror::Error)]
-    #[error("{0}")]
-    pub struct EncodeError(Error);
+#[cfg(feature = "cbor")]
+mod cbor {
+    use super::{DecodeError, EncodeError};
 
     #[inline]
     pub fn to_vec<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, EncodeError> {
-        serde_json::to_vec(value).map_err(EncodeError)
+        let mut vec = Vec::new();
+        ciborium::ser::into_writer(value, &mut vec).map_err(|e| EncodeError(e.into()))?;
+        Ok(vec)
     }
 
     #[inline]
     pub fn from_slice<T: serde::de::DeserializeOwned>(slice: &[u8]) -> Result<T, DecodeError> {
-        serde_json::from_slice(slice).map_err(DecodeError)
+        ciborium::de::from_reader(slice).map_err(|e| DecodeError(e.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{RpcMessage, RpcStreamMessage};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Echo {
+        msg: String,
+        data: Vec<u8>,
+    }
+
+    impl RpcMessage for Echo {
+        const ID: &'static str = "echo";
+        type Item = String;
+        type Error = String;
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Tail(u32);
+
+    impl RpcStreamMessage for Tail {
+        const ID: &'static str = "tail";
+        type Item = Vec<u64>;
+        type Error = String;
+    }
+
+    fn round_trip<T: serde::Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+        from_slice(&to_vec(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_rpc_message_round_trip() {
+        let body = Echo {
+            msg: "zażółć".into(),
+            data: vec![0, 1, 255],
+        };
+        assert_eq!(round_trip(&body), body);
+
+        let ok: Result<String, String> = Ok("pong".into());
+        let err: Result<String, String> = Err("failed".into());
+        assert_eq!(round_trip(&ok), ok);
+        assert_eq!(round_trip(&err), err);
+    }
+
+    #[test]
+    fn test_stream_message_round_trip() {
+        assert_eq!(round_trip(&Tail(7)), Tail(7));
+
+        let items: Vec<Result<Vec<u64>, String>> =
+            vec![Ok(vec![]), Ok(vec![1, u64::MAX]), Err("eof".into())];
+        for item in items {
+            assert_eq!(round_trip(&item), item);
+        }
+    }
+
+    #[test]
+    // single format with default features
+    #[allow(clippy::single_element_loop)]
+    fn test_format_round_trip() {
+        let body = Echo {
+            msg: "ping".into(),
+            data: vec![7],
+        };
+        for format in [
+            #[cfg(feature = "flex")]
+            Format::Flex,
+            #[cfg(feature = "json")]
+            Format::Json,
+            #[cfg(feature = "cbor")]
+            Format::Cbor,
+        ] {
+            let bytes = to_vec_as(format, &body).unwrap();
+            assert_eq!(from_slice_as::<Echo>(format, &bytes).unwrap(), body);
+            assert_eq!(Format::from_u8(format as u8), format);
+        }
+        assert_eq!(CONFIG.format(), Format::default());
+    }
+
+    #[test]
+    fn test_unit_round_trip() {
+        let unit: Result<(), ()> = Ok(());
+        assert_eq!(round_trip(&unit), unit);
     }
 }
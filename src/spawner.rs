@@ -0,0 +1,146 @@
+//! Executor of background tasks started by the router and connections.
+use actix::{Arbiter, ArbiterHandle};
+use futures::future::BoxFuture;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+type SpawnFn = dyn Fn(BoxFuture<'static, ()>) + Send + Sync;
+
+/// Runs detached tasks of the bus, by default on the current arbiter.
+///
+/// Tasks talk to actix actors, so they have to run within actix system.
+/// Use `Spawner::arbiter` to isolate bus work on a dedicated arbiter.
+#[derive(Clone, Default)]
+pub struct Spawner(Option<Arc<SpawnFn>>);
+
+impl Spawner {
+    /// Spawns on the arbiter current at the time of spawning.
+    pub fn current() -> Self {
+        Spawner(None)
+    }
+
+    /// Spawns on given arbiter.
+    pub fn arbiter(arbiter: ArbiterHandle) -> Self {
+        Self::from_fn(move |task| {
+            if !arbiter.spawn(task) {
+                log::error!("bus arbiter is stopped, task dropped");
+            }
+        })
+    }
+
+    pub fn from_fn(spawn: impl Fn(BoxFuture<'static, ()>) + Send + Sync + 'static) -> Self {
+        Spawner(Some(Arc::new(spawn)))
+    }
+
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        match &self.0 {
+            Some(spawn) => spawn(Box::pin(task)),
+            None => {
+                let _ = Arbiter::current().spawn(task);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Spawner(custom)"),
+            None => f.write_str("Spawner(current)"),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref SPAWNER: RwLock<Spawner> = Default::default();
+}
+
+/// Spawns task with the spawner set by `set_spawner`.
+pub(crate) fn spawn(task: impl Future<Output = ()> + Send + 'static) {
+    let spawner = SPAWNER.read().unwrap().clone();
+    spawner.spawn(task)
+}
+
+pub(crate) fn set(spawner: Spawner) {
+    *SPAWNER.write().unwrap() = spawner;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::{connect_with_config, ClientInfo, LocalRouterHandler};
+    use crate::RpcMessage;
+    use futures::{SinkExt, StreamExt};
+    use std::sync::Mutex;
+    use std::thread::{self, ThreadId};
+    use tokio_util::codec::Framed;
+    use ya_sb_proto::codec::{GsbMessage, GsbMessageCodec};
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Ping;
+
+    impl RpcMessage for Ping {
+        const ID: &'static str = "Ping";
+        type Item = ();
+        type Error = ();
+    }
+
+    #[actix_rt::test]
+    async fn test_tasks_spawned_on_arbiter() {
+        let arbiter = Arbiter::new();
+        let on_arbiter = Spawner::arbiter(arbiter.handle());
+        let test_thread = thread::current().id();
+        let threads: Arc<Mutex<Vec<ThreadId>>> = Default::default();
+        let spawned = threads.clone();
+        crate::untyped::set_spawner(Spawner::from_fn(move |task| {
+            // tasks of tests running in parallel stay on their arbiters
+            if thread::current().id() != test_thread {
+                return Spawner::current().spawn(task);
+            }
+            let spawned = spawned.clone();
+            on_arbiter.spawn(async move {
+                spawned.lock().unwrap().push(thread::current().id());
+                task.await
+            })
+        }));
+
+        // router hands the binding over to the remote router
+        let handle = crate::typed::bind("/local/spawner", |_: Ping| async { Ok(()) });
+        handle.registered().await.unwrap();
+        assert_eq!(threads.lock().unwrap().len(), 1);
+
+        // connection sends streaming calls from a spawned task
+        let (client, router) = tokio::io::duplex(64 * 1024);
+        let connection = connect_with_config(
+            ClientInfo::new("test"),
+            Default::default(),
+            Framed::new(client, GsbMessageCodec::default()),
+            LocalRouterHandler::default(),
+        );
+        let mut peer = Framed::new(router, GsbMessageCodec::default());
+        let mut stream = connection.call_streaming("test", "/remote/stream", Vec::new());
+        let request = loop {
+            match peer.next().await.unwrap().unwrap() {
+                GsbMessage::CallRequest(r) => break r,
+                _ => continue,
+            }
+        };
+        peer.send(GsbMessage::CallReply(ya_sb_proto::CallReply {
+            request_id: request.request_id,
+            code: ya_sb_proto::CallReplyCode::CallReplyOk as i32,
+            reply_type: ya_sb_proto::CallReplyType::Full as i32,
+            data: b"pong".to_vec(),
+            trailers: Default::default(),
+        }))
+        .await
+        .unwrap();
+        assert!(stream.next().await.unwrap().is_ok());
+
+        crate::untyped::set_spawner(Spawner::current());
+        arbiter.stop();
+        let threads = threads.lock().unwrap();
+        assert!(threads.len() >= 2, "{:?}", threads);
+        assert!(threads.iter().all(|id| *id != test_thread), "{:?}", threads);
+    }
+}
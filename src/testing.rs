@@ -0,0 +1,240 @@
+//! Helpers for testing code talking to the bus.
+//!
+//! `MockCallHandler` serves connections with scripted replies, recording
+//! what it received:
+//!
+//! ```no_run
+//! use ya_service_bus::connection::{self, ClientInfo};
+//! use ya_service_bus::testing::MockCallHandler;
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let handler = MockCallHandler::default();
+//! handler.respond("/public/echo", b"pong".to_vec());
+//!
+//! let transport = connection::tcp("127.0.0.1:7464").await?;
+//! let _connection =
+//!     connection::connect_with_handler(ClientInfo::new("test"), transport, handler.clone());
+//! // ... let the router call /public/echo ...
+//! assert_eq!(handler.calls()[0].address, "/public/echo");
+//! # Ok(())
+//! # }
+//! ```
+use futures::stream::{self, LocalBoxStream, StreamExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::connection::{CallContext, CallRequestHandler, DisconnectReason};
+use crate::{Error, ResponseChunk};
+
+/// Broadcast received by `MockCallHandler`.
+#[derive(Clone, Debug)]
+pub struct RecordedEvent {
+    pub caller: String,
+    pub topic: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone)]
+enum MockReply {
+    Full(Vec<u8>),
+    Stream(Vec<Vec<u8>>),
+    Failure(String),
+    BadRequest(String),
+}
+
+#[derive(Default)]
+struct MockState {
+    replies: HashMap<String, MockReply>,
+    calls: Vec<CallContext>,
+    events: Vec<RecordedEvent>,
+    disconnects: Vec<DisconnectReason>,
+}
+
+/// `CallRequestHandler` answering calls with replies scripted per address.
+///
+/// Clones share replies and records, keep one to inspect the handler after
+/// passing another to the connection. Calls to addresses without a reply
+/// fail with `GsbBadRequest`.
+#[derive(Clone, Default)]
+pub struct MockCallHandler {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockCallHandler {
+    /// Answers calls to `address` with `body`.
+    pub fn respond(&self, address: impl Into<String>, body: impl Into<Vec<u8>>) {
+        self.script(address.into(), MockReply::Full(body.into()));
+    }
+
+    /// Answers calls to `address` with `chunks` streamed one by one.
+    pub fn respond_stream(&self, address: impl Into<String>, chunks: Vec<Vec<u8>>) {
+        self.script(address.into(), MockReply::Stream(chunks));
+    }
+
+    /// Fails calls to `address`, the caller gets `ServiceFailure` reply.
+    pub fn fail(&self, address: impl Into<String>, message: impl Into<String>) {
+        self.script(address.into(), MockReply::Failure(message.into()));
+    }
+
+    /// Rejects calls to `address`, the caller gets `CallReplyBadRequest` reply.
+    pub fn reject(&self, address: impl Into<String>, message: impl Into<String>) {
+        self.script(address.into(), MockReply::BadRequest(message.into()));
+    }
+
+    /// Calls received so far, in order of arrival.
+    pub fn calls(&self) -> Vec<CallContext> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Broadcasts received so far, in order of arrival.
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        self.state.lock().unwrap().events.clone()
+    }
+
+    /// Reasons of connection closes reported to the handler.
+    pub fn disconnects(&self) -> Vec<DisconnectReason> {
+        self.state.lock().unwrap().disconnects.clone()
+    }
+
+    fn script(&self, address: String, reply: MockReply) {
+        let _ = self.state.lock().unwrap().replies.insert(address, reply);
+    }
+}
+
+impl CallRequestHandler for MockCallHandler {
+    type Reply = LocalBoxStream<'static, Result<ResponseChunk, Error>>;
+
+    fn do_call(
+        &mut self,
+        request_id: String,
+        caller: String,
+        address: String,
+        data: Vec<u8>,
+        no_reply: bool,
+    ) -> Self::Reply {
+        self.handle_call(CallContext {
+            request_id,
+            caller,
+            address,
+            data,
+            no_reply,
+            deadline: None,
+            headers: Default::default(),
+            trailers: Default::default(),
+        })
+    }
+
+    fn handle_call(&mut self, call: CallContext) -> Self::Reply {
+        let mut state = self.state.lock().unwrap();
+        let reply = state.replies.get(&call.address).cloned();
+        let address = call.address.clone();
+        state.calls.push(call);
+        match reply {
+            Some(MockReply::Full(body)) => {
+                stream::once(async { Ok(ResponseChunk::Full(body)) }).boxed_local()
+            }
+            Some(MockReply::Stream(chunks)) => stream::iter(
+                chunks
+                    .into_iter()
+                    .map(|chunk| Ok(ResponseChunk::Part(chunk)))
+                    .chain(Some(Ok(ResponseChunk::eos()))),
+            )
+            .boxed_local(),
+            Some(MockReply::Failure(message)) => {
+                stream::once(async { Err(Error::GsbFailure(message)) }).boxed_local()
+            }
+            Some(MockReply::BadRequest(message)) => {
+                stream::once(async { Err(Error::GsbBadRequest(message)) }).boxed_local()
+            }
+            None => stream::once(async move {
+                Err(Error::GsbBadRequest(format!(
+                    "no mock reply for {}",
+                    address
+                )))
+            })
+            .boxed_local(),
+        }
+    }
+
+    fn handle_event(&mut self, caller: String, topic: String, data: Vec<u8>) {
+        self.state.lock().unwrap().events.push(RecordedEvent {
+            caller,
+            topic,
+            data,
+        });
+    }
+
+    fn on_disconnect(&mut self, reason: DisconnectReason) {
+        self.state.lock().unwrap().disconnects.push(reason);
+    }
+
+    fn serves(&mut self, address: &str) -> bool {
+        self.state.lock().unwrap().replies.contains_key(address)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use futures::SinkExt;
+    use tokio_util::codec::Framed;
+    use ya_sb_proto::codec::{GsbMessage, GsbMessageCodec};
+    use ya_sb_proto::{CallReply, CallReplyCode, CallRequest};
+
+    use super::*;
+    use crate::connection::{connect_with_handler, ClientInfo};
+
+    type Peer = Framed<tokio::io::DuplexStream, GsbMessageCodec>;
+
+    async fn request(peer: &mut Peer, address: &str, ping: bool) -> CallReply {
+        let headers = match ping {
+            true => std::iter::once((ya_sb_proto::PING_HEADER.to_string(), Vec::new())).collect(),
+            false => Default::default(),
+        };
+        peer.send(GsbMessage::CallRequest(CallRequest {
+            request_id: address.to_string(),
+            caller: "router".to_string(),
+            address: address.to_string(),
+            headers,
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        loop {
+            match peer.next().await.unwrap().unwrap() {
+                GsbMessage::CallReply(r) => return r,
+                _ => continue,
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_round_trip() {
+        let handler = MockCallHandler::default();
+        handler.respond("/public/echo", b"pong".to_vec());
+        let (client, router) = tokio::io::duplex(64 * 1024);
+        let _connection = connect_with_handler(
+            ClientInfo::new("test"),
+            Framed::new(client, GsbMessageCodec::default()),
+            handler.clone(),
+        );
+        let mut peer = Framed::new(router, GsbMessageCodec::default());
+
+        let reply = request(&mut peer, "/public/echo", false).await;
+        assert_eq!(reply.code, CallReplyCode::CallReplyOk as i32);
+        assert_eq!(reply.data, b"pong");
+        let reply = request(&mut peer, "/public/other", false).await;
+        assert_eq!(reply.code, CallReplyCode::CallReplyBadRequest as i32);
+
+        // pings answered only for scripted addresses
+        let reply = request(&mut peer, "/public/echo", true).await;
+        assert_eq!(reply.code, CallReplyCode::CallReplyOk as i32);
+        let reply = request(&mut peer, "/public/other", true).await;
+        assert_eq!(reply.code, CallReplyCode::CallReplyBadRequest as i32);
+        assert_eq!(reply.data, ya_sb_proto::ENDPOINT_NOT_FOUND.as_bytes());
+
+        let calls = handler.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].caller, "router");
+        assert_eq!(calls[0].address, "/public/echo");
+    }
+}
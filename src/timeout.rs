@@ -1,9 +1,17 @@
 use futures::future::{Either, Future, Map};
 use futures::FutureExt;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::error::Elapsed;
 use tokio::time::{timeout, Timeout};
 
+/// Current time as seen by tokio timers.
+///
+/// Unlike `Instant::now` it follows `tokio::time::pause` and `advance`, so
+/// deadlines and heartbeats computed from it can be driven from tests.
+pub fn now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
+
 pub trait IntoDuration {
     fn into_duration(self) -> Duration;
 }
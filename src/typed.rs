@@ -1,9 +1,11 @@
 use crate::error::Error;
-use crate::local_router::{router, Router};
+use crate::local_router::{router, PinnedReply, RoutedReply, Router};
 use crate::{
-    Handle, RpcEndpoint, RpcEnvelope, RpcHandler, RpcMessage, RpcStreamHandler, RpcStreamMessage,
+    AliasHandle, Handle, PreparedCall, RpcEndpoint, RpcEnvelope, RpcHandler, RpcMessage,
+    RpcStreamHandler, RpcStreamMessage, RpcTryStreamHandler, StreamPolicy, StreamReply,
 };
 use futures::prelude::*;
+use futures::stream::LocalBoxStream;
 use futures::FutureExt;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
@@ -40,12 +42,66 @@ pub fn bind<T: RpcMessage>(addr: &str, endpoint: impl RpcHandler<T> + Unpin + 's
     router().lock().unwrap().bind(addr, endpoint)
 }
 
+/// Binds RpcHandler created by `factory` on first call, see `Router::bind_lazy`.
+pub fn bind_lazy<T: RpcMessage, H: RpcHandler<T> + Unpin + 'static>(
+    addr: &str,
+    factory: impl Fn() -> H + Send + Sync + 'static,
+) -> Handle {
+    router().lock().unwrap().bind_lazy(addr, factory)
+}
+
+/// Binds RpcHandler answering calls with message id `type_id` instead of
+/// `T::ID`. Both ids may be served during message id migration by binding
+/// the handler twice.
+#[inline]
+pub fn bind_as<T: RpcMessage>(
+    addr: &str,
+    type_id: &str,
+    endpoint: impl RpcHandler<T> + Unpin + 'static,
+) -> Handle {
+    router().lock().unwrap().bind_as(addr, type_id, endpoint)
+}
+
+/// Binds single RpcHandler under all given addresses at once.
+///
+/// Aliases stay bound as long as returned handle is kept.
+#[inline]
+pub fn bind_aliases<T: RpcMessage>(
+    addrs: &[&str],
+    endpoint: impl RpcHandler<T> + Unpin + 'static,
+) -> AliasHandle {
+    router().lock().unwrap().bind_aliases(addrs, endpoint)
+}
+
+/// Binds RpcHandler to given service address, failing with
+/// `Error::GsbAlreadyRegistered` when the address is already bound.
+#[inline]
+pub fn try_bind<T: RpcMessage>(
+    addr: &str,
+    endpoint: impl RpcHandler<T> + Unpin + 'static,
+) -> Result<Handle, Error> {
+    router().lock().unwrap().try_bind(addr, endpoint)
+}
+
+/// Binds RpcHandler to given service address, replacing previously bound handler.
+#[inline]
+pub fn rebind<T: RpcMessage>(addr: &str, endpoint: impl RpcHandler<T> + Unpin + 'static) -> Handle {
+    router().lock().unwrap().rebind(addr, endpoint)
+}
+
 #[inline]
 pub async fn unbind(addr: &str) -> Result<bool, Error> {
     let future = { router().lock().unwrap().unbind(addr) };
     future.await
 }
 
+/// Unbinds all services under `addr`, reporting for each removed address
+/// whether the gsb router dropped it, see `Router::unbind_with_report`.
+pub async fn unbind_with_report(addr: &str) -> Vec<(String, Result<(), Error>)> {
+    let future = { router().lock().unwrap().unbind_with_report(addr) };
+    future.await
+}
+
 pub fn bind_stream<T: RpcStreamMessage>(
     addr: &str,
     endpoint: impl RpcStreamHandler<T> + Unpin + 'static,
@@ -53,6 +109,28 @@ pub fn bind_stream<T: RpcStreamMessage>(
     router().lock().unwrap().bind_stream(addr, endpoint)
 }
 
+/// Binds streaming handler that may end the stream with fatal error, see
+/// `RpcTryStreamHandler`.
+pub fn bind_try_stream<T: RpcStreamMessage>(
+    addr: &str,
+    endpoint: impl RpcTryStreamHandler<T> + Unpin + 'static,
+) -> Handle {
+    router().lock().unwrap().bind_try_stream(addr, endpoint)
+}
+
+/// Binds RpcStreamHandler to given service address, rejecting calls with
+/// `Error::GsbFailure("busy")` once limits from `policy` are exceeded.
+pub fn bind_stream_with_policy<T: RpcStreamMessage>(
+    addr: &str,
+    endpoint: impl RpcStreamHandler<T> + Unpin + 'static,
+    policy: StreamPolicy,
+) -> Handle {
+    router()
+        .lock()
+        .unwrap()
+        .bind_stream_with_policy(addr, endpoint, policy)
+}
+
 #[inline]
 pub fn bind_with_caller<T: RpcMessage, Output, F>(addr: &str, f: F) -> Handle
 where
@@ -62,6 +140,31 @@ where
     router().lock().unwrap().bind(addr, WithCaller(f))
 }
 
+/// Binds closure sharing `state` between calls, each call gets its own
+/// reference to it, so the returned future may hold it across awaits.
+#[inline]
+pub fn bind_with_state<T: RpcMessage, S, Output, F>(addr: &str, state: Arc<S>, f: F) -> Handle
+where
+    S: ?Sized + 'static,
+    Output: Future<Output = Result<T::Item, T::Error>> + 'static,
+    F: Fn(Arc<S>, String, T) -> Output + 'static,
+{
+    bind_with_caller(addr, move |caller, msg| f(state.clone(), caller, msg))
+}
+
+/// Binds closure returning reply stream to given service address.
+///
+/// Unlike plain closures accepted by `bind_stream`, `f` gets the caller
+/// and the returned stream doesn't have to be `Unpin`.
+#[inline]
+pub fn bind_stream_fn<T: RpcStreamMessage, Output, F>(addr: &str, f: F) -> Handle
+where
+    Output: Stream<Item = Result<T::Item, T::Error>> + 'static,
+    F: FnMut(String, T) -> Output + 'static,
+{
+    router().lock().unwrap().bind_stream(addr, StreamFn(f))
+}
+
 #[derive(Clone)]
 pub struct Endpoint {
     router: Arc<Mutex<Router>>,
@@ -84,6 +187,43 @@ impl Endpoint {
             .forward(&self.addr, RpcEnvelope::local(msg))
     }
 
+    /// Like `call`, also reporting whether it was served locally or sent to
+    /// the gsb router.
+    pub fn call_with_route<T: RpcMessage + Unpin>(
+        &self,
+        msg: T,
+    ) -> impl Future<Output = RoutedReply<T>> {
+        self.router
+            .lock()
+            .unwrap()
+            .forward_with_route(&self.addr, RpcEnvelope::local(msg))
+    }
+
+    /// Calls the service on router connection of given `instance_id`,
+    /// for calls that have to reach the instance holding session state.
+    pub fn call_instance<T: RpcMessage + Unpin>(
+        &self,
+        instance_id: &[u8],
+        msg: T,
+    ) -> impl Future<Output = PinnedReply<T>> {
+        self.router.lock().unwrap().forward_to_instance(
+            &self.addr,
+            instance_id,
+            RpcEnvelope::local(msg),
+        )
+    }
+
+    /// Calls the service with body serialized beforehand, see `PreparedCall`.
+    pub fn call_prepared<T: RpcMessage>(
+        &self,
+        prepared: &PreparedCall<T>,
+    ) -> impl Future<Output = Result<Result<T::Item, T::Error>, Error>> {
+        self.router
+            .lock()
+            .unwrap()
+            .forward_prepared(&self.addr, prepared)
+    }
+
     pub fn call_as<T: RpcMessage + Unpin>(
         &self,
         caller: impl ToString,
@@ -92,13 +232,53 @@ impl Endpoint {
         self.router
             .lock()
             .unwrap()
-            .forward(&self.addr, RpcEnvelope::with_caller(caller, msg))
+            .forward_from(&self.addr, &caller.to_string(), msg)
+    }
+
+    pub fn call_batch<T: RpcMessage + Unpin>(
+        &self,
+        msgs: Vec<T>,
+    ) -> impl Stream<Item = Result<Result<T::Item, T::Error>, Error>> {
+        self.router.lock().unwrap().forward_batch(
+            &self.addr,
+            msgs.into_iter().map(RpcEnvelope::local).collect(),
+        )
     }
 
     pub fn call_streaming<T: RpcStreamMessage>(
         &self,
         msg: T,
     ) -> impl Stream<Item = Result<Result<T::Item, T::Error>, Error>> + Unpin {
+        self.call_streaming_items(msg)
+            .try_filter_map(|item| future::ok(item.into_item()))
+    }
+
+    pub fn call_streaming_as<T: RpcStreamMessage>(
+        &self,
+        caller: &str,
+        msg: T,
+    ) -> impl Stream<Item = Result<Result<T::Item, T::Error>, Error>> + Unpin {
+        self.router
+            .lock()
+            .unwrap()
+            .streaming_forward_from(&self.addr, caller, msg)
+            .try_filter_map(|item| future::ok(item.into_item()))
+    }
+
+    /// Calls streaming endpoint for its first item only.
+    pub fn call_first<T: RpcStreamMessage>(
+        &self,
+        msg: T,
+    ) -> impl Future<Output = Result<Result<T::Item, T::Error>, Error>> {
+        self.router.lock().unwrap().forward_first(&self.addr, msg)
+    }
+
+    /// Like `call_streaming`, but reports graceful end of the response as
+    /// `StreamItem::Complete`.
+    pub fn call_streaming_items<T: RpcStreamMessage>(
+        &self,
+        msg: T,
+    ) -> impl Stream<Item = Result<StreamReply<T>, Error>> + Unpin {
         self.router
             .lock()
             .unwrap()
@@ -116,6 +296,19 @@ impl Endpoint {
             .forward_bytes(&self.addr, caller, msg, false)
     }
 
+    /// Calls the service as `T` with body serialized beforehand, returning
+    /// serialized reply, see `Router::forward_raw_typed`.
+    pub fn call_raw_typed_as<T: RpcMessage>(
+        &self,
+        caller: &str,
+        msg: Vec<u8>,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> {
+        self.router
+            .lock()
+            .unwrap()
+            .forward_raw_typed::<T>(&self.addr, caller, msg)
+    }
+
     pub fn push<T: RpcMessage + Unpin>(&self, msg: T) -> impl Future<Output = Result<(), Error>> {
         self.router
             .lock()
@@ -123,6 +316,16 @@ impl Endpoint {
             .push(&self.addr, RpcEnvelope::local(msg))
     }
 
+    pub fn push_prepared<T: RpcMessage>(
+        &self,
+        prepared: &PreparedCall<T>,
+    ) -> impl Future<Output = Result<(), Error>> {
+        self.router
+            .lock()
+            .unwrap()
+            .push_prepared(&self.addr, prepared)
+    }
+
     pub fn push_as<T: RpcMessage + Unpin>(
         &self,
         caller: impl ToString,
@@ -131,7 +334,7 @@ impl Endpoint {
         self.router
             .lock()
             .unwrap()
-            .push(&self.addr, RpcEnvelope::with_caller(caller, msg))
+            .push_from(&self.addr, &caller.to_string(), msg)
     }
 
     pub fn push_raw_as(
@@ -209,6 +412,24 @@ impl<
     }
 }
 
+struct StreamFn<F>(F);
+
+// closure is never pinned, only called through `&mut`
+impl<F> Unpin for StreamFn<F> {}
+
+impl<
+        T: RpcStreamMessage,
+        Output: Stream<Item = Result<T::Item, T::Error>> + 'static,
+        F: FnMut(String, T) -> Output + 'static,
+    > RpcStreamHandler<T> for StreamFn<F>
+{
+    type Result = LocalBoxStream<'static, Result<T::Item, T::Error>>;
+
+    fn handle(&mut self, caller: &str, msg: T) -> Self::Result {
+        (self.0)(caller.to_string(), msg).boxed_local()
+    }
+}
+
 pub struct ServiceBinder<'a, 'b, DB, AUX>
 where
     DB: std::clone::Clone + 'static,
@@ -271,3 +492,46 @@ where
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Visit;
+
+    impl RpcMessage for Visit {
+        const ID: &'static str = "Visit";
+        type Item = (String, usize);
+        type Error = ();
+    }
+
+    #[actix_rt::test]
+    async fn test_bind_with_state() {
+        let visits = Arc::new(AtomicUsize::new(0));
+        let _handle = bind_with_state(
+            "/local/stateful",
+            visits.clone(),
+            |visits: Arc<AtomicUsize>, caller, _: Visit| async move {
+                // state is held across awaits
+                tokio::task::yield_now().await;
+                Ok((caller, visits.fetch_add(1, Ordering::SeqCst) + 1))
+            },
+        );
+
+        let endpoint = service("/local/stateful");
+        let (first, second) = future::join(
+            endpoint.call_as("alice", Visit),
+            endpoint.call_as("bob", Visit),
+        )
+        .await;
+        let (first, second) = (first.unwrap().unwrap(), second.unwrap().unwrap());
+        assert_eq!(first.0, "alice");
+        assert_eq!(second.0, "bob");
+        let mut counts = vec![first.1, second.1];
+        counts.sort();
+        assert_eq!(counts, vec![1, 2]);
+        assert_eq!(visits.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,9 +1,19 @@
 use super::Handle;
+use crate::backoff::Backoff;
 use crate::error::Error;
 use crate::local_router::router;
-use crate::ResponseChunk;
-use futures::{Future, Stream, StreamExt};
+use crate::remote_router::{
+    CallQueue, RemoteRouter, SetCallQueue, SetEndpoint, SetReconnectBackoff,
+};
+use crate::{
+    CircuitBreakerConfig, CircuitState, EmptyReplyPolicy, EndpointKind, ResponseChunk, Route,
+    RpcRawCall, StreamItem,
+};
+use actix::SystemService;
+use bytes::Bytes;
+use futures::{future, Future, Stream, StreamExt, TryStreamExt};
 use std::pin::Pin;
+use std::time::Duration;
 
 pub fn send(
     addr: &str,
@@ -26,13 +36,30 @@ pub fn call_stream(
     caller: &str,
     bytes: &[u8],
 ) -> Pin<Box<dyn Stream<Item = Result<ResponseChunk, Error>>>> {
+    call_stream_items(addr, caller, bytes)
+        .try_filter_map(|item| future::ok(item.into_item()))
+        .boxed_local()
+}
+
+/// Like `call_stream`, but reports graceful end of the response as
+/// `StreamItem::Complete`.
+pub fn call_stream_items(
+    addr: &str,
+    caller: &str,
+    bytes: &[u8],
+) -> Pin<Box<dyn Stream<Item = Result<StreamItem<ResponseChunk>, Error>>>> {
     router()
         .lock()
         .unwrap()
-        .streaming_forward_bytes(addr, caller, bytes.into())
+        .streaming_forward_bytes(addr, caller, Bytes::copy_from_slice(bytes))
         .boxed_local()
 }
 
+/// Forwards raw call built by the caller, see `Router::forward_raw`.
+pub fn forward_raw(call: RpcRawCall) -> impl Future<Output = Result<Vec<u8>, Error>> {
+    router().lock().unwrap().forward_raw(call)
+}
+
 fn forward_bytes(
     addr: &str,
     caller: &str,
@@ -42,7 +69,7 @@ fn forward_bytes(
     router()
         .lock()
         .unwrap()
-        .forward_bytes(addr, caller, bytes.into(), no_reply)
+        .forward_bytes(addr, caller, Bytes::copy_from_slice(bytes), no_reply)
 }
 
 pub trait RawHandler {
@@ -222,3 +249,96 @@ pub fn subscribe(
     let (rr, rs) = raw_actor::recipients(rpc, stream);
     router().lock().unwrap().bind_raw_dual(addr, rr, rs)
 }
+
+/// Removes raw binding registered with `subscribe` at exact `addr`.
+pub async fn unbind_raw(addr: &str) -> Result<bool, Error> {
+    let future = { router().lock().unwrap().unbind_raw(addr) };
+    future.await
+}
+
+/// Checks that service at `addr` answers within `timeout`, returns the
+/// round-trip time. See `Error::NoEndpoint` and `Error::Timeout` for failures.
+pub async fn ping_service(addr: &str, timeout: Duration) -> Result<Duration, Error> {
+    let future = { router().lock().unwrap().ping_service(addr, timeout) };
+    future.await
+}
+
+/// Checks shape of service address: `/` followed by non-empty segments
+/// separated with `/`.
+pub fn validate_addr(addr: &str) -> Result<(), Error> {
+    router().lock().unwrap().validate_addr(addr)
+}
+
+/// Returns kind of the handler which would serve calls to `addr`.
+pub fn endpoint_kind(addr: &str) -> Option<EndpointKind> {
+    router().lock().unwrap().endpoint_kind(addr)
+}
+
+/// Reports whether calls to `addr` would be served locally or sent to the
+/// gsb router, without calling.
+pub fn resolve(addr: &str) -> Route {
+    router().lock().unwrap().resolve(addr)
+}
+
+/// Fails calls made more than `max_hops` forwards deep into a call chain,
+/// see `HOPS_HEADER`. `None` disables the check.
+pub fn set_max_hops(max_hops: Option<u32>) {
+    router().lock().unwrap().set_max_hops(max_hops)
+}
+
+/// Sets how empty replies of typed calls are decoded, see `EmptyReplyPolicy`.
+pub fn set_empty_reply_policy(policy: EmptyReplyPolicy) {
+    router().lock().unwrap().empty_reply_means(policy)
+}
+
+/// Enables circuit breaking of remote addresses, see `Router::set_circuit_breaker`.
+pub fn set_circuit_breaker(config: Option<CircuitBreakerConfig>) {
+    router().lock().unwrap().set_circuit_breaker(config)
+}
+
+/// State of circuit breaker of full address `addr`, e.g. `/svc/Msg`.
+pub fn circuit_state(addr: &str) -> CircuitState {
+    router().lock().unwrap().circuit_state(addr)
+}
+
+/// Closes circuit of full address `addr`, letting calls through again.
+pub fn reset_circuit(addr: &str) {
+    router().lock().unwrap().reset_circuit(addr)
+}
+
+/// Reconnects to the gsb router at `addr`, registering local services
+/// again. Calls made meanwhile wait for the new connection.
+///
+/// Has to be called within running actix system.
+pub fn set_gsb_endpoint(addr: ya_sb_proto::GsbAddr) {
+    RemoteRouter::from_registry().do_send(SetEndpoint(addr));
+}
+
+/// Sets delays between attempts to reconnect to the gsb router.
+///
+/// Has to be called within running actix system.
+pub fn set_reconnect_backoff(backoff: impl Backoff + Send + 'static) {
+    RemoteRouter::from_registry().do_send(SetReconnectBackoff(Box::new(backoff)));
+}
+
+/// Limits calls held while the gsb router is unreachable, see `CallQueue`.
+///
+/// Has to be called within running actix system.
+pub fn set_call_queue(queue: CallQueue) {
+    RemoteRouter::from_registry().do_send(SetCallQueue(queue));
+}
+
+/// Sets where the router and connections spawn their background tasks,
+/// the current arbiter by default.
+pub fn set_spawner(spawner: crate::Spawner) {
+    crate::spawner::set(spawner)
+}
+
+/// Sets canonicalization applied to every bound and called address, e.g.
+/// trimming trailing slashes. Has to be set before anything is bound.
+pub fn set_address_normalizer(normalizer: impl Fn(&str) -> String + Send + Sync + 'static) {
+    router()
+        .lock()
+        .unwrap()
+        .set_normalizer(Box::new(normalizer))
+}